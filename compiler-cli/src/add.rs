@@ -1,13 +1,40 @@
 use camino::{Utf8Path, Utf8PathBuf};
 
 use gleam_core::{
+    build::Mode,
     error::{FileIoAction, FileKind},
+    manifest::Manifest,
     Error, Result,
 };
+use hexpm::version::Version;
+use strum::{Display, EnumString, EnumVariantNames};
 
-use crate::{cli, dependencies::UseManifest, fs};
+use crate::{cli, dependencies::DownloadOptions, fs};
 
-pub fn command(packages: Vec<String>, dev: bool) -> Result<()> {
+/// How much the version requirement written to `gleam.toml` for an added
+/// package allows the version selected for it to change.
+#[derive(Debug, Display, EnumString, EnumVariantNames, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "kebab_case")]
+pub enum RequirementStyle {
+    /// Minor and patch upgrades, so 1.2.3 becomes `~> 1.2`
+    Minor,
+    /// Patch upgrades only, so 1.2.3 becomes `~> 1.2.3`
+    Patch,
+    /// No upgrades, so 1.2.3 becomes `== 1.2.3`
+    Exact,
+}
+
+impl RequirementStyle {
+    fn requirement(&self, version: &Version) -> String {
+        match self {
+            Self::Minor => format!("~> {}.{}", version.major, version.minor),
+            Self::Patch => format!("~> {version}"),
+            Self::Exact => format!("== {version}"),
+        }
+    }
+}
+
+pub fn command(packages: Vec<String>, dev: bool, style: RequirementStyle) -> Result<()> {
     let paths = crate::find_project_paths()?;
 
     // Insert the new packages into the manifest and perform dependency
@@ -16,7 +43,8 @@ pub fn command(packages: Vec<String>, dev: bool) -> Result<()> {
         &paths,
         cli::Reporter::new(),
         Some((packages.to_vec(), dev)),
-        UseManifest::Yes,
+        Mode::Dev,
+        DownloadOptions::default(),
     )?;
 
     // Read gleam.toml and manifest.toml so we can insert new deps into it
@@ -24,6 +52,37 @@ pub fn command(packages: Vec<String>, dev: bool) -> Result<()> {
     let mut manifest_toml = read_toml_edit("manifest.toml")?;
 
     // Insert the new deps
+    let added = add_requirements(
+        &mut gleam_toml,
+        &mut manifest_toml,
+        &manifest,
+        &packages,
+        dev,
+        style,
+    );
+    for (package, version) in added {
+        cli::print_added(&format!("{package} v{version}"));
+    }
+
+    // Write the updated config
+    fs::write(Utf8Path::new("gleam.toml"), &gleam_toml.to_string())?;
+    fs::write(Utf8Path::new("manifest.toml"), &manifest_toml.to_string())?;
+
+    Ok(())
+}
+
+/// Replace the `>= 0.0.0` requirements the added packages were resolved with
+/// with ones based on the versions that were selected, returning those
+/// versions.
+fn add_requirements(
+    gleam_toml: &mut toml_edit::Document,
+    manifest_toml: &mut toml_edit::Document,
+    manifest: &Manifest,
+    packages: &[String],
+    dev: bool,
+    style: RequirementStyle,
+) -> Vec<(String, Version)> {
+    let mut added = Vec::with_capacity(packages.len());
     for package_to_add in packages {
         // Pull the selected version out of the new manifest so we know what it is
         let version = &manifest
@@ -35,31 +94,24 @@ pub fn command(packages: Vec<String>, dev: bool) -> Result<()> {
 
         tracing::info!(version=%version, "new_package_version_resolved");
 
-        // Produce a version requirement locked to the major version.
-        // i.e. if 1.2.3 is selected we want ~> 1.2
-        let range = format!("~> {}.{}", version.major, version.minor);
+        let range = style.requirement(version);
 
         // False positive. This package doesn't use the indexing API correctly.
         #[allow(clippy::indexing_slicing)]
         {
             if dev {
-                gleam_toml["dev-dependencies"][&package_to_add] = toml_edit::value(range.clone());
+                gleam_toml["dev-dependencies"][package_to_add] = toml_edit::value(range.clone());
             } else {
-                gleam_toml["dependencies"][&package_to_add] = toml_edit::value(range.clone());
+                gleam_toml["dependencies"][package_to_add] = toml_edit::value(range.clone());
             };
-            manifest_toml["requirements"][&package_to_add]
+            manifest_toml["requirements"][package_to_add]
                 .as_inline_table_mut()
                 .expect("Invalid manifest format")["version"] = range.into();
         }
 
-        cli::print_added(&format!("{package_to_add} v{version}"));
+        added.push((package_to_add.clone(), version.clone()));
     }
-
-    // Write the updated config
-    fs::write(Utf8Path::new("gleam.toml"), &gleam_toml.to_string())?;
-    fs::write(Utf8Path::new("manifest.toml"), &manifest_toml.to_string())?;
-
-    Ok(())
+    added
 }
 
 fn read_toml_edit(name: &str) -> Result<toml_edit::Document, Error> {
@@ -72,3 +124,69 @@ fn read_toml_edit(name: &str) -> Result<toml_edit::Document, Error> {
             err: Some(e.to_string()),
         })
 }
+
+#[test]
+fn add_requirements_uses_selected_version() {
+    use gleam_core::manifest::{Base16Checksum, ManifestPackage, ManifestPackageSource};
+
+    let manifest = Manifest {
+        requirements: Default::default(),
+        packages: vec![ManifestPackage {
+            name: "gleam_json".into(),
+            version: Version::new(1, 2, 3),
+            build_tools: vec!["gleam".into()],
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![]),
+                repository: None,
+            },
+        }],
+    };
+    let added_requirements = |style| {
+        let mut gleam_toml: toml_edit::Document = r#"name = "app"
+
+[dependencies]
+gleam_json = ">= 0.0.0"
+
+[dev-dependencies]
+"#
+        .parse()
+        .unwrap();
+        let mut manifest_toml: toml_edit::Document = r#"packages = []
+
+[requirements]
+gleam_json = { version = ">= 0.0.0" }
+"#
+        .parse()
+        .unwrap();
+        let added = add_requirements(
+            &mut gleam_toml,
+            &mut manifest_toml,
+            &manifest,
+            &["gleam_json".into()],
+            false,
+            style,
+        );
+        assert_eq!(added, vec![("gleam_json".into(), Version::new(1, 2, 3))]);
+        #[allow(clippy::indexing_slicing)]
+        let requirement = gleam_toml["dependencies"]["gleam_json"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        #[allow(clippy::indexing_slicing)]
+        let manifest_requirement = manifest_toml["requirements"]["gleam_json"]["version"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(requirement, manifest_requirement);
+        assert!(hexpm::version::Range::new(requirement.clone())
+            .to_pubgrub()
+            .is_ok());
+        requirement
+    };
+
+    assert_eq!(added_requirements(RequirementStyle::Minor), "~> 1.2");
+    assert_eq!(added_requirements(RequirementStyle::Patch), "~> 1.2.3");
+    assert_eq!(added_requirements(RequirementStyle::Exact), "== 1.2.3");
+}
@@ -1,22 +1,29 @@
 use std::{sync::Arc, time::Instant};
 
 use gleam_core::{
-    build::{Built, Codegen, Options, ProjectCompiler},
+    build::{Built, Codegen, Mode, Options, ProjectCompiler},
     manifest::Manifest,
     paths::ProjectPaths,
-    Result,
+    warning::WarningEmitterIO,
+    Error, Result, Warning,
 };
 
 use crate::{
     build_lock::BuildLock,
     cli,
-    dependencies::UseManifest,
+    dependencies::DownloadOptions,
     fs::{self, get_current_directory, get_project_root, ConsoleWarningEmitter},
 };
 
-pub fn download_dependencies() -> Result<Manifest> {
+pub fn download_dependencies(mode: Mode) -> Result<Manifest> {
     let paths = crate::find_project_paths()?;
-    crate::dependencies::download(&paths, cli::Reporter::new(), None, UseManifest::Yes)
+    crate::dependencies::download(
+        &paths,
+        cli::Reporter::new(),
+        None,
+        mode,
+        DownloadOptions::default(),
+    )
 }
 
 pub fn main(options: Options, manifest: Manifest) -> Result<Built> {
@@ -33,6 +40,18 @@ pub fn main(options: Options, manifest: Manifest) -> Result<Built> {
     )?;
     let current_dir = get_project_root(get_current_directory()?)?;
 
+    // Check the build tools needed for the dependencies are installed before
+    // starting, rather than failing part way through the build
+    let warnings = missing_build_tools(&manifest, fs::program_installed);
+    for warning in &warnings {
+        ConsoleWarningEmitter.emit_warning(warning.clone());
+    }
+    if options.warnings_as_errors && !warnings.is_empty() {
+        return Err(Error::ForbiddenWarnings {
+            count: warnings.len(),
+        });
+    }
+
     tracing::info!("Compiling packages");
     let compiled = {
         let _guard = lock.lock(telemetry.as_ref());
@@ -54,3 +73,64 @@ pub fn main(options: Options, manifest: Manifest) -> Result<Built> {
     };
     Ok(compiled)
 }
+
+/// Warnings for the packages in the manifest which are built with a build tool
+/// other than Gleam that isn't installed.
+fn missing_build_tools(manifest: &Manifest, installed: impl Fn(&str) -> bool) -> Vec<Warning> {
+    manifest
+        .packages
+        .iter()
+        .filter(|package| !package.build_tools.iter().any(|tool| tool == "gleam"))
+        .filter_map(|package| {
+            let build_tools = package
+                .build_tools
+                .iter()
+                .filter(|tool| matches!(tool.as_str(), "rebar3" | "mix"))
+                .cloned()
+                .collect::<Vec<_>>();
+            // Packages with no supported build tool are reported when they are
+            // compiled
+            if build_tools.is_empty() || build_tools.iter().any(|tool| installed(tool)) {
+                return None;
+            }
+            Some(Warning::MissingBuildTool {
+                package: package.name.clone(),
+                build_tools,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn missing_build_tools_warns_when_mix_is_not_installed() {
+    use gleam_core::manifest::{Base16Checksum, ManifestPackage, ManifestPackageSource};
+
+    let package = |name: &str, build_tools: &[&str]| ManifestPackage {
+        name: name.into(),
+        version: hexpm::version::Version::new(1, 0, 0),
+        build_tools: build_tools.iter().map(|tool| (*tool).into()).collect(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let manifest = Manifest {
+        requirements: Default::default(),
+        packages: vec![
+            package("gleam_package", &["gleam"]),
+            package("elixir_package", &["mix"]),
+            package("either_package", &["mix", "rebar3"]),
+            package("make_package", &["make"]),
+        ],
+    };
+
+    assert_eq!(
+        missing_build_tools(&manifest, |tool| tool != "mix"),
+        vec![Warning::MissingBuildTool {
+            package: "elixir_package".into(),
+            build_tools: vec!["mix".into()],
+        }]
+    );
+}
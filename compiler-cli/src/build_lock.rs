@@ -1,4 +1,4 @@
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use gleam_core::{
     build::{Mode, Target, Telemetry},
     paths::ProjectPaths,
@@ -28,6 +28,15 @@ impl BuildLock {
         })
     }
 
+    /// Lock a repository in the global git cache, which is shared by every
+    /// project.
+    pub fn new_git_cache(repository: &Utf8Path) -> Result<Self> {
+        crate::fs::mkdir(repository)?;
+        Ok(Self {
+            directory: repository.to_path_buf(),
+        })
+    }
+
     /// Lock the specified directory
     pub fn lock<Telem: Telemetry>(&self, telemetry: &Telem) -> Result<Guard> {
         tracing::debug!(path=?self.directory, "locking_build_directory");
@@ -1,46 +1,209 @@
 use gleam_core::{
-    build::Telemetry,
+    build::{TelemetryEvent, TelemetryEventHandler},
     error::{Error, StandardIoAction},
+    manifest::PackageChange,
+    warning::WarningEmitterIO,
+    Warning,
 };
 use hexpm::version::Version;
 use std::{
+    collections::HashMap,
     io::Write,
-    time::{Duration, Instant},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 
 #[derive(Debug, Default, Clone)]
-pub struct Reporter;
+pub struct Reporter {
+    download_progress: Arc<Mutex<DownloadProgress>>,
+}
 
 impl Reporter {
     pub fn new() -> Self {
-        Self
+        Self::default()
     }
 }
 
-impl Telemetry for Reporter {
-    fn compiling_package(&self, name: &str) {
-        print_compiling(name);
+/// The bytes received so far and total size of each package being downloaded, used
+/// to draw a single progress bar for all of them.
+#[derive(Debug, Default)]
+struct DownloadProgress {
+    packages: HashMap<String, (u64, u64)>,
+    drawn_percent: Option<u64>,
+}
+
+impl DownloadProgress {
+    fn update(&mut self, name: &str, bytes: u64, total: u64) {
+        let _ = self.packages.insert(name.into(), (bytes, total));
+        let (bytes, total) = self
+            .packages
+            .values()
+            .fold((0, 0), |(bytes, total), (b, t)| (bytes + b, total + t));
+        let percent = (bytes * 100).checked_div(total).unwrap_or(100);
+
+        // Only redraw when there is a visible change, and never when the output
+        // is not a terminal as the bar is drawn over itself.
+        if self.drawn_percent == Some(percent) || !atty::is(atty::Stream::Stdout) {
+            return;
+        }
+        self.drawn_percent = Some(percent);
+        print_progress_bar(percent, bytes, total);
     }
 
-    fn checking_package(&self, name: &str) {
-        print_checking(name);
+    fn finish(&mut self) {
+        if self.drawn_percent.is_some() {
+            println!();
+        }
+        *self = Self::default();
     }
+}
+
+/// Reports only the warnings found while resolving and downloading packages,
+/// leaving out the progress so that it doesn't clutter the output of scripts.
+#[derive(Debug, Clone)]
+pub struct QuietReporter<Warnings = crate::fs::ConsoleWarningEmitter> {
+    warnings: Warnings,
+}
 
-    fn downloading_package(&self, name: &str) {
-        print_downloading(name)
+impl QuietReporter {
+    pub fn new() -> Self {
+        Self::with_warnings(crate::fs::ConsoleWarningEmitter)
     }
+}
 
-    fn packages_downloaded(&self, start: Instant, count: usize) {
-        print_packages_downloaded(start, count)
+impl<Warnings> QuietReporter<Warnings> {
+    pub fn with_warnings(warnings: Warnings) -> Self {
+        Self { warnings }
     }
+}
 
-    fn resolving_package_versions(&self) {
-        print_resolving_versions()
+impl<Warnings: WarningEmitterIO + std::fmt::Debug> TelemetryEventHandler
+    for QuietReporter<Warnings>
+{
+    fn handle(&self, event: TelemetryEvent) {
+        if let Ok(warning) = event_warning(event) {
+            self.warnings.emit_warning(warning)
+        }
     }
+}
 
-    fn waiting_for_build_directory_lock(&self) {
-        print_waiting_for_build_directory_lock()
+/// The warning for the user that an event is about, if it is one that warns,
+/// otherwise the event is given back.
+fn event_warning(event: TelemetryEvent) -> Result<Warning, TelemetryEvent> {
+    match event {
+        TelemetryEvent::RetiredPackageResolved {
+            name,
+            version,
+            retirement,
+        } => Ok(Warning::RetiredPackage {
+            package: name,
+            version,
+            retirement,
+        }),
+        TelemetryEvent::UnboundedRequirementResolved { name, version } => {
+            Ok(Warning::UnboundedRequirement {
+                package: name,
+                version,
+            })
+        }
+        TelemetryEvent::ExtraPackageNotDeleted { name, error } => {
+            Ok(Warning::ExtraPackageNotDeleted {
+                package: name,
+                error: Box::new(error),
+            })
+        }
+        TelemetryEvent::DuplicateOtpAppResolved { otp_app, packages } => {
+            Ok(Warning::DuplicateOtpApp { otp_app, packages })
+        }
+        event => Err(event),
+    }
+}
+
+#[test]
+fn quiet_reporter_only_reports_warnings() {
+    use gleam_core::{build::Telemetry, warning::VectorWarningEmitterIO};
+
+    let warnings = VectorWarningEmitterIO::new();
+    let reporter = QuietReporter::with_warnings(warnings.clone());
+    let version = Version::new(1, 0, 0);
+    let retirement = hexpm::RetirementStatus {
+        reason: hexpm::RetirementReason::Security,
+        message: "Vulnerable".into(),
+    };
+
+    reporter.resolving_package_versions();
+    reporter.retired_package_resolved("wibble", &version, &retirement);
+    reporter.downloading_package("wibble");
+    reporter.package_progress("wibble", 50, 100);
+    reporter.packages_downloaded(std::time::Instant::now(), 1, 100);
+    reporter.compiling_package("wibble");
+
+    assert_eq!(
+        warnings.take(),
+        vec![Warning::RetiredPackage {
+            package: "wibble".into(),
+            version,
+            retirement,
+        }]
+    );
+}
+
+impl TelemetryEventHandler for Reporter {
+    fn handle(&self, event: TelemetryEvent) {
+        let event = match event_warning(event) {
+            Ok(warning) => return crate::fs::ConsoleWarningEmitter.emit_warning(warning),
+            Err(event) => event,
+        };
+        match event {
+            TelemetryEvent::CompilingPackage { name } => print_compiling(&name),
+            TelemetryEvent::CheckingPackage { name } => print_checking(&name),
+            TelemetryEvent::DownloadingPackage { name } => print_downloading(&name),
+            TelemetryEvent::ResolvedPackageChanges { changes } => {
+                // Only version changes are printed as the add and remove
+                // commands already report the packages they change.
+                for change in changes {
+                    if let PackageChange::Changed { name, old, new } = change {
+                        print_version_change(&name, &old, &new)
+                    }
+                }
+            }
+            // Reported as warnings above
+            TelemetryEvent::RetiredPackageResolved { .. }
+            | TelemetryEvent::UnboundedRequirementResolved { .. }
+            | TelemetryEvent::ExtraPackageNotDeleted { .. }
+            | TelemetryEvent::DuplicateOtpAppResolved { .. } => (),
+            // Shown with `GLEAM_LOG=info` to help find where resolution is
+            // spending its time.
+            TelemetryEvent::ResolutionPhaseFinished { phase, elapsed } => {
+                tracing::info!(phase=?phase, elapsed=?elapsed, "resolution_phase_finished")
+            }
+            // Shown with `GLEAM_LOG=info` to help find why packages are
+            // downloaded again.
+            TelemetryEvent::PackageMissing { name, reason } => {
+                tracing::info!(package=%name, reason=?reason, "package_missing")
+            }
+            TelemetryEvent::PackageProgress { name, bytes, total } => self
+                .download_progress
+                .lock()
+                .expect("download progress lock")
+                .update(&name, bytes, total),
+            TelemetryEvent::PackagesDownloaded {
+                elapsed,
+                count,
+                bytes,
+            } => {
+                self.download_progress
+                    .lock()
+                    .expect("download progress lock")
+                    .finish();
+                print_packages_downloaded(elapsed, count, bytes)
+            }
+            TelemetryEvent::ResolvingPackageVersions => print_resolving_versions(),
+            TelemetryEvent::WaitingForBuildDirectoryLock => {
+                print_waiting_for_build_directory_lock()
+            }
+        }
     }
 }
 
@@ -91,6 +254,10 @@ pub fn print_unretired(package: &str, version: &str) {
     print_colourful_prefix("Unretired", &format!("{package} {version}"))
 }
 
+pub fn print_repairing(name: &str, version: &Version) {
+    print_colourful_prefix("Repairing", &format!("{name} v{version}"))
+}
+
 pub fn print_publishing_documentation() {
     print_colourful_prefix("Publishing", "documentation");
 }
@@ -99,6 +266,32 @@ fn print_downloading(text: &str) {
     print_colourful_prefix("Downloading", text)
 }
 
+fn print_progress_bar(percent: u64, bytes: u64, total: u64) {
+    const WIDTH: u64 = 30;
+    let filled = (percent * WIDTH / 100) as usize;
+    let bar = format!(
+        "[{}{}]",
+        "=".repeat(filled),
+        " ".repeat(WIDTH as usize - filled)
+    );
+    let mut stdout = std::io::stdout();
+    // The line is redrawn in place, so it is cleared to the end
+    write!(
+        stdout,
+        "\r{prefix: >11} {bar} {} / {}\x1b[K",
+        byte_size(bytes),
+        byte_size(total),
+        prefix = "Unpacking",
+    )
+    .expect("print_progress_bar");
+    stdout.flush().expect("print_progress_bar");
+}
+
+fn print_version_change(name: &str, old: &Version, new: &Version) {
+    let prefix = if new > old { "Upgraded" } else { "Downgraded" };
+    print_colourful_prefix(prefix, &format!("{name} {old} -> {new}"))
+}
+
 fn print_waiting_for_build_directory_lock() {
     print_colourful_prefix("Waiting", "for build directory lock")
 }
@@ -139,15 +332,44 @@ pub(crate) fn print_removed(text: &str) {
     print_colourful_prefix("Removed", text)
 }
 
+pub(crate) fn print_locked(text: &str) {
+    print_colourful_prefix("Locked", text)
+}
+
+pub(crate) fn print_vendored(text: &str) {
+    print_colourful_prefix("Vendored", text)
+}
+
+pub(crate) fn print_validated(text: &str) {
+    print_colourful_prefix("Validated", text)
+}
+
 pub(crate) fn print_generating_documentation() {
     print_colourful_prefix("Generating", "documentation")
 }
 
-fn print_packages_downloaded(start: Instant, count: usize) {
-    let elapsed = seconds(start.elapsed());
+pub(crate) fn print_dry_run(prefix: &str, text: &str) {
+    print_colourful_prefix(prefix, text)
+}
+
+pub(crate) fn print_cleaned(bytes: u64) {
+    print_colourful_prefix("Cleaned", &format!("{} reclaimed", byte_size(bytes)))
+}
+
+pub fn byte_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{bytes} B"),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.),
+    }
+}
+
+fn print_packages_downloaded(elapsed: Duration, count: usize, bytes: u64) {
+    let elapsed = seconds(elapsed);
+    let size = byte_size(bytes);
     let msg = match count {
-        1 => format!("1 package in {elapsed}"),
-        _ => format!("{count} packages in {elapsed}"),
+        1 => format!("1 package ({size}) in {elapsed}"),
+        _ => format!("{count} packages ({size}) in {elapsed}"),
     };
     print_colourful_prefix("Downloaded", &msg)
 }
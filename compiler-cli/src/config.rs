@@ -43,7 +43,7 @@ pub fn find_package_config_for_module(
     root_config()
 }
 
-fn package_root(package: &ManifestPackage, project_paths: &ProjectPaths) -> Utf8PathBuf {
+pub(crate) fn package_root(package: &ManifestPackage, project_paths: &ProjectPaths) -> Utf8PathBuf {
     match &package.source {
         ManifestPackageSource::Local { path } => project_paths.root().join(path),
 
@@ -94,6 +94,7 @@ mod tests {
             requirements: vec![],
             source: ManifestPackageSource::Hex {
                 outer_checksum: Base16Checksum(vec![]),
+                repository: None,
             },
         };
         assert_eq!(
@@ -114,6 +115,7 @@ mod tests {
             source: ManifestPackageSource::Git {
                 repo: "repo".into(),
                 commit: "commit".into(),
+                subdir: None,
             },
         };
         assert_eq!(
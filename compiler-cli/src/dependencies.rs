@@ -1,50 +1,105 @@
 use std::{
-    collections::{HashMap, HashSet},
-    time::Instant,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use camino::{Utf8Path, Utf8PathBuf};
 use ecow::EcoString;
 use flate2::read::GzDecoder;
-use futures::future;
+use futures::{stream, StreamExt, TryStreamExt};
 use gleam_core::{
-    build::{Mode, Target, Telemetry},
-    config::PackageConfig,
+    build::{Mode, NullTelemetry, ResolutionPhase, Target, Telemetry},
+    config::{HexCredentials, PackageConfig, Patch},
     dependency,
-    error::{FileIoAction, FileKind, StandardIoAction},
-    hex::{self, HEXPM_PUBLIC_KEY},
-    io::{HttpClient as _, TarUnpacker, WrappedReader},
-    manifest::{Base16Checksum, Manifest, ManifestPackage, ManifestPackageSource},
-    paths::ProjectPaths,
+    error::{FileIoAction, FileKind, RequirementProblem, ResolutionFailure, StandardIoAction},
+    hex::{self, HexRepository},
+    io::{make_relative, TarUnpacker, WrappedReader},
+    manifest::{
+        Base16Checksum, Manifest, ManifestPackage, ManifestPackageSource, MissingPackageReason,
+    },
+    paths::{self, ProjectPaths},
     requirement::Requirement,
-    Error, Result,
+    warning::WarningEmitterIO,
+    Error, Result, Warning,
 };
 use hexpm::version::Version;
 use itertools::Itertools;
 use same_file::is_same_file;
-use strum::IntoEnumIterator;
+use strum::{EnumString, EnumVariantNames, IntoEnumIterator};
 
 use crate::{
     build_lock::BuildLock,
     cli,
     fs::{self, ProjectIO},
-    http::HttpClient,
+    http::{
+        ClientHeaders, HttpClient, Proxies, RequestKind, RetryingHttpClient, SnapshotHttpClient,
+    },
 };
 
-pub fn list() -> Result<()> {
-    let runtime = tokio::runtime::Runtime::new().expect("Unable to start Tokio async runtime");
-    let project = fs::get_project_root(fs::get_current_directory()?)?;
-    let paths = ProjectPaths::new(project);
-    let config = crate::config::root_config()?;
+/// Read the config and manifest of the project, resolving the dependency
+/// versions if the manifest is missing or out of date.
+fn project_manifest(paths: &ProjectPaths) -> Result<(PackageConfig, Manifest)> {
+    let runtime = crate::http::async_runtime()?;
+    let config = crate::config::read(paths.root_config())?;
     let (_, manifest) = get_manifest(
-        &paths,
+        paths,
         runtime.handle().clone(),
         Mode::Dev,
         &config,
         &cli::Reporter::new(),
-        UseManifest::Yes,
+        &DownloadOptions::default(),
     )?;
-    list_manifest_packages(std::io::stdout(), manifest)
+    Ok((config, manifest))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Text,
+    Json,
+}
+
+pub fn list(paths: &ProjectPaths, format: ListFormat) -> Result<()> {
+    list_packages(std::io::stdout(), paths, format)
+}
+
+fn list_packages<W: std::io::Write>(
+    buffer: W,
+    paths: &ProjectPaths,
+    format: ListFormat,
+) -> Result<()> {
+    let (_, manifest) = project_manifest(paths)?;
+    match format {
+        ListFormat::Text => list_manifest_packages(buffer, manifest),
+        ListFormat::Json => list_manifest_packages_json(buffer, manifest),
+    }
+}
+
+#[test]
+fn list_packages_of_another_directory() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../local\" }\n",
+    );
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.2.0\"\n",
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+    assert_ne!(fs::get_current_directory().unwrap(), paths.root());
+
+    let mut buffer = vec![];
+    list_packages(&mut buffer, &paths, ListFormat::Text).unwrap();
+
+    assert_eq!(std::str::from_utf8(&buffer).unwrap(), "local 1.2.0\n");
 }
 
 fn list_manifest_packages<W: std::io::Write>(mut buffer: W, manifest: Manifest) -> Result<()> {
@@ -58,726 +113,6874 @@ fn list_manifest_packages<W: std::io::Write>(mut buffer: W, manifest: Manifest)
         })
 }
 
-#[test]
-fn list_manifest_format() {
-    let mut buffer = vec![];
-    let manifest = Manifest {
-        requirements: HashMap::new(),
-        packages: vec![
-            ManifestPackage {
-                name: "root".into(),
-                version: Version::parse("1.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4]),
-                },
-            },
-            ManifestPackage {
-                name: "aaa".into(),
-                version: Version::new(0, 4, 2),
-                build_tools: ["rebar3".into(), "make".into()].into(),
-                otp_app: Some("aaa_app".into()),
-                requirements: vec!["zzz".into(), "gleam_stdlib".into()],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![3, 22]),
-                },
-            },
-            ManifestPackage {
-                name: "zzz".into(),
-                version: Version::new(0, 4, 0),
-                build_tools: ["mix".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![3, 22]),
-                },
-            },
-        ],
-    };
-    list_manifest_packages(&mut buffer, manifest).unwrap();
-    assert_eq!(
-        std::str::from_utf8(&buffer).unwrap(),
-        r#"root 1.0.0
-aaa 0.4.2
-zzz 0.4.0
-"#
-    )
+pub fn outdated() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let (config, manifest) = project_manifest(&paths)?;
+    let runtime = crate::http::async_runtime()?;
+    let fetcher = PackageFetcher::boxed(
+        runtime.handle().clone(),
+        Rc::new(RefCell::new(hex_repository_for_manifest(
+            &config, &manifest,
+        )?)),
+        paths.build_hex_metadata_directory(),
+        Some(hex_metadata_ttl()),
+        ClientHeaders::for_config(&config.hex),
+    );
+    let packages = outdated_packages(fetcher.as_ref(), &config, &manifest)?;
+    print_outdated_packages(std::io::stdout(), &packages)
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum UseManifest {
-    Yes,
-    No,
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OutdatedPackage {
+    name: EcoString,
+    current: Version,
+    available: AvailableVersions,
 }
 
-pub fn update() -> Result<()> {
-    let paths = crate::find_project_paths()?;
-    _ = download(&paths, cli::Reporter::new(), None, UseManifest::No)?;
-    Ok(())
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AvailableVersions {
+    /// Local and git packages are not published to Hex.
+    NotApplicable,
+    Hex {
+        /// The highest version that satisfies the requirement in gleam.toml.
+        /// This is only known for direct dependencies.
+        compatible: Option<Version>,
+        latest: Option<Version>,
+    },
 }
 
-pub fn download<Telem: Telemetry>(
-    paths: &ProjectPaths,
-    telemetry: Telem,
-    new_package: Option<(Vec<String>, bool)>,
-    // If true we read the manifest from disc. If not set then we ignore any
-    // manifest which will result in the latest versions of the dependency
-    // packages being resolved (not the locked ones).
-    use_manifest: UseManifest,
-) -> Result<Manifest> {
-    let span = tracing::info_span!("download_deps");
-    let _enter = span.enter();
-
-    let mode = Mode::Dev;
+/// Look up the versions available on Hex for each package in the manifest.
+/// Retired releases and pre-releases are not considered.
+fn outdated_packages(
+    fetcher: &dyn dependency::PackageFetcher,
+    config: &PackageConfig,
+    manifest: &Manifest,
+) -> Result<Vec<OutdatedPackage>> {
+    let requirements = config.all_dependencies()?;
+    manifest
+        .packages
+        .iter()
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+        .map(|package| {
+            let available = if package.is_hex() {
+                let releases = fetcher
+                    .get_dependencies(&package.name)
+                    .map_err(|error| Error::Hex(error.to_string()))?
+                    .releases;
+                let versions = releases
+                    .into_iter()
+                    .filter(|release| !release.is_retired() && !release.version.is_pre())
+                    .map(|release| release.version)
+                    .collect_vec();
+                let range = match requirements.get(&package.name) {
+                    Some(Requirement::Hex { version, .. }) => {
+                        Some(version.to_pubgrub().map_err(Error::hex)?)
+                    }
+                    _ => None,
+                };
+                let compatible = range.and_then(|range| {
+                    versions
+                        .iter()
+                        .filter(|version| range.contains(version))
+                        .max()
+                        .cloned()
+                });
+                AvailableVersions::Hex {
+                    compatible,
+                    latest: versions.into_iter().max(),
+                }
+            } else {
+                AvailableVersions::NotApplicable
+            };
+            Ok(OutdatedPackage {
+                name: package.name.clone(),
+                current: package.version.clone(),
+                available,
+            })
+        })
+        .collect()
+}
 
-    // We do this before acquiring the build lock so that we don't create the
-    // build directory if there is no gleam.toml
-    crate::config::ensure_config_exists(paths)?;
+fn print_outdated_packages<W: std::io::Write>(
+    mut buffer: W,
+    packages: &[OutdatedPackage],
+) -> Result<()> {
+    let version = |version: &Option<Version>| match version {
+        Some(version) => version.to_string(),
+        None => "-".into(),
+    };
+    let rows = packages
+        .iter()
+        .map(|package| {
+            let (compatible, latest) = match &package.available {
+                AvailableVersions::NotApplicable => ("n/a".into(), "n/a".into()),
+                AvailableVersions::Hex { compatible, latest } => {
+                    (version(compatible), version(latest))
+                }
+            };
+            [
+                package.name.to_string(),
+                package.current.to_string(),
+                compatible,
+                latest,
+            ]
+        })
+        .collect_vec();
+    let header = ["Package", "Current", "Compatible", "Latest"].map(String::from);
 
-    let lock = BuildLock::new_packages(paths)?;
-    let _guard = lock.lock(&telemetry);
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
 
-    let fs = ProjectIO::boxed();
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .join("  ");
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    buffer
+        .write_all(output.as_bytes())
+        .map_err(|e| Error::StandardIo {
+            action: StandardIoAction::Write,
+            err: Some(e.kind()),
+        })
+}
 
-    // Read the project config
-    let mut config = crate::config::read(paths.root_config())?;
-    let project_name = config.name.clone();
+#[test]
+fn outdated_packages_compares_versions() {
+    struct Releases;
 
-    // Insert the new packages to add, if it exists
-    if let Some((packages, dev)) = new_package {
-        for package in packages {
-            let version = Requirement::hex(">= 0.0.0");
-            let _ = if dev {
-                config.dev_dependencies.insert(package.into(), version)
-            } else {
-                config.dependencies.insert(package.into(), version)
+    impl dependency::PackageFetcher for Releases {
+        fn get_dependencies(
+            &self,
+            package: &str,
+        ) -> Result<hexpm::Package, Box<dyn std::error::Error>> {
+            let release = |version: &str, retired: bool| hexpm::Release {
+                version: Version::parse(version).unwrap(),
+                requirements: HashMap::new(),
+                retirement_status: retired.then(|| hexpm::RetirementStatus {
+                    reason: hexpm::RetirementReason::Security,
+                    message: "".into(),
+                }),
+                outer_checksum: vec![],
+                meta: (),
             };
+            Ok(hexpm::Package {
+                name: package.into(),
+                repository: "hexpm".into(),
+                releases: vec![
+                    release("1.0.0", false),
+                    release("1.2.0", false),
+                    release("1.3.0", true),
+                    release("2.0.0", false),
+                    release("3.0.0-rc1", false),
+                ],
+            })
         }
     }
 
-    // Start event loop so we can run async functions to call the Hex API
-    let runtime = tokio::runtime::Runtime::new().expect("Unable to start Tokio async runtime");
-
-    // Determine what versions we need
-    let (manifest_updated, manifest) = get_manifest(
-        paths,
-        runtime.handle().clone(),
-        mode,
-        &config,
-        &telemetry,
-        use_manifest,
-    )?;
-    let local = LocalPackages::read_from_disc(paths)?;
-
-    // Remove any packages that are no longer required due to gleam.toml changes
-    remove_extra_packages(paths, &local, &manifest, &telemetry)?;
+    let package = |name: &str, source: ManifestPackageSource| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source,
+    };
+    let hex = || ManifestPackageSource::Hex {
+        outer_checksum: Base16Checksum(vec![]),
+        repository: None,
+    };
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("direct".into(), Requirement::hex("~> 1.0")),
+        ("local".into(), Requirement::path("../local")),
+    ]
+    .into();
+    let manifest = Manifest {
+        requirements: config.dependencies.clone(),
+        packages: vec![
+            package("transitive", hex()),
+            package(
+                "local",
+                ManifestPackageSource::Local {
+                    path: "../local".into(),
+                },
+            ),
+            package("direct", hex()),
+        ],
+    };
 
-    // Download them from Hex to the local cache
-    runtime.block_on(add_missing_packages(
-        paths,
-        fs,
-        &manifest,
-        &local,
-        project_name,
-        &telemetry,
-    ))?;
+    let packages = outdated_packages(&Releases, &config, &manifest).unwrap();
+    assert_eq!(
+        packages,
+        vec![
+            OutdatedPackage {
+                name: "direct".into(),
+                current: Version::new(1, 0, 0),
+                available: AvailableVersions::Hex {
+                    compatible: Some(Version::new(1, 2, 0)),
+                    latest: Some(Version::new(2, 0, 0)),
+                },
+            },
+            OutdatedPackage {
+                name: "local".into(),
+                current: Version::new(1, 0, 0),
+                available: AvailableVersions::NotApplicable,
+            },
+            OutdatedPackage {
+                name: "transitive".into(),
+                current: Version::new(1, 0, 0),
+                available: AvailableVersions::Hex {
+                    compatible: None,
+                    latest: Some(Version::new(2, 0, 0)),
+                },
+            },
+        ]
+    );
 
-    if manifest_updated {
-        // Record new state of the packages directory
-        // TODO: test
-        tracing::debug!("writing_manifest_toml");
-        write_manifest_to_disc(paths, &manifest)?;
-    }
-    LocalPackages::from_manifest(&manifest).write_to_disc(paths)?;
+    let mut buffer = vec![];
+    print_outdated_packages(&mut buffer, &packages).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&buffer).unwrap(),
+        r#"Package     Current  Compatible  Latest
+direct      1.0.0    1.2.0       2.0.0
+local       1.0.0    n/a         n/a
+transitive  1.0.0    -           2.0.0
+"#
+    );
+}
 
-    Ok(manifest)
+pub fn tree() -> Result<()> {
+    let (config, manifest) = project_manifest(&crate::find_project_paths()?)?;
+    print_dependency_tree(std::io::stdout(), &config.name, &manifest)
 }
 
-async fn add_missing_packages<Telem: Telemetry>(
-    paths: &ProjectPaths,
-    fs: Box<ProjectIO>,
+/// Print the dependency graph as a tree starting from the root package, whose
+/// children are its direct dependencies. A package that has already been
+/// printed is marked with `(*)` and its dependencies are not printed again,
+/// and a package that depends on one of its own ancestors is marked with
+/// `(cycle)`.
+fn print_dependency_tree<W: std::io::Write>(
+    mut buffer: W,
+    root: &str,
     manifest: &Manifest,
-    local: &LocalPackages,
-    project_name: EcoString,
-    telemetry: &Telem,
-) -> Result<(), Error> {
-    let missing_packages = local.missing_local_packages(manifest, &project_name);
+) -> Result<()> {
+    let packages: HashMap<&str, &ManifestPackage> = manifest
+        .packages
+        .iter()
+        .map(|package| (package.name.as_str(), package))
+        .collect();
+    let direct = manifest
+        .requirements
+        .keys()
+        .map(|name| name.as_str())
+        .sorted()
+        .collect_vec();
 
-    let mut num_to_download = 0;
-    let mut missing_hex_packages = missing_packages
-        .into_iter()
-        .filter(|package| package.is_hex())
-        .map(|package| {
-            num_to_download += 1;
-            package
+    let mut output = format!("{root}\n");
+    let mut visited = HashSet::new();
+    let mut ancestors = vec![root];
+    push_dependency_subtree(
+        &mut output,
+        &packages,
+        &direct,
+        "",
+        &mut visited,
+        &mut ancestors,
+    );
+    buffer
+        .write_all(output.as_bytes())
+        .map_err(|e| Error::StandardIo {
+            action: StandardIoAction::Write,
+            err: Some(e.kind()),
         })
-        .peekable();
-
-    // If we need to download at-least one package
-    if missing_hex_packages.peek().is_some() {
-        let http = HttpClient::boxed();
-        let downloader = hex::Downloader::new(fs.clone(), fs, http, Untar::boxed(), paths.clone());
-        let start = Instant::now();
-        telemetry.downloading_package("packages");
-        downloader
-            .download_hex_packages(missing_hex_packages, &project_name)
-            .await?;
-        telemetry.packages_downloaded(start, num_to_download);
-    }
-
-    Ok(())
 }
 
-fn remove_extra_packages<Telem: Telemetry>(
-    paths: &ProjectPaths,
-    local: &LocalPackages,
-    manifest: &Manifest,
-    telemetry: &Telem,
-) -> Result<()> {
-    let _guard = BuildLock::lock_all_build(paths, telemetry)?;
+fn push_dependency_subtree<'a>(
+    output: &mut String,
+    packages: &HashMap<&'a str, &'a ManifestPackage>,
+    children: &[&'a str],
+    prefix: &str,
+    visited: &mut HashSet<&'a str>,
+    ancestors: &mut Vec<&'a str>,
+) {
+    for (index, name) in children.iter().enumerate() {
+        let last = index + 1 == children.len();
+        let (branch, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        output.push_str(prefix);
+        output.push_str(branch);
+        output.push_str(name);
 
-    for (package_name, version) in local.extra_local_packages(manifest) {
-        // TODO: test
-        // Delete the package source
-        let path = paths.build_packages_package(&package_name);
-        if path.exists() {
-            tracing::debug!(package=%package_name, version=%version, "removing_unneeded_package");
-            fs::delete_directory(&path)?;
-        }
+        let Some(package) = packages.get(name) else {
+            output.push_str(" (missing)\n");
+            continue;
+        };
+        output.push(' ');
+        output.push_str(&package.version.to_string());
 
-        // TODO: test
-        // Delete any build artefacts for the package
-        for mode in Mode::iter() {
-            for target in Target::iter() {
-                let name = manifest
-                    .packages
-                    .iter()
-                    .find(|p| p.name == package_name)
-                    .map(|p| p.application_name().as_str())
-                    .unwrap_or(package_name.as_str());
-                let path = paths.build_directory_for_package(mode, target, name);
-                if path.exists() {
-                    tracing::debug!(package=%package_name, version=%version, "deleting_build_cache");
-                    fs::delete_directory(&path)?;
-                }
-            }
+        if ancestors.contains(name) {
+            output.push_str(" (cycle)\n");
+            continue;
         }
+        if !visited.insert(name) {
+            output.push_str(" (*)\n");
+            continue;
+        }
+        output.push('\n');
+
+        let grandchildren = package
+            .requirements
+            .iter()
+            .map(|name| name.as_str())
+            .sorted()
+            .collect_vec();
+        ancestors.push(name);
+        push_dependency_subtree(
+            output,
+            packages,
+            &grandchildren,
+            &format!("{prefix}{indent}"),
+            visited,
+            ancestors,
+        );
+        let _ = ancestors.pop();
     }
-    Ok(())
 }
 
-fn read_manifest_from_disc(paths: &ProjectPaths) -> Result<Manifest> {
-    tracing::debug!("reading_manifest_toml");
-    let manifest_path = paths.manifest();
-    let toml = crate::fs::read(&manifest_path)?;
-    let manifest = toml::from_str(&toml).map_err(|e| Error::FileIo {
-        action: FileIoAction::Parse,
-        kind: FileKind::File,
-        path: manifest_path.clone(),
-        err: Some(e.to_string()),
-    })?;
-    Ok(manifest)
+#[derive(Debug, EnumString, EnumVariantNames, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
 }
 
-fn write_manifest_to_disc(paths: &ProjectPaths, manifest: &Manifest) -> Result<()> {
-    let path = paths.manifest();
-    fs::write(&path, &manifest.to_toml(paths.root()))
+pub fn graph(format: GraphFormat, output: Option<Utf8PathBuf>) -> Result<()> {
+    use std::io::Write;
+
+    let (config, manifest) = project_manifest(&crate::find_project_paths()?)?;
+    let graph = match format {
+        GraphFormat::Dot => dependency_graph_dot(&config.name, &manifest),
+    };
+    match output {
+        Some(path) => fs::write(&path, &graph),
+        None => std::io::stdout()
+            .write_all(graph.as_bytes())
+            .map_err(|e| Error::StandardIo {
+                action: StandardIoAction::Write,
+                err: Some(e.kind()),
+            }),
+    }
 }
 
-// This is the container for locally pinned packages, representing the current contents of
-// the `project/build/packages` directory.
-// For descriptions of packages provided by paths and git deps, see the ProvidedPackage struct.
-// The same package may appear in both at different times.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-struct LocalPackages {
-    packages: HashMap<String, Version>,
+/// The dependency graph in Graphviz DOT format. Each package is labelled with
+/// its version, and the direct dependencies of the project are drawn in bold.
+fn dependency_graph_dot(root: &str, manifest: &Manifest) -> String {
+    let mut output = format!("digraph dependencies {{\n  {root:?} [shape=box];\n");
+    for package in &manifest.packages {
+        let style = if manifest.requirements.contains_key(&package.name) {
+            ", style=bold"
+        } else {
+            ""
+        };
+        let label = format!("{}\n{}", package.name, package.version);
+        output.push_str(&format!(
+            "  {:?} [label={label:?}{style}];\n",
+            package.name.as_str()
+        ));
+    }
+    for name in manifest.requirements.keys().sorted() {
+        output.push_str(&format!("  {root:?} -> {:?};\n", name.as_str()));
+    }
+    for package in &manifest.packages {
+        for requirement in package.requirements.iter().sorted() {
+            output.push_str(&format!(
+                "  {:?} -> {:?};\n",
+                package.name.as_str(),
+                requirement.as_str()
+            ));
+        }
+    }
+    output.push_str("}\n");
+    output
 }
 
-impl LocalPackages {
-    pub fn extra_local_packages(&self, manifest: &Manifest) -> Vec<(String, Version)> {
-        let manifest_packages: HashSet<_> = manifest
-            .packages
-            .iter()
-            .map(|p| (&p.name, &p.version))
-            .collect();
-        self.packages
-            .iter()
-            .filter(|(n, v)| !manifest_packages.contains(&(&EcoString::from(n.as_ref()), v)))
-            .map(|(n, v)| (n.clone(), v.clone()))
-            .collect()
-    }
+#[test]
+fn dependency_graph_dot_marks_direct_dependencies() {
+    let package = |name: &str, requirements: Vec<EcoString>| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 2, 3),
+        build_tools: vec!["gleam".into()],
+        otp_app: None,
+        requirements,
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let manifest = Manifest {
+        requirements: [("gleam_json".into(), Requirement::hex("~> 1.0"))].into(),
+        packages: vec![
+            package("gleam_json", vec!["gleam_stdlib".into(), "thoas".into()]),
+            package("gleam_stdlib", vec![]),
+            package("thoas", vec![]),
+        ],
+    };
 
-    pub fn missing_local_packages<'a>(
-        &self,
-        manifest: &'a Manifest,
-        root: &str,
-    ) -> Vec<&'a ManifestPackage> {
-        manifest
-            .packages
-            .iter()
-            // We don't need to download the root package
-            .filter(|p| p.name != root)
-            // We don't need to download local packages because we use the linked source directly
-            .filter(|p| !p.is_local())
-            // We don't need to download packages which we have the correct version of
-            .filter(|p| self.packages.get(p.name.as_str()) != Some(&p.version))
-            .collect()
-    }
+    assert_eq!(
+        dependency_graph_dot("app", &manifest),
+        r#"digraph dependencies {
+  "app" [shape=box];
+  "gleam_json" [label="gleam_json\n1.2.3", style=bold];
+  "gleam_stdlib" [label="gleam_stdlib\n1.2.3"];
+  "thoas" [label="thoas\n1.2.3"];
+  "app" -> "gleam_json";
+  "gleam_json" -> "gleam_stdlib";
+  "gleam_json" -> "thoas";
+}
+"#
+    );
+}
 
-    pub fn read_from_disc(paths: &ProjectPaths) -> Result<Self> {
-        let path = paths.build_packages_toml();
-        if !path.exists() {
-            return Ok(Self {
-                packages: HashMap::new(),
-            });
+pub fn why(package: String) -> Result<()> {
+    let (config, manifest) = project_manifest(&crate::find_project_paths()?)?;
+    print_dependency_paths(std::io::stdout(), &config, &manifest, &package)
+}
+
+/// Print every chain of dependencies from the project to the given package.
+/// Links from the project to its dev dependencies are marked with `[dev]`.
+fn print_dependency_paths<W: std::io::Write>(
+    mut buffer: W,
+    config: &PackageConfig,
+    manifest: &Manifest,
+    target: &str,
+) -> Result<()> {
+    let root = config.name.as_str();
+    let output = match manifest.packages.iter().find(|p| p.name == target) {
+        None => format!("{target} is not a dependency of {root}\n"),
+        Some(package) => {
+            let mut output = format!("{target} {} is required by:\n", package.version);
+            for path in dependency_paths(manifest, target) {
+                let direct = path.first().copied().unwrap_or_default();
+                let link = if config.dev_dependencies.contains_key(direct) {
+                    " [dev]"
+                } else {
+                    ""
+                };
+                output.push_str(&format!("  {root}{link} -> {}\n", path.join(" -> ")));
+            }
+            output
         }
-        let toml = crate::fs::read(&path)?;
-        toml::from_str(&toml).map_err(|e| Error::FileIo {
-            action: FileIoAction::Parse,
-            kind: FileKind::File,
-            path: path.clone(),
-            err: Some(e.to_string()),
+    };
+    buffer
+        .write_all(output.as_bytes())
+        .map_err(|e| Error::StandardIo {
+            action: StandardIoAction::Write,
+            err: Some(e.kind()),
         })
-    }
+}
 
-    pub fn write_to_disc(&self, paths: &ProjectPaths) -> Result<()> {
-        let path = paths.build_packages_toml();
-        let toml = toml::to_string(&self).expect("packages.toml serialization");
-        fs::write(&path, &toml)
+/// Every chain of packages from a direct dependency of the project to the
+/// target package, sorted. Packages are not repeated within a chain, so
+/// cycles are not followed.
+fn dependency_paths<'a>(manifest: &'a Manifest, target: &str) -> Vec<Vec<&'a str>> {
+    fn walk<'a>(
+        packages: &HashMap<&'a str, &'a ManifestPackage>,
+        target: &str,
+        path: &mut Vec<&'a str>,
+        found: &mut Vec<Vec<&'a str>>,
+    ) {
+        let Some(&name) = path.last() else { return };
+        if name == target {
+            found.push(path.clone());
+            return;
+        }
+        let Some(package) = packages.get(name) else {
+            return;
+        };
+        for dependency in &package.requirements {
+            if path.contains(&dependency.as_str()) {
+                continue;
+            }
+            path.push(dependency.as_str());
+            walk(packages, target, path, found);
+            let _ = path.pop();
+        }
     }
 
-    pub fn from_manifest(manifest: &Manifest) -> Self {
-        Self {
-            packages: manifest
-                .packages
-                .iter()
-                .map(|p| (p.name.to_string(), p.version.clone()))
-                .collect(),
-        }
+    let packages: HashMap<&str, &ManifestPackage> = manifest
+        .packages
+        .iter()
+        .map(|package| (package.name.as_str(), package))
+        .collect();
+    let mut found = vec![];
+    for direct in manifest.requirements.keys() {
+        walk(&packages, target, &mut vec![direct.as_str()], &mut found);
     }
+    found.sort();
+    found
 }
 
 #[test]
-fn missing_local_packages() {
+fn dependency_paths_format() {
+    let package = |name: &str, requirements: &[&str]| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: requirements.iter().map(|name| (*name).into()).collect(),
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1, 2, 3, 4]),
+            repository: None,
+        },
+    };
+    let mut config = PackageConfig::default();
+    config.name = "root".into();
+    config.dependencies = [("left".into(), Requirement::hex("~> 1.0"))].into();
+    config.dev_dependencies = [("right".into(), Requirement::hex("~> 1.0"))].into();
     let manifest = Manifest {
-        requirements: HashMap::new(),
+        requirements: config.all_dependencies().unwrap(),
         packages: vec![
-            ManifestPackage {
-                name: "root".into(),
-                version: Version::parse("1.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4]),
-                },
-            },
-            ManifestPackage {
-                name: "local1".into(),
-                version: Version::parse("1.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
-                },
-            },
-            ManifestPackage {
-                name: "local2".into(),
-                version: Version::parse("3.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
-                },
-            },
+            package("left", &["bottom", "cyclic"]),
+            package("right", &["bottom"]),
+            package("bottom", &["cyclic"]),
+            package("cyclic", &["bottom"]),
         ],
     };
-    let mut extra = LocalPackages {
-        packages: [
-            ("local2".into(), Version::parse("2.0.0").unwrap()),
-            ("local3".into(), Version::parse("3.0.0").unwrap()),
-        ]
-        .into(),
-    }
-    .missing_local_packages(&manifest, "root");
-    extra.sort();
+    let why = |target| {
+        let mut buffer = vec![];
+        print_dependency_paths(&mut buffer, &config, &manifest, target).unwrap();
+        String::from_utf8(buffer).unwrap()
+    };
     assert_eq!(
-        extra,
-        [
-            &ManifestPackage {
-                name: "local1".into(),
-                version: Version::parse("1.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
-                },
-            },
-            &ManifestPackage {
-                name: "local2".into(),
-                version: Version::parse("3.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
-                },
-            },
-        ]
-    )
+        why("cyclic"),
+        r#"cyclic 1.0.0 is required by:
+  root -> left -> bottom -> cyclic
+  root -> left -> cyclic
+  root [dev] -> right -> bottom -> cyclic
+"#
+    );
+    assert_eq!(
+        why("right"),
+        r#"right 1.0.0 is required by:
+  root [dev] -> right
+"#
+    );
+    assert_eq!(why("unknown"), "unknown is not a dependency of root\n");
 }
 
-#[test]
-fn extra_local_packages() {
-    let mut extra = LocalPackages {
-        packages: [
-            ("local1".into(), Version::parse("1.0.0").unwrap()),
-            ("local2".into(), Version::parse("2.0.0").unwrap()),
-            ("local3".into(), Version::parse("3.0.0").unwrap()),
-        ]
-        .into(),
+pub fn unused() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let config = crate::config::root_config()?;
+    // The dependencies' sources are needed to know which modules they have
+    let manifest = crate::build::download_dependencies(Mode::Dev)?;
+    let unused = unused_dependencies(&paths, &config, &manifest)?;
+    print_unused_dependencies(std::io::stdout(), &config, &unused)
+}
+
+/// The direct dependencies of the project that none of its modules import,
+/// sorted by name. Packages that are not built with Gleam are never reported
+/// as they are used through externals rather than imports.
+fn unused_dependencies(
+    paths: &ProjectPaths,
+    config: &PackageConfig,
+    manifest: &Manifest,
+) -> Result<Vec<EcoString>> {
+    let mut imported = HashSet::new();
+    for directory in [paths.src_directory(), paths.test_directory()] {
+        for path in fs::gleam_files_excluding_gitignore(&directory) {
+            let src = fs::read(&path)?;
+            let parsed = gleam_core::parse::parse_module(&src).map_err(|error| Error::Parse {
+                path: path.clone(),
+                src: src.into(),
+                error,
+            })?;
+            imported.extend(
+                parsed
+                    .module
+                    .definitions
+                    .into_iter()
+                    .filter_map(|definition| match definition.definition {
+                        gleam_core::ast::Definition::Import(import) => Some(import.module),
+                        _ => None,
+                    }),
+            );
+        }
+    }
+
+    let mut unused = vec![];
+    for name in config
+        .dependencies
+        .keys()
+        .chain(config.dev_dependencies.keys())
+        .sorted()
+    {
+        let Some(package) = manifest.packages.iter().find(|p| &p.name == name) else {
+            continue;
+        };
+        if !package.build_tools.iter().any(|tool| tool == "gleam") {
+            continue;
+        }
+        let source = match &package.source {
+            ManifestPackageSource::Local { path } => paths.root().join(path),
+            ManifestPackageSource::Hex { .. } | ManifestPackageSource::Git { .. } => {
+                paths.build_packages_package(name)
+            }
+        };
+        let source = source.join("src");
+        let used = fs::gleam_files_excluding_gitignore(&source).any(|path| {
+            let module = path
+                .strip_prefix(&source)
+                .unwrap_or(&path)
+                .with_extension("")
+                .as_str()
+                .replace('\\', "/");
+            imported.contains(module.as_str())
+        });
+        if !used {
+            unused.push(name.clone());
+        }
+    }
+    Ok(unused)
+}
+
+fn print_unused_dependencies<W: std::io::Write>(
+    mut buffer: W,
+    config: &PackageConfig,
+    unused: &[EcoString],
+) -> Result<()> {
+    let mut output = String::new();
+    if unused.is_empty() {
+        output.push_str("All dependencies are imported\n");
     }
-    .extra_local_packages(&Manifest {
+    for name in unused {
+        let dev = if config.dev_dependencies.contains_key(name) {
+            " [dev]"
+        } else {
+            ""
+        };
+        output.push_str(&format!("{name}{dev}\n"));
+    }
+    buffer
+        .write_all(output.as_bytes())
+        .map_err(|e| Error::StandardIo {
+            action: StandardIoAction::Write,
+            err: Some(e.kind()),
+        })
+}
+
+#[test]
+fn unused_dependencies_are_listed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        paths.src_directory().join("app.gleam"),
+        "import wibble/thing\npub fn main() { thing.go() }\n",
+    );
+    write(
+        paths.test_directory().join("app_test.gleam"),
+        "import gleeunit\n",
+    );
+    write(
+        paths
+            .build_packages_package("wibble")
+            .join("src/wibble/thing.gleam"),
+        "pub fn go() { Nil }\n",
+    );
+    write(
+        paths
+            .build_packages_package("wobble")
+            .join("src/wobble.gleam"),
+        "pub fn go() { Nil }\n",
+    );
+    write(
+        paths
+            .build_packages_package("gleeunit")
+            .join("src/gleeunit.gleam"),
+        "pub fn main() { Nil }\n",
+    );
+    let package = |name: &str, build_tool: &str| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: vec![build_tool.into()],
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("wibble".into(), Requirement::hex("~> 1.0")),
+        ("wobble".into(), Requirement::hex("~> 1.0")),
+        ("cowboy".into(), Requirement::hex("~> 1.0")),
+    ]
+    .into();
+    config.dev_dependencies = [("gleeunit".into(), Requirement::hex("~> 1.0"))].into();
+    let manifest = Manifest {
         requirements: HashMap::new(),
         packages: vec![
-            ManifestPackage {
-                name: "local1".into(),
-                version: Version::parse("1.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
-                },
-            },
-            ManifestPackage {
-                name: "local2".into(),
-                version: Version::parse("3.0.0").unwrap(),
-                build_tools: ["gleam".into()].into(),
-                otp_app: None,
-                requirements: vec![],
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(vec![4, 5]),
-                },
-            },
+            package("wibble", "gleam"),
+            package("wobble", "gleam"),
+            package("cowboy", "rebar3"),
+            package("gleeunit", "gleam"),
         ],
-    });
-    extra.sort();
-    assert_eq!(
-        extra,
-        [
-            ("local2".into(), Version::new(2, 0, 0)),
-            ("local3".into(), Version::new(3, 0, 0)),
-        ]
-    )
+    };
+
+    let unused = unused_dependencies(&paths, &config, &manifest).unwrap();
+    assert_eq!(unused, vec![EcoString::from("wobble")]);
+
+    let mut buffer = vec![];
+    print_unused_dependencies(&mut buffer, &config, &unused).unwrap();
+    assert_eq!(String::from_utf8(buffer).unwrap(), "wobble\n");
 }
 
-fn get_manifest<Telem: Telemetry>(
+pub fn licences(allowed: Vec<String>) -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let (config, manifest) = project_manifest(&paths)?;
+    let runtime = crate::http::async_runtime()?;
+    let packages = runtime.block_on(package_licences(
+        &paths,
+        &manifest,
+        &hex_repository_for_manifest(&config, &manifest)?,
+        &RetryingHttpClient::new(RequestKind::Metadata)
+            .with_headers(ClientHeaders::for_config(&config.hex)),
+    ))?;
+    let summary = LicenceSummary::new(&packages, &allowed);
+    print_licences(std::io::stdout(), &packages, &summary)?;
+
+    // Without an allowlist every licence is acceptable
+    if allowed.is_empty() || summary.problems.is_empty() {
+        return Ok(());
+    }
+    Err(Error::DisallowedLicences {
+        packages: summary.problems.into_keys().collect(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PackageLicences {
+    name: EcoString,
+    version: Version,
+    licences: Vec<String>,
+}
+
+/// Find the licences of each package in the manifest. Hex packages are looked
+/// up on Hex, with the result kept on disc, and local and git packages use the
+/// licences in their gleam.toml.
+async fn package_licences<Http: gleam_core::io::HttpClient>(
     paths: &ProjectPaths,
-    runtime: tokio::runtime::Handle,
-    mode: Mode,
-    config: &PackageConfig,
-    telemetry: &Telem,
-    use_manifest: UseManifest,
-) -> Result<(bool, Manifest)> {
-    // If there's no manifest (or we have been asked not to use it) then resolve
-    // the versions anew
-    let should_resolve = match use_manifest {
-        _ if !paths.manifest().exists() => {
-            tracing::debug!("manifest_not_present");
-            true
-        }
-        UseManifest::No => {
-            tracing::debug!("ignoring_manifest");
+    manifest: &Manifest,
+    hex_repository: &HexRepository,
+    http: &Http,
+) -> Result<Vec<PackageLicences>> {
+    let mut packages = Vec::with_capacity(manifest.packages.len());
+    for package in manifest
+        .packages
+        .iter()
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+    {
+        let licences = match &package.source {
+            ManifestPackageSource::Hex { .. } => {
+                let path = paths
+                    .build_hex_metadata_directory()
+                    .join(format!("{}-{}.licences", package.name, package.version));
+                if path.is_file() {
+                    fs::read(&path)?.lines().map(String::from).collect()
+                } else {
+                    let (config, api_key) = hex_repository.for_package(&package.name);
+                    let licences = hex::get_package_licences(
+                        &package.name,
+                        &package.version,
+                        &config,
+                        api_key,
+                        http,
+                    )
+                    .await?;
+                    fs::mkdir(paths.build_hex_metadata_directory())?;
+                    fs::write(&path, &licences.join("\n"))?;
+                    licences
+                }
+            }
+            ManifestPackageSource::Local { .. } | ManifestPackageSource::Git { .. } => {
+                let config = crate::config::package_root(package, paths).join("gleam.toml");
+                if config.is_file() {
+                    crate::config::read(config)?
+                        .licences
+                        .iter()
+                        .map(|licence| licence.to_string())
+                        .collect()
+                } else {
+                    vec![]
+                }
+            }
+        };
+        packages.push(PackageLicences {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            licences,
+        });
+    }
+    Ok(packages)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LicenceProblem {
+    Undeclared,
+    NotAllowed,
+}
+
+/// The number of packages using each licence, and the packages whose licences
+/// are a problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LicenceSummary {
+    counts: BTreeMap<String, usize>,
+    problems: BTreeMap<EcoString, LicenceProblem>,
+}
+
+impl LicenceSummary {
+    /// Packages are a problem if they declare no licences or, when there is an
+    /// allowlist, if none of their licences are allowed.
+    fn new(packages: &[PackageLicences], allowed: &[String]) -> Self {
+        let mut counts = BTreeMap::new();
+        let mut problems = BTreeMap::new();
+        for package in packages {
+            for licence in &package.licences {
+                *counts.entry(licence.clone()).or_default() += 1;
+            }
+            let problem = if package.licences.is_empty() {
+                Some(LicenceProblem::Undeclared)
+            } else if !allowed.is_empty()
+                && !package
+                    .licences
+                    .iter()
+                    .any(|licence| allowed.contains(licence))
+            {
+                Some(LicenceProblem::NotAllowed)
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                let _ = problems.insert(package.name.clone(), problem);
+            }
+        }
+        Self { counts, problems }
+    }
+}
+
+fn print_licences<W: std::io::Write>(
+    mut buffer: W,
+    packages: &[PackageLicences],
+    summary: &LicenceSummary,
+) -> Result<()> {
+    let rows = packages
+        .iter()
+        .map(|package| {
+            let problem = match summary.problems.get(&package.name) {
+                Some(LicenceProblem::Undeclared) => "no licence declared",
+                Some(LicenceProblem::NotAllowed) => "not allowed",
+                None => "",
+            };
+            [
+                package.name.to_string(),
+                package.version.to_string(),
+                package.licences.join(", "),
+                problem.into(),
+            ]
+        })
+        .collect_vec();
+    let header = ["Package", "Version", "Licences", "Problem"].map(String::from);
+
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut output = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .join("  ");
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output.push('\n');
+    for (licence, count) in &summary.counts {
+        let packages = if *count == 1 { "package" } else { "packages" };
+        output.push_str(&format!("{licence}: {count} {packages}\n"));
+    }
+    buffer
+        .write_all(output.as_bytes())
+        .map_err(|e| Error::StandardIo {
+            action: StandardIoAction::Write,
+            err: Some(e.kind()),
+        })
+}
+
+#[test]
+fn licences_are_summarised() {
+    #[derive(Debug)]
+    struct LicencesHttpClient;
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for LicencesHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            let body = match request.uri().path() {
+                "/api/packages/gleam_stdlib/releases/1.0.0" => {
+                    r#"{"meta": {"licenses": ["Apache-2.0"]}}"#
+                }
+                "/api/packages/gleam_json/releases/1.0.0" => {
+                    r#"{"meta": {"licenses": ["Apache-2.0"]}}"#
+                }
+                "/api/packages/copyleft/releases/1.0.0" => r#"{"meta": {"licenses": ["GPL-3.0"]}}"#,
+                "/api/packages/unlicensed/releases/1.0.0" => r#"{"meta": {}}"#,
+                path => panic!("Unexpected request for {path}"),
+            };
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(body.as_bytes().to_vec())
+                .unwrap())
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    std::fs::create_dir(root.join("local")).unwrap();
+    std::fs::write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\nlicences = [\"MIT\"]\n",
+    )
+    .unwrap();
+
+    let package = |name: &str, source| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source,
+    };
+    let hex = || ManifestPackageSource::Hex {
+        outer_checksum: Base16Checksum(vec![]),
+        repository: None,
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            package("gleam_stdlib", hex()),
+            package("gleam_json", hex()),
+            package("copyleft", hex()),
+            package("unlicensed", hex()),
+            package(
+                "local",
+                ManifestPackageSource::Local {
+                    path: "local".into(),
+                },
+            ),
+        ],
+    };
+    let packages = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(package_licences(
+            &paths,
+            &manifest,
+            &HexRepository::default(),
+            &LicencesHttpClient,
+        ))
+        .unwrap();
+    let summary = LicenceSummary::new(&packages, &["Apache-2.0".into(), "MIT".into()]);
+
+    assert_eq!(
+        summary.counts,
+        [
+            ("Apache-2.0".into(), 2),
+            ("GPL-3.0".into(), 1),
+            ("MIT".into(), 1)
+        ]
+        .into()
+    );
+    assert_eq!(
+        summary.problems,
+        [
+            ("copyleft".into(), LicenceProblem::NotAllowed),
+            ("unlicensed".into(), LicenceProblem::Undeclared),
+        ]
+        .into()
+    );
+
+    let mut buffer = vec![];
+    print_licences(&mut buffer, &packages, &summary).unwrap();
+    assert_eq!(
+        String::from_utf8(buffer).unwrap(),
+        r#"Package       Version  Licences    Problem
+copyleft      1.0.0    GPL-3.0     not allowed
+gleam_json    1.0.0    Apache-2.0
+gleam_stdlib  1.0.0    Apache-2.0
+local         1.0.0    MIT
+unlicensed    1.0.0                no licence declared
+
+Apache-2.0: 2 packages
+GPL-3.0: 1 package
+MIT: 1 package
+"#
+    );
+}
+
+#[test]
+fn dependency_tree_format() {
+    let package = |name: &str, requirements: &[&str]| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: requirements.iter().map(|name| (*name).into()).collect(),
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1, 2, 3, 4]),
+            repository: None,
+        },
+    };
+    let manifest = Manifest {
+        requirements: [
+            ("left".into(), Requirement::hex("~> 1.0")),
+            ("right".into(), Requirement::hex("~> 1.0")),
+        ]
+        .into(),
+        packages: vec![
+            package("left", &["bottom"]),
+            package("right", &["bottom"]),
+            package("bottom", &["cyclic"]),
+            package("cyclic", &["bottom"]),
+        ],
+    };
+    let mut buffer = vec![];
+    print_dependency_tree(&mut buffer, "root", &manifest).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&buffer).unwrap(),
+        r#"root
+├── left 1.0.0
+│   └── bottom 1.0.0
+│       └── cyclic 1.0.0
+│           └── bottom 1.0.0 (cycle)
+└── right 1.0.0
+    └── bottom 1.0.0 (*)
+"#
+    )
+}
+
+#[derive(serde::Serialize)]
+struct PackageListing<'a> {
+    name: &'a str,
+    version: String,
+    build_tools: &'a [EcoString],
+    otp_app: Option<&'a str>,
+    source: &'static str,
+    requirements: &'a [EcoString],
+}
+
+fn list_manifest_packages_json<W: std::io::Write>(mut buffer: W, manifest: Manifest) -> Result<()> {
+    let packages = manifest
+        .packages
+        .iter()
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+        .map(|package| PackageListing {
+            name: &package.name,
+            version: package.version.to_string(),
+            build_tools: &package.build_tools,
+            otp_app: package.otp_app.as_deref(),
+            source: match package.source {
+                ManifestPackageSource::Hex { .. } => "hex",
+                ManifestPackageSource::Git { .. } => "git",
+                ManifestPackageSource::Local { .. } => "local",
+            },
+            requirements: &package.requirements,
+        })
+        .collect_vec();
+    let json = serde_json::to_string_pretty(&packages).map_err(|e| Error::StandardIo {
+        action: StandardIoAction::Write,
+        err: Some(std::io::Error::from(e).kind()),
+    })?;
+    writeln!(buffer, "{json}").map_err(|e| Error::StandardIo {
+        action: StandardIoAction::Write,
+        err: Some(e.kind()),
+    })
+}
+
+#[test]
+fn list_manifest_format() {
+    let mut buffer = vec![];
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            ManifestPackage {
+                name: "root".into(),
+                version: Version::parse("1.0.0").unwrap(),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4]),
+                    repository: None,
+                },
+            },
+            ManifestPackage {
+                name: "aaa".into(),
+                version: Version::new(0, 4, 2),
+                build_tools: ["rebar3".into(), "make".into()].into(),
+                otp_app: Some("aaa_app".into()),
+                requirements: vec!["zzz".into(), "gleam_stdlib".into()],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![3, 22]),
+                    repository: None,
+                },
+            },
+            ManifestPackage {
+                name: "zzz".into(),
+                version: Version::new(0, 4, 0),
+                build_tools: ["mix".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![3, 22]),
+                    repository: None,
+                },
+            },
+        ],
+    };
+    list_manifest_packages(&mut buffer, manifest).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&buffer).unwrap(),
+        r#"root 1.0.0
+aaa 0.4.2
+zzz 0.4.0
+"#
+    )
+}
+
+#[test]
+fn list_manifest_json_format() {
+    let mut buffer = vec![];
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            ManifestPackage {
+                name: "zzz".into(),
+                version: Version::new(0, 4, 0),
+                build_tools: ["mix".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Local {
+                    path: "../zzz".into(),
+                },
+            },
+            ManifestPackage {
+                name: "aaa".into(),
+                version: Version::new(0, 4, 2),
+                build_tools: ["rebar3".into(), "make".into()].into(),
+                otp_app: Some("aaa_app".into()),
+                requirements: vec!["zzz".into(), "gleam_stdlib".into()],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![3, 22]),
+                    repository: None,
+                },
+            },
+        ],
+    };
+    list_manifest_packages_json(&mut buffer, manifest).unwrap();
+    assert_eq!(
+        std::str::from_utf8(&buffer).unwrap(),
+        r#"[
+  {
+    "name": "aaa",
+    "version": "0.4.2",
+    "build_tools": [
+      "rebar3",
+      "make"
+    ],
+    "otp_app": "aaa_app",
+    "source": "hex",
+    "requirements": [
+      "zzz",
+      "gleam_stdlib"
+    ]
+  },
+  {
+    "name": "zzz",
+    "version": "0.4.0",
+    "build_tools": [
+      "mix"
+    ],
+    "otp_app": null,
+    "source": "local",
+    "requirements": []
+  }
+]
+"#
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UseManifest {
+    Yes,
+    No,
+    /// Use the manifest, failing if it would have to be changed.
+    Frozen,
+    /// Resolve again keeping the versions in the manifest, except for these
+    /// packages which may be upgraded.
+    Unlock(Vec<EcoString>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UseNetwork {
+    Yes,
+    No,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DryRun {
+    Yes,
+    No,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verify {
+    Yes,
+    No,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowDowngrade {
+    Yes,
+    No,
+}
+
+/// The Hex packages that are downloaded again even if they are already
+/// present, discarding the copies in the build directory and package cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refresh {
+    None,
+    All,
+    Packages(Vec<String>),
+}
+
+/// How the dependencies of a project are resolved and downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// If `Yes` we read the manifest from disc. If `No` then we ignore any
+    /// manifest which will result in the latest versions of the dependency
+    /// packages being resolved (not the locked ones).
+    pub use_manifest: UseManifest,
+    /// If `No` then Hex is never contacted. The manifest must already be up to
+    /// date and any missing packages must be in the local package cache.
+    pub use_network: UseNetwork,
+    /// If `Yes` then the versions are resolved but nothing is written to disc.
+    /// Instead the packages that would be added and removed are printed.
+    pub dry_run: DryRun,
+    /// If `Yes` then packages already in the build directory are checked
+    /// against the checksums in the manifest and any that don't match are
+    /// replaced.
+    pub verify: Verify,
+    /// These packages are downloaded again even if they are already present.
+    pub refresh: Refresh,
+    /// If `No` then resolving a lower version of a package than the one locked
+    /// in the manifest is an error rather than the manifest being updated.
+    pub allow_downgrade: AllowDowngrade,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            use_manifest: UseManifest::Yes,
+            use_network: UseNetwork::Yes,
+            dry_run: DryRun::No,
+            verify: Verify::No,
+            refresh: Refresh::None,
+            allow_downgrade: AllowDowngrade::Yes,
+        }
+    }
+}
+
+/// Update the named packages to their latest versions, or every package if
+/// none are named.
+pub fn update(
+    paths: &ProjectPaths,
+    packages: Vec<String>,
+    dry_run: DryRun,
+    allow_downgrade: AllowDowngrade,
+) -> Result<()> {
+    let use_manifest = if packages.is_empty() {
+        UseManifest::No
+    } else {
+        UseManifest::Unlock(packages.into_iter().map(EcoString::from).collect())
+    };
+    _ = download(
+        paths,
+        cli::Reporter::new(),
+        None,
+        Mode::Dev,
+        DownloadOptions {
+            use_manifest,
+            dry_run,
+            allow_downgrade,
+            ..Default::default()
+        },
+    )?;
+    Ok(())
+}
+
+pub fn lock() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let manifest = lock_manifest(&paths, &cli::Reporter::new())?;
+    cli::print_locked(&format!("{} packages", manifest.packages.len()));
+    Ok(())
+}
+
+/// Resolve the latest versions of the dependencies and write them to the
+/// manifest, without downloading any packages or changing the build directory.
+fn lock_manifest<Telem: Telemetry>(paths: &ProjectPaths, telemetry: &Telem) -> Result<Manifest> {
+    let config = crate::config::read(paths.root_config())?;
+    let runtime = crate::http::async_runtime()?;
+    let (_, manifest) = get_manifest(
+        paths,
+        runtime.handle().clone(),
+        Mode::Dev,
+        &config,
+        telemetry,
+        &DownloadOptions {
+            use_manifest: UseManifest::No,
+            ..Default::default()
+        },
+    )?;
+    write_manifest_to_disc(paths, &manifest)?;
+    Ok(manifest)
+}
+
+#[test]
+fn lock_writes_manifest_without_changing_build_directory() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../local\" }\n",
+    );
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\n",
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+
+    let manifest = lock_manifest(&paths, &gleam_core::build::NullTelemetry).unwrap();
+
+    assert_eq!(read_manifest_from_disc(&paths).unwrap(), manifest);
+    assert_eq!(manifest.packages.len(), 1);
+    assert!(!paths.build_packages_directory().exists());
+}
+
+pub fn resolve() -> Result<()> {
+    let src = crate::format::read_stdin()?;
+    let requirements = toml::from_str(&src).map_err(|e| Error::FileIo {
+        action: FileIoAction::Parse,
+        kind: FileKind::File,
+        path: Utf8PathBuf::from("<stdin>"),
+        err: Some(e.to_string()),
+    })?;
+    let paths = ProjectPaths::new(fs::get_current_directory()?);
+    let manifest = resolve_requirements(&paths, requirements, &cli::Reporter::new())?;
+    print!("{}", manifest.to_toml(paths.root()));
+    Ok(())
+}
+
+/// Resolve the versions of an in-memory set of requirements, without reading
+/// or writing gleam.toml or manifest.toml. Path requirements are relative to
+/// the root of the given paths. Tools that only need Hex packages can use
+/// `gleam_core::dependency::resolve_requirements` instead.
+fn resolve_requirements<Telem: Telemetry>(
+    paths: &ProjectPaths,
+    requirements: HashMap<EcoString, Requirement>,
+    telemetry: &Telem,
+) -> Result<Manifest> {
+    let config = PackageConfig {
+        dependencies: requirements,
+        ..Default::default()
+    };
+    let runtime = crate::http::async_runtime()?;
+    resolve_versions(
+        runtime.handle().clone(),
+        Mode::Dev,
+        paths,
+        &config,
+        None,
+        &[],
+        telemetry,
+    )
+}
+
+#[test]
+fn resolve_requirements_returns_manifest_without_writing_files() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    std::fs::create_dir_all(root.join("local")).unwrap();
+    std::fs::write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.2.0\"\n",
+    )
+    .unwrap();
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let requirements = HashMap::from([(
+        "local".into(),
+        Requirement::Path {
+            path: "local".into(),
+        },
+    )]);
+
+    let manifest = resolve_requirements(
+        &paths,
+        requirements.clone(),
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    assert_eq!(manifest.requirements, requirements);
+    assert_eq!(
+        manifest
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.to_string()))
+            .collect_vec(),
+        vec![("local", "1.2.0".to_string())]
+    );
+    assert!(!paths.root_config().exists());
+    assert!(!paths.manifest().exists());
+}
+
+/// Copy the source of every dependency package into the vendor directory and
+/// record those copies as the package sources in the manifest, so the project
+/// can be built without fetching any packages.
+pub fn vendor() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let manifest = download(
+        &paths,
+        cli::Reporter::new(),
+        None,
+        Mode::Dev,
+        DownloadOptions::default(),
+    )?;
+    let config = crate::config::read(paths.root_config())?;
+    let manifest = vendor_packages(&paths, manifest, &config.patch)?;
+    write_manifest_to_disc(&paths, &manifest)?;
+    cli::print_vendored(&format!("{} packages", manifest.packages.len()));
+    Ok(())
+}
+
+/// Copy the packages into the vendor directory and point the manifest at
+/// them. Packages already vendored are left as they are, and patched packages
+/// keep using the local copy the project chose for them.
+fn vendor_packages(
+    paths: &ProjectPaths,
+    mut manifest: Manifest,
+    patches: &HashMap<EcoString, Patch>,
+) -> Result<Manifest> {
+    for package in &mut manifest.packages {
+        if patches.contains_key(&package.name) {
+            continue;
+        }
+        let vendored = Utf8PathBuf::from("vendor").join(package.name.as_str());
+        let source = match &package.source {
+            ManifestPackageSource::Local { path } if *path == vendored => continue,
+            ManifestPackageSource::Local { path } => paths.root().join(path),
+            ManifestPackageSource::Hex { .. } | ManifestPackageSource::Git { .. } => {
+                paths.build_packages_package(&package.name)
+            }
+        };
+        let destination = paths.vendor_package(&package.name);
+        tracing::debug!(package = %package.name, "vendoring_package");
+        fs::delete_directory(&destination)?;
+        fs::mkdir(&destination)?;
+        fs::copy_dir(&source, &destination)?;
+        package.source = ManifestPackageSource::Local { path: vendored };
+    }
+    Ok(manifest)
+}
+
+#[test]
+fn vendor_packages_copies_sources_into_vendor() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    let paths = ProjectPaths::new(root.join("app"));
+    write(
+        paths.build_packages_package_config("wibble"),
+        "name = \"wibble\"\nversion = \"1.0.0\"\n",
+    );
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"0.1.0\"\n",
+    );
+    let package = |name: &str, source| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: vec!["gleam".into()],
+        otp_app: None,
+        requirements: vec![],
+        source,
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            package(
+                "local",
+                ManifestPackageSource::Local {
+                    path: "../local".into(),
+                },
+            ),
+            package(
+                "wibble",
+                ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![]),
+                    repository: None,
+                },
+            ),
+        ],
+    };
+
+    let vendored = vendor_packages(&paths, manifest, &HashMap::new()).unwrap();
+
+    for name in ["local", "wibble"] {
+        assert!(paths.vendor_package(name).join("gleam.toml").is_file());
+        let package = vendored.packages.iter().find(|p| p.name == name).unwrap();
+        assert_eq!(
+            package.source,
+            ManifestPackageSource::Local {
+                path: Utf8PathBuf::from("vendor").join(name)
+            }
+        );
+    }
+
+    // Vendoring again changes nothing
+    assert_eq!(
+        vendor_packages(&paths, vendored.clone(), &HashMap::new()).unwrap(),
+        vendored
+    );
+    assert!(paths.vendor_package("wibble").join("gleam.toml").is_file());
+}
+
+pub fn validate() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let config = crate::config::read(paths.root_config())?;
+    let problems = requirement_problems(&config, paths.root());
+    if !problems.is_empty() {
+        return Err(Error::InvalidDependencyRequirements { problems });
+    }
+    let count = config.dependencies.len() + config.dev_dependencies.len();
+    cli::print_validated(&format!("{count} dependency requirements"));
+    Ok(())
+}
+
+pub fn check() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let config = crate::config::read(paths.root_config())?;
+    let manifest = read_manifest_from_disc(&paths)?;
+    let problems = manifest_problems(&config, &manifest, paths.root())?;
+    if !problems.is_empty() {
+        return Err(Error::InconsistentManifest { problems });
+    }
+    cli::print_validated(&format!("{} manifest packages", manifest.packages.len()));
+    Ok(())
+}
+
+/// Check that the manifest was resolved from the requirements in the config
+/// and that every package it depends on is in the manifest, without
+/// contacting Hex.
+fn manifest_problems(
+    config: &PackageConfig,
+    manifest: &Manifest,
+    root_path: &Utf8Path,
+) -> Result<Vec<RequirementProblem>> {
+    let mut problems = vec![];
+    let mut problem = |package: &EcoString, problem: String| {
+        problems.push(RequirementProblem {
+            package: package.clone(),
+            problem,
+        })
+    };
+    let requirements = config.all_dependencies()?;
+    let versions: HashMap<_, _> = manifest
+        .packages
+        .iter()
+        .map(|package| (&package.name, &package.version))
+        .collect();
+
+    for name in manifest
+        .requirements
+        .keys()
+        .filter(|name| !requirements.contains_key(*name))
+        .sorted()
+    {
+        problem(name, "in manifest.toml but not in gleam.toml".into());
+    }
+
+    for (name, requirement) in requirements.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+        if !manifest.requirements.contains_key(name) {
+            problem(name, "in gleam.toml but not in manifest.toml".into());
+        } else if !dependency::same_requirements(
+            &ProjectIO::new(),
+            requirement,
+            manifest.requirements.get(name),
+            root_path,
+        )? {
+            problem(name, "requirement differs from manifest.toml".into());
+        }
+
+        match (requirement, versions.get(name)) {
+            (_, None) => problem(name, "no version of the package in manifest.toml".into()),
+            (Requirement::Hex { version: range, .. }, Some(version)) => {
+                let satisfied = range
+                    .to_pubgrub()
+                    .map(|range| range.contains(version))
+                    .unwrap_or(false);
+                if !satisfied {
+                    problem(
+                        name,
+                        format!("locked version {version} does not match `{range}`"),
+                    );
+                }
+            }
+            (Requirement::Path { .. } | Requirement::Git { .. }, Some(_)) => (),
+        }
+    }
+
+    for package in manifest
+        .packages
+        .iter()
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+    {
+        for dependency in &package.requirements {
+            if !versions.contains_key(dependency) {
+                problem(
+                    &package.name,
+                    format!("requires {dependency} which is not in manifest.toml"),
+                );
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+#[test]
+fn manifest_problems_consistent() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [("wibble".into(), Requirement::hex("~> 1.0"))].into();
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![
+            ManifestPackage {
+                name: "wibble".into(),
+                version: Version::new(1, 2, 0),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec!["wobble".into()],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![]),
+                    repository: None,
+                },
+            },
+            ManifestPackage {
+                name: "wobble".into(),
+                version: Version::new(0, 1, 0),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![]),
+                    repository: None,
+                },
+            },
+        ],
+    };
+    assert_eq!(
+        manifest_problems(&config, &manifest, Utf8Path::new("/app")),
+        Ok(vec![])
+    );
+}
+
+#[test]
+fn manifest_problems_missing_package() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("wibble".into(), Requirement::hex("~> 1.0")),
+        ("wubble".into(), Requirement::hex("~> 2.0")),
+    ]
+    .into();
+    let manifest = Manifest {
+        requirements: [("wibble".into(), Requirement::hex("~> 1.0"))].into(),
+        packages: vec![ManifestPackage {
+            name: "wibble".into(),
+            version: Version::new(0, 9, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec!["wobble".into()],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![]),
+                repository: None,
+            },
+        }],
+    };
+    let problem = |package: &str, problem: &str| RequirementProblem {
+        package: package.into(),
+        problem: problem.into(),
+    };
+    assert_eq!(
+        manifest_problems(&config, &manifest, Utf8Path::new("/app")),
+        Ok(vec![
+            problem("wibble", "locked version 0.9.0 does not match `~> 1.0`"),
+            problem("wubble", "in gleam.toml but not in manifest.toml"),
+            problem("wubble", "no version of the package in manifest.toml"),
+            problem("wibble", "requires wobble which is not in manifest.toml"),
+        ])
+    );
+}
+
+/// Check every requirement in the config for mistakes that can be detected
+/// without performing any network requests or version resolution.
+fn requirement_problems(config: &PackageConfig, root_path: &Utf8Path) -> Vec<RequirementProblem> {
+    let mut problems = vec![];
+
+    for name in config
+        .dependencies
+        .keys()
+        .filter(|name| config.dev_dependencies.contains_key(*name))
+        .sorted()
+    {
+        problems.push(RequirementProblem {
+            package: name.clone(),
+            problem: "listed in both dependencies and dev-dependencies".into(),
+        });
+    }
+
+    for (name, requirement) in config
+        .dependencies
+        .iter()
+        .chain(&config.dev_dependencies)
+        .sorted_by(|a, b| a.0.cmp(b.0))
+    {
+        if let Some(problem) = requirement_problem(requirement, root_path) {
+            problems.push(RequirementProblem {
+                package: name.clone(),
+                problem,
+            });
+        }
+    }
+
+    problems
+}
+
+fn requirement_problem(requirement: &Requirement, root_path: &Utf8Path) -> Option<String> {
+    match requirement {
+        Requirement::Hex { version, .. } => version_requirement_problem(version),
+
+        Requirement::Path { path } => {
+            let full_path = root_path.join(path);
+            if !full_path.is_dir() {
+                Some(format!("the path `{path}` does not exist"))
+            } else if !full_path.join("gleam.toml").is_file() {
+                Some(format!(
+                    "the path `{path}` does not contain a gleam.toml file"
+                ))
+            } else {
+                None
+            }
+        }
+
+        Requirement::Git { git, .. } if !is_valid_git_url(git) => {
+            Some(format!("`{git}` is not a valid git repository URL"))
+        }
+
+        Requirement::Git { .. } => None,
+    }
+}
+
+fn version_requirement_problem(version: &hexpm::version::Range) -> Option<String> {
+    version.to_pubgrub().err().map(|error| {
+        format!(
+            "invalid version requirement `{version}`: {error}. \
+Requirements are written like `~> 1.0` or `>= 1.0.0 and < 2.0.0`"
+        )
+    })
+}
+
+/// Git accepts both URLs such as `https://host/repo.git` and the scp-like
+/// `user@host:repo.git` syntax.
+fn is_valid_git_url(url: &str) -> bool {
+    if url.is_empty() || url.contains(char::is_whitespace) {
+        return false;
+    }
+    match url.split_once("://") {
+        Some(("file", path)) => !path.is_empty(),
+        Some((scheme, _))
+            if matches!(scheme, "http" | "https" | "git") || is_ssh_scheme(scheme) =>
+        {
+            url.parse::<http::Uri>()
+                .map(|uri| uri.host().is_some_and(|host| !host.is_empty()))
+                .unwrap_or(false)
+        }
+        Some(_) => false,
+        None => match url.split_once(':') {
+            Some((host, path)) => !host.is_empty() && !host.contains('/') && !path.is_empty(),
+            None => false,
+        },
+    }
+}
+
+#[test]
+fn requirement_problems_malformed_version() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("good".into(), Requirement::hex("~> 1.0")),
+        ("bad".into(), Requirement::hex("~> wibble")),
+    ]
+    .into();
+    let problems = requirement_problems(&config, Utf8Path::new("./"));
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].package, "bad");
+    assert!(problems[0].problem.contains("~> wibble"));
+}
+
+#[test]
+fn requirement_problems_missing_path() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        (
+            "hello_world".into(),
+            Requirement::path("./test/hello_world"),
+        ),
+        ("missing".into(), Requirement::path("./test/does_not_exist")),
+    ]
+    .into();
+    let problems = requirement_problems(&config, Utf8Path::new("./"));
+    assert_eq!(
+        problems,
+        vec![RequirementProblem {
+            package: "missing".into(),
+            problem: "the path `./test/does_not_exist` does not exist".into(),
+        }]
+    );
+}
+
+#[test]
+fn requirement_problems_bad_git_url() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        (
+            "https".into(),
+            Requirement::git("https://github.com/gleam-lang/otp.git"),
+        ),
+        (
+            "scp".into(),
+            Requirement::git("git@github.com:gleam-lang/otp.git"),
+        ),
+        (
+            "git_ssh".into(),
+            Requirement::git("git+ssh://git@github.com/gleam-lang/otp.git"),
+        ),
+        (
+            "ssh_git".into(),
+            Requirement::git("ssh+git://git@github.com/gleam-lang/otp.git"),
+        ),
+        ("bad".into(), Requirement::git("not a url")),
+    ]
+    .into();
+    config.dev_dependencies = [("missing_host".into(), Requirement::git("https://"))].into();
+    let problems = requirement_problems(&config, Utf8Path::new("./"));
+    assert_eq!(
+        problems
+            .iter()
+            .map(|problem| problem.package.as_str())
+            .collect_vec(),
+        vec!["bad", "missing_host"]
+    );
+}
+
+#[test]
+fn requirement_problems_reports_all_problems() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("one".into(), Requirement::hex(">= nope")),
+        ("two".into(), Requirement::path("./test/does_not_exist")),
+        ("both".into(), Requirement::hex("~> 1.0")),
+    ]
+    .into();
+    config.dev_dependencies = [("both".into(), Requirement::hex("~> 1.0"))].into();
+    let problems = requirement_problems(&config, Utf8Path::new("./"));
+    assert_eq!(
+        problems
+            .iter()
+            .map(|problem| problem.package.as_str())
+            .collect_vec(),
+        vec!["both", "one", "two"]
+    );
+}
+
+pub fn download<Telem: Telemetry + 'static>(
+    paths: &ProjectPaths,
+    telemetry: Telem,
+    new_package: Option<(Vec<String>, bool)>,
+    // In production mode dev dependencies are not put in the build directory,
+    // though they are still resolved so that the manifest is complete.
+    mode: Mode,
+    options: DownloadOptions,
+) -> Result<Manifest> {
+    let span = tracing::info_span!("download_deps");
+    let _enter = span.enter();
+    let telemetry = Arc::new(telemetry);
+
+    // We do this before acquiring the build lock so that we don't create the
+    // build directory if there is no gleam.toml
+    crate::config::ensure_config_exists(paths)?;
+
+    // A dry run doesn't change the build directory so it doesn't need the
+    // lock, which would create the directory.
+    let _guard = match options.dry_run {
+        DryRun::No => Some(BuildLock::new_packages(paths)?.lock(telemetry.as_ref())),
+        DryRun::Yes => None,
+    };
+
+    // Read the project config
+    let mut config = crate::config::read(paths.root_config())?;
+
+    // Insert the new packages to add, if it exists
+    let mut added = vec![];
+    if let Some((packages, dev)) = new_package {
+        for package in packages {
+            added.push(package.clone());
+            let version = Requirement::hex(">= 0.0.0");
+            let _ = if dev {
+                config.dev_dependencies.insert(package.into(), version)
+            } else {
+                config.dependencies.insert(package.into(), version)
+            };
+        }
+    }
+
+    // Start event loop so we can run async functions to call the Hex API
+    let runtime = crate::http::async_runtime()?;
+
+    // Determine what versions we need
+    let (manifest_updated, manifest) = get_manifest(
+        paths,
+        runtime.handle().clone(),
+        Mode::Dev,
+        &config,
+        telemetry.as_ref(),
+        &options,
+    )?;
+    report_unbounded_requirements(&config, &manifest, &added, telemetry.as_ref());
+    report_duplicate_otp_apps(&manifest, telemetry.as_ref());
+    let packages = match mode {
+        Mode::Prod => production_packages(&config, &manifest),
+        Mode::Dev | Mode::Lsp => manifest.clone(),
+    };
+    let mut local = LocalPackages::read_from_disc(paths)?;
+    let hex_repository = hex_repository_for_manifest(&config, &packages)?;
+
+    if options.dry_run == DryRun::Yes {
+        let plan = DependencyPlan::new(&local, &packages, &config.name, paths);
+        plan.print();
+        return Ok(packages);
+    }
+
+    // Remove any packages that are no longer required due to gleam.toml
+    // changes, or that are only needed for development
+    let _ = remove_extra_packages(
+        paths,
+        &local,
+        &packages,
+        BuildFilter::default(),
+        telemetry.as_ref(),
+    )?;
+
+    // Remove any packages that have been modified since they were downloaded so
+    // that they are fetched again below
+    if options.verify == Verify::Yes {
+        remove_corrupted_packages(paths, &mut local, &packages)?;
+    }
+
+    // Remove the packages to refresh, along with their cached tarballs, so
+    // that they are downloaded again below. Offline they couldn't be, so the
+    // cached copies are kept.
+    if options.use_network == UseNetwork::Yes {
+        remove_refreshed_packages(paths, &mut local, &packages, &options.refresh)?;
+    }
+
+    // Download them from Hex to the local cache
+    runtime.block_on(add_missing_packages(
+        paths,
+        &packages,
+        &local,
+        &config,
+        &hex_repository,
+        telemetry.clone(),
+        options.use_network,
+    ))?;
+
+    if manifest_updated {
+        // Record new state of the packages directory
+        // TODO: test
+        tracing::debug!("writing_manifest_toml");
+        write_manifest_to_disc(paths, &manifest)?;
+    }
+    LocalPackages::from_manifest(&packages, &config).write_to_disc(paths)?;
+
+    Ok(packages)
+}
+
+/// Download only the given packages and the packages they depend on, leaving
+/// the rest of the build directory as it is. This is quicker than downloading
+/// every package when warming the package cache for a few of them.
+pub fn download_only<Telem: Telemetry + 'static>(
+    paths: &ProjectPaths,
+    telemetry: Telem,
+    packages: &[String],
+    use_network: UseNetwork,
+) -> Result<Manifest> {
+    let span = tracing::info_span!("download_only_deps");
+    let _enter = span.enter();
+    let telemetry = Arc::new(telemetry);
+
+    crate::config::ensure_config_exists(paths)?;
+    let _guard = BuildLock::new_packages(paths)?.lock(telemetry.as_ref());
+    let config = crate::config::read(paths.root_config())?;
+    let runtime = crate::http::async_runtime()?;
+
+    let (manifest_updated, manifest) = get_manifest(
+        paths,
+        runtime.handle().clone(),
+        Mode::Dev,
+        &config,
+        telemetry.as_ref(),
+        &DownloadOptions {
+            use_network,
+            ..Default::default()
+        },
+    )?;
+    if manifest_updated {
+        write_manifest_to_disc(paths, &manifest)?;
+    }
+
+    let only = packages_with_dependencies(&manifest, packages)?;
+    let mut local = LocalPackages::read_from_disc(paths)?;
+    runtime.block_on(add_missing_packages(
+        paths,
+        &only,
+        &local,
+        &config,
+        &hex_repository_for_manifest(&config, &only)?,
+        telemetry.clone(),
+        use_network,
+    ))?;
+    local.extend_from_manifest(&only, &manifest, &config);
+    local.write_to_disc(paths)?;
+
+    Ok(only)
+}
+
+/// The part of the manifest made up of the given packages and every package
+/// they depend on, directly or indirectly.
+fn packages_with_dependencies(manifest: &Manifest, names: &[String]) -> Result<Manifest> {
+    let packages: HashMap<&str, &ManifestPackage> = manifest
+        .packages
+        .iter()
+        .map(|package| (package.name.as_str(), package))
+        .collect();
+    let unknown = names
+        .iter()
+        .filter(|name| !packages.contains_key(name.as_str()))
+        .cloned()
+        .collect_vec();
+    if !unknown.is_empty() {
+        return Err(Error::PackagesNotInManifest { packages: unknown });
+    }
+
+    let mut required = HashSet::new();
+    let mut unvisited = names.iter().map(String::as_str).collect_vec();
+    while let Some(name) = unvisited.pop() {
+        if !required.insert(name) {
+            continue;
+        }
+        if let Some(package) = packages.get(name) {
+            unvisited.extend(package.requirements.iter().map(EcoString::as_str));
+        }
+    }
+
+    Ok(Manifest {
+        requirements: manifest
+            .requirements
+            .iter()
+            .filter(|(name, _)| required.contains(name.as_str()))
+            .map(|(name, requirement)| (name.clone(), requirement.clone()))
+            .collect(),
+        packages: manifest
+            .packages
+            .iter()
+            .filter(|package| required.contains(package.name.as_str()))
+            .cloned()
+            .collect(),
+    })
+}
+
+#[test]
+fn packages_with_dependencies_include_transitive_dependencies() {
+    let package = |name: &str, requirements: &[&str]| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: requirements.iter().map(|name| (*name).into()).collect(),
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1]),
+            repository: None,
+        },
+    };
+    let manifest = Manifest {
+        requirements: [
+            ("wibble".into(), Requirement::hex("~> 1.0")),
+            ("wobble".into(), Requirement::hex("~> 1.0")),
+        ]
+        .into(),
+        packages: vec![
+            package("shared", &["shared_dep"]),
+            package("shared_dep", &[]),
+            package("wibble", &["shared"]),
+            package("wobble", &["wobble_dep", "shared"]),
+            package("wobble_dep", &[]),
+        ],
+    };
+
+    let only = packages_with_dependencies(&manifest, &["wibble".into()]).unwrap();
+    assert_eq!(
+        only.packages
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect_vec(),
+        vec!["shared", "shared_dep", "wibble"]
+    );
+    assert_eq!(
+        only.requirements,
+        [("wibble".into(), Requirement::hex("~> 1.0"))].into()
+    );
+
+    // Only these packages are downloaded, as the others are treated as
+    // already present
+    let local = LocalPackages::read_from_disc(&ProjectPaths::new("/nowhere".into())).unwrap();
+    assert_eq!(
+        local
+            .missing_local_packages(&only, "app", &ProjectPaths::new("/nowhere".into()))
+            .iter()
+            .map(|(package, _)| package.name.as_str())
+            .collect_vec(),
+        vec!["shared", "shared_dep", "wibble"]
+    );
+
+    assert_eq!(
+        packages_with_dependencies(&manifest, &["wubble".into()]),
+        Err(Error::PackagesNotInManifest {
+            packages: vec!["wubble".into()]
+        })
+    );
+}
+
+/// Report the packages the project requires with `>= 0.0.0`, which permits
+/// breaking versions to be used. Packages being added are skipped as `gleam
+/// add` replaces their requirement once a version has been selected.
+fn report_unbounded_requirements<Telem: Telemetry>(
+    config: &PackageConfig,
+    manifest: &Manifest,
+    added: &[String],
+    telemetry: &Telem,
+) {
+    let unbounded: HashSet<&EcoString> = config
+        .dependencies
+        .iter()
+        .chain(&config.dev_dependencies)
+        .filter(|(name, _)| !added.iter().any(|package| package == name.as_str()))
+        .filter(|(_, requirement)| match requirement {
+            Requirement::Hex { version, .. } => version.as_str().trim() == ">= 0.0.0",
+            Requirement::Path { .. } | Requirement::Git { .. } => false,
+        })
+        .map(|(name, _)| name)
+        .collect();
+    for package in &manifest.packages {
+        if unbounded.contains(&package.name) {
+            telemetry.unbounded_requirement_resolved(&package.name, &package.version);
+        }
+    }
+}
+
+/// Report the OTP applications provided by more than one package, which would
+/// clash when the project runs on the BEAM.
+fn report_duplicate_otp_apps<Telem: Telemetry>(manifest: &Manifest, telemetry: &Telem) {
+    let duplicates = manifest
+        .packages
+        .iter()
+        .filter_map(|package| Some((package.otp_app.as_ref()?, package.name.clone())))
+        .into_group_map()
+        .into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .sorted();
+    for (otp_app, mut packages) in duplicates {
+        packages.sort();
+        telemetry.duplicate_otp_app_resolved(otp_app, &packages);
+    }
+}
+
+#[test]
+fn duplicate_otp_apps_are_reported() {
+    use gleam_core::warning::VectorWarningEmitterIO;
+
+    let package = |name: &str, otp_app: Option<&str>| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: vec!["rebar3".into()],
+        otp_app: otp_app.map(Into::into),
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            package("wobble_fork", Some("wobble")),
+            package("wibble", Some("wibble")),
+            package("wobble", Some("wobble")),
+            package("wubble", None),
+            package("gleam_wubble", None),
+        ],
+    };
+    let warnings = VectorWarningEmitterIO::new();
+
+    report_duplicate_otp_apps(
+        &manifest,
+        &cli::QuietReporter::with_warnings(warnings.clone()),
+    );
+
+    assert_eq!(
+        warnings.take(),
+        vec![Warning::DuplicateOtpApp {
+            otp_app: "wobble".into(),
+            packages: vec!["wobble".into(), "wobble_fork".into()],
+        }]
+    );
+}
+
+/// The part of the manifest needed for production, which is the packages
+/// required by the project's dependencies, excluding its dev dependencies.
+fn production_packages(config: &PackageConfig, manifest: &Manifest) -> Manifest {
+    let packages: HashMap<&EcoString, &ManifestPackage> = manifest
+        .packages
+        .iter()
+        .map(|package| (&package.name, package))
+        .collect();
+    let mut required = HashSet::new();
+    let mut unvisited = config.dependencies.keys().collect_vec();
+    while let Some(name) = unvisited.pop() {
+        if !required.insert(name) {
+            continue;
+        }
+        if let Some(package) = packages.get(name) {
+            unvisited.extend(package.requirements.iter());
+        }
+    }
+
+    Manifest {
+        requirements: manifest
+            .requirements
+            .iter()
+            .filter(|(name, _)| config.dependencies.contains_key(*name))
+            .map(|(name, requirement)| (name.clone(), requirement.clone()))
+            .collect(),
+        packages: manifest
+            .packages
+            .iter()
+            .filter(|package| required.contains(&package.name))
+            .cloned()
+            .collect(),
+    }
+}
+
+#[test]
+fn production_packages_exclude_dev_dependencies() {
+    let package = |name: &str, requirements: &[&str]| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: requirements.iter().map(|name| (*name).into()).collect(),
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let mut config = PackageConfig::default();
+    config.dependencies = [("app_dep".into(), Requirement::hex("~> 1.0"))].into();
+    config.dev_dependencies = [("dev_dep".into(), Requirement::hex("~> 1.0"))].into();
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![
+            package("app_dep", &["shared"]),
+            package("dev_dep", &["shared", "dev_only"]),
+            package("dev_only", &[]),
+            package("shared", &[]),
+        ],
+    };
+
+    let production = production_packages(&config, &manifest);
+    assert_eq!(
+        production.requirements,
+        [("app_dep".into(), Requirement::hex("~> 1.0"))].into()
+    );
+    assert_eq!(
+        production
+            .packages
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect_vec(),
+        vec!["app_dep", "shared"]
+    );
+}
+
+/// The changes to the build packages directory that downloading the
+/// dependencies would make.
+#[derive(Debug, PartialEq, Eq)]
+struct DependencyPlan {
+    added: Vec<(EcoString, Version)>,
+    removed: Vec<(String, Version)>,
+}
+
+impl DependencyPlan {
+    fn new(local: &LocalPackages, manifest: &Manifest, root: &str, paths: &ProjectPaths) -> Self {
+        let added = local
+            .missing_local_packages(manifest, root, paths)
+            .into_iter()
+            .map(|(package, _)| (package.name.clone(), package.version.clone()))
+            .sorted()
+            .collect();
+        let removed = local
+            .extra_local_packages(manifest, None)
+            .into_iter()
+            .sorted()
+            .collect();
+        Self { added, removed }
+    }
+
+    fn print(&self) {
+        if self.added.is_empty() && self.removed.is_empty() {
+            cli::print_dry_run("No changes", "dependencies are up to date");
+        }
+        for (name, version) in &self.added {
+            cli::print_dry_run("Would add", &format!("{name} {version}"));
+        }
+        for (name, version) in &self.removed {
+            cli::print_dry_run("Would remove", &format!("{name} {version}"));
+        }
+    }
+}
+
+#[test]
+fn download_does_not_link_local_packages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../local\" }\n",
+    );
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\n",
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+
+    // Local packages are compiled from the path in the manifest, so there is
+    // no symlink or copy in the build directory that could fail to be made.
+    let manifest = download(
+        &paths,
+        gleam_core::build::NullTelemetry,
+        None,
+        Mode::Dev,
+        DownloadOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        manifest.packages[0].source,
+        ManifestPackageSource::Local {
+            path: "../local".into()
+        }
+    );
+    assert!(std::fs::symlink_metadata(paths.build_packages_package("local")).is_err());
+}
+
+#[test]
+fn download_uses_the_new_path_of_a_moved_local_package() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    let local = "name = \"local\"\nversion = \"1.0.0\"\n";
+    write(root.join("old/local/gleam.toml"), local);
+    write(root.join("new/local/gleam.toml"), local);
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../old/local\" }\n",
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+    let download = || {
+        download(
+            &paths,
+            gleam_core::build::NullTelemetry,
+            None,
+            Mode::Dev,
+            DownloadOptions::default(),
+        )
+        .unwrap()
+    };
+    let _ = download();
+
+    // The package is compiled from the path in the manifest, so once that is
+    // updated there is no stale link to the old location left to repair.
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../new/local\" }\n",
+    );
+    let manifest = download();
+    assert_eq!(
+        manifest.packages[0].source,
+        ManifestPackageSource::Local {
+            path: "../new/local".into()
+        }
+    );
+    assert_eq!(read_manifest_from_disc(&paths).unwrap(), manifest);
+    assert!(std::fs::symlink_metadata(paths.build_packages_package("local")).is_err());
+}
+
+#[test]
+fn download_dry_run_changes_no_files() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    std::fs::write(
+        paths.root_config(),
+        "name = \"app\"\n[dependencies]\ngleam_stdlib = \"~> 0.34\"\n",
+    )
+    .unwrap();
+    let config = crate::config::read(paths.root_config()).unwrap();
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![ManifestPackage {
+            name: "gleam_stdlib".into(),
+            version: Version::new(0, 34, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![1, 2, 3]),
+                repository: None,
+            },
+        }],
+    };
+    write_manifest_to_disc(&paths, &manifest).unwrap();
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [("stale".into(), Version::new(1, 0, 0))].into(),
+    };
+    std::fs::create_dir_all(paths.build_packages_directory()).unwrap();
+    local.write_to_disc(&paths).unwrap();
+
+    let files = || {
+        walkdir::WalkDir::new(root)
+            .into_iter()
+            .map(|entry| {
+                let path = entry.unwrap().into_path();
+                let content = std::fs::read(&path).ok();
+                (path, content)
+            })
+            .collect_vec()
+    };
+    let before = files();
+    let result = download(
+        &paths,
+        gleam_core::build::NullTelemetry,
+        None,
+        Mode::Dev,
+        DownloadOptions {
+            use_network: UseNetwork::No,
+            dry_run: DryRun::Yes,
+            ..Default::default()
+        },
+    );
+    assert_eq!(result, Ok(manifest.clone()));
+    assert_eq!(files(), before);
+
+    assert_eq!(
+        DependencyPlan::new(&local, &manifest, "app", &paths),
+        DependencyPlan {
+            added: vec![("gleam_stdlib".into(), Version::new(0, 34, 0))],
+            removed: vec![("stale".into(), Version::new(1, 0, 0))],
+        }
+    );
+}
+
+/// The tarballs on disc that packages are unpacked from rather than
+/// downloaded, resolved relative to the project root.
+fn local_tarballs(config: &PackageConfig, paths: &ProjectPaths) -> HashMap<EcoString, Utf8PathBuf> {
+    config
+        .tarballs
+        .iter()
+        .map(|(name, path)| (name.clone(), paths.root().join(path)))
+        .collect()
+}
+
+async fn add_missing_packages(
+    paths: &ProjectPaths,
+    manifest: &Manifest,
+    local: &LocalPackages,
+    config: &PackageConfig,
+    hex_repository: &HexRepository,
+    telemetry: Arc<dyn Telemetry>,
+    use_network: UseNetwork,
+) -> Result<(), Error> {
+    let missing_packages = local
+        .missing_local_packages(manifest, &config.name, paths)
+        .into_iter()
+        .map(|(package, reason)| {
+            telemetry.package_missing(&package.name, &reason);
+            package
+        })
+        .collect_vec();
+
+    // Packages left part way through unpacking by an earlier run that was
+    // killed, such as by Ctrl-C, are never used, so they are cleared away.
+    // Nothing else removes them as the process exits without unwinding.
+    fs::delete_directory(&paths.build_packages_staging_directory())?;
+
+    // Without network access the packages can only come from the caches or
+    // from tarballs on disc, so they are all checked before any git or Hex
+    // work is done.
+    let tarballs = local_tarballs(config, paths);
+    if use_network == UseNetwork::No {
+        let git_cache = paths::global_git_cache();
+        let uncached = missing_packages
+            .iter()
+            .filter(|package| !is_available_offline(package, &tarballs, &git_cache))
+            .map(|package| format!("{} {}", package.name, package.version))
+            .collect_vec();
+        if !uncached.is_empty() {
+            return Err(Error::PackagesNotCached { packages: uncached });
+        }
+    }
+
+    // Git packages are checked out from their repository at the locked commit
+    for package in missing_packages.iter().filter(|package| package.is_git()) {
+        telemetry.downloading_package(&package.name);
+        checkout_git_package(paths, package)?;
+    }
+
+    let missing_hex_packages = missing_packages
+        .into_iter()
+        .filter(|package| package.is_hex())
+        .collect_vec();
+
+    // If we don't need to download any packages then we're done
+    if missing_hex_packages.is_empty() {
+        return Ok(());
+    }
+
+    let fs = ProjectIO::boxed();
+    let http = RetryingHttpClient::new(RequestKind::Download)
+        .with_headers(ClientHeaders::for_config(&config.hex));
+    let downloader = hex::Downloader::new(
+        fs.clone(),
+        fs,
+        Box::new(http),
+        Untar::boxed(),
+        paths.clone(),
+    )
+    .with_hex_repository(hex_repository.clone())
+    .with_concurrency_limit(download_concurrency_limit())
+    .with_local_tarballs(tarballs);
+
+    match use_network {
+        UseNetwork::Yes => {
+            telemetry.downloading_package("packages");
+            downloader
+                .with_telemetry(telemetry.clone())
+                .download_hex_packages(missing_hex_packages.iter().copied(), &config.name)
+                .await?;
+        }
+
+        // Without network access the packages have been checked to be in the
+        // cache or in tarballs on disc above
+        UseNetwork::No => {
+            for package in missing_hex_packages {
+                if downloader.has_local_tarball(&package.name) {
+                    let _ = downloader.ensure_package_downloaded(package).await?;
+                }
+                let _ = downloader.extract_package_from_cache(&package.name, &package.version)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a missing package can be added without network access, from the
+/// package caches or a tarball on disc.
+fn is_available_offline(
+    package: &ManifestPackage,
+    tarballs: &HashMap<EcoString, Utf8PathBuf>,
+    git_cache: &Utf8Path,
+) -> bool {
+    match &package.source {
+        ManifestPackageSource::Hex { .. } => {
+            tarballs.contains_key(&package.name)
+                || paths::global_package_cache_package_tarball(
+                    &package.name,
+                    &package.version.to_string(),
+                )
+                .is_file()
+        }
+        ManifestPackageSource::Git { repo, commit, .. } => {
+            is_git_commit_cached(&git_cache_repository(git_cache, repo), commit)
+        }
+        ManifestPackageSource::Local { .. } => true,
+    }
+}
+
+#[test]
+fn add_missing_packages_offline_uncached() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![ManifestPackage {
+            name: "gleam_offline_never_cached".into(),
+            version: Version::new(0, 0, 1),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![1, 2, 3]),
+                repository: None,
+            },
+        }],
+    };
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: HashMap::new(),
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = runtime.block_on(add_missing_packages(
+        &paths,
+        &manifest,
+        &local,
+        &PackageConfig {
+            name: "root".into(),
+            ..Default::default()
+        },
+        &HexRepository::default(),
+        Arc::new(gleam_core::build::NullTelemetry),
+        UseNetwork::No,
+    ));
+    assert_eq!(
+        result,
+        Err(Error::PackagesNotCached {
+            packages: vec!["gleam_offline_never_cached 0.0.1".into()]
+        })
+    );
+    assert!(!paths
+        .build_packages_package("gleam_offline_never_cached")
+        .exists());
+}
+
+#[test]
+fn add_missing_packages_removes_interrupted_unpacks() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    // A package that was being unpacked when an earlier run was killed
+    let staged = paths.build_packages_staging_directory().join("wibble");
+    fs::write(&staged.join("src/wibble.gleam"), "pub fn main() {").unwrap();
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![],
+    };
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: HashMap::new(),
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+        .block_on(add_missing_packages(
+            &paths,
+            &manifest,
+            &local,
+            &PackageConfig {
+                name: "root".into(),
+                ..Default::default()
+            },
+            &HexRepository::default(),
+            Arc::new(gleam_core::build::NullTelemetry),
+            UseNetwork::No,
+        ))
+        .unwrap();
+    assert!(!paths.build_packages_staging_directory().exists());
+    assert!(!paths.build_packages_package("wibble").exists());
+}
+
+#[test]
+fn get_manifest_offline_without_manifest() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let mut config = PackageConfig::default();
+    config.dependencies = [("gleam_stdlib".into(), Requirement::hex("~> 0.34"))].into();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = get_manifest(
+        &paths,
+        runtime.handle().clone(),
+        Mode::Dev,
+        &config,
+        &gleam_core::build::NullTelemetry,
+        &DownloadOptions {
+            use_network: UseNetwork::No,
+            ..Default::default()
+        },
+    );
+    assert_eq!(result, Err(Error::OfflineDependencyResolution));
+}
+
+#[test]
+fn get_manifest_offline_with_up_to_date_manifest() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let mut config = PackageConfig::default();
+    config.dependencies = [("gleam_stdlib".into(), Requirement::hex("~> 0.34"))].into();
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![ManifestPackage {
+            name: "gleam_stdlib".into(),
+            version: Version::new(0, 34, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![1, 2, 3]),
+                repository: None,
+            },
+        }],
+    };
+    write_manifest_to_disc(&paths, &manifest).unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = get_manifest(
+        &paths,
+        runtime.handle().clone(),
+        Mode::Dev,
+        &config,
+        &gleam_core::build::NullTelemetry,
+        &DownloadOptions {
+            use_network: UseNetwork::No,
+            ..Default::default()
+        },
+    );
+    assert_eq!(result, Ok((false, manifest)));
+}
+
+#[test]
+fn get_manifest_frozen() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let mut config = PackageConfig::default();
+    config.dependencies = [("gleam_stdlib".into(), Requirement::hex("~> 0.34"))].into();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let get = |config: &PackageConfig| {
+        get_manifest(
+            &paths,
+            runtime.handle().clone(),
+            Mode::Dev,
+            config,
+            &gleam_core::build::NullTelemetry,
+            &DownloadOptions {
+                use_manifest: UseManifest::Frozen,
+                ..Default::default()
+            },
+        )
+    };
+
+    // There is no manifest yet
+    assert_eq!(get(&config), Err(Error::FrozenManifestOutdated));
+
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![ManifestPackage {
+            name: "gleam_stdlib".into(),
+            version: Version::new(0, 34, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![1, 2, 3]),
+                repository: None,
+            },
+        }],
+    };
+    write_manifest_to_disc(&paths, &manifest).unwrap();
+    assert_eq!(get(&config), Ok((false, manifest)));
+
+    let _ = config
+        .dependencies
+        .insert("gleam_json".into(), Requirement::hex("~> 1.0"));
+    assert_eq!(get(&config), Err(Error::FrozenManifestOutdated));
+}
+
+/// How long Hex package information is kept on disc before being requested
+/// again, which can be set in seconds with the `GLEAM_HEX_METADATA_TTL`
+/// environment variable.
+fn hex_metadata_ttl() -> Duration {
+    const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+    let Ok(ttl) = std::env::var("GLEAM_HEX_METADATA_TTL") else {
+        return DEFAULT_TTL;
+    };
+    match ttl.parse() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            tracing::warn!(ttl = ttl.as_str(), "invalid_hex_metadata_ttl");
+            DEFAULT_TTL
+        }
+    }
+}
+
+/// Whether retired Hex releases may be used, which is only when they are
+/// already locked unless the `GLEAM_REJECT_RETIRED_RELEASES` environment
+/// variable is set.
+fn retired_releases() -> dependency::RetiredReleases {
+    match std::env::var_os("GLEAM_REJECT_RETIRED_RELEASES") {
+        Some(value) if !value.is_empty() => dependency::RetiredReleases::Reject,
+        _ => dependency::RetiredReleases::AllowLocked,
+    }
+}
+
+/// The maximum number of packages to download or look up at once, which can be
+/// set with the `GLEAM_DOWNLOAD_CONCURRENCY` environment variable.
+fn download_concurrency_limit() -> usize {
+    let Ok(limit) = std::env::var("GLEAM_DOWNLOAD_CONCURRENCY") else {
+        return hex::DEFAULT_DOWNLOAD_CONCURRENCY;
+    };
+    match limit.parse() {
+        Ok(limit) => limit,
+        Err(_) => {
+            tracing::warn!(limit = limit.as_str(), "invalid_download_concurrency");
+            hex::DEFAULT_DOWNLOAD_CONCURRENCY
+        }
+    }
+}
+
+/// The Hex repository to resolve and download packages from. The `[hex]`
+/// section of gleam.toml can be overridden with the `GLEAM_HEX_REPOSITORY`,
+/// `GLEAM_HEX_API`, and `GLEAM_HEX_PUBLIC_KEY` environment variables.
+///
+/// Dependencies that declare a Hex organization are fetched using the API key
+/// from the `HEX_API_KEY` environment variable or the Hex API key file, and
+/// those that name a repository from the `[repositories]` section are fetched
+/// from it instead.
+fn hex_repository(config: &PackageConfig) -> Result<HexRepository> {
+    hex_repository_with(config, paths::global_hex_credentials().as_deref())
+}
+
+/// The Hex repository, using the credentials of private repositories from the
+/// given file. Without a file no credentials are used.
+fn hex_repository_with(
+    config: &PackageConfig,
+    credentials: Option<&Utf8Path>,
+) -> Result<HexRepository> {
+    let mut hex_config = config.hex.clone();
+    if let Some(uri) = uri_from_environment("GLEAM_HEX_REPOSITORY")? {
+        hex_config.repository = Some(uri);
+    }
+    if let Some(uri) = uri_from_environment("GLEAM_HEX_API")? {
+        hex_config.api = Some(uri);
+    }
+    if let Ok(public_key) = std::env::var("GLEAM_HEX_PUBLIC_KEY") {
+        hex_config.public_key = Some(public_key);
+    }
+    let mut repository = HexRepository::from_config(&hex_config);
+    for (name, named) in &config.repositories {
+        repository = repository.with_named_repository(name.clone(), named);
+    }
+
+    for (name, requirement) in config.dependencies.iter().chain(&config.dev_dependencies) {
+        let Requirement::Hex {
+            organization,
+            repository: named,
+            ..
+        } = requirement
+        else {
+            continue;
+        };
+        if let Some(organization) = organization {
+            repository = repository.with_organization(name.clone(), organization.clone());
+        }
+        if let Some(named) = named {
+            if !config.repositories.contains_key(named) {
+                return Err(Error::UnknownHexRepository {
+                    package: name.clone(),
+                    repository: named.clone(),
+                });
+            }
+            repository = repository.with_package_repository(name.clone(), named.clone());
+        }
+    }
+
+    if let Some(api_key) = hex_api_key()? {
+        repository = repository.with_api_key(api_key);
+    }
+    if let Some(path) = credentials {
+        repository = repository.with_credentials(read_hex_credentials(path)?);
+    }
+    Ok(repository)
+}
+
+/// The Hex repository for the packages of a manifest, which also fetches each
+/// package from the repository it was resolved from, even if it is not a
+/// direct dependency of the project.
+fn hex_repository_for_manifest(
+    config: &PackageConfig,
+    manifest: &Manifest,
+) -> Result<HexRepository> {
+    hex_repository_for_manifest_with(config, manifest, paths::global_hex_credentials().as_deref())
+}
+
+fn hex_repository_for_manifest_with(
+    config: &PackageConfig,
+    manifest: &Manifest,
+    credentials: Option<&Utf8Path>,
+) -> Result<HexRepository> {
+    let mut repository = hex_repository_with(config, credentials)?;
+    for package in &manifest.packages {
+        let ManifestPackageSource::Hex {
+            repository: Some(named),
+            ..
+        } = &package.source
+        else {
+            continue;
+        };
+        if !repository.route_package(package.name.clone(), named) {
+            return Err(Error::UnknownHexRepository {
+                package: package.name.clone(),
+                repository: named.clone(),
+            });
+        }
+    }
+    Ok(repository)
+}
+
+/// Read the credentials of private Hex repositories from the given file, if
+/// it exists, warning if other users could read the API keys in it. The file
+/// is read each time a Hex repository is built, but the warning is only
+/// printed once.
+fn read_hex_credentials(path: &Utf8Path) -> Result<HexCredentials> {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    if !path.is_file() {
+        return Ok(HexCredentials::default());
+    }
+    if let Some(warning) = credentials_warning(path) {
+        WARNED.call_once(|| crate::fs::ConsoleWarningEmitter.emit_warning(warning));
+    }
+    let toml = fs::read(path)?;
+    toml::from_str(&toml).map_err(|e| Error::FileIo {
+        action: FileIoAction::Parse,
+        kind: FileKind::File,
+        path: path.to_path_buf(),
+        err: Some(e.to_string()),
+    })
+}
+
+#[cfg(unix)]
+fn credentials_warning(path: &Utf8Path) -> Option<Warning> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path).ok()?.permissions().mode();
+    (mode & 0o004 != 0).then(|| Warning::ReadableCredentials {
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(not(unix))]
+fn credentials_warning(_path: &Utf8Path) -> Option<Warning> {
+    None
+}
+
+#[test]
+fn hex_credentials_are_used_for_their_repository() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let path = root.join("credentials");
+    fs::write(
+        &path,
+        r#"
+[repositories.acme]
+api_key = "acme-key"
+
+[repositories.initech]
+api_key = "initech-key"
+repository = "https://hex.initech.example.com/repo"
+api = "https://hex.initech.example.com/api"
+"#,
+    )
+    .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    let credentials = read_hex_credentials(&path).unwrap();
+    let repository = HexRepository::default()
+        .with_api_key("global-key".into())
+        .with_organization("wibble".into(), "acme".into())
+        .with_organization("wobble".into(), "initech".into())
+        .with_organization("wubble".into(), "globex".into())
+        .with_credentials(credentials);
+
+    let (config, api_key) = repository.for_package("wibble");
+    assert_eq!(api_key, Some("acme-key"));
+    assert_eq!(
+        config.repository_base,
+        http::Uri::from_static("https://repo.hex.pm/repos/acme/")
+    );
+    let (config, api_key) = repository.for_package("wobble");
+    assert_eq!(api_key, Some("initech-key"));
+    assert_eq!(
+        config.repository_base,
+        http::Uri::from_static("https://hex.initech.example.com/repo/")
+    );
+    assert_eq!(
+        config.api_base,
+        http::Uri::from_static("https://hex.initech.example.com/api/")
+    );
+    // Repositories without credentials use the global key
+    assert_eq!(repository.for_package("wubble").1, Some("global-key"));
+    assert_eq!(repository.for_package("public").1, None);
+}
+
+#[cfg(unix)]
+#[test]
+fn readable_hex_credentials_are_warned_about() {
+    use std::os::unix::fs::PermissionsExt;
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let path = root.join("credentials");
+    fs::write(&path, "").unwrap();
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(credentials_warning(&path), None);
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert_eq!(
+        credentials_warning(&path),
+        Some(Warning::ReadableCredentials { path })
+    );
+}
+
+#[test]
+fn hex_repository_uses_organization_for_declared_packages() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        (
+            "private".into(),
+            Requirement::Hex {
+                version: hexpm::version::Range::new("~> 1.0".into()),
+                organization: Some("acme".into()),
+                repository: None,
+            },
+        ),
+        ("public".into(), Requirement::hex("~> 1.0")),
+    ]
+    .into();
+    let repository = hex_repository_with(&config, None).unwrap();
+    assert_eq!(
+        repository.for_package("private").0.repository_base,
+        http::Uri::from_static("https://repo.hex.pm/repos/acme/")
+    );
+    assert_eq!(
+        repository.for_package("public").0.repository_base,
+        http::Uri::from_static("https://repo.hex.pm/")
+    );
+}
+
+/// Print the Hex settings used when resolving and downloading the packages of
+/// the project, assembled from the environment, the credentials file, and
+/// gleam.toml. API keys and proxy passwords are never printed.
+pub fn config() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let config = crate::config::read(paths.root_config())?;
+    let repository = hex_repository(&config)?;
+    print_hex_config(
+        std::io::stdout(),
+        &repository,
+        &Proxies::from_environment(),
+        &paths::default_global_gleam_cache(),
+    )
+}
+
+fn print_hex_config<W: std::io::Write>(
+    mut buffer: W,
+    repository: &HexRepository,
+    proxies: &Proxies,
+    cache: &Utf8Path,
+) -> Result<()> {
+    let set_or_not = |value: Option<&str>| value.unwrap_or("not set").to_string();
+    let key = |set: bool| if set { "***" } else { "not set" };
+    let mut lines = vec![
+        ("Repository", "hexpm".to_string()),
+        (
+            "Repository URL",
+            repository.config.repository_base.to_string(),
+        ),
+        ("API URL", repository.config.api_base.to_string()),
+        ("API key", key(repository.has_api_key()).into()),
+        ("HTTP proxy", set_or_not(proxies.http.as_deref())),
+        ("HTTPS proxy", set_or_not(proxies.https.as_deref())),
+        ("No proxy", set_or_not(proxies.no_proxy.as_deref())),
+        (
+            "Proxy credentials",
+            match &proxies.credentials {
+                Some(credentials) => format!("{}:***", credentials.username),
+                None => "not set".into(),
+            },
+        ),
+        ("Cache", cache.to_string()),
+    ];
+    for organization in repository.organizations() {
+        let (config, api_key) = repository.for_organization(organization);
+        lines.push(("Repository", format!("hexpm:{organization}")));
+        lines.push(("Repository URL", config.repository_base.to_string()));
+        lines.push(("API URL", config.api_base.to_string()));
+        lines.push(("API key", key(api_key.is_some()).into()));
+    }
+    for name in repository.named_repositories() {
+        let Some((config, api_key)) = repository.for_named_repository(name) else {
+            continue;
+        };
+        lines.push(("Repository", name.to_string()));
+        lines.push(("Repository URL", config.repository_base.to_string()));
+        lines.push(("API URL", config.api_base.to_string()));
+        lines.push(("API key", key(api_key.is_some()).into()));
+    }
+
+    lines
+        .into_iter()
+        .try_for_each(|(name, value)| writeln!(buffer, "{name}: {value}"))
+        .map_err(|e| Error::StandardIo {
+            action: StandardIoAction::Write,
+            err: Some(e.kind()),
+        })
+}
+
+#[test]
+fn print_hex_config_redacts_the_api_key() {
+    let hex_config = gleam_core::config::HexConfig {
+        repository: Some(http::Uri::from_static("https://hex.example.com/repo")),
+        ..Default::default()
+    };
+    let repository = HexRepository::from_config(&hex_config)
+        .with_api_key("secret-key".into())
+        .with_named_repository(
+            "internal".into(),
+            &gleam_core::config::NamedRepository {
+                url: http::Uri::from_static("https://hex.internal.example.com"),
+                api: Some(http::Uri::from_static(
+                    "https://hex.internal.example.com/api",
+                )),
+                public_key: None,
+            },
+        );
+
+    let mut buffer = vec![];
+    print_hex_config(
+        &mut buffer,
+        &repository,
+        &Proxies::default(),
+        Utf8Path::new("/cache"),
+    )
+    .unwrap();
+
+    let output = String::from_utf8(buffer).unwrap();
+    assert!(output.contains("Repository URL: https://hex.example.com/repo/\n"));
+    assert!(output.contains("API key: ***\n"));
+    assert!(output.contains("Cache: /cache\n"));
+    // The API key is never sent to repositories declared in gleam.toml
+    assert!(output.contains(
+        "Repository: internal
+Repository URL: https://hex.internal.example.com/
+API URL: https://hex.internal.example.com/api/
+API key: not set
+"
+    ));
+    assert!(!output.contains("secret-key"));
+}
+
+fn hex_api_key() -> Result<Option<String>> {
+    if let Ok(api_key) = std::env::var("HEX_API_KEY") {
+        return Ok(Some(api_key));
+    }
+    let path = paths::global_hex_api_key();
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let api_key = fs::read(&path)?;
+    Ok(Some(api_key.trim().to_string()))
+}
+
+fn uri_from_environment(variable: &str) -> Result<Option<http::Uri>> {
+    let Ok(url) = std::env::var(variable) else {
+        return Ok(None);
+    };
+    match url.parse::<http::Uri>() {
+        Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => Ok(Some(uri)),
+        _ => Err(Error::InvalidHexRepositoryUrl {
+            variable: variable.into(),
+            url,
+        }),
+    }
+}
+
+/// Check out the locked commit of a git package into the build packages
+/// directory, replacing any previous checkout.
+fn checkout_git_package(paths: &ProjectPaths, package: &ManifestPackage) -> Result<()> {
+    let ssh_command = git_ssh_command_from_environment();
+    checkout_git_package_with(paths, package, &paths::global_git_cache(), &ssh_command)
+}
+
+fn git_ssh_command_from_environment() -> String {
+    git_ssh_command(
+        std::env::var("GIT_SSH_COMMAND").ok(),
+        std::env::var("GLEAM_GIT_SSH_KEY").ok(),
+    )
+}
+
+/// The command git uses to connect to SSH remotes. The user's SSH agent is
+/// used unless a key file is given, and ssh is never allowed to prompt as
+/// there is nobody to answer.
+fn git_ssh_command(base: Option<String>, key: Option<String>) -> String {
+    let mut command = base.unwrap_or_else(|| "ssh".into());
+    command.push_str(" -o BatchMode=yes");
+    if let Some(key) = key {
+        command.push_str(&format!(
+            " -o IdentitiesOnly=yes -i '{}'",
+            key.replace('\'', "'\\''")
+        ));
+    }
+    command
+}
+
+/// The URL schemes git connects to repositories with using SSH.
+fn is_ssh_scheme(scheme: &str) -> bool {
+    matches!(scheme, "ssh" | "git+ssh" | "ssh+git")
+}
+
+/// Whether git will connect to the repository using SSH, either with an
+/// `ssh://` URL or the scp-like `user@host:path` syntax.
+fn is_ssh_repository(repo: &str) -> bool {
+    if let Some((scheme, _)) = repo.split_once("://") {
+        return is_ssh_scheme(scheme);
+    }
+    // Without a scheme git treats the repository as scp-like if there is a
+    // colon before the first slash, other than a Windows drive letter.
+    match (repo.find(':'), repo.find('/')) {
+        (Some(colon), slash) => colon > 1 && slash.map_or(true, |slash| colon < slash),
+        (None, _) => false,
+    }
+}
+
+/// The environment git is run with for the given repository.
+fn git_environment(repo: &str, ssh_command: &str) -> Vec<(&'static str, String)> {
+    // Git must not prompt for credentials as there is nobody to answer.
+    let mut env = vec![("GIT_TERMINAL_PROMPT", "0".to_string())];
+    if is_ssh_repository(repo) {
+        tracing::debug!(repo=%repo, "using_ssh_transport");
+        env.push(("GIT_SSH_COMMAND", ssh_command.to_string()));
+    }
+    env
+}
+
+/// Classify a git failure so that authentication problems and missing
+/// repositories are reported clearly.
+fn git_failure(package: &EcoString, repo: &EcoString, commit: &EcoString, error: String) -> Error {
+    let lowercase = error.to_lowercase();
+    let is_auth_failure = [
+        "permission denied",
+        "authentication failed",
+        "could not read username",
+        "could not read password",
+        "host key verification failed",
+    ]
+    .iter()
+    .any(|message| lowercase.contains(message));
+    let is_not_found = lowercase.contains("does not appear to be a git repository")
+        || (lowercase.contains("repository") && lowercase.contains("not found"));
+
+    if is_auth_failure {
+        Error::GitDependencyAuthenticationFailed {
+            package: package.clone(),
+            repo: repo.clone(),
+            error,
+        }
+    } else if is_not_found {
+        Error::GitDependencyRepositoryNotFound {
+            package: package.clone(),
+            repo: repo.clone(),
+            error,
+        }
+    } else {
+        Error::GitDependencyCheckoutFailed {
+            package: package.clone(),
+            repo: repo.clone(),
+            commit: commit.clone(),
+            error,
+        }
+    }
+}
+
+fn checkout_git_package_with(
+    paths: &ProjectPaths,
+    package: &ManifestPackage,
+    git_cache: &Utf8Path,
+    ssh_command: &str,
+) -> Result<()> {
+    let (repo, commit, subdir) = match &package.source {
+        ManifestPackageSource::Git {
+            repo,
+            commit,
+            subdir,
+        } => (repo, commit, subdir),
+        ManifestPackageSource::Hex { .. } | ManifestPackageSource::Local { .. } => {
+            panic!("Attempt to check out non-git package from git")
+        }
+    };
+    let destination = paths.build_packages_package(&package.name);
+    let cache = git_cache_repository(git_cache, repo);
+    tracing::info!(package=%package.name, repo=%repo, commit=%commit, "checking_out_git_package");
+
+    let checkout_failed = |error: String| Error::GitDependencyCheckoutFailed {
+        package: package.name.clone(),
+        repo: repo.clone(),
+        commit: commit.clone(),
+        error,
+    };
+    let env = git_environment(repo, ssh_command);
+    let run_git = |directory: &Utf8Path, args: &[&str]| -> Result<String> {
+        let output = fs::git(args, directory, &env)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            Err(git_failure(&package.name, repo, commit, error))
+        }
+    };
+    // Only the locked commit is needed so we try to fetch it without its
+    // history, falling back to a full fetch as some servers don't permit
+    // shallow fetches of a commit.
+    let fetch = |directory: &Utf8Path, source: &str, refspec: &str| -> Result<String> {
+        run_git(
+            directory,
+            &["fetch", "--quiet", "--depth", "1", "--", source, refspec],
+        )
+        .or_else(|error| {
+            tracing::debug!(source=%source, error=%error, "shallow_git_fetch_failed");
+            run_git(directory, &["fetch", "--quiet", "--", source, refspec])
+        })
+    };
+
+    // The repository is cloned once into the global cache, shared by every
+    // project, and the commit is only fetched from the remote if the cache
+    // doesn't have it yet. It is kept under a ref so git doesn't discard it.
+    // Other builds may be using the same cache, so it is locked while in use.
+    let _guard = BuildLock::new_git_cache(&cache)?.lock(&NullTelemetry)?;
+    if !cache.join("HEAD").is_file() {
+        fs::mkdir(&cache)?;
+        let _ = run_git(&cache, &["init", "--bare", "--quiet"])?;
+    }
+    if !is_git_commit_cached(&cache, commit) {
+        tracing::info!(repo=%repo, commit=%commit, "fetching_git_package_to_cache");
+        let _ = fetch(&cache, repo, &format!("{commit}:refs/gleam/{commit}"))?;
+    }
+
+    // A package in a subdirectory is exported from the cache on its own, with
+    // the commit it came from recorded in place of a git checkout.
+    if let Some(subdir) = subdir {
+        fs::delete_directory(&destination)?;
+        let tree = format!("{commit}:{}", subdir.as_str().replace('\\', "/"));
+        let output = fs::git(&["archive", "--format=tar", &tree], &cache, &env)?;
+        let result = if output.status.success() {
+            tar::Archive::new(output.stdout.as_slice())
+                .unpack(&destination)
+                .map_err(|error| checkout_failed(error.to_string()))
+                .and_then(|_| fs::write(&destination.join(GIT_EXPORT_COMMIT_FILE), commit))
+        } else {
+            Err(checkout_failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        };
+        if result.is_err() {
+            fs::delete_directory(&destination)?;
+        }
+        return result;
+    }
+
+    // Check the commit out from the cache into the project
+    fs::delete_directory(&destination)?;
+    fs::mkdir(&destination)?;
+    let result = run_git(&destination, &["init", "--quiet"])
+        .and_then(|_| fetch(&destination, cache.as_str(), commit))
+        .and_then(|_| {
+            run_git(
+                &destination,
+                &["checkout", "--quiet", "--detach", "FETCH_HEAD"],
+            )
+        })
+        .and_then(|_| run_git(&destination, &["rev-parse", "HEAD"]))
+        .and_then(|head| {
+            // Ensure the repository gave us the commit that was locked and not
+            // some other commit that happens to have the same name.
+            if is_same_commit(&head, commit) {
+                Ok(())
+            } else {
+                Err(checkout_failed(format!(
+                    "The repository returned commit {head} instead"
+                )))
+            }
+        });
+
+    // If we failed to check out the package remove anything that was partially
+    // written so that we don't mistakenly think the operation succeeded next
+    // time we run.
+    if result.is_err() {
+        fs::delete_directory(&destination)?;
+    }
+    result
+}
+
+/// The bare repository in the given cache directory that commits of the git
+/// repository are fetched into, named after a hash of its URL.
+fn git_cache_repository(git_cache: &Utf8Path, repo: &str) -> Utf8PathBuf {
+    use sha2::Digest;
+    git_cache.join(base16::encode_lower(&sha2::Sha256::digest(repo.as_bytes())))
+}
+
+/// Whether the commit has already been fetched into the cache repository.
+fn is_git_commit_cached(cache: &Utf8Path, commit: &str) -> bool {
+    cache.join("HEAD").is_file()
+        && fs::git(
+            &["cat-file", "-e", &format!("{commit}^{{commit}}")],
+            cache,
+            &[],
+        )
+        .is_ok_and(|output| output.status.success())
+}
+
+/// The file recording the commit a package in a subdirectory of a git
+/// repository was exported from.
+const GIT_EXPORT_COMMIT_FILE: &str = ".gleam_git_commit";
+
+/// Whether the given git checkout has the locked commit checked out.
+fn has_git_checkout(destination: &Utf8Path, commit: &str) -> bool {
+    [
+        destination.join(".git").join("HEAD"),
+        destination.join(GIT_EXPORT_COMMIT_FILE),
+    ]
+    .iter()
+    .any(|path| {
+        fs::read(path)
+            .map(|head| is_same_commit(head.trim(), commit))
+            .unwrap_or(false)
+    })
+}
+
+fn is_same_commit(head: &str, commit: &str) -> bool {
+    !commit.is_empty() && head.starts_with(commit)
+}
+
+#[cfg(test)]
+fn git_repository_with_commit(path: &Utf8Path) -> EcoString {
+    let git = |args: &[&str]| -> String {
+        let output = fs::git(args, path, &[]).unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().into()
+    };
+    fs::mkdir(path).unwrap();
+    fs::write(&path.join("gleam.toml"), "name = \"wibble\"").unwrap();
+    let _ = git(&["init", "--quiet"]);
+    let _ = git(&["add", "gleam.toml"]);
+    let _ = git(&[
+        "-c",
+        "user.name=Gleam",
+        "-c",
+        "user.email=gleam@example.com",
+        "commit",
+        "--quiet",
+        "--message",
+        "Initial commit",
+    ]);
+    git(&["rev-parse", "HEAD"]).into()
+}
+
+#[test]
+fn add_missing_git_package() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let commit = git_repository_with_commit(&repo);
+    let paths = ProjectPaths::new(root.join("project"));
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![ManifestPackage {
+            name: "wibble".into(),
+            version: Version::new(0, 1, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Git {
+                repo: repo.as_str().into(),
+                commit: commit.clone(),
+                subdir: None,
+            },
+        }],
+    };
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: HashMap::new(),
+    };
+    assert_eq!(
+        local
+            .missing_local_packages(&manifest, "root", &paths)
+            .len(),
+        1
+    );
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+        .block_on(add_missing_packages(
+            &paths,
+            &manifest,
+            &local,
+            &PackageConfig {
+                name: "root".into(),
+                ..Default::default()
+            },
+            &HexRepository::default(),
+            Arc::new(gleam_core::build::NullTelemetry),
+            UseNetwork::Yes,
+        ))
+        .unwrap();
+
+    let package = paths.build_packages_package("wibble");
+    assert!(package.join("gleam.toml").is_file());
+    assert!(has_git_checkout(&package, &commit));
+    let local = LocalPackages::from_manifest(&manifest, &PackageConfig::default());
+    assert!(local
+        .missing_local_packages(&manifest, "root", &paths)
+        .is_empty());
+
+    // The checkout is considered missing again if it is removed from disc
+    fs::delete_directory(&package).unwrap();
+    assert_eq!(
+        local
+            .missing_local_packages(&manifest, "root", &paths)
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn git_package_is_fetched_shallowly() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let _ = git_repository_with_commit(&repo);
+    // Add a second commit so there is history that can be left out
+    fs::write(&repo.join("README.md"), "Wibble").unwrap();
+    let git = |args: &[&str]| {
+        let output = fs::git(args, &repo, &[]).unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    let _ = git(&["add", "README.md"]);
+    let _ = git(&[
+        "-c",
+        "user.name=Gleam",
+        "-c",
+        "user.email=gleam@example.com",
+        "commit",
+        "--quiet",
+        "--message",
+        "Second commit",
+    ]);
+    let commit: EcoString = git(&["rev-parse", "HEAD"]).into();
+    let paths = ProjectPaths::new(root.join("project"));
+    let package = ManifestPackage {
+        name: "wibble".into(),
+        version: Version::new(0, 1, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Git {
+            repo: format!("file://{repo}").into(),
+            commit: commit.clone(),
+            subdir: None,
+        },
+    };
+
+    checkout_git_package_with(&paths, &package, &root.join("cache"), "ssh").unwrap();
+
+    let checkout = paths.build_packages_package("wibble");
+    assert!(has_git_checkout(&checkout, &commit));
+    assert!(checkout.join("README.md").is_file());
+    // Only the locked commit was fetched
+    assert!(checkout.join(".git/shallow").is_file());
+    let output = fs::git(&["rev-list", "--count", "HEAD"], &checkout, &[]).unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "1");
+}
+
+#[test]
+fn concurrent_git_checkouts_share_the_cache() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let commit = git_repository_with_commit(&repo);
+    let cache = root.join("cache");
+    let package = ManifestPackage {
+        name: "wibble".into(),
+        version: Version::new(0, 1, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Git {
+            repo: repo.as_str().into(),
+            commit: commit.clone(),
+            subdir: None,
+        },
+    };
+
+    // Each build takes its turn with the cache repository, so none of them
+    // see it part way through being created or fetched into
+    std::thread::scope(|scope| {
+        let checkouts = (0..4)
+            .map(|i| {
+                let paths = ProjectPaths::new(root.join(format!("project{i}")));
+                let (package, cache) = (&package, &cache);
+                scope.spawn(move || {
+                    checkout_git_package_with(&paths, package, cache, "ssh").unwrap();
+                    paths
+                })
+            })
+            .collect_vec();
+        for checkout in checkouts {
+            let paths = checkout.join().unwrap();
+            assert!(has_git_checkout(
+                &paths.build_packages_package("wibble"),
+                &commit
+            ));
+        }
+    });
+}
+
+#[test]
+fn git_packages_are_available_offline_once_cached() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let commit = git_repository_with_commit(&repo);
+    let cache = root.join("cache");
+    let package = |commit: &str| ManifestPackage {
+        name: "wibble".into(),
+        version: Version::new(0, 1, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Git {
+            repo: repo.as_str().into(),
+            commit: commit.into(),
+            subdir: None,
+        },
+    };
+    let tarballs = HashMap::new();
+    assert!(!is_available_offline(&package(&commit), &tarballs, &cache));
+
+    let paths = ProjectPaths::new(root.join("app"));
+    checkout_git_package_with(&paths, &package(&commit), &cache, "ssh").unwrap();
+    assert!(is_available_offline(&package(&commit), &tarballs, &cache));
+    let other = "0000000000000000000000000000000000000000";
+    assert!(!is_available_offline(&package(other), &tarballs, &cache));
+}
+
+#[test]
+fn add_missing_git_packages_offline_uncached() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.join("app"));
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![ManifestPackage {
+            name: "wibble".into(),
+            version: Version::new(0, 1, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Git {
+                repo: root.join("never_cloned").as_str().into(),
+                commit: "0000000000000000000000000000000000000000".into(),
+                subdir: None,
+            },
+        }],
+    };
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: HashMap::new(),
+    };
+
+    // The repository is never contacted, so the package is reported as not
+    // cached rather than as a failed checkout
+    let result = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(add_missing_packages(
+            &paths,
+            &manifest,
+            &local,
+            &PackageConfig {
+                name: "app".into(),
+                ..Default::default()
+            },
+            &HexRepository::default(),
+            Arc::new(gleam_core::build::NullTelemetry),
+            UseNetwork::No,
+        ));
+    assert_eq!(
+        result,
+        Err(Error::PackagesNotCached {
+            packages: vec!["wibble 0.1.0".into()]
+        })
+    );
+    assert!(!paths.build_packages_package("wibble").exists());
+}
+
+#[test]
+fn git_packages_share_a_cached_clone() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let commit = git_repository_with_commit(&repo);
+    let cache = root.join("cache");
+    let package = ManifestPackage {
+        name: "wibble".into(),
+        version: Version::new(0, 1, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Git {
+            repo: repo.as_str().into(),
+            commit: commit.clone(),
+            subdir: None,
+        },
+    };
+    let first = ProjectPaths::new(root.join("first"));
+    let second = ProjectPaths::new(root.join("second"));
+
+    checkout_git_package_with(&first, &package, &cache, "ssh").unwrap();
+    // The second project is checked out from the cache without contacting
+    // the repository
+    fs::delete_directory(&repo).unwrap();
+    checkout_git_package_with(&second, &package, &cache, "ssh").unwrap();
+
+    assert!(has_git_checkout(
+        &first.build_packages_package("wibble"),
+        &commit
+    ));
+    assert!(has_git_checkout(
+        &second.build_packages_package("wibble"),
+        &commit
+    ));
+    assert_eq!(
+        std::fs::read_dir(&cache).unwrap().count(),
+        1,
+        "one clone is shared by both projects"
+    );
+}
+
+#[test]
+fn add_missing_git_package_commit_not_found() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let _ = git_repository_with_commit(&repo);
+    let paths = ProjectPaths::new(root.join("project"));
+    let package = ManifestPackage {
+        name: "wibble".into(),
+        version: Version::new(0, 1, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Git {
+            repo: repo.as_str().into(),
+            commit: "1111111111111111111111111111111111111111".into(),
+            subdir: None,
+        },
+    };
+
+    let result = checkout_git_package_with(&paths, &package, &root.join("cache"), "ssh");
+    assert!(matches!(
+        result,
+        Err(Error::GitDependencyCheckoutFailed { .. })
+    ));
+    assert!(!paths.build_packages_package("wibble").exists());
+}
+
+#[test]
+fn ssh_repositories_are_recognised() {
+    assert!(is_ssh_repository("git@github.com:gleam-lang/stdlib.git"));
+    assert!(is_ssh_repository(
+        "ssh://git@github.com/gleam-lang/stdlib.git"
+    ));
+    assert!(is_ssh_repository("github.com:gleam-lang/stdlib"));
+    assert!(!is_ssh_repository(
+        "https://github.com/gleam-lang/stdlib.git"
+    ));
+    assert!(!is_ssh_repository("/home/lucy/stdlib"));
+    assert!(!is_ssh_repository("../stdlib:v2"));
+    assert!(!is_ssh_repository("C:/Users/lucy/stdlib"));
+}
+
+#[test]
+fn ssh_command_uses_key_file() {
+    assert_eq!(git_ssh_command(None, None), "ssh -o BatchMode=yes");
+    assert_eq!(
+        git_ssh_command(Some("ssh -v".into()), Some("/keys/id_ed25519".into())),
+        "ssh -v -o BatchMode=yes -o IdentitiesOnly=yes -i '/keys/id_ed25519'"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn ssh_git_package_authentication_failure() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    // A fake ssh that records how it was called and rejects the connection
+    let ssh = root.join("ssh");
+    let calls = root.join("calls");
+    fs::write(
+        &ssh,
+        &format!(
+            "#!/bin/sh\necho \"$@\" >> '{calls}'\necho 'git@example.com: Permission denied (publickey).' >&2\nexit 255\n"
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let paths = ProjectPaths::new(root.join("project"));
+    let package = ManifestPackage {
+        name: "wibble".into(),
+        version: Version::new(0, 1, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Git {
+            repo: "git@example.com:lucy/wibble.git".into(),
+            commit: "1111111111111111111111111111111111111111".into(),
+            subdir: None,
+        },
+    };
+
+    let result = checkout_git_package_with(&paths, &package, &root.join("cache"), ssh.as_str());
+    assert!(
+        matches!(
+            result,
+            Err(Error::GitDependencyAuthenticationFailed { ref package, .. }) if package == "wibble"
+        ),
+        "{result:?}"
+    );
+    let calls = fs::read(&calls).unwrap();
+    assert!(calls.contains("git@example.com"), "{calls}");
+    assert!(!paths.build_packages_package("wibble").exists());
+}
+
+#[test]
+fn git_failures_are_classified() {
+    let failure = |error: &str| {
+        git_failure(
+            &"wibble".into(),
+            &"repo".into(),
+            &"commit".into(),
+            error.into(),
+        )
+    };
+    assert!(matches!(
+        failure("remote: Repository not found.\nfatal: repository 'https://example.com/repo/' not found"),
+        Error::GitDependencyRepositoryNotFound { .. }
+    ));
+    assert!(matches!(
+        failure(
+            "fatal: could not read Username for 'https://example.com': terminal prompts disabled"
+        ),
+        Error::GitDependencyAuthenticationFailed { .. }
+    ));
+    assert!(matches!(
+        failure("fatal: couldn't find remote ref 1111111"),
+        Error::GitDependencyCheckoutFailed { .. }
+    ));
+}
+
+/// The build directories that cleaning applies to. A mode or target that is
+/// not given matches all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BuildFilter {
+    pub mode: Option<Mode>,
+    pub target: Option<Target>,
+}
+
+impl BuildFilter {
+    fn includes(&self, mode: Mode, target: Target) -> bool {
+        self.mode.map_or(true, |m| m == mode) && self.target.map_or(true, |t| t == target)
+    }
+
+    fn is_everything(&self) -> bool {
+        self.mode.is_none() && self.target.is_none()
+    }
+}
+
+/// Delete the sources and build artefacts of packages that are no longer in
+/// the manifest, returning the number of bytes freed.
+///
+/// When the filter doesn't match every build directory only the matching
+/// build artefacts are deleted, and the package sources are kept for the
+/// other builds.
+fn remove_extra_packages<Telem: Telemetry>(
+    paths: &ProjectPaths,
+    local: &LocalPackages,
+    manifest: &Manifest,
+    filter: BuildFilter,
+    telemetry: &Telem,
+) -> Result<u64> {
+    remove_extra_packages_with(
+        paths,
+        local,
+        manifest,
+        filter,
+        telemetry,
+        fs::delete_directory,
+    )
+}
+
+/// Remove the packages that are no longer needed using the given function to
+/// delete directories. A package whose files can't be deleted is reported to
+/// the telemetry and skipped rather than stopping the others being removed.
+fn remove_extra_packages_with<Telem: Telemetry>(
+    paths: &ProjectPaths,
+    local: &LocalPackages,
+    manifest: &Manifest,
+    filter: BuildFilter,
+    telemetry: &Telem,
+    delete_directory: impl Fn(&Utf8Path) -> Result<()>,
+) -> Result<u64> {
+    let _guard = BuildLock::lock_all_build(paths, telemetry)?;
+    let mut freed = 0;
+    let mut delete = |package_name: &str, path: &Utf8Path| {
+        let size = fs::directory_size(path);
+        match delete_directory(path) {
+            Ok(()) => {
+                freed += size;
+                true
+            }
+            Err(error) => {
+                telemetry.extra_package_not_deleted(package_name, &error);
+                false
+            }
+        }
+    };
+
+    for (package_name, version) in local.extra_local_packages(manifest, filter.mode) {
+        // Delete the package source
+        let path = paths.build_packages_package(&package_name);
+        if filter.is_everything() && path.exists() {
+            tracing::debug!(package=%package_name, version=%version, "removing_unneeded_package");
+            if delete(&package_name, &path) {
+                fs::delete_empty_parents(&path, &paths.build_packages_directory())?;
+            }
+        }
+
+        // Delete any build artefacts for the package
+        for mode in Mode::iter() {
+            for target in Target::iter().filter(|target| filter.includes(mode, *target)) {
+                let name = manifest
+                    .packages
+                    .iter()
+                    .find(|p| p.name == package_name)
+                    .map(|p| p.application_name().as_str())
+                    .unwrap_or(package_name.as_str());
+                let path = paths.build_directory_for_package(mode, target, name);
+                if path.exists() {
+                    tracing::debug!(package=%package_name, version=%version, "deleting_build_cache");
+                    let _ = delete(&package_name, &path);
+                }
+            }
+        }
+    }
+    Ok(freed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CachedPackage {
+    Valid,
+    InvalidTarball,
+    InvalidContents,
+}
+
+fn remove_corrupted_packages(
+    paths: &ProjectPaths,
+    local: &mut LocalPackages,
+    manifest: &Manifest,
+) -> Result<()> {
+    for package in &manifest.packages {
+        let ManifestPackageSource::Hex { outer_checksum, .. } = &package.source else {
+            continue;
+        };
+        if local.packages.get(package.name.as_str()) != Some(&package.version) {
+            continue;
+        }
+        let destination = paths.build_packages_package(&package.name);
+        if !destination.is_dir() {
+            continue;
+        }
+        let tarball = paths::global_package_cache_package_tarball(
+            &package.name,
+            &package.version.to_string(),
+        );
+        let check = check_cached_package(&tarball, &destination, outer_checksum)?;
+        if check == CachedPackage::Valid {
+            continue;
+        }
+
+        tracing::debug!(package=%package.name, version=%package.version, "repairing_package");
+        cli::print_repairing(&package.name, &package.version);
+        if check == CachedPackage::InvalidTarball && tarball.is_file() {
+            fs::delete_file(&tarball)?;
+        }
+        fs::delete_directory(&destination)?;
+        let _ = local.packages.remove(package.name.as_str());
+    }
+    Ok(())
+}
+
+pub fn verify() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let manifest = read_manifest_from_disc(&paths)?;
+    let verified = verify_packages(&paths, &manifest, &paths::global_packages_cache())?;
+    cli::print_validated(&format!("{verified} packages"));
+    Ok(())
+}
+
+/// Check every Hex package in the build directory against its tarball in the
+/// package cache and the checksum in the manifest, returning how many were
+/// checked. All the packages that don't match are reported together.
+fn verify_packages(
+    paths: &ProjectPaths,
+    manifest: &Manifest,
+    packages_cache: &Utf8Path,
+) -> Result<usize> {
+    let mut verified = 0;
+    let mut corrupted = vec![];
+    for package in &manifest.packages {
+        let ManifestPackageSource::Hex { outer_checksum, .. } = &package.source else {
+            continue;
+        };
+        let destination = paths.build_packages_package(&package.name);
+        if !destination.is_dir() {
+            continue;
+        }
+        let tarball = packages_cache.join(format!("{}-{}.tar", package.name, package.version));
+        let problem = match check_cached_package(&tarball, &destination, outer_checksum)? {
+            CachedPackage::Valid => {
+                verified += 1;
+                continue;
+            }
+            CachedPackage::InvalidTarball => "the cached tarball does not match",
+            CachedPackage::InvalidContents => "the files differ from the tarball",
+        };
+        corrupted.push(format!("{} {} ({problem})", package.name, package.version));
+    }
+    if !corrupted.is_empty() {
+        return Err(Error::PackagesCorrupted {
+            packages: corrupted,
+        });
+    }
+    Ok(verified)
+}
+
+fn remove_refreshed_packages(
+    paths: &ProjectPaths,
+    local: &mut LocalPackages,
+    manifest: &Manifest,
+    refresh: &Refresh,
+) -> Result<()> {
+    let names = match refresh {
+        Refresh::None => return Ok(()),
+        Refresh::All => vec![],
+        Refresh::Packages(names) => names.clone(),
+    };
+    let unknown = names
+        .iter()
+        .filter(|name| !manifest.packages.iter().any(|p| p.name == name.as_str()))
+        .cloned()
+        .collect_vec();
+    if !unknown.is_empty() {
+        return Err(Error::PackagesNotInManifest { packages: unknown });
+    }
+
+    let refreshed = manifest
+        .packages
+        .iter()
+        .filter(|package| package.is_hex())
+        .filter(|package| {
+            names.is_empty() || names.iter().any(|name| package.name == name.as_str())
+        });
+    for package in refreshed {
+        tracing::debug!(package=%package.name, version=%package.version, "refreshing_package");
+        let tarball = paths::global_package_cache_package_tarball(
+            &package.name,
+            &package.version.to_string(),
+        );
+        if tarball.is_file() {
+            fs::delete_file(&tarball)?;
+        }
+        fs::delete_directory(&paths.build_packages_package(&package.name))?;
+        let _ = local.packages.remove(package.name.as_str());
+    }
+    Ok(())
+}
+
+/// Check that the cached tarball for a Hex package matches the checksum
+/// recorded in the manifest, and that the package source in the build
+/// directory is exactly the contents of that tarball.
+fn check_cached_package(
+    tarball: &Utf8Path,
+    destination: &Utf8Path,
+    outer_checksum: &Base16Checksum,
+) -> Result<CachedPackage> {
+    use sha2::Digest;
+    use std::io::Read;
+
+    if !tarball.is_file() {
+        return Ok(CachedPackage::InvalidTarball);
+    }
+    let bytes = fs::read_bytes(tarball)?;
+    if sha2::Sha256::digest(&bytes).as_slice() != outer_checksum.0.as_slice() {
+        return Ok(CachedPackage::InvalidTarball);
+    }
+
+    let mut archive = tar::Archive::new(bytes.as_slice());
+    for entry in archive.entries().map_err(Error::expand_tar)? {
+        let entry = entry.map_err(Error::expand_tar)?;
+        if entry.path().map_err(Error::expand_tar)?.as_ref() != Utf8Path::new("contents.tar.gz") {
+            continue;
+        }
+
+        let mut contents = tar::Archive::new(GzDecoder::new(entry));
+        let mut expected_files = 0;
+        for file in contents.entries().map_err(Error::expand_tar)? {
+            let mut file = file.map_err(Error::expand_tar)?;
+            if !file.header().entry_type().is_file() {
+                continue;
+            }
+            expected_files += 1;
+            let path = destination
+                .as_std_path()
+                .join(file.path().map_err(Error::expand_tar)?);
+            let mut expected = Vec::new();
+            let _ = file.read_to_end(&mut expected).map_err(Error::expand_tar)?;
+            match std::fs::read(path) {
+                Ok(actual) if actual == expected => (),
+                _ => return Ok(CachedPackage::InvalidContents),
+            }
+        }
+
+        // Any extra files would also be compiled as part of the package
+        let actual_files = walkdir::WalkDir::new(destination)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .count();
+        return Ok(if actual_files == expected_files {
+            CachedPackage::Valid
+        } else {
+            CachedPackage::InvalidContents
+        });
+    }
+
+    Ok(CachedPackage::InvalidTarball)
+}
+
+#[cfg(test)]
+fn cached_package_tarball(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut contents = tar::Builder::new(flate2::write::GzEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    ));
+    for (path, text) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(text.len() as u64);
+        header.set_mode(0o644);
+        contents
+            .append_data(&mut header, path, text.as_bytes())
+            .unwrap();
+    }
+    let contents = contents.into_inner().unwrap().finish().unwrap();
+
+    let mut tarball = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    tarball
+        .append_data(&mut header, "contents.tar.gz", contents.as_slice())
+        .unwrap();
+    tarball.into_inner().unwrap()
+}
+
+#[test]
+fn check_cached_package_detects_corruption() {
+    use sha2::Digest;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let tarball = root.join("wibble-1.0.0.tar");
+    let destination = root.join("wibble");
+    let bytes = cached_package_tarball(&[("src/wibble.gleam", "pub fn main() { 1 }")]);
+    std::fs::write(&tarball, &bytes).unwrap();
+    let checksum = Base16Checksum(sha2::Sha256::digest(&bytes).to_vec());
+    std::fs::create_dir_all(destination.join("src")).unwrap();
+    let source = destination.join("src/wibble.gleam");
+    std::fs::write(&source, "pub fn main() { 1 }").unwrap();
+
+    let check =
+        |checksum: &Base16Checksum| check_cached_package(&tarball, &destination, checksum).unwrap();
+    assert_eq!(check(&checksum), CachedPackage::Valid);
+    assert_eq!(
+        check(&Base16Checksum(vec![1, 2, 3])),
+        CachedPackage::InvalidTarball
+    );
+
+    std::fs::write(destination.join("src/wobble.gleam"), "").unwrap();
+    assert_eq!(check(&checksum), CachedPackage::InvalidContents);
+    std::fs::remove_file(destination.join("src/wobble.gleam")).unwrap();
+
+    std::fs::write(&source, "pub fn main() { 2 }").unwrap();
+    assert_eq!(check(&checksum), CachedPackage::InvalidContents);
+
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(check(&checksum), CachedPackage::InvalidContents);
+}
+
+#[test]
+fn verify_packages_reports_every_corrupted_package() {
+    use sha2::Digest;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.join("app"));
+    let cache = root.join("cache");
+    let mut packages = vec![];
+    for name in ["wibble", "wobble", "wubble"] {
+        let source = format!("pub fn {name}() {{ 1 }}");
+        let file = format!("src/{name}.gleam");
+        let bytes = cached_package_tarball(&[(&file, &source)]);
+        fs::write_bytes(&cache.join(format!("{name}-1.0.0.tar")), &bytes).unwrap();
+        fs::write(&paths.build_packages_package(name).join(&file), &source).unwrap();
+        packages.push(ManifestPackage {
+            name: name.into(),
+            version: Version::new(1, 0, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(sha2::Sha256::digest(&bytes).to_vec()),
+                repository: None,
+            },
+        });
+    }
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages,
+    };
+    assert_eq!(verify_packages(&paths, &manifest, &cache), Ok(3));
+
+    fs::write(&cache.join("wibble-1.0.0.tar"), "corrupted").unwrap();
+    fs::write(
+        &paths
+            .build_packages_package("wubble")
+            .join("src/wubble.gleam"),
+        "corrupted",
+    )
+    .unwrap();
+    assert_eq!(
+        verify_packages(&paths, &manifest, &cache),
+        Err(Error::PackagesCorrupted {
+            packages: vec![
+                "wibble 1.0.0 (the cached tarball does not match)".into(),
+                "wubble 1.0.0 (the files differ from the tarball)".into(),
+            ]
+        })
+    );
+}
+
+#[test]
+fn remove_corrupted_packages_marks_them_missing() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    // This package is never in the cache, so its tarball cannot be verified
+    let name = "gleam_verify_never_cached";
+    let destination = paths.build_packages_package(name);
+    std::fs::create_dir_all(&destination).unwrap();
+    std::fs::write(destination.join("file"), "corrupted").unwrap();
+    let mut local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [(name.into(), Version::new(1, 0, 0))].into(),
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![ManifestPackage {
+            name: name.into(),
+            version: Version::new(1, 0, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![]),
+                repository: None,
+            },
+        }],
+    };
+
+    remove_corrupted_packages(&paths, &mut local, &manifest).unwrap();
+
+    assert!(!destination.exists());
+    assert_eq!(
+        local
+            .missing_local_packages(&manifest, "root", &paths)
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn remove_refreshed_packages_marks_them_missing() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let package = |name: &str| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    // These packages are never in the cache, so only the build directory
+    // copies are removed
+    let refreshed = "gleam_refresh_never_cached";
+    let kept = "gleam_refresh_kept";
+    for name in [refreshed, kept] {
+        let destination = paths.build_packages_package(name);
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("file"), "present").unwrap();
+    }
+    let mut local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [
+            (refreshed.into(), Version::new(1, 0, 0)),
+            (kept.into(), Version::new(1, 0, 0)),
+        ]
+        .into(),
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![package(refreshed), package(kept)],
+    };
+
+    let refresh = Refresh::Packages(vec![refreshed.into()]);
+    remove_refreshed_packages(&paths, &mut local, &manifest, &refresh).unwrap();
+
+    assert!(!paths.build_packages_package(refreshed).exists());
+    assert!(paths.build_packages_package(kept).join("file").exists());
+    let missing = local
+        .missing_local_packages(&manifest, "root", &paths)
+        .into_iter()
+        .map(|(package, _)| package.name.as_str())
+        .collect_vec();
+    assert_eq!(missing, vec![refreshed]);
+
+    let refresh = Refresh::Packages(vec!["gleam_refresh_unknown".into()]);
+    assert_eq!(
+        remove_refreshed_packages(&paths, &mut local, &manifest, &refresh),
+        Err(Error::PackagesNotInManifest {
+            packages: vec!["gleam_refresh_unknown".into()]
+        })
+    );
+}
+
+#[test]
+fn remove_extra_packages_keeps_manifest_packages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let extra_build = paths.build_directory_for_package(Mode::Dev, Target::Erlang, "extra");
+    for directory in [
+        paths.build_packages_package("extra"),
+        paths.build_packages_package("kept"),
+        extra_build.clone(),
+    ] {
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("file"), "0123456789").unwrap();
+    }
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [
+            ("extra".into(), Version::new(1, 0, 0)),
+            ("kept".into(), Version::new(1, 0, 0)),
+        ]
+        .into(),
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![ManifestPackage {
+            name: "kept".into(),
+            version: Version::new(1, 0, 0),
+            build_tools: ["gleam".into()].into(),
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![]),
+                repository: None,
+            },
+        }],
+    };
+
+    let freed = remove_extra_packages(
+        &paths,
+        &local,
+        &manifest,
+        BuildFilter::default(),
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    assert_eq!(freed, 20);
+    assert!(!paths.build_packages_package("extra").exists());
+    assert!(!extra_build.exists());
+    assert!(paths.build_packages_package("kept").join("file").exists());
+}
+
+#[test]
+fn remove_extra_packages_deletes_empty_parent_directories() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let package = paths.build_packages_package("namespace/extra");
+    std::fs::create_dir_all(&package).unwrap();
+    std::fs::write(package.join("file"), "0123456789").unwrap();
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [("namespace/extra".into(), Version::new(1, 0, 0))].into(),
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![],
+    };
+
+    let _ = remove_extra_packages(
+        &paths,
+        &local,
+        &manifest,
+        BuildFilter::default(),
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    assert!(!paths.build_packages_package("namespace").exists());
+    assert!(paths.build_packages_directory().is_dir());
+}
+
+#[test]
+fn remove_extra_packages_for_one_target() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let erlang_build = paths.build_directory_for_package(Mode::Dev, Target::Erlang, "extra");
+    let javascript_build =
+        paths.build_directory_for_package(Mode::Dev, Target::JavaScript, "extra");
+    for directory in [
+        paths.build_packages_package("extra"),
+        erlang_build.clone(),
+        javascript_build.clone(),
+    ] {
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("file"), "0123456789").unwrap();
+    }
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [("extra".into(), Version::new(1, 0, 0))].into(),
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![],
+    };
+    let filter = BuildFilter {
+        mode: None,
+        target: Some(Target::JavaScript),
+    };
+
+    let freed = remove_extra_packages(
+        &paths,
+        &local,
+        &manifest,
+        filter,
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    assert_eq!(freed, 10);
+    assert!(!javascript_build.exists());
+    assert!(erlang_build.join("file").exists());
+    assert!(paths.build_packages_package("extra").join("file").exists());
+}
+
+#[test]
+fn remove_extra_packages_continues_when_a_delete_fails() {
+    use gleam_core::build::{TelemetryEvent, TelemetryEventHandler};
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct DeleteTelemetry {
+        not_deleted: Mutex<Vec<String>>,
+    }
+
+    impl TelemetryEventHandler for DeleteTelemetry {
+        fn handle(&self, event: TelemetryEvent) {
+            if let TelemetryEvent::ExtraPackageNotDeleted { name, .. } = event {
+                self.not_deleted.lock().unwrap().push(name.into());
+            }
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    for name in ["locked", "removed"] {
+        let directory = paths.build_packages_package(name);
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("file"), "0123456789").unwrap();
+    }
+    let local = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [
+            ("locked".into(), Version::new(1, 0, 0)),
+            ("removed".into(), Version::new(1, 0, 0)),
+        ]
+        .into(),
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![],
+    };
+    let locked = paths.build_packages_package("locked");
+    let telemetry = DeleteTelemetry::default();
+
+    let freed = remove_extra_packages_with(
+        &paths,
+        &local,
+        &manifest,
+        BuildFilter::default(),
+        &telemetry,
+        |path| {
+            if path == locked {
+                Err(Error::FileIo {
+                    action: FileIoAction::Delete,
+                    kind: FileKind::Directory,
+                    path: path.to_path_buf(),
+                    err: Some("Permission denied".into()),
+                })
+            } else {
+                fs::delete_directory(path)
+            }
+        },
+    )
+    .unwrap();
+
+    assert_eq!(freed, 10);
+    assert!(locked.join("file").exists());
+    assert!(!paths.build_packages_package("removed").exists());
+    assert_eq!(telemetry.not_deleted.into_inner().unwrap(), vec!["locked"]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanCache {
+    Yes,
+    No,
+}
+
+pub fn clean(clean_cache: CleanCache, filter: BuildFilter) -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let telemetry = cli::Reporter::new();
+    let lock = BuildLock::new_packages(&paths)?;
+    let _guard = lock.lock(&telemetry);
+
+    let config = crate::config::read(paths.root_config())?;
+    let manifest = read_manifest_from_disc(&paths)?;
+    let local = LocalPackages::read_from_disc(&paths)?;
+    let mut freed = remove_extra_packages(&paths, &local, &manifest, filter, &telemetry)?;
+    // The sources of removed packages are kept when only some builds are
+    // cleaned, so they are still recorded to be removed later.
+    if filter.is_everything() {
+        LocalPackages::from_manifest(&manifest, &config).write_to_disc(&paths)?;
+    }
+
+    // The global cache is shared between projects, so it is only cleared when
+    // asked for.
+    if clean_cache == CleanCache::Yes {
+        let cache = paths::global_packages_cache();
+        if cache.exists() {
+            freed += fs::directory_size(&cache);
+            fs::delete_directory(&cache)?;
+        }
+    }
+
+    cli::print_cleaned(freed);
+    Ok(())
+}
+
+fn read_manifest_from_disc(paths: &ProjectPaths) -> Result<Manifest> {
+    tracing::debug!("reading_manifest_toml");
+    let manifest_path = paths.manifest();
+    let toml = crate::fs::read(&manifest_path)?;
+    let manifest = Manifest::parse(&toml, &manifest_path)?;
+    if Manifest::is_edited(&toml) {
+        crate::fs::ConsoleWarningEmitter.emit_warning(Warning::EditedManifest {
+            path: manifest_path,
+        });
+    }
+    Ok(manifest)
+}
+
+fn write_manifest_to_disc(paths: &ProjectPaths, manifest: &Manifest) -> Result<()> {
+    let path = paths.manifest();
+    fs::write_atomically(&path, &manifest.to_toml(paths.root()))
+}
+
+#[test]
+fn failed_manifest_write_keeps_previous_manifest() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![],
+    };
+    write_manifest_to_disc(&paths, &manifest).unwrap();
+    let previous = std::fs::read_to_string(paths.manifest()).unwrap();
+
+    // A directory in the way of the temporary file makes the write fail
+    std::fs::create_dir(root.join(".manifest.toml.tmp")).unwrap();
+    let manifest = Manifest {
+        requirements: [("wibble".into(), Requirement::hex("~> 1.0"))].into(),
+        packages: vec![],
+    };
+    assert!(write_manifest_to_disc(&paths, &manifest).is_err());
+    assert_eq!(std::fs::read_to_string(paths.manifest()).unwrap(), previous);
+}
+
+// This is the container for locally pinned packages, representing the current contents of
+// the `project/build/packages` directory.
+// For descriptions of packages provided by paths and git deps, see the ProvidedPackage struct.
+// The same package may appear in both at different times.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LocalPackages {
+    /// The packages that are only needed for development, as they are not
+    /// required by the project's dependencies. Older versions of this file
+    /// don't record them.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    dev_packages: BTreeSet<String>,
+    packages: HashMap<String, Version>,
+}
+
+impl LocalPackages {
+    /// The local packages that are not in the manifest. Packages only needed
+    /// for development are also extra when the mode is production.
+    pub fn extra_local_packages(
+        &self,
+        manifest: &Manifest,
+        mode: Option<Mode>,
+    ) -> Vec<(String, Version)> {
+        let manifest_packages: HashSet<_> = manifest
+            .packages
+            .iter()
+            .map(|p| (&p.name, &p.version))
+            .collect();
+        self.packages
+            .iter()
+            .filter(|(n, v)| {
+                !manifest_packages.contains(&(&EcoString::from(n.as_ref()), v))
+                    || (mode == Some(Mode::Prod) && self.dev_packages.contains(n.as_str()))
+            })
+            .map(|(n, v)| (n.clone(), v.clone()))
+            .collect()
+    }
+
+    pub fn missing_local_packages<'a>(
+        &self,
+        manifest: &'a Manifest,
+        root: &str,
+        paths: &ProjectPaths,
+    ) -> Vec<(&'a ManifestPackage, MissingPackageReason)> {
+        manifest
+            .packages
+            .iter()
+            // We don't need to download the root package
+            .filter(|p| p.name != root)
+            // We don't need to download local packages because we use the linked source directly
+            .filter(|p| !p.is_local())
+            // We don't need to download packages which we have the correct version of
+            .filter_map(|p| Some((p, self.missing_reason(p, paths)?)))
+            .collect()
+    }
+
+    fn missing_reason(
+        &self,
+        package: &ManifestPackage,
+        paths: &ProjectPaths,
+    ) -> Option<MissingPackageReason> {
+        match self.packages.get(package.name.as_str()) {
+            None => Some(MissingPackageReason::NotPresent),
+            Some(version) if version != &package.version => {
+                Some(MissingPackageReason::VersionChanged {
+                    old: version.clone(),
+                    new: package.version.clone(),
+                })
+            }
+            Some(_) if !Self::has_source(package, paths) => {
+                Some(MissingPackageReason::SourceMissing)
+            }
+            Some(_) => None,
+        }
+    }
+
+    // Git packages may change commit without changing version, and their
+    // checkout could have been removed, so we check the source is on disc.
+    fn has_source(package: &ManifestPackage, paths: &ProjectPaths) -> bool {
+        match &package.source {
+            ManifestPackageSource::Git { commit, .. } => {
+                has_git_checkout(&paths.build_packages_package(&package.name), commit)
+            }
+            ManifestPackageSource::Hex { .. } | ManifestPackageSource::Local { .. } => true,
+        }
+    }
+
+    pub fn read_from_disc(paths: &ProjectPaths) -> Result<Self> {
+        let path = paths.build_packages_toml();
+        if !path.exists() {
+            return Ok(Self {
+                dev_packages: BTreeSet::new(),
+                packages: HashMap::new(),
+            });
+        }
+        let toml = crate::fs::read(&path)?;
+        toml::from_str(&toml).map_err(|e| Error::FileIo {
+            action: FileIoAction::Parse,
+            kind: FileKind::File,
+            path: path.clone(),
+            err: Some(e.to_string()),
+        })
+    }
+
+    pub fn write_to_disc(&self, paths: &ProjectPaths) -> Result<()> {
+        let path = paths.build_packages_toml();
+        let toml = toml::to_string(&self).map_err(|error| Error::FileIo {
+            action: FileIoAction::WriteTo,
+            kind: FileKind::File,
+            path: path.clone(),
+            err: Some(error.to_string()),
+        })?;
+        fs::write_atomically(&path, &toml)
+    }
+
+    /// Record that the packages of `only`, which is part of `manifest`, are
+    /// now in the build directory along with the ones that already were.
+    pub fn extend_from_manifest(
+        &mut self,
+        only: &Manifest,
+        manifest: &Manifest,
+        config: &PackageConfig,
+    ) {
+        let all = Self::from_manifest(manifest, config);
+        for package in &only.packages {
+            let name = package.name.to_string();
+            if all.dev_packages.contains(&name) {
+                let _ = self.dev_packages.insert(name.clone());
+            } else {
+                let _ = self.dev_packages.remove(&name);
+            }
+            let _ = self.packages.insert(name, package.version.clone());
+        }
+    }
+
+    pub fn from_manifest(manifest: &Manifest, config: &PackageConfig) -> Self {
+        let production: HashSet<EcoString> = production_packages(config, manifest)
+            .packages
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        Self {
+            dev_packages: manifest
+                .packages
+                .iter()
+                .filter(|p| !production.contains(&p.name))
+                .map(|p| p.name.to_string())
+                .collect(),
+            packages: manifest
+                .packages
+                .iter()
+                .map(|p| (p.name.to_string(), p.version.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[test]
+fn missing_local_packages() {
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            ManifestPackage {
+                name: "root".into(),
+                version: Version::parse("1.0.0").unwrap(),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4]),
+                    repository: None,
+                },
+            },
+            ManifestPackage {
+                name: "local1".into(),
+                version: Version::parse("1.0.0").unwrap(),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
+                    repository: None,
+                },
+            },
+            ManifestPackage {
+                name: "local2".into(),
+                version: Version::parse("3.0.0").unwrap(),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
+                    repository: None,
+                },
+            },
+        ],
+    };
+    let mut extra = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [
+            ("local2".into(), Version::parse("2.0.0").unwrap()),
+            ("local3".into(), Version::parse("3.0.0").unwrap()),
+        ]
+        .into(),
+    }
+    .missing_local_packages(
+        &manifest,
+        "root",
+        &crate::project_paths_at_current_directory_without_toml(),
+    );
+    extra.sort_by(|(a, _), (b, _)| a.cmp(b));
+    assert_eq!(
+        extra,
+        [
+            (
+                &ManifestPackage {
+                    name: "local1".into(),
+                    version: Version::parse("1.0.0").unwrap(),
+                    build_tools: ["gleam".into()].into(),
+                    otp_app: None,
+                    requirements: vec![],
+                    source: ManifestPackageSource::Hex {
+                        outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
+                        repository: None,
+                    },
+                },
+                MissingPackageReason::NotPresent
+            ),
+            (
+                &ManifestPackage {
+                    name: "local2".into(),
+                    version: Version::parse("3.0.0").unwrap(),
+                    build_tools: ["gleam".into()].into(),
+                    otp_app: None,
+                    requirements: vec![],
+                    source: ManifestPackageSource::Hex {
+                        outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
+                        repository: None,
+                    },
+                },
+                MissingPackageReason::VersionChanged {
+                    old: Version::parse("2.0.0").unwrap(),
+                    new: Version::parse("3.0.0").unwrap(),
+                }
+            ),
+        ]
+    )
+}
+
+#[test]
+fn extra_local_packages() {
+    let mut extra = LocalPackages {
+        dev_packages: BTreeSet::new(),
+        packages: [
+            ("local1".into(), Version::parse("1.0.0").unwrap()),
+            ("local2".into(), Version::parse("2.0.0").unwrap()),
+            ("local3".into(), Version::parse("3.0.0").unwrap()),
+        ]
+        .into(),
+    }
+    .extra_local_packages(
+        &Manifest {
+            requirements: HashMap::new(),
+            packages: vec![
+                ManifestPackage {
+                    name: "local1".into(),
+                    version: Version::parse("1.0.0").unwrap(),
+                    build_tools: ["gleam".into()].into(),
+                    otp_app: None,
+                    requirements: vec![],
+                    source: ManifestPackageSource::Hex {
+                        outer_checksum: Base16Checksum(vec![1, 2, 3, 4, 5]),
+                        repository: None,
+                    },
+                },
+                ManifestPackage {
+                    name: "local2".into(),
+                    version: Version::parse("3.0.0").unwrap(),
+                    build_tools: ["gleam".into()].into(),
+                    otp_app: None,
+                    requirements: vec![],
+                    source: ManifestPackageSource::Hex {
+                        outer_checksum: Base16Checksum(vec![4, 5]),
+                        repository: None,
+                    },
+                },
+            ],
+        },
+        None,
+    );
+    extra.sort();
+    assert_eq!(
+        extra,
+        [
+            ("local2".into(), Version::new(2, 0, 0)),
+            ("local3".into(), Version::new(3, 0, 0)),
+        ]
+    )
+}
+
+#[test]
+fn local_packages_record_dev_packages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let paths = ProjectPaths::new(root.to_path_buf());
+    std::fs::create_dir_all(paths.build_packages_directory()).unwrap();
+    let package = |name: &str, requirements: Vec<EcoString>| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements,
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let mut config = PackageConfig::default();
+    config.dependencies = [("gleam_json".into(), Requirement::hex("~> 1.0"))].into();
+    config.dev_dependencies = [("gleeunit".into(), Requirement::hex("~> 1.0"))].into();
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![
+            package("gleam_json", vec!["gleam_stdlib".into()]),
+            package("gleam_stdlib", vec![]),
+            package("gleeunit", vec!["gleam_stdlib".into()]),
+        ],
+    };
+
+    let local = LocalPackages::from_manifest(&manifest, &config);
+    assert_eq!(local.dev_packages, ["gleeunit".to_string()].into());
+    local.write_to_disc(&paths).unwrap();
+    assert_eq!(LocalPackages::read_from_disc(&paths).unwrap(), local);
+
+    assert_eq!(local.extra_local_packages(&manifest, None), vec![]);
+    assert_eq!(
+        local.extra_local_packages(&manifest, Some(Mode::Prod)),
+        vec![("gleeunit".to_string(), Version::new(1, 0, 0))]
+    );
+
+    // Files written before dev packages were recorded can still be read
+    std::fs::write(
+        paths.build_packages_toml(),
+        "[packages]\ngleeunit = \"1.0.0\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        LocalPackages::read_from_disc(&paths).unwrap().dev_packages,
+        BTreeSet::new()
+    );
+}
+
+fn get_manifest<Telem: Telemetry>(
+    paths: &ProjectPaths,
+    runtime: tokio::runtime::Handle,
+    mode: Mode,
+    config: &PackageConfig,
+    telemetry: &Telem,
+    options: &DownloadOptions,
+) -> Result<(bool, Manifest)> {
+    // If there's no manifest (or we have been asked not to use it) then resolve
+    // the versions anew
+    let should_resolve = match options.use_manifest {
+        UseManifest::Frozen if !paths.manifest().exists() => {
+            return Err(Error::FrozenManifestOutdated);
+        }
+        _ if !paths.manifest().exists() => {
+            tracing::debug!("manifest_not_present");
             true
         }
-        UseManifest::Yes => false,
-    };
+        UseManifest::No => {
+            tracing::debug!("ignoring_manifest");
+            true
+        }
+        UseManifest::Yes | UseManifest::Frozen | UseManifest::Unlock(_) => false,
+    };
+
+    if should_resolve {
+        if options.use_network == UseNetwork::No {
+            return Err(Error::OfflineDependencyResolution);
+        }
+        let manifest = resolve_versions(runtime, mode, paths, config, None, &[], telemetry)?;
+        // The manifest may have been ignored rather than missing, in which case
+        // it is still the previous state of the dependencies.
+        if let Some(previous) = paths
+            .manifest()
+            .exists()
+            .then(|| read_manifest_from_disc(paths).ok())
+            .flatten()
+        {
+            check_downgrades(&previous, &manifest, options.allow_downgrade)?;
+            report_changes(&previous, &manifest, telemetry);
+        }
+        return Ok((true, manifest));
+    }
+
+    let manifest = read_manifest_from_disc(paths)?;
+
+    // If the config has unchanged since the manifest was written then it is up
+    // to date so we can return it unmodified.
+    let unlocked = match &options.use_manifest {
+        UseManifest::Unlock(packages) => packages.as_slice(),
+        UseManifest::Yes | UseManifest::No | UseManifest::Frozen => &[],
+    };
+    if unlocked.is_empty()
+        && dependency::is_manifest_up_to_date(&ProjectIO::new(), &manifest, config, paths.root())?
+    {
+        tracing::debug!("manifest_up_to_date");
+        Ok((false, manifest))
+    } else {
+        tracing::debug!("manifest_outdated");
+        if let UseManifest::Frozen = options.use_manifest {
+            return Err(Error::FrozenManifestOutdated);
+        }
+        if options.use_network == UseNetwork::No {
+            return Err(Error::OfflineDependencyResolution);
+        }
+        let previous = manifest;
+        let manifest = resolve_versions(
+            runtime,
+            mode,
+            paths,
+            config,
+            Some(&previous),
+            unlocked,
+            telemetry,
+        )?;
+        check_downgrades(&previous, &manifest, options.allow_downgrade)?;
+        report_changes(&previous, &manifest, telemetry);
+        Ok((true, manifest))
+    }
+}
+
+/// Packages are not moved to lower versions than the ones locked in the
+/// manifest unless this has been explicitly allowed, as it is surprising and
+/// could reintroduce bugs that have since been fixed.
+fn check_downgrades(
+    previous: &Manifest,
+    manifest: &Manifest,
+    allow_downgrade: AllowDowngrade,
+) -> Result<()> {
+    let packages = previous.downgrades_to(manifest);
+    match allow_downgrade {
+        AllowDowngrade::No if !packages.is_empty() => Err(Error::PackagesDowngraded { packages }),
+        AllowDowngrade::Yes | AllowDowngrade::No => Ok(()),
+    }
+}
+
+#[test]
+fn resolving_anew_rejects_downgrades_unless_allowed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../local\" }\n",
+    );
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\n",
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+    let _ = lock_manifest(&paths, &gleam_core::build::NullTelemetry).unwrap();
+
+    // The only version that can now be used is lower than the locked one
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"0.9.0\"\n",
+    );
+    let config = crate::config::read(paths.root_config()).unwrap();
+    let runtime = crate::http::async_runtime().unwrap();
+    let update = |allow_downgrade| {
+        get_manifest(
+            &paths,
+            runtime.handle().clone(),
+            Mode::Dev,
+            &config,
+            &gleam_core::build::NullTelemetry,
+            &DownloadOptions {
+                use_manifest: UseManifest::No,
+                allow_downgrade,
+                ..Default::default()
+            },
+        )
+        .map(|(_, manifest)| manifest.packages[0].version.clone())
+    };
+
+    assert_eq!(
+        update(AllowDowngrade::No),
+        Err(Error::PackagesDowngraded {
+            packages: vec![("local".into(), Version::new(1, 0, 0), Version::new(0, 9, 0))],
+        })
+    );
+    assert_eq!(update(AllowDowngrade::Yes), Ok(Version::new(0, 9, 0)));
+}
+
+pub fn status() -> Result<()> {
+    let paths = crate::find_project_paths()?;
+    let config = crate::config::read(paths.root_config())?;
+    let report = dependency::manifest_status(&ProjectIO::new(), &paths, &config)?;
+    for warning in report.warnings {
+        crate::fs::ConsoleWarningEmitter.emit_warning(warning);
+    }
+    println!("{}", report.status.as_str());
+    Ok(())
+}
+
+fn report_changes<Telem: Telemetry>(previous: &Manifest, manifest: &Manifest, telemetry: &Telem) {
+    let changes = previous.changes_to(manifest);
+    if !changes.is_empty() {
+        telemetry.resolved_package_changes(&changes);
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct ProvidedPackage {
+    version: Version,
+    otp_app: Option<EcoString>,
+    source: ProvidedPackageSource,
+    requirements: HashMap<EcoString, hexpm::version::Range>,
+}
+
+#[derive(Clone, Eq, Debug)]
+enum ProvidedPackageSource {
+    Git {
+        repo: EcoString,
+        commit: EcoString,
+        subdir: Option<Utf8PathBuf>,
+    },
+    Local {
+        path: Utf8PathBuf,
+    },
+}
+
+impl ProvidedPackage {
+    fn to_hex_package(&self, name: &EcoString) -> hexpm::Package {
+        let requirements = self
+            .requirements
+            .iter()
+            .map(|(name, version)| {
+                (
+                    name.as_str().into(),
+                    hexpm::Dependency {
+                        requirement: version.clone(),
+                        optional: false,
+                        app: None,
+                        repository: None,
+                    },
+                )
+            })
+            .collect();
+        let release = hexpm::Release {
+            version: self.version.clone(),
+            requirements,
+            retirement_status: None,
+            outer_checksum: vec![],
+            meta: (),
+        };
+        hexpm::Package {
+            name: name.as_str().into(),
+            repository: "local".into(),
+            releases: vec![release],
+        }
+    }
+
+    fn to_manifest_package(&self, name: &str) -> ManifestPackage {
+        let mut package = ManifestPackage {
+            name: name.into(),
+            version: self.version.clone(),
+            otp_app: self.otp_app.clone(),
+            build_tools: vec!["gleam".into()],
+            requirements: self.requirements.keys().cloned().collect(),
+            source: self.source.to_manifest_package_source(),
+        };
+        package.requirements.sort();
+        package
+    }
+}
+
+impl ProvidedPackageSource {
+    fn to_manifest_package_source(&self) -> ManifestPackageSource {
+        match self {
+            Self::Git {
+                repo,
+                commit,
+                subdir,
+            } => ManifestPackageSource::Git {
+                repo: repo.clone(),
+                commit: commit.clone(),
+                subdir: subdir.clone(),
+            },
+            Self::Local { path } => ManifestPackageSource::Local { path: path.clone() },
+        }
+    }
+
+    fn to_toml(&self) -> String {
+        match self {
+            Self::Git {
+                repo,
+                commit,
+                subdir: None,
+            } => {
+                format!(r#"{{ repo: "{}", commit: "{}" }}"#, repo, commit)
+            }
+            Self::Git {
+                repo,
+                commit,
+                subdir: Some(subdir),
+            } => {
+                format!(
+                    r#"{{ repo: "{}", commit: "{}", subdir: "{}" }}"#,
+                    repo, commit, subdir
+                )
+            }
+            Self::Local { path } => {
+                format!(r#"{{ path: "{}" }}"#, path)
+            }
+        }
+    }
+}
+
+impl PartialEq for ProvidedPackageSource {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Local { path: own_path }, Self::Local { path: other_path }) => {
+                is_same_file(own_path, other_path).unwrap_or(false)
+            }
+
+            (
+                Self::Git {
+                    repo: own_repo,
+                    commit: own_commit,
+                    subdir: own_subdir,
+                },
+                Self::Git {
+                    repo: other_repo,
+                    commit: other_commit,
+                    subdir: other_subdir,
+                },
+            ) => own_repo == other_repo && own_commit == other_commit && own_subdir == other_subdir,
+
+            (Self::Git { .. }, Self::Local { .. }) | (Self::Local { .. }, Self::Git { .. }) => {
+                false
+            }
+        }
+    }
+}
+
+/// Check that the packages have names that Hex would accept, so a mistyped
+/// name is reported before any requests are made for it.
+fn validate_package_names<'a>(names: impl Iterator<Item = &'a EcoString>) -> Result<()> {
+    let valid = regex::Regex::new("^[a-z][a-z0-9_]*$").expect("package name regex");
+    let names = names
+        .filter(|name| !valid.is_match(name))
+        .cloned()
+        .sorted()
+        .collect_vec();
+    if names.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidPackageNames { names })
+    }
+}
+
+/// Check that the version requirements can be parsed, so that a mistake is
+/// reported along with the dependency it was made in rather than part way
+/// through resolution.
+fn validate_version_requirements(dependencies: &HashMap<EcoString, Requirement>) -> Result<()> {
+    let problems = dependencies
+        .iter()
+        .sorted_by(|a, b| a.0.cmp(b.0))
+        .filter_map(|(name, requirement)| match requirement {
+            Requirement::Hex { version, .. } => {
+                version_requirement_problem(version).map(|problem| RequirementProblem {
+                    package: name.clone(),
+                    problem,
+                })
+            }
+            Requirement::Path { .. } | Requirement::Git { .. } => None,
+        })
+        .collect_vec();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidDependencyRequirements { problems })
+    }
+}
+
+fn resolve_versions<Telem: Telemetry>(
+    runtime: tokio::runtime::Handle,
+    mode: Mode,
+    project_paths: &ProjectPaths,
+    config: &PackageConfig,
+    manifest: Option<&Manifest>,
+    // These packages are not locked to their versions in the manifest.
+    unlocked: &[EcoString],
+    telemetry: &Telem,
+) -> Result<Manifest, Error> {
+    telemetry.resolving_package_versions();
+    let mut dependencies = config.dependencies_for(mode)?;
+    for (name, requirement) in
+        dependency::workspace_dependencies(&ProjectIO::new(), config, project_paths.root())?
+    {
+        let _ = dependencies.entry(name).or_insert(requirement);
+    }
+    validate_package_names(dependencies.keys().chain(config.patch.keys()))?;
+    validate_version_requirements(&dependencies)?;
+    let locked = locked_versions(config, manifest, unlocked)?;
+
+    // Packages which are provided directly instead of downloaded from hex
+    let provided_start = Instant::now();
+    let mut provided_packages = HashMap::new();
+    let git_sources = GitSources::from_environment().with_locked(manifest, unlocked);
+    // The version requires of the current project
+    let mut root_requirements = HashMap::new();
+
+    // Patched packages are provided by their local copy wherever they are
+    // required in the dependency tree.
+    for (name, patch) in &config.patch {
+        let _ = provide_local_package(
+            name.clone(),
+            &patch.path,
+            project_paths.root(),
+            project_paths,
+            &git_sources,
+            &mut provided_packages,
+            &mut vec![],
+        )?;
+    }
+
+    // Populate the provided_packages and root_requirements maps
+    for (name, requirement) in dependencies.clone().into_iter() {
+        let version = match requirement {
+            Requirement::Hex { version, .. } => version,
+            Requirement::Path { path } => provide_local_package(
+                name.clone(),
+                &path,
+                project_paths.root(),
+                project_paths,
+                &git_sources,
+                &mut provided_packages,
+                &mut vec![],
+            )?,
+            Requirement::Git { git, subdir } => provide_git_package(
+                name.clone(),
+                &git,
+                subdir.as_deref(),
+                &git_sources,
+                &mut provided_packages,
+                &mut vec![],
+            )?,
+        };
+        let _ = root_requirements.insert(name, version);
+    }
+    let unpatched = dependencies
+        .iter()
+        .filter(|(name, _)| !config.patch.contains_key(*name))
+        .map(|(name, requirement)| (name.clone(), requirement.clone()))
+        .collect();
+    check_for_source_conflicts(&unpatched, &provided_packages, project_paths.root())?;
+    telemetry
+        .resolution_phase_finished(ResolutionPhase::ProvidedPackages, provided_start.elapsed());
+
+    // Convert provided packages into hex packages for pub-grub resolve
+    let provided_hex_packages = provided_packages
+        .iter()
+        .map(|(name, package)| (name.clone(), package.to_hex_package(name)))
+        .collect();
+
+    let hex_repository = Rc::new(RefCell::new(hex_repository(config)?));
+    let snapshot = config
+        .hex
+        .snapshot
+        .as_ref()
+        .map(|snapshot| project_paths.root().join(snapshot));
+    let package_fetcher: Box<dyn dependency::PackageFetcher> = match &snapshot {
+        Some(snapshot) => {
+            PackageFetcher::snapshot(runtime.clone(), hex_repository.clone(), snapshot.clone())
+        }
+        None => PackageFetcher::boxed(
+            runtime.clone(),
+            hex_repository.clone(),
+            project_paths.build_hex_metadata_directory(),
+            Some(hex_metadata_ttl()),
+            ClientHeaders::for_config(&config.hex),
+        ),
+    };
+    let package_fetcher: Rc<dyn dependency::PackageFetcher> = Rc::from(package_fetcher);
+    let solver_start = Instant::now();
+    let mut resolved = dependency::resolve_versions(
+        Box::new(SharedPackageFetcher(package_fetcher.clone())),
+        provided_hex_packages,
+        config.name.clone(),
+        root_requirements.into_iter(),
+        dependency::ResolutionOptions {
+            locked,
+            overrides: config.overrides.clone(),
+            patched: config.patch.keys().cloned().collect(),
+            retired_releases: retired_releases(),
+            overrides_transform: None,
+        },
+    )?;
+    telemetry.resolution_phase_finished(ResolutionPhase::Solver, solver_start.elapsed());
+    // Resolving routed transitive dependencies to the repositories of the
+    // packages that require them, so they are looked up there too.
+    let hex_repository = hex_repository.borrow().clone();
+
+    // Convert the hex packages and local packages into manliest packages
+    let lookup_start = Instant::now();
+    // Packages still at their version in the previous manifest keep their
+    // entries, so only the packages that changed are looked up.
+    let unchanged = unchanged_packages(
+        &mut resolved,
+        manifest,
+        &provided_packages,
+        &hex_repository,
+        package_fetcher.as_ref(),
+    );
+    let mut looked_up = match snapshot {
+        Some(snapshot) => runtime.block_on(lookup_packages(
+            resolved,
+            &config.name,
+            &provided_packages,
+            &hex_repository,
+            &SnapshotHttpClient::new(snapshot),
+            download_concurrency_limit(),
+        )),
+        None => runtime.block_on(lookup_packages(
+            resolved,
+            &config.name,
+            &provided_packages,
+            &hex_repository,
+            &RetryingHttpClient::new(RequestKind::Metadata)
+                .with_headers(ClientHeaders::for_config(&config.hex)),
+            download_concurrency_limit(),
+        )),
+    }?;
+    looked_up.extend(unchanged);
+    telemetry.resolution_phase_finished(ResolutionPhase::ReleaseLookup, lookup_start.elapsed());
+    let mut manifest_packages = report_retired_packages(looked_up, telemetry);
+
+    // Local packages are recorded relative to the project root so that the
+    // manifest is the same wherever the project is on disc.
+    let root = fs::canonicalise(project_paths.root())?;
+    // Optional dependencies that were not included in the resolution are not
+    // requirements of the packages that declare them.
+    let resolved_names: HashSet<EcoString> =
+        manifest_packages.iter().map(|p| p.name.clone()).collect();
+    for package in manifest_packages.iter_mut() {
+        if let ManifestPackageSource::Local { path } = &mut package.source {
+            *path = make_relative(&root, path);
+        }
+        package
+            .requirements
+            .retain(|name| resolved_names.contains(name));
+        package.requirements.sort();
+    }
+
+    // The packages are looked up in no particular order, so they are sorted to
+    // keep the manifest the same each time it is resolved.
+    manifest_packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let manifest = Manifest {
+        packages: manifest_packages,
+        requirements: dependency::project_requirements(
+            &ProjectIO::new(),
+            config,
+            project_paths.root(),
+        )?,
+    };
+
+    Ok(manifest)
+}
+
+/// The versions in the manifest that the packages are locked to, except for
+/// the unlocked packages which are free to change.
+fn locked_versions(
+    config: &PackageConfig,
+    manifest: Option<&Manifest>,
+    unlocked: &[EcoString],
+) -> Result<HashMap<EcoString, Version>> {
+    if let Some(manifest) = manifest {
+        let unknown = unlocked
+            .iter()
+            .filter(|name| !manifest.packages.iter().any(|p| &p.name == *name))
+            .map(|name| name.to_string())
+            .collect_vec();
+        if !unknown.is_empty() {
+            return Err(Error::PackagesNotInManifest { packages: unknown });
+        }
+    }
+    let mut locked = config.locked(manifest)?;
+    locked.retain(|name, _| !unlocked.contains(name));
+    Ok(locked)
+}
+
+#[test]
+fn locked_versions_exclude_unlocked_packages() {
+    let package = |name: &str, version: Version| ManifestPackage {
+        name: name.into(),
+        version,
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
+        },
+    };
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("gleam_json".into(), Requirement::hex("~> 1.0")),
+        ("gleam_stdlib".into(), Requirement::hex("~> 0.34")),
+    ]
+    .into();
+    let manifest = Manifest {
+        requirements: config.all_dependencies().unwrap(),
+        packages: vec![
+            package("gleam_json", Version::new(1, 0, 0)),
+            package("gleam_stdlib", Version::new(0, 34, 0)),
+        ],
+    };
+
+    assert_eq!(
+        locked_versions(&config, Some(&manifest), &["gleam_json".into()]),
+        Ok([("gleam_stdlib".into(), Version::new(0, 34, 0))].into())
+    );
+    assert_eq!(
+        locked_versions(&config, Some(&manifest), &[])
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        locked_versions(&config, Some(&manifest), &["wibble".into()]),
+        Err(Error::PackagesNotInManifest {
+            packages: vec!["wibble".into()]
+        })
+    );
+}
+
+#[test]
+fn local_package_paths_are_independent_of_project_location() {
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    let resolve = |root: &Utf8Path| {
+        write(
+            root.join("app/gleam.toml"),
+            "name = \"app\"\n[dependencies]\nlocal = { path = \"../local\" }\n",
+        );
+        write(
+            root.join("local/gleam.toml"),
+            "name = \"local\"\nversion = \"1.0.0\"\n",
+        );
+        let paths = ProjectPaths::new(root.join("app"));
+        let config = crate::config::read(paths.root_config()).unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let manifest = resolve_versions(
+            runtime.handle().clone(),
+            Mode::Dev,
+            &paths,
+            &config,
+            None,
+            &[],
+            &gleam_core::build::NullTelemetry,
+        )
+        .unwrap();
+        (manifest.to_toml(paths.root()), manifest)
+    };
+
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    let (first_toml, first_manifest) = resolve(Utf8Path::from_path(first.path()).unwrap());
+    let (second_toml, second_manifest) = resolve(Utf8Path::from_path(second.path()).unwrap());
+
+    assert_eq!(first_toml, second_toml);
+    assert_eq!(first_manifest, second_manifest);
+    assert_eq!(
+        first_manifest.packages[0].source,
+        ManifestPackageSource::Local {
+            path: "../local".into()
+        }
+    );
+}
+
+#[test]
+fn patched_transitive_dependencies_are_provided_locally() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nlocal = { path = \"../local\" }\n\
+[patch]\nwibble = { path = \"../wibble\" }\n",
+    );
+    // The local package requires a version of wibble from Hex that the
+    // patched copy doesn't have.
+    write(
+        root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\n[dependencies]\nwibble = \"~> 2.0\"\n",
+    );
+    write(
+        root.join("wibble/gleam.toml"),
+        "name = \"wibble\"\nversion = \"0.1.0\"\n",
+    );
+
+    let paths = ProjectPaths::new(root.join("app"));
+    let config = crate::config::read(paths.root_config()).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let manifest = resolve_versions(
+        runtime.handle().clone(),
+        Mode::Dev,
+        &paths,
+        &config,
+        None,
+        &[],
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    let wibble = manifest
+        .packages
+        .iter()
+        .find(|package| package.name == "wibble")
+        .unwrap();
+    assert_eq!(wibble.version, Version::new(0, 1, 0));
+    assert!(matches!(
+        &wibble.source,
+        ManifestPackageSource::Local { path } if path.ends_with("wibble")
+    ));
+    assert!(
+        dependency::patches_applied(&ProjectIO::new(), &manifest, &config.patch, paths.root())
+            .unwrap()
+    );
+}
+
+#[test]
+fn resolve_versions_times_each_phase() {
+    #[derive(Debug, Default)]
+    struct PhaseTelemetry {
+        phases: std::sync::Mutex<Vec<ResolutionPhase>>,
+    }
+
+    impl gleam_core::build::TelemetryEventHandler for PhaseTelemetry {
+        fn handle(&self, event: gleam_core::build::TelemetryEvent) {
+            if let gleam_core::build::TelemetryEvent::ResolutionPhaseFinished { phase, .. } = event
+            {
+                self.phases.lock().unwrap().push(phase);
+            }
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    fs::mkdir(&root.join("app")).unwrap();
+    fs::mkdir(&root.join("local")).unwrap();
+    fs::write(
+        &root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\n",
+    )
+    .unwrap();
+    let paths = ProjectPaths::new(root.join("app"));
+    let config = PackageConfig {
+        dependencies: [("local".into(), Requirement::path("../local"))].into(),
+        ..Default::default()
+    };
+    let telemetry = PhaseTelemetry::default();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let _ = resolve_versions(
+        runtime.handle().clone(),
+        Mode::Dev,
+        &paths,
+        &config,
+        None,
+        &[],
+        &telemetry,
+    )
+    .unwrap();
+
+    assert_eq!(
+        *telemetry.phases.lock().unwrap(),
+        vec![
+            ResolutionPhase::ProvidedPackages,
+            ResolutionPhase::Solver,
+            ResolutionPhase::ReleaseLookup,
+        ]
+    );
+}
+
+/// The signed response of hex.pm for the exfmt package, checked against the
+/// checksum recorded in test/README.md so that a changed fixture is noticed.
+#[cfg(test)]
+fn signed_package_exfmt() -> &'static [u8] {
+    use sha2::Digest;
+    let response = include_bytes!("../test/package_exfmt");
+    assert_eq!(
+        base16::encode_lower(&sha2::Sha256::digest(response)),
+        "f5e61937c8836f3fc766f75e8c0f1bcdc446a58e24e223bfa7ee9acd1067e269"
+    );
+    response
+}
+
+#[test]
+fn resolve_versions_against_hex_snapshot() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).unwrap();
+    let paths = ProjectPaths::new(root.to_path_buf());
+    // A signed package response from hex.pm, and the release it resolves to
+    let snapshot = root.join("hex-snapshot");
+    fs::write_bytes(&snapshot.join("packages/exfmt"), signed_package_exfmt()).unwrap();
+    fs::write(
+        &snapshot.join("api/packages/exfmt/releases/0.5.0"),
+        r#"{"version": "0.5.0", "requirements": {}, "retirement_status": null,
+            "checksum": "0102", "meta": {"app": "exfmt", "build_tools": ["mix"]}}"#,
+    )
+    .unwrap();
+    let config: PackageConfig = toml::from_str(
+        r#"
+name = "app"
+
+[hex]
+snapshot = "hex-snapshot"
+
+[dependencies]
+exfmt = ">= 0.0.0"
+"#,
+    )
+    .unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let resolve = || {
+        resolve_versions(
+            runtime.handle().clone(),
+            Mode::Dev,
+            &paths,
+            &config,
+            None,
+            &[],
+            &gleam_core::build::NullTelemetry,
+        )
+    };
+
+    let manifest = resolve().unwrap();
+    assert_eq!(
+        manifest.packages,
+        vec![ManifestPackage {
+            name: "exfmt".into(),
+            version: Version::new(0, 5, 0),
+            build_tools: vec!["mix".into()],
+            otp_app: Some("exfmt".into()),
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![1, 2]),
+                repository: None,
+            },
+        }]
+    );
+    // Nothing is fetched from Hex or stored, so each resolution is the same
+    assert_eq!(resolve().unwrap(), manifest);
+    assert!(!paths.build_hex_metadata_directory().exists());
+
+    // Releases missing from the snapshot are not looked up on Hex
+    fs::delete_file(&snapshot.join("api/packages/exfmt/releases/0.5.0")).unwrap();
+    assert_eq!(
+        resolve().unwrap_err(),
+        Error::NotInHexSnapshot {
+            snapshot,
+            path: "api/packages/exfmt/releases/0.5.0".into(),
+        }
+    );
+}
+
+#[test]
+fn resolve_versions_keeps_unchanged_manifest_packages() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).unwrap();
+    let paths = ProjectPaths::new(root.to_path_buf());
+    // The snapshot has no releases, so any release looked up is an error
+    fs::write_bytes(
+        &root.join("hex-snapshot/packages/exfmt"),
+        signed_package_exfmt(),
+    )
+    .unwrap();
+    fs::write(
+        &root.join("local/gleam.toml"),
+        "name = \"local\"\nversion = \"1.0.0\"\n",
+    )
+    .unwrap();
+    let exfmt = ManifestPackage {
+        name: "exfmt".into(),
+        version: Version::new(0, 5, 0),
+        build_tools: vec!["mix".into()],
+        otp_app: Some("exfmt".into()),
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1, 2]),
+            repository: None,
+        },
+    };
+    let previous = Manifest {
+        requirements: [("exfmt".into(), Requirement::hex(">= 0.0.0"))].into(),
+        packages: vec![exfmt.clone()],
+    };
+    // A dependency is added to the project after the manifest was written
+    let config: PackageConfig = toml::from_str(
+        r#"
+name = "app"
+
+[hex]
+snapshot = "hex-snapshot"
+
+[dependencies]
+exfmt = ">= 0.0.0"
+local = { path = "local" }
+"#,
+    )
+    .unwrap();
+
+    let manifest = resolve_versions(
+        tokio::runtime::Runtime::new().unwrap().handle().clone(),
+        Mode::Dev,
+        &paths,
+        &config,
+        Some(&previous),
+        &[],
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    assert_eq!(
+        manifest
+            .packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect_vec(),
+        ["exfmt", "local"]
+    );
+    assert_eq!(manifest.packages[0], exfmt);
+    let entry = |manifest: &Manifest| {
+        manifest
+            .to_toml(root)
+            .lines()
+            .find(|line| line.contains("name = \"exfmt\""))
+            .map(String::from)
+    };
+    assert_eq!(entry(&manifest), entry(&previous));
+}
+
+#[test]
+fn resolve_versions_rejects_invalid_package_names() {
+    let tmp = tempfile::tempdir().unwrap();
+    let paths = ProjectPaths::new(Utf8Path::from_path(tmp.path()).unwrap().to_path_buf());
+    let config = PackageConfig {
+        dependencies: [
+            ("Wibble".into(), Requirement::hex("~> 1.0")),
+            ("wobble/wubble".into(), Requirement::hex("~> 1.0")),
+            ("valid_name".into(), Requirement::hex("~> 1.0")),
+        ]
+        .into(),
+        ..Default::default()
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let error = resolve_versions(
+        runtime.handle().clone(),
+        Mode::Dev,
+        &paths,
+        &config,
+        None,
+        &[],
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error,
+        Error::InvalidPackageNames {
+            names: vec!["Wibble".into(), "wobble/wubble".into()]
+        }
+    );
+}
+
+#[test]
+fn resolve_versions_rejects_invalid_version_requirements() {
+    let tmp = tempfile::tempdir().unwrap();
+    let paths = ProjectPaths::new(Utf8Path::from_path(tmp.path()).unwrap().to_path_buf());
+    let config: PackageConfig = toml::from_str(
+        r#"
+name = "app"
+
+[dependencies]
+wibble = { version = "not-a-version" }
+wobble = "~> 1.0"
+"#,
+    )
+    .unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let error = resolve_versions(
+        runtime.handle().clone(),
+        Mode::Dev,
+        &paths,
+        &config,
+        None,
+        &[],
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap_err();
+
+    let Error::InvalidDependencyRequirements { problems } = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(
+        problems
+            .iter()
+            .map(|problem| problem.package.as_str())
+            .collect_vec(),
+        vec!["wibble"]
+    );
+    assert!(problems[0].problem.contains("`not-a-version`"));
+    assert!(problems[0].problem.contains("`~> 1.0`"));
+}
+
+#[test]
+fn workspace_member_globs_match_each_package() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("gleam.toml"),
+        "name = \"root\"\n[workspace]\nmembers = [\"packages/*\"]\n",
+    );
+    write(
+        root.join("packages/one/gleam.toml"),
+        "name = \"one\"\nversion = \"1.0.0\"\n",
+    );
+    write(
+        root.join("packages/two/gleam.toml"),
+        "name = \"two\"\nversion = \"2.0.0\"\n",
+    );
+    // Neither a package nor directly within the matched directory
+    std::fs::create_dir_all(root.join("packages/notes")).unwrap();
+    write(
+        root.join("packages/two/nested/gleam.toml"),
+        "name = \"nested\"\nversion = \"1.0.0\"\n",
+    );
+    // Directories deeper than the pattern are not searched, so one that
+    // cannot be read doesn't stop the members from being found
+    #[cfg(unix)]
+    let unreadable = {
+        use std::os::unix::fs::PermissionsExt;
+        let unreadable = root.join("packages/two/nested/private");
+        std::fs::create_dir_all(&unreadable).unwrap();
+        std::fs::set_permissions(&unreadable, std::fs::Permissions::from_mode(0o000)).unwrap();
+        unreadable
+    };
+
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let config = crate::config::read(paths.root_config()).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let resolve = |config: &PackageConfig| {
+        resolve_versions(
+            runtime.handle().clone(),
+            Mode::Dev,
+            &paths,
+            config,
+            None,
+            &[],
+            &gleam_core::build::NullTelemetry,
+        )
+    };
+    let manifest = resolve(&config).unwrap();
+    assert_eq!(
+        manifest
+            .packages
+            .iter()
+            .map(|package| (package.name.as_str(), &package.source))
+            .collect_vec(),
+        vec![
+            (
+                "one",
+                &ManifestPackageSource::Local {
+                    path: "packages/one".into()
+                }
+            ),
+            (
+                "two",
+                &ManifestPackageSource::Local {
+                    path: "packages/two".into()
+                }
+            ),
+        ]
+    );
+
+    // Directories that cannot be searched are reported. Superusers can read
+    // them regardless of their permissions.
+    #[cfg(unix)]
+    if std::fs::read_dir(&unreadable).is_err() {
+        use std::os::unix::fs::PermissionsExt;
+        let searched = root.join("packages");
+        let permissions = |mode| std::fs::Permissions::from_mode(mode);
+        std::fs::set_permissions(&searched, permissions(0o000)).unwrap();
+        let error = resolve(&config).unwrap_err();
+        std::fs::set_permissions(&searched, permissions(0o755)).unwrap();
+        std::fs::set_permissions(&unreadable, permissions(0o755)).unwrap();
+        assert!(
+            matches!(&error, Error::FileIo { path, .. } if path == &searched),
+            "{error:?}"
+        );
+    }
+
+    // Two matched directories with the same package name are reported
+    write(
+        root.join("packages/three/gleam.toml"),
+        "name = \"one\"\nversion = \"3.0.0\"\n",
+    );
+    assert_eq!(
+        resolve(&config).unwrap_err(),
+        Error::DependencyResolutionFailed(ResolutionFailure::ConflictingSources {
+            package: "one".into(),
+            source_1: r#"{ path = "packages/one" }"#.into(),
+            source_2: r#"{ path = "packages/three" }"#.into(),
+        })
+    );
+}
+
+#[test]
+fn workspace_members_are_resolved_together() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("gleam.toml"),
+        "name = \"root\"\n[workspace]\nmembers = [\"packages/one\", \"packages/two\"]\n",
+    );
+    write(
+        root.join("packages/one/gleam.toml"),
+        "name = \"one\"\nversion = \"1.0.0\"\n[dependencies]\n\
+two = { path = \"../two\" }\nshared = { path = \"../shared\" }\n",
+    );
+    write(
+        root.join("packages/two/gleam.toml"),
+        "name = \"two\"\nversion = \"2.0.0\"\n[dependencies]\n\
+shared = { path = \"../../packages/shared\" }\n",
+    );
+    write(
+        root.join("packages/shared/gleam.toml"),
+        "name = \"shared\"\nversion = \"0.3.0\"\n",
+    );
+
+    let paths = ProjectPaths::new(root.to_path_buf());
+    let config = crate::config::read(paths.root_config()).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let manifest = resolve_versions(
+        runtime.handle().clone(),
+        Mode::Dev,
+        &paths,
+        &config,
+        None,
+        &[],
+        &gleam_core::build::NullTelemetry,
+    )
+    .unwrap();
+
+    let packages = manifest
+        .packages
+        .iter()
+        .map(|package| (package.name.as_str(), package.version.to_string()))
+        .collect_vec();
+    assert_eq!(
+        packages,
+        vec![
+            ("one", "1.0.0".into()),
+            ("shared", "0.3.0".into()),
+            ("two", "2.0.0".into())
+        ]
+    );
+    let shared = manifest.packages.get(1).unwrap();
+    assert_eq!(
+        shared.source,
+        ManifestPackageSource::Local {
+            path: "packages/shared".into()
+        }
+    );
+    assert_eq!(
+        manifest.requirements,
+        [
+            ("one".into(), Requirement::path("packages/one")),
+            ("two".into(), Requirement::path("packages/two")),
+        ]
+        .into()
+    );
+}
 
-    if should_resolve {
-        let manifest = resolve_versions(runtime, mode, paths, config, None, telemetry)?;
-        return Ok((true, manifest));
+#[test]
+fn resolved_manifest_is_deterministic() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let names = ["one", "two", "three", "four", "five", "six"];
+    for name in names {
+        std::fs::create_dir_all(root.join(name)).unwrap();
+        std::fs::write(
+            root.join(name).join("gleam.toml"),
+            format!("name = \"{name}\"\nversion = \"1.0.0\"\n"),
+        )
+        .unwrap();
     }
+    let dependencies = names
+        .iter()
+        .map(|name| format!("{name} = {{ path = \"../{name}\" }}\n"))
+        .join("");
+    std::fs::create_dir_all(root.join("app")).unwrap();
+    std::fs::write(
+        root.join("app/gleam.toml"),
+        format!("name = \"app\"\n[dependencies]\n{dependencies}"),
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("one/gleam.toml"),
+        "name = \"one\"\nversion = \"1.0.0\"\n[dependencies]\nthree = { path = \"../three\" }\ntwo = { path = \"../two\" }\n",
+    )
+    .unwrap();
 
-    let manifest = read_manifest_from_disc(paths)?;
+    let paths = ProjectPaths::new(root.join("app"));
+    let config = crate::config::read(paths.root_config()).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let resolve = || {
+        resolve_versions(
+            runtime.handle().clone(),
+            Mode::Dev,
+            &paths,
+            &config,
+            None,
+            &[],
+            &gleam_core::build::NullTelemetry,
+        )
+        .unwrap()
+    };
 
-    // If the config has unchanged since the manifest was written then it is up
-    // to date so we can return it unmodified.
-    if is_same_requirements(
-        &manifest.requirements,
-        &config.all_dependencies()?,
-        paths.root(),
-    )? {
-        tracing::debug!("manifest_up_to_date");
-        Ok((false, manifest))
-    } else {
-        tracing::debug!("manifest_outdated");
-        let manifest = resolve_versions(runtime, mode, paths, config, Some(&manifest), telemetry)?;
-        Ok((true, manifest))
-    }
+    let first = resolve();
+    let second = resolve();
+    assert_eq!(first, second);
+    assert_eq!(first.to_toml(paths.root()), second.to_toml(paths.root()));
+    assert_eq!(
+        first.packages.iter().map(|p| p.name.as_str()).collect_vec(),
+        vec!["five", "four", "one", "six", "three", "two"]
+    );
+    assert_eq!(first.packages[2].requirements, vec!["three", "two"]);
 }
 
-fn is_same_requirements(
-    requirements1: &HashMap<EcoString, Requirement>,
-    requirements2: &HashMap<EcoString, Requirement>,
+/// Path and git packages that conflict with each other are caught as they are
+/// provided, but a package the project requires from Hex could also be provided
+/// by a path or git dependency of another package.
+fn check_for_source_conflicts(
+    requirements: &HashMap<EcoString, Requirement>,
+    provided: &HashMap<EcoString, ProvidedPackage>,
     root_path: &Utf8Path,
-) -> Result<bool> {
-    if requirements1.len() != requirements2.len() {
-        return Ok(false);
+) -> Result<()> {
+    let conflict = requirements
+        .iter()
+        .filter(|(_, requirement)| matches!(requirement, Requirement::Hex { .. }))
+        .filter_map(|(name, requirement)| Some((name, requirement, provided.get(name)?)))
+        .sorted_by(|a, b| a.0.cmp(b.0))
+        .next();
+    match conflict {
+        Some((name, requirement, package)) => Err(Error::DependencyResolutionFailed(
+            ResolutionFailure::ConflictingSources {
+                package: name.to_string(),
+                source_1: requirement.to_toml(root_path),
+                source_2: package.source.to_toml(),
+            },
+        )),
+        None => Ok(()),
     }
+}
+
+#[test]
+fn hex_requirement_conflicts_with_provided_package() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    std::fs::create_dir_all(root.join("wibble")).unwrap();
+    std::fs::write(
+        root.join("wibble/gleam.toml"),
+        "name = \"wibble\"\nversion = \"1.0.0\"\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("wobble")).unwrap();
+    std::fs::write(
+        root.join("wobble/gleam.toml"),
+        "name = \"wobble\"\n[dependencies]\nwibble = { path = \"../wibble\" }\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("app")).unwrap();
+    let project_paths = ProjectPaths::new(root.join("app"));
+    let mut provided = HashMap::new();
+    let _ = provide_local_package(
+        "wobble".into(),
+        Utf8Path::new("../wobble"),
+        &root.join("app"),
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec![],
+    )
+    .unwrap();
 
-    for (key, requirement1) in requirements1 {
-        if !same_requirements(requirement1, requirements2.get(key), root_path)? {
-            return Ok(false);
+    let requirements: HashMap<EcoString, Requirement> = [
+        ("wibble".into(), Requirement::hex("~> 1.0")),
+        ("wobble".into(), Requirement::path("../wobble")),
+    ]
+    .into();
+    let result = check_for_source_conflicts(&requirements, &provided, &root.join("app"));
+    match result {
+        Err(Error::DependencyResolutionFailed(ResolutionFailure::ConflictingSources {
+            package,
+            source_1,
+            source_2,
+        })) => {
+            assert_eq!(package, "wibble");
+            assert_eq!(source_1, r#"{ version = "~> 1.0" }"#);
+            assert!(source_2.contains("wibble"), "{source_2}");
         }
+        other => panic!("unexpected result {other:?}"),
     }
 
-    Ok(true)
+    let requirements: HashMap<EcoString, Requirement> =
+        [("wobble".into(), Requirement::path("../wobble"))].into();
+    assert_eq!(
+        check_for_source_conflicts(&requirements, &provided, &root.join("app")),
+        Ok(())
+    );
 }
 
-fn same_requirements(
-    requirement1: &Requirement,
-    requirement2: Option<&Requirement>,
-    root_path: &Utf8Path,
-) -> Result<bool> {
-    let (left, right) = match (requirement1, requirement2) {
-        (Requirement::Path { path: path1 }, Some(Requirement::Path { path: path2 })) => {
-            (path1, path2)
-        }
-        (_, Some(requirement2)) => return Ok(requirement1 == requirement2),
-        (_, None) => return Ok(false),
-    };
-
-    let left = if left.is_absolute() {
-        left.to_owned()
-    } else {
-        fs::canonicalise(&root_path.join(left))?
+#[test]
+fn local_packages_sharing_a_dependency_resolve_deterministically() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
     };
+    let names = ["one", "two", "three", "four", "five"];
+    let mut dependencies = String::new();
+    for name in names {
+        write(
+            root.join(format!("{name}/gleam.toml")),
+            &format!(
+                "name = \"{name}\"\nversion = \"1.0.0\"\n[dependencies]\ncommon = {{ path = \"../common\" }}\n"
+            ),
+        );
+        dependencies.push_str(&format!("{name} = {{ path = \"../{name}\" }}\n"));
+    }
+    write(
+        root.join("common/gleam.toml"),
+        "name = \"common\"\nversion = \"2.0.0\"\n",
+    );
+    write(
+        root.join("app/gleam.toml"),
+        &format!("name = \"app\"\n[dependencies]\n{dependencies}"),
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+    let config = crate::config::read(paths.root_config()).unwrap();
 
-    let right = if right.is_absolute() {
-        right.to_owned()
-    } else {
-        fs::canonicalise(&root_path.join(right))?
+    // The manifest is the same every time
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let resolve = || {
+        resolve_versions(
+            runtime.handle().clone(),
+            Mode::Dev,
+            &paths,
+            &config,
+            None,
+            &[],
+            &gleam_core::build::NullTelemetry,
+        )
+        .unwrap()
     };
-
-    Ok(left == right)
+    let manifest = resolve();
+    assert_eq!(
+        manifest
+            .packages
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect_vec(),
+        vec!["common", "five", "four", "one", "three", "two"]
+    );
+    for _ in 0..3 {
+        assert_eq!(resolve(), manifest);
+    }
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
-struct ProvidedPackage {
-    version: Version,
-    source: ProvidedPackageSource,
-    requirements: HashMap<EcoString, hexpm::version::Range>,
+/// Provide a package from a local project
+fn provide_local_package(
+    package_name: EcoString,
+    package_path: &Utf8Path,
+    parent_path: &Utf8Path,
+    project_paths: &ProjectPaths,
+    git_sources: &GitSources,
+    provided: &mut HashMap<EcoString, ProvidedPackage>,
+    parents: &mut Vec<EcoString>,
+) -> Result<hexpm::version::Range> {
+    // Absolute paths are canonicalised too so that a package reached through
+    // different paths is recognised as the same package.
+    let package_path = fs::canonicalise(&parent_path.join(package_path))?;
+    let package_source = ProvidedPackageSource::Local {
+        path: package_path.clone(),
+    };
+    provide_package(
+        package_name,
+        package_path,
+        package_source,
+        project_paths,
+        git_sources,
+        provided,
+        parents,
+    )
 }
 
-#[derive(Clone, Eq, Debug)]
-enum ProvidedPackageSource {
-    Git { repo: EcoString, commit: EcoString },
-    Local { path: Utf8PathBuf },
+/// How git dependencies are fetched while versions are resolved.
+struct GitSources {
+    /// The cache that repositories are fetched into, shared by every project.
+    cache: Utf8PathBuf,
+    ssh_command: String,
+    /// The sources of the git packages in the manifest, which stay at their
+    /// locked commits while their requirements are unchanged.
+    locked: HashMap<EcoString, ManifestPackageSource>,
 }
 
-impl ProvidedPackage {
-    fn to_hex_package(&self, name: &EcoString) -> hexpm::Package {
-        let requirements = self
-            .requirements
-            .iter()
-            .map(|(name, version)| {
-                (
-                    name.as_str().into(),
-                    hexpm::Dependency {
-                        requirement: version.clone(),
-                        optional: false,
-                        app: None,
-                        repository: None,
-                    },
-                )
-            })
-            .collect();
-        let release = hexpm::Release {
-            version: self.version.clone(),
-            requirements,
-            retirement_status: None,
-            outer_checksum: vec![],
-            meta: (),
-        };
-        hexpm::Package {
-            name: name.as_str().into(),
-            repository: "local".into(),
-            releases: vec![release],
+impl GitSources {
+    fn new(cache: Utf8PathBuf, ssh_command: String) -> Self {
+        Self {
+            cache,
+            ssh_command,
+            locked: HashMap::new(),
         }
     }
 
-    fn to_manifest_package(&self, name: &str) -> ManifestPackage {
-        let mut package = ManifestPackage {
-            name: name.into(),
-            version: self.version.clone(),
-            otp_app: None, // Note, this will probably need to be set to something eventually
-            build_tools: vec!["gleam".into()],
-            requirements: self.requirements.keys().cloned().collect(),
-            source: self.source.to_manifest_package_source(),
-        };
-        package.requirements.sort();
-        package
+    fn from_environment() -> Self {
+        Self::new(
+            paths::global_git_cache(),
+            git_ssh_command_from_environment(),
+        )
     }
-}
 
-impl ProvidedPackageSource {
-    fn to_manifest_package_source(&self) -> ManifestPackageSource {
-        match self {
-            Self::Git { repo, commit } => ManifestPackageSource::Git {
-                repo: repo.clone(),
-                commit: commit.clone(),
-            },
-            Self::Local { path } => ManifestPackageSource::Local { path: path.clone() },
-        }
+    /// Keep the git packages in the manifest at their locked commits, except
+    /// for the unlocked packages which are free to move to a newer commit.
+    fn with_locked(mut self, manifest: Option<&Manifest>, unlocked: &[EcoString]) -> Self {
+        self.locked = manifest
+            .into_iter()
+            .flat_map(|manifest| &manifest.packages)
+            .filter(|package| package.is_git() && !unlocked.contains(&package.name))
+            .map(|package| (package.name.clone(), package.source.clone()))
+            .collect();
+        self
     }
 
-    fn to_toml(&self) -> String {
-        match self {
-            Self::Git { repo, commit } => {
-                format!(r#"{{ repo: "{}", commit: "{}" }}"#, repo, commit)
-            }
-            Self::Local { path } => {
-                format!(r#"{{ path: "{}" }}"#, path)
-            }
+    /// The commit the package is locked to, if it is still required from the
+    /// same repository and subdirectory.
+    fn locked_commit(
+        &self,
+        package_name: &str,
+        repo: &str,
+        subdir: Option<&Utf8Path>,
+    ) -> Option<&EcoString> {
+        match self.locked.get(package_name) {
+            Some(ManifestPackageSource::Git {
+                repo: locked_repo,
+                commit,
+                subdir: locked_subdir,
+            }) if locked_repo == repo && locked_subdir.as_deref() == subdir => Some(commit),
+            Some(_) | None => None,
         }
     }
 }
 
-impl PartialEq for ProvidedPackageSource {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Local { path: own_path }, Self::Local { path: other_path }) => {
-                is_same_file(own_path, other_path).unwrap_or(false)
-            }
+/// Adds a gleam project from a git repository to the list of "provided
+/// packages", using the commit it is locked to in the manifest or, if there is
+/// none, the commit at the head of the repository's default branch. The
+/// project is read from the given subdirectory of the repository, if any.
+fn provide_git_package(
+    package_name: EcoString,
+    repo: &str,
+    subdir: Option<&Utf8Path>,
+    git_sources: &GitSources,
+    provided: &mut HashMap<EcoString, ProvidedPackage>,
+    parents: &mut Vec<EcoString>,
+) -> Result<hexpm::version::Range> {
+    // Return early if a package cycle is detected
+    check_for_cycle(&package_name, parents)?;
 
-            (
-                Self::Git {
-                    repo: own_repo,
-                    commit: own_commit,
-                },
-                Self::Git {
-                    repo: other_repo,
-                    commit: other_commit,
-                },
-            ) => own_repo == other_repo && own_commit == other_commit,
+    let repo: EcoString = repo.into();
+    let cache = git_cache_repository(&git_sources.cache, &repo);
+    let env = git_environment(&repo, &git_sources.ssh_command);
+    let run_git = |args: &[&str], commit: &EcoString| -> Result<String> {
+        let output = fs::git(args, &cache, &env)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            let error = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            Err(git_failure(&package_name, &repo, commit, error))
+        }
+    };
 
-            (Self::Git { .. }, Self::Local { .. }) | (Self::Local { .. }, Self::Git { .. }) => {
-                false
+    // The commit is fetched into the shared cache, where it is kept under a ref
+    // so that it can be checked out once resolved. Other builds may be using
+    // the same cache, so it is locked while in use.
+    let guard = BuildLock::new_git_cache(&cache)?.lock(&NullTelemetry)?;
+    let head = EcoString::from("HEAD");
+    if !cache.join("HEAD").is_file() {
+        let _ = run_git(&["init", "--bare", "--quiet"], &head)?;
+    }
+    let commit = match git_sources.locked_commit(&package_name, &repo, subdir) {
+        // The locked commit is kept so that resolving again doesn't move the
+        // package, and the remote is only contacted if the cache lacks it.
+        Some(commit) => {
+            if !is_git_commit_cached(&cache, commit) {
+                tracing::info!(package=%package_name, repo=%repo, commit=%commit, "fetching_locked_git_package");
+                let refspec = format!("{commit}:refs/gleam/{commit}");
+                let _ = run_git(
+                    &["fetch", "--quiet", "--depth", "1", "--", &repo, &refspec],
+                    commit,
+                )
+                .or_else(|_| run_git(&["fetch", "--quiet", "--", &repo, &refspec], commit))?;
             }
+            commit.clone()
         }
-    }
-}
+        None => {
+            tracing::info!(package=%package_name, repo=%repo, "fetching_git_package_head");
+            let _ = run_git(
+                &["fetch", "--quiet", "--depth", "1", "--", &repo, "HEAD"],
+                &head,
+            )?;
+            let commit: EcoString = run_git(&["rev-parse", "FETCH_HEAD"], &head)?.into();
+            let _ = run_git(
+                &["update-ref", &format!("refs/gleam/{commit}"), &commit],
+                &commit,
+            )?;
+            commit
+        }
+    };
 
-fn resolve_versions<Telem: Telemetry>(
-    runtime: tokio::runtime::Handle,
-    mode: Mode,
-    project_paths: &ProjectPaths,
-    config: &PackageConfig,
-    manifest: Option<&Manifest>,
-    telemetry: &Telem,
-) -> Result<Manifest, Error> {
-    telemetry.resolving_package_versions();
-    let dependencies = config.dependencies_for(mode)?;
-    let locked = config.locked(manifest)?;
+    let package_source = ProvidedPackageSource::Git {
+        repo: repo.clone(),
+        commit: commit.clone(),
+        subdir: subdir.map(Utf8Path::to_path_buf),
+    };
+    match provided.get(&package_name) {
+        Some(package) if package.source == package_source => {
+            return Ok(hexpm::version::Range::new(format!(
+                "== {}",
+                &package.version
+            )));
+        }
+        Some(package) => {
+            return Err(Error::DependencyResolutionFailed(
+                ResolutionFailure::ConflictingSources {
+                    package: package_name.into(),
+                    source_1: package_source.to_toml(),
+                    source_2: package.source.to_toml(),
+                },
+            ));
+        }
+        None => (),
+    }
 
-    // Packages which are provided directly instead of downloaded from hex
-    let mut provided_packages = HashMap::new();
-    // The version requires of the current project
-    let mut root_requirements = HashMap::new();
+    // The configuration is read from the commit without checking it out
+    let config_path = match subdir {
+        Some(subdir) => subdir.join("gleam.toml"),
+        None => Utf8PathBuf::from("gleam.toml"),
+    };
+    let object = format!("{commit}:{}", config_path.as_str().replace('\\', "/"));
+    let toml =
+        run_git(&["show", &object], &commit).map_err(|_| Error::GitDependencyCheckoutFailed {
+            package: package_name.clone(),
+            repo: repo.clone(),
+            commit: commit.clone(),
+            error: format!("The repository has no {config_path} file"),
+        })?;
+    // The dependencies may come from the same repository, so the cache is
+    // unlocked before they are walked.
+    drop(guard);
+    let config: PackageConfig = toml::from_str(&toml).map_err(|e| Error::FileIo {
+        action: FileIoAction::Parse,
+        kind: FileKind::File,
+        path: config_path.clone(),
+        err: Some(e.to_string()),
+    })?;
+    if config.name != package_name {
+        return Err(Error::DependencyResolutionFailed(
+            ResolutionFailure::PackageNameMismatch {
+                expected: package_name.into(),
+                path: config_path,
+                found: config.name.into(),
+            },
+        ));
+    }
 
-    // Populate the provided_packages and root_requirements maps
-    for (name, requirement) in dependencies.into_iter() {
+    // Walk the requirements of the package
+    let mut requirements = HashMap::new();
+    parents.push(package_name.clone());
+    for (name, requirement) in config.dependencies.into_iter() {
         let version = match requirement {
-            Requirement::Hex { version } => version,
-            Requirement::Path { path } => provide_local_package(
+            Requirement::Hex { version, .. } => version,
+            Requirement::Git { git, subdir } => provide_git_package(
                 name.clone(),
-                &path,
-                project_paths.root(),
-                project_paths,
-                &mut provided_packages,
-                &mut vec![],
+                &git,
+                subdir.as_deref(),
+                git_sources,
+                provided,
+                parents,
             )?,
-            Requirement::Git { git } => {
-                provide_git_package(name.clone(), &git, project_paths, &mut provided_packages)?
+            Requirement::Path { path } => {
+                return Err(Error::DependencyResolutionFailed(
+                    ResolutionFailure::Failure(format!(
+                        "The git package `{package_name}` depends on `{name}` by the \
+path `{path}`, but git packages can only depend on Hex and git packages."
+                    )),
+                ));
             }
         };
-        let _ = root_requirements.insert(name, version);
+        let _ = requirements.insert(name, version);
     }
+    let _ = parents.pop();
 
-    // Convert provided packages into hex packages for pub-grub resolve
-    let provided_hex_packages = provided_packages
-        .iter()
-        .map(|(name, package)| (name.clone(), package.to_hex_package(name)))
-        .collect();
-
-    let resolved = dependency::resolve_versions(
-        PackageFetcher::boxed(runtime.clone()),
-        provided_hex_packages,
-        config.name.clone(),
-        root_requirements.into_iter(),
-        &locked,
-    )?;
-
-    // Convert the hex packages and local packages into manliest packages
-    let manifest_packages = runtime.block_on(future::try_join_all(
-        resolved
-            .into_iter()
-            .map(|(name, version)| lookup_package(name, version, &provided_packages)),
-    ))?;
+    let version = hexpm::version::Range::new(format!("== {}", &config.version));
+    let _ = provided.insert(
+        config.name,
+        ProvidedPackage {
+            version: config.version,
+            otp_app: config.erlang.application_name,
+            source: package_source,
+            requirements,
+        },
+    );
+    Ok(version)
+}
 
-    let manifest = Manifest {
-        packages: manifest_packages,
-        requirements: config.all_dependencies()?,
+#[test]
+fn provide_git_package_from_subdirectory() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let git = |args: &[&str]| -> String {
+        let output = fs::git(args, &repo, &[]).unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().into()
     };
+    fs::mkdir(&repo.join("packages/foo/src")).unwrap();
+    fs::write(
+        &repo.join("packages/foo/gleam.toml"),
+        "name = \"foo\"\nversion = \"1.2.0\"\n[dependencies]\ngleam_stdlib = \"~> 0.34\"\n",
+    )
+    .unwrap();
+    fs::write(
+        &repo.join("packages/foo/src/foo.gleam"),
+        "pub fn main() { 1 }",
+    )
+    .unwrap();
+    fs::write(&repo.join("README.md"), "A monorepo").unwrap();
+    let _ = git(&["init", "--quiet"]);
+    let _ = git(&["add", "."]);
+    let _ = git(&[
+        "-c",
+        "user.name=Gleam",
+        "-c",
+        "user.email=gleam@example.com",
+        "commit",
+        "--quiet",
+        "--message",
+        "Initial commit",
+    ]);
+    let commit: EcoString = git(&["rev-parse", "HEAD"]).into();
+    let cache = root.join("cache");
 
-    Ok(manifest)
+    let mut provided = HashMap::new();
+    let version = provide_git_package(
+        "foo".into(),
+        repo.as_str(),
+        Some(Utf8Path::new("packages/foo")),
+        &GitSources::new(cache.clone(), "ssh".into()),
+        &mut provided,
+        &mut vec![],
+    )
+    .unwrap();
+
+    assert_eq!(version, hexpm::version::Range::new("== 1.2.0".into()));
+    let package = &provided["foo"];
+    assert_eq!(
+        package.source,
+        ProvidedPackageSource::Git {
+            repo: repo.as_str().into(),
+            commit: commit.clone(),
+            subdir: Some("packages/foo".into()),
+        }
+    );
+    assert_eq!(
+        package.requirements,
+        [(
+            "gleam_stdlib".into(),
+            hexpm::version::Range::new("~> 0.34".into())
+        )]
+        .into()
+    );
+
+    // Only the subdirectory is exported into the project's packages
+    let paths = ProjectPaths::new(root.join("project"));
+    checkout_git_package_with(&paths, &package.to_manifest_package("foo"), &cache, "ssh").unwrap();
+    let destination = paths.build_packages_package("foo");
+    assert!(destination.join("gleam.toml").is_file());
+    assert!(destination.join("src/foo.gleam").is_file());
+    assert!(!destination.join("README.md").exists());
+    assert!(has_git_checkout(&destination, &commit));
 }
 
-/// Provide a package from a local project
-fn provide_local_package(
-    package_name: EcoString,
-    package_path: &Utf8Path,
-    parent_path: &Utf8Path,
-    project_paths: &ProjectPaths,
-    provided: &mut HashMap<EcoString, ProvidedPackage>,
-    parents: &mut Vec<EcoString>,
-) -> Result<hexpm::version::Range> {
-    let package_path = if package_path.is_absolute() {
-        package_path.to_path_buf()
-    } else {
-        fs::canonicalise(&parent_path.join(package_path))?
+#[test]
+fn provide_git_package_keeps_the_locked_commit() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let repo = root.join("repo");
+    let git = |args: &[&str]| -> String {
+        let output = fs::git(args, &repo, &[]).unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().into()
     };
-    let package_source = ProvidedPackageSource::Local {
-        path: package_path.clone(),
+    let commit_version = |version: &str| -> EcoString {
+        fs::write(
+            &repo.join("gleam.toml"),
+            &format!("name = \"foo\"\nversion = \"{version}\"\n"),
+        )
+        .unwrap();
+        let _ = git(&["add", "."]);
+        let _ = git(&[
+            "-c",
+            "user.name=Gleam",
+            "-c",
+            "user.email=gleam@example.com",
+            "commit",
+            "--quiet",
+            "--message",
+            version,
+        ]);
+        git(&["rev-parse", "HEAD"]).into()
     };
-    provide_package(
-        package_name,
-        package_path,
-        package_source,
-        project_paths,
-        provided,
-        parents,
-    )
+    fs::mkdir(&repo).unwrap();
+    let _ = git(&["init", "--quiet"]);
+    let first = commit_version("1.0.0");
+    let mut git_sources = GitSources::new(root.join("cache"), "ssh".into());
+    let provide = |git_sources: &GitSources| {
+        let mut provided = HashMap::new();
+        let version = provide_git_package(
+            "foo".into(),
+            repo.as_str(),
+            None,
+            git_sources,
+            &mut provided,
+            &mut vec![],
+        )
+        .unwrap();
+        (version, provided.remove("foo").unwrap().source)
+    };
+    let source = |commit: &EcoString| ProvidedPackageSource::Git {
+        repo: repo.as_str().into(),
+        commit: commit.clone(),
+        subdir: None,
+    };
+    assert_eq!(
+        provide(&git_sources),
+        (
+            hexpm::version::Range::new("== 1.0.0".into()),
+            source(&first)
+        )
+    );
+
+    // The repository moves on, but the locked commit is still used
+    let second = commit_version("1.1.0");
+    let _ = git_sources.locked.insert(
+        "foo".into(),
+        ManifestPackageSource::Git {
+            repo: repo.as_str().into(),
+            commit: first.clone(),
+            subdir: None,
+        },
+    );
+    assert_eq!(
+        provide(&git_sources),
+        (
+            hexpm::version::Range::new("== 1.0.0".into()),
+            source(&first)
+        )
+    );
+
+    // Once unlocked the head of the repository is used
+    git_sources.locked.clear();
+    assert_eq!(
+        provide(&git_sources),
+        (
+            hexpm::version::Range::new("== 1.1.0".into()),
+            source(&second)
+        )
+    );
 }
 
-/// Provide a package from a git repository
-fn provide_git_package(
-    _package_name: EcoString,
-    _repo: &str,
-    _project_paths: &ProjectPaths,
-    _provided: &mut HashMap<EcoString, ProvidedPackage>,
-) -> Result<hexpm::version::Range> {
-    let _git = ProvidedPackageSource::Git {
-        repo: "repo".into(),
-        commit: "commit".into(),
-    };
-    Err(Error::GitDependencyUnsupported)
+/// Returns an error if the package is already one of the packages being walked.
+fn check_for_cycle(package_name: &EcoString, parents: &[EcoString]) -> Result<()> {
+    if parents.contains(package_name) {
+        let mut last_cycle = parents
+            .split(|p| p == package_name)
+            .last()
+            .unwrap_or_default()
+            .to_vec();
+        last_cycle.push(package_name.clone());
+        return Err(Error::PackageCycle {
+            packages: last_cycle,
+        });
+    }
+    Ok(())
 }
 
 /// Adds a gleam project located at a specific path to the list of "provided packages"
@@ -786,21 +6989,12 @@ fn provide_package(
     package_path: Utf8PathBuf,
     package_source: ProvidedPackageSource,
     project_paths: &ProjectPaths,
+    git_sources: &GitSources,
     provided: &mut HashMap<EcoString, ProvidedPackage>,
     parents: &mut Vec<EcoString>,
 ) -> Result<hexpm::version::Range> {
     // Return early if a package cycle is detected
-    if parents.contains(&package_name) {
-        let mut last_cycle = parents
-            .split(|p| p == &package_name)
-            .last()
-            .unwrap_or_default()
-            .to_vec();
-        last_cycle.push(package_name);
-        return Err(Error::PackageCycle {
-            packages: last_cycle,
-        });
-    }
+    check_for_cycle(&package_name, parents)?;
     // Check that we do not have a cached version of this package already
     match provided.get(&package_name) {
         Some(package) if package.source == package_source => {
@@ -810,30 +7004,47 @@ fn provide_package(
         }
         Some(package) => {
             // This package has already been provided from a different source which conflicts
-            return Err(Error::ProvidedDependencyConflict {
-                package: package_name.into(),
-                source_1: package_source.to_toml(),
-                source_2: package.source.to_toml(),
-            });
+            return Err(Error::DependencyResolutionFailed(
+                ResolutionFailure::ConflictingSources {
+                    package: package_name.into(),
+                    source_1: package_source.to_toml(),
+                    source_2: package.source.to_toml(),
+                },
+            ));
         }
         None => (),
     }
     // Load the package
-    let config = crate::config::read(package_path.join("gleam.toml"))?;
+    if !package_path.is_dir() {
+        return Err(Error::LocalDependencyNotADirectory {
+            package: package_name.into(),
+            path: package_path,
+        });
+    }
+    let config_path = package_path.join("gleam.toml");
+    if !config_path.is_file() {
+        return Err(Error::MissingLocalDependencyConfig {
+            package: package_name.into(),
+            path: package_path,
+        });
+    }
+    let config = crate::config::read(config_path)?;
     // Check that we are loading the correct project
     if config.name != package_name {
-        return Err(Error::WrongDependencyProvided {
-            expected: package_name.into(),
-            path: package_path.to_path_buf(),
-            found: config.name.into(),
-        });
+        return Err(Error::DependencyResolutionFailed(
+            ResolutionFailure::PackageNameMismatch {
+                expected: package_name.into(),
+                path: package_path.to_path_buf(),
+                found: config.name.into(),
+            },
+        ));
     };
     // Walk the requirements of the package
     let mut requirements = HashMap::new();
     parents.push(package_name);
     for (name, requirement) in config.dependencies.into_iter() {
         let version = match requirement {
-            Requirement::Hex { version } => version,
+            Requirement::Hex { version, .. } => version,
             Requirement::Path { path } => {
                 // Recursively walk local packages
                 provide_local_package(
@@ -841,13 +7052,19 @@ fn provide_package(
                     &path,
                     &package_path,
                     project_paths,
+                    git_sources,
                     provided,
                     parents,
                 )?
             }
-            Requirement::Git { git } => {
-                provide_git_package(name.clone(), &git, project_paths, provided)?
-            }
+            Requirement::Git { git, subdir } => provide_git_package(
+                name.clone(),
+                &git,
+                subdir.as_deref(),
+                git_sources,
+                provided,
+                parents,
+            )?,
         };
         let _ = requirements.insert(name, version);
     }
@@ -858,6 +7075,7 @@ fn provide_package(
         config.name,
         ProvidedPackage {
             version: config.version,
+            otp_app: config.erlang.application_name,
             source: package_source,
             requirements,
         },
@@ -866,6 +7084,41 @@ fn provide_package(
     Ok(version)
 }
 
+#[test]
+fn provide_package_without_config() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    std::fs::create_dir_all(root.join("wibble")).unwrap();
+    std::fs::write(root.join("wobble"), "").unwrap();
+    let project_paths = ProjectPaths::new(root.join("app"));
+    let provide = |name: &str| {
+        provide_local_package(
+            name.into(),
+            Utf8Path::new(name),
+            root,
+            &project_paths,
+            &GitSources::from_environment(),
+            &mut HashMap::new(),
+            &mut vec![],
+        )
+    };
+
+    let error = provide("wibble").unwrap_err();
+    assert_eq!(
+        error,
+        Error::MissingLocalDependencyConfig {
+            package: "wibble".into(),
+            path: fs::canonicalise(&root.join("wibble")).unwrap(),
+        }
+    );
+    assert!(error.to_string().contains("has no gleam.toml"));
+
+    assert!(matches!(
+        provide("wobble"),
+        Err(Error::LocalDependencyNotADirectory { package, .. }) if package == "wobble"
+    ));
+}
+
 #[test]
 fn provide_wrong_package() {
     let mut provided = HashMap::new();
@@ -875,17 +7128,20 @@ fn provide_wrong_package() {
         Utf8Path::new("./test/hello_world"),
         Utf8Path::new("./"),
         &project_paths,
+        &GitSources::from_environment(),
         &mut provided,
         &mut vec!["root".into(), "subpackage".into()],
     );
-    if let Err(Error::WrongDependencyProvided {
-        expected, found, ..
-    }) = result
+    if let Err(Error::DependencyResolutionFailed(ResolutionFailure::PackageNameMismatch {
+        expected,
+        found,
+        ..
+    })) = result
     {
         assert_eq!(expected, "wrong_name");
         assert_eq!(found, "hello_world");
     } else {
-        panic!("Expected WrongDependencyProvided error")
+        panic!("Expected PackageNameMismatch error")
     }
 }
 
@@ -899,145 +7155,887 @@ fn provide_existing_package() {
         Utf8Path::new("./test/hello_world"),
         Utf8Path::new("./"),
         &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec!["root".into(), "subpackage".into()],
+    );
+    assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
+
+    let result = provide_local_package(
+        "hello_world".into(),
+        Utf8Path::new("./test/hello_world"),
+        Utf8Path::new("./"),
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec!["root".into(), "subpackage".into()],
+    );
+    assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
+}
+
+#[test]
+fn provide_conflicting_package() {
+    let mut provided = HashMap::new();
+    let project_paths = crate::project_paths_at_current_directory_without_toml();
+    let result = provide_local_package(
+        "hello_world".into(),
+        Utf8Path::new("./test/hello_world"),
+        Utf8Path::new("./"),
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec!["root".into(), "subpackage".into()],
+    );
+    assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
+
+    let result = provide_package(
+        "hello_world".into(),
+        Utf8PathBuf::from("./test/other"),
+        ProvidedPackageSource::Local {
+            path: Utf8Path::new("./test/other").to_path_buf(),
+        },
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec!["root".into(), "subpackage".into()],
+    );
+    if let Err(Error::DependencyResolutionFailed(ResolutionFailure::ConflictingSources {
+        package,
+        ..
+    })) = result
+    {
+        assert_eq!(package, "hello_world");
+    } else {
+        panic!("Expected ConflictingSources error")
+    }
+}
+
+#[test]
+fn provided_is_absolute() {
+    let mut provided = HashMap::new();
+    let project_paths = crate::project_paths_at_current_directory_without_toml();
+    let result = provide_local_package(
+        "hello_world".into(),
+        Utf8Path::new("./test/hello_world"),
+        Utf8Path::new("./"),
+        &project_paths,
+        &GitSources::from_environment(),
         &mut provided,
         &mut vec!["root".into(), "subpackage".into()],
     );
     assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
+    let package = provided.get("hello_world").unwrap().clone();
+    if let ProvidedPackageSource::Local { path } = package.source {
+        assert!(path.is_absolute())
+    } else {
+        panic!("Provide_local_package provided a package that is not local!")
+    }
+}
+
+#[test]
+fn provided_recursive() {
+    let mut provided = HashMap::new();
+    let project_paths = crate::project_paths_at_current_directory_without_toml();
+    let result = provide_local_package(
+        "hello_world".into(),
+        Utf8Path::new("./test/hello_world"),
+        Utf8Path::new("./"),
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec!["root".into(), "hello_world".into(), "subpackage".into()],
+    );
+    assert_eq!(
+        result,
+        Err(Error::PackageCycle {
+            packages: vec!["subpackage".into(), "hello_world".into()],
+        })
+    )
+}
+
+#[test]
+fn provide_local_packages_that_depend_on_each_other() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    for (name, dependency) in [("one", "two"), ("two", "one")] {
+        std::fs::create_dir_all(root.join(name)).unwrap();
+        std::fs::write(
+            root.join(name).join("gleam.toml"),
+            format!("name = \"{name}\"\n[dependencies]\n{dependency} = {{ path = \"../{dependency}\" }}\n"),
+        )
+        .unwrap();
+    }
+    std::fs::create_dir_all(root.join("app")).unwrap();
+    let project_paths = ProjectPaths::new(root.join("app"));
+    let mut provided = HashMap::new();
+    let result = provide_local_package(
+        "one".into(),
+        Utf8Path::new("../one"),
+        &root.join("app"),
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec![],
+    );
+    assert_eq!(
+        result,
+        Err(Error::PackageCycle {
+            packages: vec!["two".into(), "one".into()],
+        })
+    );
+}
+
+#[test]
+fn provide_local_package_with_application_name() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    std::fs::create_dir_all(root.join("wibble")).unwrap();
+    std::fs::write(
+        root.join("wibble/gleam.toml"),
+        "name = \"wibble\"\nversion = \"1.0.0\"\n[erlang]\napplication_name = \"wibble_app\"\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("app")).unwrap();
+    let project_paths = ProjectPaths::new(root.join("app"));
+    let mut provided = HashMap::new();
+    let _ = provide_local_package(
+        "wibble".into(),
+        Utf8Path::new("../wibble"),
+        &root.join("app"),
+        &project_paths,
+        &GitSources::from_environment(),
+        &mut provided,
+        &mut vec![],
+    )
+    .unwrap();
+
+    let package = provided["wibble"].to_manifest_package("wibble");
+    assert_eq!(package.otp_app, Some("wibble_app".into()));
+    assert_eq!(package.application_name(), "wibble_app");
+}
+
+#[test]
+fn provided_package_requirements_round_trip_through_the_manifest() {
+    let tmp = tempfile::tempdir().unwrap();
+    let root = Utf8Path::from_path(tmp.path()).expect("Non Utf-8 Path");
+    let write = |path: Utf8PathBuf, content: &str| {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write(
+        root.join("app/gleam.toml"),
+        "name = \"app\"\n[dependencies]\nwobble = { path = \"../wobble\" }\n",
+    );
+    write(
+        root.join("wobble/gleam.toml"),
+        "name = \"wobble\"\nversion = \"1.0.0\"\n[dependencies]\nwibble = { path = \"../wibble\" }\n",
+    );
+    write(
+        root.join("wibble/gleam.toml"),
+        "name = \"wibble\"\nversion = \"1.0.0\"\n[erlang]\napplication_name = \"wibble_app\"\n",
+    );
+    let paths = ProjectPaths::new(root.join("app"));
+
+    // gleam.toml has no optional requirements, so every requirement of a
+    // provided package is a required one and is in the manifest. The
+    // application name is recorded on the package that is required.
+    let manifest = lock_manifest(&paths, &gleam_core::build::NullTelemetry).unwrap();
+    let package = |name: &str| {
+        manifest
+            .packages
+            .iter()
+            .find(|package| package.name == name)
+            .unwrap()
+    };
+    assert_eq!(package("wobble").requirements, vec!["wibble"]);
+    assert_eq!(package("wibble").otp_app, Some("wibble_app".into()));
+    assert_eq!(read_manifest_from_disc(&paths).unwrap(), manifest);
+}
+
+/// Take the resolved packages that are at the same version as in the previous
+/// manifest out of `resolved`, keeping their manifest entries rather than
+/// looking their releases up on Hex again. Their requirements come from the
+/// package information fetched while resolving, as an optional dependency may
+/// now be included.
+fn unchanged_packages(
+    resolved: &mut dependency::PackageVersions,
+    manifest: Option<&Manifest>,
+    provided: &HashMap<EcoString, ProvidedPackage>,
+    hex_repository: &HexRepository,
+    fetcher: &dyn dependency::PackageFetcher,
+) -> Vec<(ManifestPackage, Option<hexpm::RetirementStatus>)> {
+    let Some(manifest) = manifest else {
+        return vec![];
+    };
+    let mut unchanged = vec![];
+    for previous in &manifest.packages {
+        let ManifestPackageSource::Hex { repository, .. } = &previous.source else {
+            continue;
+        };
+        if provided.contains_key(&previous.name)
+            || resolved.get(previous.name.as_str()) != Some(&previous.version)
+            || *repository != hex_repository.source_repository(&previous.name)
+        {
+            continue;
+        }
+        let Some(release) = fetcher
+            .get_dependencies(&previous.name)
+            .ok()
+            .and_then(|package| {
+                package
+                    .releases
+                    .into_iter()
+                    .find(|release| release.version == previous.version)
+            })
+        else {
+            continue;
+        };
+        let _ = resolved.remove(previous.name.as_str());
+        let requirements = release
+            .requirements
+            .keys()
+            .map(|s| EcoString::from(s.as_str()))
+            .collect_vec();
+        let package = ManifestPackage {
+            requirements,
+            ..previous.clone()
+        };
+        unchanged.push((package, release.retirement_status));
+    }
+    unchanged
+}
+
+/// A package fetcher shared with the solver, so that the package information
+/// it fetched can be used again once the versions are resolved.
+struct SharedPackageFetcher(Rc<dyn dependency::PackageFetcher>);
+
+impl dependency::PackageFetcher for SharedPackageFetcher {
+    fn get_dependencies(
+        &self,
+        package: &str,
+    ) -> Result<hexpm::Package, Box<dyn std::error::Error>> {
+        self.0.get_dependencies(package)
+    }
+}
+
+/// Report the looked up packages that have been retired, returning all the
+/// packages.
+fn report_retired_packages<Telem: Telemetry>(
+    looked_up: Vec<(ManifestPackage, Option<hexpm::RetirementStatus>)>,
+    telemetry: &Telem,
+) -> Vec<ManifestPackage> {
+    looked_up
+        .into_iter()
+        .map(|(package, retirement)| {
+            if let Some(retirement) = retirement {
+                telemetry.retired_package_resolved(&package.name, &package.version, &retirement);
+            }
+            package
+        })
+        .collect()
+}
+
+/// Determine the information to add to the manifest for each resolved package,
+/// making at most `limit` requests to Hex at once. This stops at the first
+/// package that cannot be looked up.
+async fn lookup_packages<Http: gleam_core::io::HttpClient>(
+    resolved: impl IntoIterator<Item = (String, Version)>,
+    root: &str,
+    provided: &HashMap<EcoString, ProvidedPackage>,
+    hex_repository: &HexRepository,
+    http: &Http,
+    limit: usize,
+) -> Result<Vec<(ManifestPackage, Option<hexpm::RetirementStatus>)>> {
+    stream::iter(
+        resolved
+            .into_iter()
+            // The root package is the project itself, which is not on Hex
+            .filter(|(name, _)| name != root)
+            .map(|(name, version)| lookup_package(name, version, provided, hex_repository, http)),
+    )
+    .buffer_unordered(limit.max(1))
+    .try_collect()
+    .await
+}
+
+#[test]
+fn lookup_packages_concurrency_is_limited() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingHttpClient {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for CountingHttpClient {
+        async fn send(
+            &self,
+            _request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            let _ = self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            let _ = self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            let body = r#"{"version": "1.0.0", "requirements": {}, "retirement_status": null,
+                "checksum": "0102", "meta": {"app": "app", "build_tools": ["gleam"]}}"#;
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(body.as_bytes().to_vec())
+                .unwrap())
+        }
+    }
+
+    let http = CountingHttpClient::default();
+    let resolved = (0..10)
+        .map(|i| (format!("package_{i}"), Version::new(1, 0, 0)))
+        .collect_vec();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let packages = runtime
+        .block_on(lookup_packages(
+            resolved,
+            "root",
+            &HashMap::new(),
+            &HexRepository::default(),
+            &http,
+            2,
+        ))
+        .unwrap();
+
+    assert_eq!(packages.len(), 10);
+    assert_eq!(http.max_in_flight.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn lookup_packages_skips_the_root_package() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingHttpClient {
+        uris: Mutex<Vec<http::Uri>>,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for RecordingHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            self.uris.lock().unwrap().push(request.uri().clone());
+            let body = r#"{"version": "1.0.0", "requirements": {}, "retirement_status": null,
+                "checksum": "0102", "meta": {"app": "wibble", "build_tools": ["gleam"]}}"#;
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(body.as_bytes().to_vec())
+                .unwrap())
+        }
+    }
+
+    let http = RecordingHttpClient::default();
+    let resolved = vec![
+        ("app".into(), Version::new(0, 0, 0)),
+        ("wibble".into(), Version::new(1, 0, 0)),
+    ];
+    let packages = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(lookup_packages(
+            resolved,
+            "app",
+            &HashMap::new(),
+            &HexRepository::default(),
+            &http,
+            2,
+        ))
+        .unwrap();
 
-    let result = provide_local_package(
-        "hello_world".into(),
-        Utf8Path::new("./test/hello_world"),
-        Utf8Path::new("./"),
-        &project_paths,
-        &mut provided,
-        &mut vec!["root".into(), "subpackage".into()],
+    assert_eq!(
+        packages
+            .iter()
+            .map(|(package, _)| package.name.as_str())
+            .collect_vec(),
+        vec!["wibble"]
+    );
+    assert_eq!(
+        http.uris.lock().unwrap().as_slice(),
+        &[http::Uri::from_static(
+            "https://hex.pm/api/packages/wibble/releases/1.0.0"
+        )]
     );
-    assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
 }
 
 #[test]
-fn provide_conflicting_package() {
-    let mut provided = HashMap::new();
-    let project_paths = crate::project_paths_at_current_directory_without_toml();
-    let result = provide_local_package(
-        "hello_world".into(),
-        Utf8Path::new("./test/hello_world"),
-        Utf8Path::new("./"),
-        &project_paths,
-        &mut provided,
-        &mut vec!["root".into(), "subpackage".into()],
-    );
-    assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
+fn unbounded_requirements_are_reported() {
+    use gleam_core::build::{TelemetryEvent, TelemetryEventHandler};
+    use std::sync::Mutex;
 
-    let result = provide_package(
-        "hello_world".into(),
-        Utf8PathBuf::from("./test/other"),
-        ProvidedPackageSource::Local {
-            path: Utf8Path::new("./test/other").to_path_buf(),
+    #[derive(Debug, Default)]
+    struct UnboundedTelemetry {
+        unbounded: Mutex<Vec<String>>,
+    }
+
+    impl TelemetryEventHandler for UnboundedTelemetry {
+        fn handle(&self, event: TelemetryEvent) {
+            if let TelemetryEvent::UnboundedRequirementResolved { name, version } = event {
+                self.unbounded
+                    .lock()
+                    .unwrap()
+                    .push(format!("{name} {version}"));
+            }
+        }
+    }
+
+    let package = |name: &str| ManifestPackage {
+        name: name.into(),
+        version: Version::new(0, 3, 1),
+        build_tools: vec!["gleam".into()],
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![]),
+            repository: None,
         },
-        &project_paths,
-        &mut provided,
-        &mut vec!["root".into(), "subpackage".into()],
+    };
+    let mut config = PackageConfig::default();
+    config.dependencies = [
+        ("loose".into(), Requirement::hex(">= 0.0.0")),
+        ("added".into(), Requirement::hex(">= 0.0.0")),
+        ("tight".into(), Requirement::hex("~> 0.3")),
+    ]
+    .into();
+    config.dev_dependencies = [("loose_dev".into(), Requirement::hex(">= 0.0.0"))].into();
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            package("loose"),
+            package("added"),
+            package("tight"),
+            package("loose_dev"),
+        ],
+    };
+    let telemetry = UnboundedTelemetry::default();
+
+    report_unbounded_requirements(&config, &manifest, &["added".into()], &telemetry);
+
+    assert_eq!(
+        telemetry.unbounded.into_inner().unwrap(),
+        vec!["loose 0.3.1", "loose_dev 0.3.1"]
     );
-    if let Err(Error::ProvidedDependencyConflict { package, .. }) = result {
-        assert_eq!(package, "hello_world");
-    } else {
-        panic!("Expected ProvidedDependencyConflict error")
-    }
 }
 
 #[test]
-fn provided_is_absolute() {
-    let mut provided = HashMap::new();
-    let project_paths = crate::project_paths_at_current_directory_without_toml();
-    let result = provide_local_package(
-        "hello_world".into(),
-        Utf8Path::new("./test/hello_world"),
-        Utf8Path::new("./"),
-        &project_paths,
-        &mut provided,
-        &mut vec!["root".into(), "subpackage".into()],
-    );
-    assert_eq!(result, Ok(hexpm::version::Range::new("== 0.1.0".into())));
-    let package = provided.get("hello_world").unwrap().clone();
-    if let ProvidedPackageSource::Local { path } = package.source {
-        assert!(path.is_absolute())
-    } else {
-        panic!("Provide_local_package provided a package that is not local!")
+fn retired_packages_are_reported() {
+    use gleam_core::build::{TelemetryEvent, TelemetryEventHandler};
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct RetiredTelemetry {
+        retired: Mutex<Vec<(String, Version, hexpm::RetirementStatus)>>,
     }
-}
 
-#[test]
-fn provided_recursive() {
-    let mut provided = HashMap::new();
-    let project_paths = crate::project_paths_at_current_directory_without_toml();
-    let result = provide_local_package(
-        "hello_world".into(),
-        Utf8Path::new("./test/hello_world"),
-        Utf8Path::new("./"),
-        &project_paths,
-        &mut provided,
-        &mut vec!["root".into(), "hello_world".into(), "subpackage".into()],
-    );
+    impl TelemetryEventHandler for RetiredTelemetry {
+        fn handle(&self, event: TelemetryEvent) {
+            if let TelemetryEvent::RetiredPackageResolved {
+                name,
+                version,
+                retirement,
+            } = event
+            {
+                self.retired
+                    .lock()
+                    .unwrap()
+                    .push((name.into(), version, retirement));
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct ReleaseHttpClient;
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for ReleaseHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            let retirement = if request.uri().path().contains("retired_package") {
+                r#"{"reason": "security", "message": "Leaks secrets"}"#
+            } else {
+                "null"
+            };
+            let body = format!(
+                r#"{{"version": "1.0.0", "requirements": {{}}, "retirement_status": {retirement},
+                "checksum": "0102", "meta": {{"app": "app", "build_tools": ["gleam"]}}}}"#
+            );
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(body.into_bytes())
+                .unwrap())
+        }
+    }
+
+    let resolved = vec![
+        ("retired_package".into(), Version::new(1, 0, 0)),
+        ("current_package".into(), Version::new(1, 0, 0)),
+    ];
+    let looked_up = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(lookup_packages(
+            resolved,
+            "root",
+            &HashMap::new(),
+            &HexRepository::default(),
+            &ReleaseHttpClient,
+            1,
+        ))
+        .unwrap();
+    let telemetry = RetiredTelemetry::default();
+    let packages = report_retired_packages(looked_up, &telemetry);
+
+    assert_eq!(packages.len(), 2);
     assert_eq!(
-        result,
-        Err(Error::PackageCycle {
-            packages: vec!["subpackage".into(), "hello_world".into()],
-        })
-    )
+        telemetry.retired.into_inner().unwrap(),
+        vec![(
+            "retired_package".into(),
+            Version::new(1, 0, 0),
+            hexpm::RetirementStatus {
+                reason: hexpm::RetirementReason::Security,
+                message: "Leaks secrets".into(),
+            }
+        )]
+    );
 }
 
-/// Determine the information to add to the manifest for a specific package
-async fn lookup_package(
+/// Determine the information to add to the manifest for a specific package,
+/// along with its retirement status if it is a Hex package.
+async fn lookup_package<Http: gleam_core::io::HttpClient>(
     name: String,
     version: Version,
     provided: &HashMap<EcoString, ProvidedPackage>,
-) -> Result<ManifestPackage> {
+    hex_repository: &HexRepository,
+    http: &Http,
+) -> Result<(ManifestPackage, Option<hexpm::RetirementStatus>)> {
     match provided.get(name.as_str()) {
-        Some(provided_package) => Ok(provided_package.to_manifest_package(name.as_str())),
-        None => {
-            let config = hexpm::Config::new();
-            let release =
-                hex::get_package_release(&name, &version, &config, &HttpClient::new()).await?;
-            let build_tools = release
-                .meta
-                .build_tools
-                .iter()
-                .map(|s| EcoString::from(s.as_str()))
-                .collect_vec();
-            let requirements = release
-                .requirements
-                .keys()
-                .map(|s| EcoString::from(s.as_str()))
-                .collect_vec();
-            Ok(ManifestPackage {
-                name: name.into(),
-                version,
-                otp_app: Some(release.meta.app.into()),
-                build_tools,
-                requirements,
-                source: ManifestPackageSource::Hex {
-                    outer_checksum: Base16Checksum(release.outer_checksum),
-                },
-            })
+        Some(provided_package) => Ok((provided_package.to_manifest_package(name.as_str()), None)),
+        None => hex::lookup_package(name, version, hex_repository, http).await,
+    }
+}
+
+#[test]
+fn lookup_package_fetches_prerelease_release() {
+    #[derive(Default)]
+    struct RecordingHttpClient {
+        paths: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for RecordingHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            self.paths
+                .lock()
+                .unwrap()
+                .push(request.uri().path().to_string());
+            let body = r#"{"version": "1.0.0-rc1", "requirements": {}, "retirement_status": null,
+                "checksum": "0102", "meta": {"app": "beta", "build_tools": ["gleam"]}}"#;
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(body.as_bytes().to_vec())
+                .unwrap())
+        }
+    }
+
+    let http = RecordingHttpClient::default();
+    let version = Version::parse("1.0.0-rc1").unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (package, _) = runtime
+        .block_on(lookup_package(
+            "beta".into(),
+            version.clone(),
+            &HashMap::new(),
+            &HexRepository::default(),
+            &http,
+        ))
+        .unwrap();
+
+    assert_eq!(package.version, version);
+    assert_eq!(
+        *http.paths.lock().unwrap(),
+        vec!["/api/packages/beta/releases/1.0.0-rc1".to_string()]
+    );
+}
+
+#[test]
+fn lookup_package_records_declared_repository() {
+    #[derive(Default)]
+    struct RecordingHttpClient {
+        uris: std::sync::Mutex<Vec<http::Uri>>,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for RecordingHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            self.uris.lock().unwrap().push(request.uri().clone());
+            let body = r#"{"version": "1.0.0", "requirements": {}, "retirement_status": null,
+                "checksum": "0102", "meta": {"app": "wibble", "build_tools": ["gleam"]}}"#;
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(body.as_bytes().to_vec())
+                .unwrap())
         }
     }
+
+    let config: PackageConfig = toml::from_str(
+        r#"
+name = "app"
+
+[repositories.internal]
+url = "https://hex.internal.example.com"
+
+[dependencies]
+wibble = { version = "~> 1.0", repository = "internal" }
+wobble = "~> 1.0"
+"#,
+    )
+    .unwrap();
+    let hex_repository = hex_repository_with(&config, None).unwrap();
+    let http = RecordingHttpClient::default();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let lookup = |name: &str| {
+        runtime
+            .block_on(lookup_package(
+                name.into(),
+                Version::new(1, 0, 0),
+                &HashMap::new(),
+                &hex_repository,
+                &http,
+            ))
+            .unwrap()
+            .0
+    };
+
+    assert_eq!(
+        lookup("wibble").source,
+        ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1, 2]),
+            repository: Some("internal".into()),
+        }
+    );
+    assert_eq!(
+        lookup("wobble").source,
+        ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1, 2]),
+            repository: None,
+        }
+    );
+    assert_eq!(
+        *http.uris.lock().unwrap(),
+        vec![
+            http::Uri::from_static(
+                "https://hex.internal.example.com/packages/wibble/releases/1.0.0"
+            ),
+            http::Uri::from_static("https://hex.pm/api/packages/wobble/releases/1.0.0"),
+        ]
+    );
+
+    // Packages are downloaded from the repository recorded in the manifest
+    // even when they are not direct dependencies
+    let transitive = |name: &str, repository: &str| ManifestPackage {
+        name: name.into(),
+        version: Version::new(1, 0, 0),
+        build_tools: ["gleam".into()].into(),
+        otp_app: None,
+        requirements: vec![],
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(vec![1, 2]),
+            repository: Some(repository.into()),
+        },
+    };
+    let manifest = Manifest {
+        requirements: HashMap::new(),
+        packages: vec![
+            transitive("transitive", "internal"),
+            transitive("organised", "hexpm:acme"),
+        ],
+    };
+    let repository = hex_repository_for_manifest_with(&config, &manifest, None).unwrap();
+    assert_eq!(
+        repository.for_package("transitive").0.repository_base,
+        http::Uri::from_static("https://hex.internal.example.com/")
+    );
+    assert_eq!(
+        repository.for_package("organised").0.repository_base,
+        http::Uri::from_static("https://repo.hex.pm/repos/acme/")
+    );
 }
 
-struct PackageFetcher {
+struct PackageFetcher<Http = RetryingHttpClient<HttpClient>> {
     runtime: tokio::runtime::Handle,
-    http: HttpClient,
+    http: Http,
+    // Shared with the resolution so that the repositories the dependencies
+    // of fetched packages were routed to are used to look their releases up.
+    hex_repository: Rc<RefCell<HexRepository>>,
+    // The solver may ask for the same package many times as it backtracks, so
+    // packages are only requested from Hex once.
+    cache: RefCell<HashMap<String, hexpm::Package>>,
+    // Responses from Hex are also written to disc so that they can be reused
+    // by later resolutions until they are older than the TTL. Without a TTL
+    // they never expire. Nothing is stored when resolving against a snapshot,
+    // which is then the only source of package information.
+    metadata_directory: Option<Utf8PathBuf>,
+    metadata_ttl: Option<Duration>,
 }
 
 impl PackageFetcher {
-    pub fn boxed(runtime: tokio::runtime::Handle) -> Box<Self> {
+    pub fn boxed(
+        runtime: tokio::runtime::Handle,
+        hex_repository: Rc<RefCell<HexRepository>>,
+        metadata_directory: Utf8PathBuf,
+        metadata_ttl: Option<Duration>,
+        headers: ClientHeaders,
+    ) -> Box<Self> {
+        Box::new(Self {
+            runtime,
+            http: RetryingHttpClient::new(RequestKind::Metadata).with_headers(headers),
+            hex_repository,
+            cache: RefCell::new(HashMap::new()),
+            metadata_directory: Some(metadata_directory),
+            metadata_ttl,
+        })
+    }
+}
+
+impl PackageFetcher<SnapshotHttpClient> {
+    /// Fetches package information from the snapshot of a Hex repository in
+    /// the directory rather than from Hex.
+    pub fn snapshot(
+        runtime: tokio::runtime::Handle,
+        hex_repository: Rc<RefCell<HexRepository>>,
+        directory: Utf8PathBuf,
+    ) -> Box<Self> {
         Box::new(Self {
             runtime,
-            http: HttpClient::new(),
+            http: SnapshotHttpClient::new(directory),
+            hex_repository,
+            cache: RefCell::new(HashMap::new()),
+            metadata_directory: None,
+            metadata_ttl: None,
         })
     }
 }
 
+impl<Http> PackageFetcher<Http> {
+    /// Where the Hex response for the package is stored. Packages with the
+    /// same name may come from different repositories, so each repository has
+    /// its own directory.
+    fn stored_response_path(&self, package: &str) -> Option<Utf8PathBuf> {
+        use sha2::Digest;
+        let (config, _) = self.hex_repository.borrow().for_package(package);
+        let repository = config.repository_base.to_string();
+        let directory = base16::encode_lower(&sha2::Sha256::digest(repository.as_bytes()));
+        Some(
+            self.metadata_directory
+                .as_ref()?
+                .join(directory)
+                .join(package),
+        )
+    }
+
+    /// The Hex response for the package written by an earlier resolution, if
+    /// there is one that hasn't expired.
+    fn stored_response(&self, package: &str) -> Option<Vec<u8>> {
+        let path = self.stored_response_path(package)?;
+        let modified = path.metadata().ok()?.modified().ok()?;
+        if let Some(ttl) = self.metadata_ttl {
+            if modified.elapsed().map_or(true, |age| age >= ttl) {
+                return None;
+            }
+        }
+        std::fs::read(path).ok()
+    }
+
+    fn store_response(&self, package: &str, body: &[u8]) {
+        let Some(path) = self.stored_response_path(package) else {
+            return;
+        };
+        if let Err(error) = fs::write_bytes(&path, body) {
+            tracing::debug!(package = package, error = %error, "hex_metadata_not_stored");
+        }
+    }
+
+    fn decode_response(
+        &self,
+        package: &str,
+        body: Vec<u8>,
+    ) -> Result<hexpm::Package, hexpm::ApiError> {
+        let response = http::Response::builder()
+            .status(http::StatusCode::OK)
+            .body(body)
+            .expect("Hex metadata response");
+        hexpm::get_package_response(
+            response,
+            self.hex_repository.borrow().public_key_for(package),
+        )
+    }
+
+    /// Fetch the dependencies of the package from the repositories its
+    /// releases say they come from.
+    fn route_dependencies(&self, package: &hexpm::Package) {
+        let mut hex_repository = self.hex_repository.borrow_mut();
+        for release in &package.releases {
+            hex_repository.route_dependencies(&package.name, &release.requirements);
+        }
+    }
+}
+
+/// Decode the package information Hex responded with. Hex bodies are always
+/// gzipped, so any other body has been replaced somewhere along the way, often
+/// with an HTML page, and cannot be verified.
+fn package_response(
+    package: &str,
+    repository: &http::Uri,
+    response: http::Response<Vec<u8>>,
+    public_key: &[u8],
+) -> Result<hexpm::Package, Box<dyn std::error::Error>> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    if response.status() == http::StatusCode::OK && !response.body().starts_with(&GZIP_MAGIC) {
+        return Err(Box::new(Error::HexResponseUnverified {
+            package: package.into(),
+            repository: repository.to_string(),
+        }));
+    }
+    Ok(hexpm::get_package_response(response, public_key)?)
+}
+
+#[test]
+fn html_hex_response_is_unverified() {
+    let response = http::Response::builder()
+        .status(http::StatusCode::OK)
+        .header("content-type", "text/html")
+        .body(b"<html><body>Down for maintenance</body></html>".to_vec())
+        .unwrap();
+    let error = package_response(
+        "gleam_stdlib",
+        &"https://repo.hex.pm/".parse().unwrap(),
+        response,
+        &HexRepository::default().public_key,
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        error.downcast_ref::<Error>(),
+        Some(&Error::HexResponseUnverified {
+            package: "gleam_stdlib".into(),
+            repository: "https://repo.hex.pm/".into(),
+        })
+    );
+}
+
 #[derive(Debug)]
 pub struct Untar;
 
@@ -1064,26 +8062,265 @@ impl TarUnpacker for Untar {
     }
 }
 
-impl dependency::PackageFetcher for PackageFetcher {
+impl<Http: gleam_core::io::HttpClient> dependency::PackageFetcher for PackageFetcher<Http> {
     fn get_dependencies(
         &self,
         package: &str,
     ) -> Result<hexpm::Package, Box<dyn std::error::Error>> {
+        if let Some(cached) = self.cache.borrow().get(package) {
+            tracing::debug!(package = package, "hex_package_in_cache");
+            return Ok(cached.clone());
+        }
+        if let Some(stored) = self
+            .stored_response(package)
+            .and_then(|body| self.decode_response(package, body).ok())
+        {
+            tracing::debug!(package = package, "hex_package_on_disc");
+            self.route_dependencies(&stored);
+            let _ = self
+                .cache
+                .borrow_mut()
+                .insert(package.into(), stored.clone());
+            return Ok(stored);
+        }
+
         tracing::debug!(package = package, "looking_up_hex_package");
-        let config = hexpm::Config::new();
-        let request = hexpm::get_package_request(package, None, &config);
+        let (config, request) = {
+            let hex_repository = self.hex_repository.borrow();
+            let (config, api_key) = hex_repository.for_package(package);
+            let request = hexpm::get_package_request(package, api_key, &config);
+            (config, request)
+        };
         let response = self
             .runtime
             .block_on(self.http.send(request))
             .map_err(Box::new)?;
-        hexpm::get_package_response(response, HEXPM_PUBLIC_KEY).map_err(|e| e.into())
+        let body = (response.status() == http::StatusCode::OK).then(|| response.body().clone());
+        let fetched = package_response(
+            package,
+            &config.repository_base,
+            response,
+            self.hex_repository.borrow().public_key_for(package),
+        )?;
+        if let Some(body) = body {
+            self.store_response(package, &body);
+        }
+        self.route_dependencies(&fetched);
+        let _ = self
+            .cache
+            .borrow_mut()
+            .insert(package.into(), fetched.clone());
+        Ok(fetched)
+    }
+}
+
+#[test]
+fn package_fetcher_uses_configured_repository() {
+    use dependency::PackageFetcher as _;
+    use std::sync::Mutex;
+
+    struct RecordingHttpClient {
+        uris: Mutex<Vec<http::Uri>>,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for RecordingHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            self.uris.lock().unwrap().push(request.uri().clone());
+            Ok(http::Response::builder()
+                .status(http::StatusCode::NOT_FOUND)
+                .body(vec![])
+                .unwrap())
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut config = PackageConfig::default();
+    config.hex.repository = Some(http::Uri::from_static("https://mirror.example.com/hex"));
+    let fetcher = PackageFetcher {
+        runtime: runtime.handle().clone(),
+        http: RecordingHttpClient {
+            uris: Mutex::new(vec![]),
+        },
+        hex_repository: Rc::new(RefCell::new(HexRepository::from_config(&config.hex))),
+        cache: RefCell::new(HashMap::new()),
+        metadata_directory: Some(Utf8Path::from_path(tmp.path()).unwrap().to_path_buf()),
+        metadata_ttl: None,
+    };
+    assert!(fetcher.get_dependencies("gleam_stdlib").is_err());
+    assert_eq!(
+        fetcher.http.uris.lock().unwrap().as_slice(),
+        &[http::Uri::from_static(
+            "https://mirror.example.com/hex/packages/gleam_stdlib"
+        )]
+    );
+}
+
+#[test]
+fn package_fetcher_uses_declared_repository_of_each_dependency() {
+    use dependency::PackageFetcher as _;
+    use std::sync::Mutex;
+
+    struct RecordingHttpClient {
+        uris: Mutex<Vec<http::Uri>>,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for RecordingHttpClient {
+        async fn send(
+            &self,
+            request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            self.uris.lock().unwrap().push(request.uri().clone());
+            Ok(http::Response::builder()
+                .status(http::StatusCode::NOT_FOUND)
+                .body(vec![])
+                .unwrap())
+        }
+    }
+
+    let config: PackageConfig = toml::from_str(
+        r#"
+name = "app"
+
+[repositories.internal]
+url = "https://hex.internal.example.com"
+
+[repositories.partner]
+url = "https://hex.partner.example.com/repo/"
+
+[dependencies]
+wibble = { version = "~> 1.0", repository = "internal" }
+wobble = { version = "~> 1.0", repository = "partner" }
+public = "~> 1.0"
+"#,
+    )
+    .unwrap();
+    let tmp = tempfile::tempdir().unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let fetcher = PackageFetcher {
+        runtime: runtime.handle().clone(),
+        http: RecordingHttpClient {
+            uris: Mutex::new(vec![]),
+        },
+        hex_repository: Rc::new(RefCell::new(hex_repository_with(&config, None).unwrap())),
+        cache: RefCell::new(HashMap::new()),
+        metadata_directory: Some(Utf8Path::from_path(tmp.path()).unwrap().to_path_buf()),
+        metadata_ttl: None,
+    };
+    for package in ["wibble", "wobble", "public"] {
+        assert!(fetcher.get_dependencies(package).is_err());
+    }
+    assert_eq!(
+        fetcher.http.uris.lock().unwrap().as_slice(),
+        &[
+            http::Uri::from_static("https://hex.internal.example.com/packages/wibble"),
+            http::Uri::from_static("https://hex.partner.example.com/repo/packages/wobble"),
+            http::Uri::from_static("https://repo.hex.pm/packages/public"),
+        ]
+    );
+}
+
+#[test]
+fn hex_repository_rejects_undeclared_repositories() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [(
+        "wibble".into(),
+        Requirement::Hex {
+            version: hexpm::version::Range::new("~> 1.0".into()),
+            organization: None,
+            repository: Some("internal".into()),
+        },
+    )]
+    .into();
+    assert_eq!(
+        hex_repository_with(&config, None).unwrap_err(),
+        Error::UnknownHexRepository {
+            package: "wibble".into(),
+            repository: "internal".into(),
+        }
+    );
+}
+
+#[test]
+fn package_fetcher_reuses_package_information() {
+    use dependency::PackageFetcher as _;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingHttpClient {
+        requests: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl gleam_core::io::HttpClient for CountingHttpClient {
+        async fn send(
+            &self,
+            _request: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Error> {
+            let _ = self.requests.fetch_add(1, Ordering::SeqCst);
+            // A signed package response from hex.pm
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(signed_package_exfmt().to_vec())
+                .unwrap())
+        }
     }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let fetcher = |metadata_ttl| PackageFetcher {
+        runtime: runtime.handle().clone(),
+        http: CountingHttpClient::default(),
+        hex_repository: Rc::new(RefCell::new(HexRepository::default())),
+        cache: RefCell::new(HashMap::new()),
+        metadata_directory: Some(Utf8Path::from_path(tmp.path()).unwrap().to_path_buf()),
+        metadata_ttl,
+    };
+
+    let first_resolution = fetcher(Some(Duration::from_secs(60)));
+    let first = first_resolution.get_dependencies("exfmt").unwrap();
+    let second = first_resolution.get_dependencies("exfmt").unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first_resolution.http.requests.load(Ordering::SeqCst), 1);
+
+    // Later resolutions use the response stored on disc until it expires
+    let second_resolution = fetcher(Some(Duration::from_secs(60)));
+    assert_eq!(second_resolution.get_dependencies("exfmt").unwrap(), first);
+    assert_eq!(second_resolution.http.requests.load(Ordering::SeqCst), 0);
+
+    let unexpiring_resolution = fetcher(None);
+    assert_eq!(
+        unexpiring_resolution.get_dependencies("exfmt").unwrap(),
+        first
+    );
+    assert_eq!(
+        unexpiring_resolution.http.requests.load(Ordering::SeqCst),
+        0
+    );
+
+    // A package with the same name from another repository is not reused
+    let mut mirror = fetcher(None);
+    let mut config = PackageConfig::default();
+    config.hex.repository = Some(http::Uri::from_static("https://mirror.example.com/hex"));
+    mirror.hex_repository = Rc::new(RefCell::new(HexRepository::from_config(&config.hex)));
+    assert_eq!(mirror.get_dependencies("exfmt").unwrap(), first);
+    assert_eq!(mirror.http.requests.load(Ordering::SeqCst), 1);
+
+    let expired_resolution = fetcher(Some(Duration::ZERO));
+    assert_eq!(expired_resolution.get_dependencies("exfmt").unwrap(), first);
+    assert_eq!(expired_resolution.http.requests.load(Ordering::SeqCst), 1);
 }
 
 #[test]
 fn provided_local_to_hex() {
     let provided_package = ProvidedPackage {
         version: hexpm::version::Version::new(1, 0, 0),
+        otp_app: None,
         source: ProvidedPackageSource::Local {
             path: "canonical/path/to/package".into(),
         },
@@ -1142,9 +8379,11 @@ fn provided_local_to_hex() {
 fn provided_git_to_hex() {
     let provided_package = ProvidedPackage {
         version: hexpm::version::Version::new(1, 0, 0),
+        otp_app: None,
         source: ProvidedPackageSource::Git {
             repo: "https://github.com/gleam-lang/gleam.git".into(),
             commit: "bd9fe02f72250e6a136967917bcb1bdccaffa3c8".into(),
+            subdir: None,
         },
         requirements: [
             (
@@ -1201,6 +8440,7 @@ fn provided_git_to_hex() {
 fn provided_local_to_manifest() {
     let provided_package = ProvidedPackage {
         version: hexpm::version::Version::new(1, 0, 0),
+        otp_app: None,
         source: ProvidedPackageSource::Local {
             path: "canonical/path/to/package".into(),
         },
@@ -1238,9 +8478,11 @@ fn provided_local_to_manifest() {
 fn provided_git_to_manifest() {
     let provided_package = ProvidedPackage {
         version: hexpm::version::Version::new(1, 0, 0),
+        otp_app: None,
         source: ProvidedPackageSource::Git {
             repo: "https://github.com/gleam-lang/gleam.git".into(),
             commit: "bd9fe02f72250e6a136967917bcb1bdccaffa3c8".into(),
+            subdir: None,
         },
         requirements: [
             (
@@ -1264,6 +8506,7 @@ fn provided_git_to_manifest() {
         source: ManifestPackageSource::Git {
             repo: "https://github.com/gleam-lang/gleam.git".into(),
             commit: "bd9fe02f72250e6a136967917bcb1bdccaffa3c8".into(),
+            subdir: None,
         },
     };
 
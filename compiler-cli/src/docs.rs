@@ -72,7 +72,7 @@ pub fn build(options: BuildOptions) -> Result<()> {
             codegen: Codegen::All,
             warnings_as_errors: false,
         },
-        crate::build::download_dependencies()?,
+        crate::build::download_dependencies(Mode::Dev)?,
     )?;
     let outputs = build_documentation(&config, &mut built.root_package)?;
 
@@ -156,7 +156,7 @@ impl PublishCommand {
                 mode: Mode::Prod,
                 target: None,
             },
-            crate::build::download_dependencies()?,
+            crate::build::download_dependencies(Mode::Dev)?,
         )?;
         let outputs = build_documentation(&config, &mut built.root_package)?;
         let archive = crate::fs::create_tar_archive(outputs)?;
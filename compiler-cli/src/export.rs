@@ -45,7 +45,7 @@ pub(crate) fn erlang_shipment() -> Result<()> {
             mode,
             target: Some(target),
         },
-        crate::build::download_dependencies()?,
+        crate::build::download_dependencies(Mode::Prod)?,
     )?;
 
     for entry in crate::fs::read_dir(&build)?.filter_map(Result::ok) {
@@ -1,5 +1,5 @@
 use gleam_core::{
-    build::{NullTelemetry, Target},
+    build::{Mode, NullTelemetry, Target},
     error::{Error, FileIoAction, FileKind},
     io::{
         CommandExecutor, Content, DirEntry, FileSystemReader, FileSystemWriter, OutputFile,
@@ -22,7 +22,7 @@ use std::{
 
 use camino::{ReadDirUtf8, Utf8Path, Utf8PathBuf};
 
-use crate::{dependencies::UseManifest, lsp::LspLocker};
+use crate::{dependencies::DownloadOptions, lsp::LspLocker};
 
 #[cfg(test)]
 mod tests;
@@ -174,6 +174,10 @@ impl FileSystemWriter for ProjectIO {
         delete_file(path)
     }
 
+    fn rename(&self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        rename(from, to)
+    }
+
     fn write(&self, path: &Utf8Path, content: &str) -> Result<(), Error> {
         write(path, content)
     }
@@ -228,10 +232,28 @@ impl MakeLocker for ProjectIO {
 
 impl DownloadDependencies for ProjectIO {
     fn download_dependencies(&self, paths: &ProjectPaths) -> Result<Manifest> {
-        crate::dependencies::download(paths, NullTelemetry, None, UseManifest::Yes)
+        crate::dependencies::download(
+            paths,
+            NullTelemetry,
+            None,
+            Mode::Dev,
+            DownloadOptions::default(),
+        )
     }
 }
 
+/// The total size in bytes of the files within a directory. Files that cannot
+/// be read are not counted.
+pub fn directory_size(dir: &Utf8Path) -> u64 {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
 pub fn delete_directory(dir: &Utf8Path) -> Result<(), Error> {
     tracing::trace!(path=?dir, "deleting_directory");
     if dir.exists() {
@@ -247,6 +269,29 @@ pub fn delete_directory(dir: &Utf8Path) -> Result<(), Error> {
     Ok(())
 }
 
+/// Delete the directories containing the given path that are empty, stopping at
+/// the first one that is not empty or at the root directory, which is never
+/// deleted.
+pub fn delete_empty_parents(path: &Utf8Path, root: &Utf8Path) -> Result<(), Error> {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || !dir.is_dir() {
+            break;
+        }
+        let empty = read_dir(dir)?.next().is_none();
+        if !empty {
+            break;
+        }
+        tracing::trace!(path=?dir, "deleting_empty_directory");
+        std::fs::remove_dir(dir).map_err(|e| Error::FileIo {
+            action: FileIoAction::Delete,
+            kind: FileKind::Directory,
+            path: dir.to_path_buf(),
+            err: Some(e.to_string()),
+        })?;
+    }
+    Ok(())
+}
+
 pub fn delete_file(file: &Utf8Path) -> Result<(), Error> {
     tracing::trace!("Deleting file {:?}", file);
     if file.exists() {
@@ -285,6 +330,25 @@ pub fn write(path: &Utf8Path, text: &str) -> Result<(), Error> {
     write_bytes(path, text.as_bytes())
 }
 
+/// Write a file by writing to a temporary file in the same directory and then
+/// renaming it into place. The rename is atomic, so if writing fails or the
+/// process is killed any previous version of the file is left intact.
+pub fn write_atomically(path: &Utf8Path, text: &str) -> Result<(), Error> {
+    let file_name = path.file_name().unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{file_name}.tmp"));
+    if let Err(error) = write(&temporary, text) {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(error);
+    }
+    tracing::trace!(from=?temporary, to=?path, "renaming_file");
+    std::fs::rename(&temporary, path).map_err(|e| Error::FileIo {
+        action: FileIoAction::Rename,
+        kind: FileKind::File,
+        path: path.to_path_buf(),
+        err: Some(e.to_string()),
+    })
+}
+
 #[cfg(target_family = "unix")]
 pub fn make_executable(path: impl AsRef<Utf8Path>) -> Result<(), Error> {
     use std::os::unix::fs::PermissionsExt;
@@ -542,19 +606,22 @@ pub fn copy(
         .map(|_| ())
 }
 
-// pub fn rename(path: impl AsRef<Utf8Path> + Debug, to: impl AsRef<Utf8Path> + Debug) -> Result<(), Error> {
-//     tracing::trace!(from=?path, to=?to, "renaming_file");
+pub fn rename(
+    path: impl AsRef<Utf8Path> + Debug,
+    to: impl AsRef<Utf8Path> + Debug,
+) -> Result<(), Error> {
+    tracing::trace!(from=?path, to=?to, "renaming_file");
 
-//     // TODO: include the destination in the error message
-//     std::fs::rename(&path, &to)
-//         .map_err(|err| Error::FileIo {
-//             action: FileIoAction::Rename,
-//             kind: FileKind::File,
-//             path: Utf8PathBuf::from(path.as_ref()),
-//             err: Some(err.to_string()),
-//         })
-//         .map(|_| ())
-// }
+    // TODO: include the destination in the error message
+    std::fs::rename(path.as_ref(), to.as_ref())
+        .map_err(|err| Error::FileIo {
+            action: FileIoAction::Rename,
+            kind: FileKind::File,
+            path: Utf8PathBuf::from(path.as_ref()),
+            err: Some(err.to_string()),
+        })
+        .map(|_| ())
+}
 
 pub fn copy_dir(
     path: impl AsRef<Utf8Path> + Debug,
@@ -672,6 +739,33 @@ pub fn git_init(path: &Utf8Path) -> Result<(), Error> {
     }
 }
 
+/// Run git with the given arguments and environment variables in the given
+/// directory, capturing its output so the caller can inspect the exit status.
+pub fn git(
+    args: &[&str],
+    cwd: &Utf8Path,
+    env: &[(&str, String)],
+) -> Result<std::process::Output, Error> {
+    tracing::trace!(args=?args.join(" "), cwd=?cwd, "running_git");
+
+    std::process::Command::new("git")
+        .args(args)
+        .envs(env.iter().cloned())
+        .stdin(std::process::Stdio::null())
+        .current_dir(cwd)
+        .output()
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => Error::ShellProgramNotFound {
+                program: "git".into(),
+            },
+
+            other => Error::ShellCommand {
+                program: "git".into(),
+                err: Some(other),
+            },
+        })
+}
+
 pub fn canonicalise(path: &Utf8Path) -> Result<Utf8PathBuf, Error> {
     std::fs::canonicalize(path)
         .map_err(|err| Error::FileIo {
@@ -683,6 +777,24 @@ pub fn canonicalise(path: &Utf8Path) -> Result<Utf8PathBuf, Error> {
         .map(|pb| Utf8PathBuf::from_path_buf(pb).expect("Non Utf8 Path"))
 }
 
+/// Whether an executable with this name can be found in a directory listed in
+/// the `PATH` environment variable.
+pub fn program_installed(program: &str) -> bool {
+    let extensions: &[&str] = if cfg!(windows) {
+        &["exe", "bat", "cmd"]
+    } else {
+        &[""]
+    };
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|directory| {
+        extensions
+            .iter()
+            .any(|extension| directory.join(program).with_extension(extension).is_file())
+    })
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ConsoleWarningEmitter;
 
@@ -20,7 +20,7 @@ pub trait ApiKeyCommand {
     ) -> Result<()>;
 
     fn run(&mut self) -> Result<()> {
-        let runtime = tokio::runtime::Runtime::new().expect("Unable to start Tokio async runtime");
+        let runtime = crate::http::async_runtime()?;
         let hostname = crate::publish::get_hostname();
         let hex_config = hexpm::Config::new();
         let http = HttpClient::new();
@@ -1,44 +1,869 @@
 use std::convert::TryInto;
 use std::sync::OnceLock;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use gleam_core::{Error, Result};
+use camino::Utf8PathBuf;
+use gleam_core::{
+    config::HexConfig,
+    error::{RedirectProblem, MAX_HTTP_REDIRECTS},
+    io::ResponseProgress,
+    version::COMPILER_VERSION,
+    Error, Result,
+};
 use http::{Request, Response};
 
-static REQWEST_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+static REQWEST_CLIENT: OnceLock<(Timeouts, Proxies, reqwest::Client)> = OnceLock::new();
+
+/// Start the async runtime used to make HTTP requests.
+pub fn async_runtime() -> Result<tokio::runtime::Runtime> {
+    tokio::runtime::Runtime::new().map_err(|error| Error::AsyncRuntimeStart {
+        error: error.to_string(),
+    })
+}
+
+/// How long a HTTP request may take before it is abandoned.
+///
+/// The connect timeout limits establishing a connection to the server, and
+/// the read timeout limits receiving the response once connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub read: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(30),
+            read: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Timeouts {
+    /// The default timeouts, overridden in seconds by the
+    /// `GLEAM_HTTP_CONNECT_TIMEOUT` and `GLEAM_HTTP_READ_TIMEOUT` environment
+    /// variables when they are set.
+    pub fn from_environment() -> Self {
+        let default = Self::default();
+        Self {
+            connect: environment_number("GLEAM_HTTP_CONNECT_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(default.connect),
+            read: environment_number("GLEAM_HTTP_READ_TIMEOUT")
+                .map(Duration::from_secs)
+                .unwrap_or(default.read),
+        }
+    }
+}
+
+/// The proxies HTTP requests are sent through.
+///
+/// By default these are read from the standard `HTTP_PROXY`, `HTTPS_PROXY`,
+/// and `NO_PROXY` environment variables. Proxy credentials can be given in
+/// the proxy URL or with the `GLEAM_HTTP_PROXY_USERNAME` and
+/// `GLEAM_HTTP_PROXY_PASSWORD` environment variables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Proxies {
+    pub http: Option<String>,
+    pub https: Option<String>,
+    pub no_proxy: Option<String>,
+    pub credentials: Option<ProxyCredentials>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Proxies {
+    pub fn from_environment() -> Self {
+        let credentials = std::env::var("GLEAM_HTTP_PROXY_USERNAME")
+            .ok()
+            .map(|username| ProxyCredentials {
+                username,
+                password: std::env::var("GLEAM_HTTP_PROXY_PASSWORD").unwrap_or_default(),
+            });
+        Self {
+            http: environment_variable("HTTP_PROXY"),
+            https: environment_variable("HTTPS_PROXY"),
+            no_proxy: environment_variable("NO_PROXY"),
+            credentials,
+        }
+    }
+
+    fn reqwest_proxies(&self) -> Vec<reqwest::Proxy> {
+        let no_proxy = self
+            .no_proxy
+            .as_deref()
+            .and_then(reqwest::NoProxy::from_string);
+        let http = self
+            .http
+            .as_deref()
+            .map(|url| (url, reqwest::Proxy::http(url)));
+        let https = self
+            .https
+            .as_deref()
+            .map(|url| (url, reqwest::Proxy::https(url)));
+        http.into_iter()
+            .chain(https)
+            .filter_map(|(url, proxy)| match proxy {
+                Ok(proxy) => Some(proxy),
+                Err(error) => {
+                    tracing::warn!(url = url, error = %error, "invalid_proxy_url");
+                    None
+                }
+            })
+            .map(|proxy| {
+                let proxy = proxy.no_proxy(no_proxy.clone());
+                match &self.credentials {
+                    Some(credentials) => {
+                        proxy.basic_auth(&credentials.username, &credentials.password)
+                    }
+                    None => proxy,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The headers sent with each HTTP request to identify the client.
+///
+/// Requests are sent with a `gleam/<version>` user agent, which can be
+/// replaced with `user_agent` in the `[hex]` section of gleam.toml or the
+/// `GLEAM_HTTP_USER_AGENT` environment variable. Setting
+/// `GLEAM_HTTP_NO_USAGE_HEADERS` sends a plain `gleam` user agent instead, so
+/// the version of Gleam being used is not reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHeaders {
+    pub user_agent: String,
+}
+
+impl Default for ClientHeaders {
+    fn default() -> Self {
+        Self {
+            user_agent: format!("gleam/{COMPILER_VERSION}"),
+        }
+    }
+}
+
+impl ClientHeaders {
+    pub fn from_environment() -> Self {
+        Self::from_variables(None, |name| std::env::var(name).ok())
+    }
+
+    /// The headers for the requests made for a project, which may set its own
+    /// user agent.
+    pub fn for_config(hex: &HexConfig) -> Self {
+        Self::from_variables(hex.user_agent.as_deref(), |name| std::env::var(name).ok())
+    }
+
+    fn from_variables(configured: Option<&str>, variable: impl Fn(&str) -> Option<String>) -> Self {
+        if variable("GLEAM_HTTP_NO_USAGE_HEADERS").is_some() {
+            return Self {
+                user_agent: "gleam".into(),
+            };
+        }
+        match variable("GLEAM_HTTP_USER_AGENT")
+            .or_else(|| configured.map(String::from))
+            .filter(|agent| !agent.is_empty())
+        {
+            Some(user_agent) => Self { user_agent },
+            None => Self::default(),
+        }
+    }
+
+    /// Replaces any identifying headers the request was built with, such as
+    /// the user agent of the Hex client library. An agent that can't be sent
+    /// as a header is replaced with the default one.
+    fn apply(&self, headers: &mut http::HeaderMap) {
+        let agent = http::HeaderValue::from_str(&self.user_agent).unwrap_or_else(|_| {
+            http::HeaderValue::from_str(&Self::default().user_agent).expect("Default user agent")
+        });
+        let _ = headers.insert(http::header::USER_AGENT, agent);
+    }
+}
+
+/// Proxy environment variables are conventionally either upper or lower case.
+fn environment_variable(name: &str) -> Option<String> {
+    std::env::var(name)
+        .or_else(|_| std::env::var(name.to_lowercase()))
+        .ok()
+        .filter(|value| !value.is_empty())
+}
+
+fn reqwest_client(timeouts: Timeouts, proxies: &Proxies) -> reqwest::Client {
+    // Proxies are configured explicitly rather than by reqwest so that they
+    // can be given credentials.
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(timeouts.connect)
+        .redirect(redirect_policy())
+        .no_proxy();
+    for proxy in proxies.reqwest_proxies() {
+        builder = builder.proxy(proxy);
+    }
+    builder.build().expect("Unable to build HTTP client")
+}
+
+/// Redirects are followed, such as when a package repository moves to a new
+/// CDN, but only a few times and never from HTTPS to HTTP.
+fn redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        let downgrade = attempt.url().scheme() == "http"
+            && attempt
+                .previous()
+                .last()
+                .is_some_and(|url| url.scheme() == "https");
+        if downgrade {
+            attempt.error(RedirectProblem::InsecureDowngrade)
+        } else if attempt.previous().len() > MAX_HTTP_REDIRECTS {
+            attempt.error(RedirectProblem::TooMany)
+        } else {
+            attempt.follow()
+        }
+    })
+}
 
 #[derive(Debug)]
-pub struct HttpClient;
+pub struct HttpClient {
+    client: reqwest::Client,
+    timeouts: Timeouts,
+    headers: ClientHeaders,
+}
 
 impl HttpClient {
     pub fn new() -> Self {
-        Self
+        Self::configured(Timeouts::from_environment(), Proxies::from_environment())
+            .with_headers(ClientHeaders::from_environment())
+    }
+
+    pub fn configured(timeouts: Timeouts, proxies: Proxies) -> Self {
+        // Clients with the same configuration as the first one share its
+        // connection pool
+        let (shared_timeouts, shared_proxies, shared_client) = REQWEST_CLIENT.get_or_init(|| {
+            let client = reqwest_client(timeouts, &proxies);
+            (timeouts, proxies.clone(), client)
+        });
+        let client = if *shared_timeouts == timeouts && *shared_proxies == proxies {
+            shared_client.clone()
+        } else {
+            reqwest_client(timeouts, &proxies)
+        };
+        Self {
+            client,
+            timeouts,
+            headers: ClientHeaders::default(),
+        }
+    }
+
+    pub fn with_headers(mut self, headers: ClientHeaders) -> Self {
+        self.headers = headers;
+        self
     }
 
-    pub fn boxed() -> Box<Self> {
-        Box::new(Self::new())
+    fn error(&self, url: &str, error: reqwest::Error) -> Error {
+        if error.is_redirect() {
+            let problem = std::error::Error::source(&error)
+                .and_then(|source| source.downcast_ref::<RedirectProblem>())
+                .copied()
+                .unwrap_or(RedirectProblem::TooMany);
+            return Error::HttpRedirect {
+                url: url.into(),
+                problem,
+            };
+        }
+        if !error.is_timeout() {
+            return Error::http(error);
+        }
+        let timeout = if error.is_connect() {
+            self.timeouts.connect
+        } else {
+            self.timeouts.read
+        };
+        Error::HttpTimeout {
+            url: url.into(),
+            timeout,
+        }
     }
 }
 
 #[async_trait]
 impl gleam_core::io::HttpClient for HttpClient {
-    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+    async fn send(&self, mut request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+        let url = request.uri().to_string();
+        self.headers.apply(request.headers_mut());
+        let progress = request.extensions().get::<ResponseProgress>().cloned();
         let request = request
             .try_into()
             .expect("Unable to convert HTTP request for use by reqwest library");
-        let mut response = REQWEST_CLIENT
-            .get_or_init(reqwest::Client::new)
-            .execute(request)
+
+        // reqwest only has a timeout for the whole request, which a large
+        // download on a slow connection could exceed, so instead the read
+        // timeout limits how long we wait for each part of the response.
+        let timed_out = |timeout| Error::HttpTimeout {
+            url: url.clone(),
+            timeout,
+        };
+        let headers_timeout = self.timeouts.connect + self.timeouts.read;
+        let mut response = tokio::time::timeout(headers_timeout, self.client.execute(request))
             .await
-            .map_err(Error::http)?;
+            .map_err(|_| timed_out(headers_timeout))?
+            .map_err(|error| self.error(&url, error))?;
+
         let mut builder = Response::builder()
             .status(response.status())
             .version(response.version());
         if let Some(headers) = builder.headers_mut() {
             std::mem::swap(headers, response.headers_mut());
         }
-        builder
-            .body(response.bytes().await.map_err(Error::http)?.to_vec())
+        let total = response.content_length();
+        let mut body = Vec::new();
+        while let Some(chunk) = tokio::time::timeout(self.timeouts.read, response.chunk())
+            .await
+            .map_err(|_| timed_out(self.timeouts.read))?
+            .map_err(|error| self.error(&url, error))?
+        {
+            body.extend_from_slice(&chunk);
+            if let Some(progress) = &progress {
+                progress.report(body.len() as u64, total);
+            }
+        }
+        builder.body(body).map_err(Error::http)
+    }
+}
+
+/// How failed HTTP requests are retried.
+///
+/// The delay before each retry doubles, starting from the base delay. With
+/// the default policy a request is retried after up to 200ms, 400ms, and
+/// 800ms. Each delay is randomly between half and all of that, so that many
+/// clients failing at once don't all retry at the same moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+/// The kinds of request made to Hex, which can be retried differently as
+/// package tarballs are much larger than the package metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    Metadata,
+    Download,
+}
+
+impl RequestKind {
+    fn variable_prefix(&self) -> &'static str {
+        match self {
+            Self::Metadata => "GLEAM_HTTP_METADATA",
+            Self::Download => "GLEAM_HTTP_DOWNLOAD",
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy for the kind of request, with the number of retries
+    /// and base delay in milliseconds overridden by the environment.
+    ///
+    /// The `GLEAM_HTTP_METADATA_RETRIES` and `GLEAM_HTTP_DOWNLOAD_RETRIES`
+    /// variables (and the matching `_RETRY_DELAY` variables) configure one
+    /// kind of request, falling back to `GLEAM_HTTP_RETRIES` and
+    /// `GLEAM_HTTP_RETRY_DELAY` which configure both.
+    pub fn from_environment(kind: RequestKind) -> Self {
+        Self::from_variables(kind, |name| std::env::var(name).ok())
+    }
+
+    fn from_variables(kind: RequestKind, variable: impl Fn(&str) -> Option<String>) -> Self {
+        let setting = |suffix: &str| {
+            let name = format!("{}_{suffix}", kind.variable_prefix());
+            variable(&name).map(|value| (name, value)).or_else(|| {
+                let name = format!("GLEAM_HTTP_{suffix}");
+                variable(&name).map(|value| (name, value))
+            })
+        };
+        let default = Self::default();
+        Self {
+            max_retries: setting("RETRIES")
+                .and_then(|(name, value)| parse_number(&name, value))
+                .unwrap_or(default.max_retries),
+            base_delay: setting("RETRY_DELAY")
+                .and_then(|(name, value)| parse_number(&name, value))
+                .map(Duration::from_millis)
+                .unwrap_or(default.base_delay),
+        }
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(retry))
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        jittered(self.backoff(retry), rand::random())
+    }
+}
+
+/// Somewhere between half and all of the delay, with a jitter from 0 to 1.
+fn jittered(delay: Duration, jitter: f64) -> Duration {
+    delay.mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+fn environment_number<T: std::str::FromStr>(name: &str) -> Option<T> {
+    parse_number(name, std::env::var(name).ok()?)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: String) -> Option<T> {
+    let number = value.parse().ok();
+    if number.is_none() {
+        tracing::warn!(
+            name = name,
+            value = value.as_str(),
+            "invalid_environment_variable"
+        );
+    }
+    number
+}
+
+/// A HTTP client that retries requests which fail due to connection problems
+/// or server errors. Client errors (4xx responses) are returned immediately as
+/// retrying will not change the outcome.
+#[derive(Debug)]
+pub struct RetryingHttpClient<Http> {
+    inner: Http,
+    policy: RetryPolicy,
+}
+
+impl RetryingHttpClient<HttpClient> {
+    pub fn new(kind: RequestKind) -> Self {
+        Self::wrapping(HttpClient::new(), RetryPolicy::from_environment(kind))
+    }
+
+    pub fn with_headers(mut self, headers: ClientHeaders) -> Self {
+        self.inner = self.inner.with_headers(headers);
+        self
+    }
+}
+
+impl<Http> RetryingHttpClient<Http> {
+    pub fn wrapping(inner: Http, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+#[async_trait]
+impl<Http> gleam_core::io::HttpClient for RetryingHttpClient<Http>
+where
+    Http: gleam_core::io::HttpClient + Send + Sync,
+{
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+        let mut retry = 0;
+        loop {
+            let delay = {
+                let result = self.inner.send(copy_request(&request)).await;
+                let transient = match &result {
+                    Ok(response) => response.status().is_server_error(),
+                    Err(Error::Http(_) | Error::HttpTimeout { .. }) => true,
+                    Err(_) => false,
+                };
+                if !transient || retry >= self.policy.max_retries {
+                    return result;
+                }
+                self.policy.delay(retry)
+            };
+            retry += 1;
+            tracing::debug!(uri = %request.uri(), retry = retry, delay = ?delay, "retrying_http_request");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// A HTTP client that answers requests from a snapshot of a Hex repository on
+/// disc rather than the network, so that resolving versions against it gives
+/// the same result however the repository changes.
+///
+/// The body of each response is read from the file at the request's path
+/// within the snapshot directory, such as `packages/gleam_stdlib` for the
+/// information of a package and `api/packages/gleam_stdlib/releases/0.34.0`
+/// for one of its releases.
+#[derive(Debug)]
+pub struct SnapshotHttpClient {
+    directory: Utf8PathBuf,
+}
+
+impl SnapshotHttpClient {
+    pub fn new(directory: Utf8PathBuf) -> Self {
+        Self { directory }
+    }
+}
+
+#[async_trait]
+impl gleam_core::io::HttpClient for SnapshotHttpClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+        let path = request.uri().path().trim_start_matches('/');
+        let body =
+            std::fs::read(self.directory.join(path)).map_err(|_| Error::NotInHexSnapshot {
+                snapshot: self.directory.clone(),
+                path: path.into(),
+            })?;
+        Response::builder()
+            .status(http::StatusCode::OK)
+            .body(body)
             .map_err(Error::http)
     }
 }
+
+fn copy_request(request: &Request<Vec<u8>>) -> Request<Vec<u8>> {
+    let mut copy = Request::new(request.body().clone());
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.version_mut() = request.version();
+    *copy.headers_mut() = request.headers().clone();
+    if let Some(progress) = request.extensions().get::<ResponseProgress>() {
+        let _ = copy.extensions_mut().insert(progress.clone());
+    }
+    copy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gleam_core::io::HttpClient as _;
+    use std::{collections::HashMap, sync::Mutex};
+
+    /// Responds to each request with the next status code in the script, or a
+    /// connection error where there is no status code.
+    #[derive(Debug)]
+    struct ScriptedHttpClient {
+        statuses: Mutex<Vec<Option<u16>>>,
+    }
+
+    impl ScriptedHttpClient {
+        fn new(mut statuses: Vec<Option<u16>>) -> Self {
+            statuses.reverse();
+            Self {
+                statuses: Mutex::new(statuses),
+            }
+        }
+
+        fn remaining(&self) -> usize {
+            self.statuses.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl gleam_core::io::HttpClient for &ScriptedHttpClient {
+        async fn send(&self, _request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+            let status = self
+                .statuses
+                .lock()
+                .unwrap()
+                .pop()
+                .expect("Unexpected HTTP request");
+            match status {
+                Some(status) => Ok(Response::builder().status(status).body(vec![]).unwrap()),
+                None => Err(Error::Http("connection reset".into())),
+            }
+        }
+    }
+
+    fn send(client: &ScriptedHttpClient) -> Result<Response<Vec<u8>>> {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let request = Request::get("https://hex.pm/api/packages/gleam_stdlib")
+            .body(vec![])
+            .unwrap();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(RetryingHttpClient::wrapping(client, policy).send(request))
+    }
+
+    #[test]
+    fn retry_succeeds_after_failures() {
+        let client = ScriptedHttpClient::new(vec![None, Some(503), Some(200)]);
+        assert_eq!(send(&client).unwrap().status(), 200);
+        assert_eq!(client.remaining(), 0);
+    }
+
+    #[test]
+    fn retry_client_errors_are_not_retried() {
+        let client = ScriptedHttpClient::new(vec![Some(404), Some(200)]);
+        assert_eq!(send(&client).unwrap().status(), 404);
+        assert_eq!(client.remaining(), 1);
+    }
+
+    #[test]
+    fn retry_gives_up_after_max_retries() {
+        let client =
+            ScriptedHttpClient::new(vec![Some(500), Some(500), Some(500), Some(502), Some(200)]);
+        assert_eq!(send(&client).unwrap().status(), 502);
+        assert_eq!(client.remaining(), 1);
+    }
+
+    #[test]
+    fn retry_policy_for_each_kind_of_request() {
+        let variables = HashMap::from([
+            ("GLEAM_HTTP_DOWNLOAD_RETRIES", "0"),
+            ("GLEAM_HTTP_RETRY_DELAY", "1"),
+        ]);
+        let variable = |name: &str| variables.get(name).map(|value| value.to_string());
+        let send = |kind| {
+            let client = ScriptedHttpClient::new(vec![Some(500), Some(500), Some(200)]);
+            let policy = RetryPolicy::from_variables(kind, variable);
+            let request = Request::get("https://repo.hex.pm/tarballs/gleam_stdlib-0.30.0.tar")
+                .body(vec![])
+                .unwrap();
+            let response = tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(RetryingHttpClient::wrapping(&client, policy).send(request))
+                .unwrap();
+            (response.status().as_u16(), client.remaining())
+        };
+
+        // Downloads are attempted once, leaving the other responses unused
+        assert_eq!(send(RequestKind::Download), (500, 2));
+        assert_eq!(send(RequestKind::Metadata), (200, 0));
+    }
+
+    #[test]
+    fn retry_delay_doubles() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(200));
+        assert_eq!(policy.backoff(1), Duration::from_millis(400));
+        assert_eq!(policy.backoff(2), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn retry_delay_is_jittered() {
+        let delay = Duration::from_millis(400);
+        assert_eq!(jittered(delay, 0.0), Duration::from_millis(200));
+        assert_eq!(jittered(delay, 0.5), Duration::from_millis(300));
+        assert_eq!(jittered(delay, 1.0), Duration::from_millis(400));
+
+        let policy = RetryPolicy::default();
+        for retry in 0..3 {
+            for _ in 0..100 {
+                let delay = policy.delay(retry);
+                let backoff = policy.backoff(retry);
+                assert!(delay >= backoff / 2 && delay <= backoff, "{delay:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn slow_responses_time_out() {
+        // A server which accepts connections but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/api/packages/gleam_stdlib",
+            listener.local_addr().unwrap()
+        );
+        let _server = std::thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_secs(5));
+        });
+
+        let timeouts = Timeouts {
+            connect: Duration::from_millis(100),
+            read: Duration::from_millis(100),
+        };
+        let request = Request::get(&url).body(vec![]).unwrap();
+        let result = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(HttpClient::configured(timeouts, Proxies::default()).send(request));
+        assert_eq!(
+            result.unwrap_err(),
+            Error::HttpTimeout {
+                url,
+                timeout: Duration::from_millis(200),
+            }
+        );
+    }
+
+    /// The request received by a server when it is sent by a client with the
+    /// given headers, in lower case.
+    fn received_request(headers: ClientHeaders) -> String {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/api/packages/gleam_stdlib",
+            listener.local_addr().unwrap()
+        );
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            while !request.ends_with("\r\n\r\n") {
+                let _ = reader.read_line(&mut request).unwrap();
+            }
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            request.to_lowercase()
+        });
+
+        // Built as the Hex client library builds requests, with its own agent
+        let request = Request::get(&url)
+            .header("user-agent", "hexpm (2.1.1)")
+            .body(vec![])
+            .unwrap();
+        let client =
+            HttpClient::configured(Timeouts::default(), Proxies::default()).with_headers(headers);
+        let _ = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(client.send(request))
+            .unwrap();
+        server.join().unwrap()
+    }
+
+    #[test]
+    fn user_agent_is_sent_as_configured() {
+        let request = received_request(ClientHeaders::default());
+        assert!(request.contains(&format!("user-agent: gleam/{COMPILER_VERSION}\r\n")));
+
+        let headers = ClientHeaders::from_variables(Some("wobble-mirror/2.0"), |_| None);
+        let request = received_request(headers);
+        assert!(request.contains("user-agent: wobble-mirror/2.0\r\n"));
+        assert!(!request.contains("hexpm"));
+
+        // The environment takes precedence over gleam.toml
+        let variables = HashMap::from([("GLEAM_HTTP_USER_AGENT", "wibble-mirror/1.0")]);
+        let headers = ClientHeaders::from_variables(Some("wobble-mirror/2.0"), |name| {
+            variables.get(name).map(|value| value.to_string())
+        });
+        let request = received_request(headers);
+        assert!(request.contains("user-agent: wibble-mirror/1.0\r\n"));
+
+        let variables = HashMap::from([
+            ("GLEAM_HTTP_USER_AGENT", "wibble-mirror/1.0"),
+            ("GLEAM_HTTP_NO_USAGE_HEADERS", "1"),
+        ]);
+        let headers = ClientHeaders::from_variables(Some("wobble-mirror/2.0"), |name| {
+            variables.get(name).map(|value| value.to_string())
+        });
+        let request = received_request(headers);
+        assert!(request.contains("user-agent: gleam\r\n"));
+    }
+
+    #[test]
+    fn requests_are_sent_through_the_proxy() {
+        use std::io::{BufRead, BufReader, Write};
+
+        // A proxy which records the request and responds to it itself
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            while !request.ends_with("\r\n\r\n") {
+                let _ = reader.read_line(&mut request).unwrap();
+            }
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .unwrap();
+            request.to_lowercase()
+        });
+
+        let proxies = Proxies {
+            http: Some(proxy),
+            https: None,
+            no_proxy: None,
+            credentials: Some(ProxyCredentials {
+                username: "lucy".into(),
+                password: "secret".into(),
+            }),
+        };
+        let request = Request::get("http://repo.hex.pm/packages/gleam_stdlib")
+            .body(vec![])
+            .unwrap();
+        let response = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(HttpClient::configured(Timeouts::default(), proxies).send(request))
+            .unwrap();
+        assert_eq!(response.body(), b"ok");
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("get http://repo.hex.pm/packages/gleam_stdlib http/1.1\r\n"));
+        // "lucy:secret" in base64
+        assert!(request.contains("proxy-authorization: basic bhvjetpzzwnyzxq=\r\n"));
+    }
+
+    /// Serves HTTP requests with the response for the path of each, returning
+    /// the address of the server.
+    fn serve(respond: impl Fn(&str) -> String + Send + 'static) -> String {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let _server = std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request = String::new();
+                while !request.ends_with("\r\n\r\n") {
+                    let _ = reader.read_line(&mut request).unwrap();
+                }
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                reader
+                    .get_mut()
+                    .write_all(respond(path).as_bytes())
+                    .unwrap();
+            }
+        });
+        address
+    }
+
+    fn redirect_to(location: &str) -> String {
+        format!(
+            "HTTP/1.1 302 Found\r\nlocation: {location}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+        )
+    }
+
+    fn get(url: &str) -> Result<Response<Vec<u8>>> {
+        let request = Request::get(url).body(vec![]).unwrap();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(HttpClient::configured(Timeouts::default(), Proxies::default()).send(request))
+    }
+
+    #[test]
+    fn redirects_are_followed() {
+        let address = serve(|path| match path {
+            "/tarballs/gleam_stdlib-0.30.0.tar" => redirect_to("/cdn/gleam_stdlib-0.30.0.tar"),
+            _ => "HTTP/1.1 200 OK\r\ncontent-length: 7\r\nconnection: close\r\n\r\ntarball".into(),
+        });
+
+        let response = get(&format!("{address}/tarballs/gleam_stdlib-0.30.0.tar")).unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body(), b"tarball");
+    }
+
+    #[test]
+    fn too_many_redirects_are_rejected() {
+        let address = serve(|_| redirect_to("/tarballs/gleam_stdlib-0.30.0.tar"));
+        let url = format!("{address}/tarballs/gleam_stdlib-0.30.0.tar");
+
+        assert_eq!(
+            get(&url).unwrap_err(),
+            Error::HttpRedirect {
+                url,
+                problem: RedirectProblem::TooMany,
+            }
+        );
+    }
+}
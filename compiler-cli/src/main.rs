@@ -73,7 +73,10 @@ mod run;
 mod shell;
 
 use config::root_config;
-use dependencies::UseManifest;
+use dependencies::{
+    AllowDowngrade, BuildFilter, CleanCache, DownloadOptions, DryRun, GraphFormat, ListFormat,
+    Refresh, UseManifest, UseNetwork, Verify,
+};
 use fs::{get_current_directory, get_project_root};
 pub use gleam_core::error::{Error, Result};
 
@@ -134,7 +137,21 @@ enum Command {
     Deps(Dependencies),
 
     /// Update dependency packages to their latest versions
-    Update,
+    Update {
+        /// The packages to update, keeping the others at their current
+        /// versions. Every package is updated if none are given
+        packages: Vec<String>,
+
+        /// Use lower versions of packages than the ones in manifest.toml if
+        /// the newer versions can no longer be used
+        #[clap(long)]
+        allow_downgrade: bool,
+
+        /// The gleam.toml of the project to use instead of the one for the
+        /// current directory
+        #[clap(long, value_name = "PATH")]
+        manifest_path: Option<Utf8PathBuf>,
+    },
 
     /// Work with the Hex package manager
     #[clap(subcommand)]
@@ -210,6 +227,16 @@ enum Command {
         /// Add the packages as dev-only dependencies
         #[clap(long)]
         dev: bool,
+
+        /// How much the version requirements allow the selected versions to
+        /// be upgraded
+        #[clap(
+            long,
+            possible_values = add::RequirementStyle::VARIANTS,
+            ignore_case = true,
+            default_value = "minor"
+        )]
+        requirement: add::RequirementStyle,
     },
 
     /// Remove project dependencies
@@ -304,14 +331,278 @@ pub struct CompilePackage {
 
 #[derive(Subcommand, Debug)]
 enum Dependencies {
+    /// Add new project dependencies, the same as `gleam add`
+    Add {
+        /// The names of Hex packages to add
+        #[clap(required = true)]
+        packages: Vec<String>,
+
+        /// Add the packages as dev-only dependencies
+        #[clap(long)]
+        dev: bool,
+
+        /// How much the version requirements allow the selected versions to
+        /// be upgraded
+        #[clap(
+            long,
+            possible_values = add::RequirementStyle::VARIANTS,
+            ignore_case = true,
+            default_value = "minor"
+        )]
+        requirement: add::RequirementStyle,
+    },
+
     /// List all dependency packages
-    List,
+    List {
+        /// Print the packages as JSON, sorted by name
+        #[clap(long)]
+        json: bool,
+
+        /// The gleam.toml of the project to use instead of the one for the
+        /// current directory
+        #[clap(long, value_name = "PATH")]
+        manifest_path: Option<Utf8PathBuf>,
+    },
+
+    /// Print the dependency packages as a tree
+    ///
+    /// The direct dependencies of the project are at the top level of the
+    /// tree. Packages that have already been printed are marked with (*) and
+    /// dependency cycles are marked with (cycle).
+    #[clap(verbatim_doc_comment)]
+    Tree,
+
+    /// Print the graph of dependency packages for rendering with other tools
+    ///
+    /// Each package is labelled with its version and the direct dependencies
+    /// of the project are drawn in bold.
+    #[clap(verbatim_doc_comment)]
+    Graph {
+        /// The format of the graph
+        #[clap(
+            long,
+            possible_values = GraphFormat::VARIANTS,
+            ignore_case = true,
+            default_value = "dot"
+        )]
+        format: GraphFormat,
+
+        /// Write the graph to this file instead of printing it
+        #[clap(short, long)]
+        output: Option<Utf8PathBuf>,
+    },
+
+    /// Print the chains of dependencies that cause a package to be included
+    ///
+    /// Each chain starts at the project. Links from the project to its dev
+    /// dependencies are marked with [dev].
+    #[clap(verbatim_doc_comment)]
+    Why {
+        /// The name of the package
+        package: String,
+    },
+
+    /// List the direct dependencies that no module of the project imports
+    ///
+    /// Packages that are not built with Gleam are never listed as they are
+    /// used through externals. Dev dependencies are marked with [dev].
+    #[clap(verbatim_doc_comment)]
+    Unused,
+
+    /// Print the licences of the dependency packages
+    ///
+    /// Packages that declare no licence, or none of the allowed licences when
+    /// any are given, are flagged, and the command fails if there are any when
+    /// licences are allowed.
+    #[clap(verbatim_doc_comment, visible_alias = "licenses")]
+    Licences {
+        /// A SPDX licence identifier that is allowed, which may be given
+        /// multiple times
+        #[clap(long = "allow")]
+        allowed: Vec<String>,
+    },
 
     /// Download all dependency packages
-    Download,
+    ///
+    /// This command uses this environment variables:
+    ///
+    /// - GLEAM_DOWNLOAD_CONCURRENCY: (optional) The maximum number of packages
+    ///   to download or look up on Hex at once. Defaults to 8.
+    /// - GLEAM_HTTP_RETRIES: (optional) The number of times a failed request to
+    ///   Hex is retried. Defaults to 3.
+    /// - GLEAM_HTTP_RETRY_DELAY: (optional) The delay in milliseconds before the
+    ///   first retry, doubling for each further retry. Defaults to 200.
+    /// - GLEAM_HTTP_DOWNLOAD_RETRIES, GLEAM_HTTP_DOWNLOAD_RETRY_DELAY: (optional)
+    ///   The retries and delay for package downloads only.
+    /// - GLEAM_HTTP_METADATA_RETRIES, GLEAM_HTTP_METADATA_RETRY_DELAY: (optional)
+    ///   The retries and delay for package metadata lookups only.
+    /// - GLEAM_REJECT_RETIRED_RELEASES: (optional) When set, releases retired on
+    ///   Hex are never selected, even if they are in the manifest.
+    /// - GLEAM_CACHE: (optional) The directory that downloaded packages are
+    ///   cached in, shared by all projects. Defaults to the user cache directory.
+    /// - GLEAM_GIT_SSH_KEY: (optional) The path of an SSH key file used to
+    ///   fetch git dependencies over SSH. Defaults to the SSH agent.
+    #[clap(verbatim_doc_comment)]
+    Download {
+        /// Use only the manifest and locally cached packages, never
+        /// contacting Hex
+        #[clap(long)]
+        offline: bool,
+
+        /// Print the packages that would be added and removed without
+        /// changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Check packages already in the build directory against the
+        /// checksums in the manifest, replacing any that have been modified
+        #[clap(long)]
+        verify: bool,
+
+        /// Fail instead of updating manifest.toml if it is missing or out of
+        /// date with gleam.toml
+        #[clap(long)]
+        frozen: bool,
+
+        /// Download Hex packages again even if they are already present,
+        /// discarding the cached copies. All packages are refreshed unless
+        /// some are named. This needs network access, so it can't be used
+        /// with --offline
+        #[clap(
+            long,
+            value_name = "PACKAGE",
+            min_values = 0,
+            use_value_delimiter = true,
+            conflicts_with = "offline"
+        )]
+        refresh: Option<Vec<String>>,
+
+        /// The gleam.toml of the project to use instead of the one for the
+        /// current directory
+        #[clap(long, value_name = "PATH")]
+        manifest_path: Option<Utf8PathBuf>,
+
+        /// Only print warnings and errors, not the progress of resolving and
+        /// downloading packages
+        #[clap(long, short)]
+        quiet: bool,
+
+        /// Download only these packages and the packages they depend on,
+        /// leaving the others as they are
+        #[clap(
+            long,
+            value_name = "PACKAGE",
+            use_value_delimiter = true,
+            conflicts_with_all = &["dry-run", "verify", "frozen", "refresh"]
+        )]
+        only: Vec<String>,
+    },
 
     /// Update dependency packages to their latest versions
-    Update,
+    Update {
+        /// The packages to update, keeping the others at their current
+        /// versions. Every package is updated if none are given
+        packages: Vec<String>,
+
+        /// Print the packages that would be added and removed without
+        /// changing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Use lower versions of packages than the ones in manifest.toml if
+        /// the newer versions can no longer be used
+        #[clap(long)]
+        allow_downgrade: bool,
+
+        /// The gleam.toml of the project to use instead of the one for the
+        /// current directory
+        #[clap(long, value_name = "PATH")]
+        manifest_path: Option<Utf8PathBuf>,
+    },
+
+    /// Resolve the dependency versions and write them to manifest.toml
+    /// without downloading any packages
+    Lock,
+
+    /// Resolve the versions of the requirements read from standard input and
+    /// print the resulting manifest, without reading or writing any project
+    /// files
+    ///
+    /// The requirements use the same format as the dependencies table of
+    /// gleam.toml, for example `gleam_stdlib = "~> 0.30"`.
+    #[clap(verbatim_doc_comment)]
+    Resolve,
+
+    /// Copy the source of every dependency package into the vendor directory
+    /// and use those copies in place of the original sources
+    ///
+    /// This lets the project be built without fetching any packages. Running
+    /// it again only copies packages that have not been vendored yet.
+    #[clap(verbatim_doc_comment)]
+    Vendor,
+
+    /// Print the Hex settings used for the project, such as the repository
+    /// URLs, proxies, and cache directory
+    ///
+    /// The settings come from the environment, the Hex credentials file, and
+    /// gleam.toml. API keys are shown as `***` when they are set.
+    #[clap(verbatim_doc_comment)]
+    Config,
+
+    /// List the newer versions of the dependency packages available on Hex
+    ///
+    /// The compatible column is the highest version that satisfies the
+    /// requirement in gleam.toml, and is only shown for direct dependencies.
+    /// The manifest is not changed.
+    #[clap(verbatim_doc_comment)]
+    Outdated,
+
+    /// Check the dependency requirements in gleam.toml without resolving them
+    Validate,
+
+    /// Check manifest.toml is up to date with gleam.toml without contacting
+    /// Hex
+    Check,
+
+    /// Print whether manifest.toml is up-to-date, outdated, or missing, so
+    /// that tools can tell if the dependencies need to be resolved again
+    Status,
+
+    /// Check every Hex package in the build directory against the checksum
+    /// in manifest.toml, reporting all that do not match
+    Verify,
+
+    /// Remove project dependencies
+    ///
+    /// Packages that were only needed by the removed dependencies are removed
+    /// from the manifest too.
+    #[clap(verbatim_doc_comment)]
+    Remove {
+        /// The names of packages to remove
+        #[clap(required = true)]
+        packages: Vec<String>,
+    },
+
+    /// Delete packages and build artefacts that are no longer in the manifest
+    ///
+    /// When a target or mode is given only the build artefacts for it are
+    /// deleted, and the packages are kept for the other builds. With the prod
+    /// mode the artefacts of packages only needed for development are also
+    /// deleted.
+    #[clap(verbatim_doc_comment)]
+    Clean {
+        /// Also delete the global cache of downloaded Hex packages
+        #[clap(long)]
+        cache: bool,
+
+        /// Only delete build artefacts for this target
+        #[clap(short, long, ignore_case = true)]
+        target: Option<Target>,
+
+        /// Only delete build artefacts for this mode
+        #[clap(long, ignore_case = true)]
+        mode: Option<Mode>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -407,11 +698,123 @@ fn main() {
 
         Command::Fix => fix::run(),
 
-        Command::Deps(Dependencies::List) => dependencies::list(),
+        Command::Deps(Dependencies::Add {
+            packages,
+            dev,
+            requirement,
+        }) => add::command(packages, dev, requirement),
+
+        Command::Deps(Dependencies::List {
+            json,
+            manifest_path,
+        }) => {
+            let format = if json {
+                ListFormat::Json
+            } else {
+                ListFormat::Text
+            };
+            project_paths_for(manifest_path).and_then(|paths| dependencies::list(&paths, format))
+        }
 
-        Command::Deps(Dependencies::Download) => download_dependencies(),
+        Command::Deps(Dependencies::Tree) => dependencies::tree(),
 
-        Command::Deps(Dependencies::Update) => dependencies::update(),
+        Command::Deps(Dependencies::Graph { format, output }) => {
+            dependencies::graph(format, output)
+        }
+
+        Command::Deps(Dependencies::Why { package }) => dependencies::why(package),
+
+        Command::Deps(Dependencies::Unused) => dependencies::unused(),
+
+        Command::Deps(Dependencies::Licences { allowed }) => dependencies::licences(allowed),
+
+        Command::Deps(Dependencies::Download {
+            offline,
+            dry_run,
+            verify,
+            frozen,
+            refresh,
+            manifest_path,
+            quiet,
+            only,
+        }) => {
+            let use_manifest = if frozen {
+                UseManifest::Frozen
+            } else {
+                UseManifest::Yes
+            };
+            let use_network = if offline {
+                UseNetwork::No
+            } else {
+                UseNetwork::Yes
+            };
+            let verify = if verify { Verify::Yes } else { Verify::No };
+            let refresh = match refresh {
+                None => Refresh::None,
+                Some(packages) if packages.is_empty() => Refresh::All,
+                Some(packages) => Refresh::Packages(packages),
+            };
+            if only.is_empty() {
+                let options = DownloadOptions {
+                    use_manifest,
+                    use_network,
+                    dry_run: dry_run_flag(dry_run),
+                    verify,
+                    refresh,
+                    ..Default::default()
+                };
+                download_dependencies(manifest_path, quiet, options)
+            } else {
+                download_only_dependencies(manifest_path, quiet, &only, use_network)
+            }
+        }
+
+        Command::Deps(Dependencies::Update {
+            packages,
+            dry_run,
+            allow_downgrade,
+            manifest_path,
+        }) => project_paths_for(manifest_path).and_then(|paths| {
+            dependencies::update(
+                &paths,
+                packages,
+                dry_run_flag(dry_run),
+                allow_downgrade_flag(allow_downgrade),
+            )
+        }),
+
+        Command::Deps(Dependencies::Lock) => dependencies::lock(),
+
+        Command::Deps(Dependencies::Resolve) => dependencies::resolve(),
+
+        Command::Deps(Dependencies::Vendor) => dependencies::vendor(),
+
+        Command::Deps(Dependencies::Config) => dependencies::config(),
+
+        Command::Deps(Dependencies::Outdated) => dependencies::outdated(),
+
+        Command::Deps(Dependencies::Validate) => dependencies::validate(),
+
+        Command::Deps(Dependencies::Check) => dependencies::check(),
+
+        Command::Deps(Dependencies::Status) => dependencies::status(),
+
+        Command::Deps(Dependencies::Verify) => dependencies::verify(),
+
+        Command::Deps(Dependencies::Remove { packages }) => remove::command(packages),
+
+        Command::Deps(Dependencies::Clean {
+            cache,
+            target,
+            mode,
+        }) => {
+            let clean_cache = if cache {
+                CleanCache::Yes
+            } else {
+                CleanCache::No
+            };
+            dependencies::clean(clean_cache, BuildFilter { mode, target })
+        }
 
         Command::New(options) => new::create(options, COMPILER_VERSION),
 
@@ -447,11 +850,26 @@ fn main() {
             hex::UnretireCommand::new(package, version).run()
         }
 
-        Command::Add { packages, dev } => add::command(packages, dev),
+        Command::Add {
+            packages,
+            dev,
+            requirement,
+        } => add::command(packages, dev, requirement),
 
         Command::Remove { packages } => remove::command(packages),
 
-        Command::Update => dependencies::update(),
+        Command::Update {
+            packages,
+            allow_downgrade,
+            manifest_path,
+        } => project_paths_for(manifest_path).and_then(|paths| {
+            dependencies::update(
+                &paths,
+                packages,
+                DryRun::No,
+                allow_downgrade_flag(allow_downgrade),
+            )
+        }),
 
         Command::Clean => clean(),
 
@@ -485,7 +903,7 @@ fn command_check(target: Option<Target>) -> Result<(), Error> {
             mode: Mode::Dev,
             target,
         },
-        build::download_dependencies()?,
+        build::download_dependencies(Mode::Dev)?,
     )?;
     Ok(())
 }
@@ -498,7 +916,7 @@ fn command_build(target: Option<Target>, warnings_as_errors: bool) -> Result<(),
             mode: Mode::Dev,
             target,
         },
-        build::download_dependencies()?,
+        build::download_dependencies(Mode::Dev)?,
     )?;
     Ok(())
 }
@@ -530,14 +948,69 @@ fn find_project_paths() -> Result<ProjectPaths> {
     get_project_root(current_dir).map(ProjectPaths::new)
 }
 
+/// The paths of the project with the given gleam.toml, or of the project
+/// containing the current directory when no gleam.toml is given. The
+/// directory of a project may be given instead of its gleam.toml.
+fn project_paths_for(manifest_path: Option<Utf8PathBuf>) -> Result<ProjectPaths> {
+    let Some(path) = manifest_path else {
+        return find_project_paths();
+    };
+    let root = match path.parent() {
+        Some(parent) if !path.is_dir() => parent.to_path_buf(),
+        _ => path,
+    };
+    let paths = ProjectPaths::new(root);
+    config::ensure_config_exists(&paths)?;
+    Ok(paths)
+}
+
 #[cfg(test)]
 fn project_paths_at_current_directory_without_toml() -> ProjectPaths {
     let current_dir = get_current_directory().expect("Failed to get current directory");
     ProjectPaths::new(current_dir)
 }
 
-fn download_dependencies() -> Result<(), Error> {
-    let paths = find_project_paths()?;
-    _ = dependencies::download(&paths, cli::Reporter::new(), None, UseManifest::Yes)?;
+fn dry_run_flag(dry_run: bool) -> DryRun {
+    if dry_run {
+        DryRun::Yes
+    } else {
+        DryRun::No
+    }
+}
+
+fn download_only_dependencies(
+    manifest_path: Option<Utf8PathBuf>,
+    quiet: bool,
+    only: &[String],
+    use_network: UseNetwork,
+) -> Result<(), Error> {
+    let paths = project_paths_for(manifest_path)?;
+    if quiet {
+        _ = dependencies::download_only(&paths, cli::QuietReporter::new(), only, use_network)?;
+    } else {
+        _ = dependencies::download_only(&paths, cli::Reporter::new(), only, use_network)?;
+    }
+    Ok(())
+}
+
+fn allow_downgrade_flag(allow_downgrade: bool) -> AllowDowngrade {
+    if allow_downgrade {
+        AllowDowngrade::Yes
+    } else {
+        AllowDowngrade::No
+    }
+}
+
+fn download_dependencies(
+    manifest_path: Option<Utf8PathBuf>,
+    quiet: bool,
+    options: DownloadOptions,
+) -> Result<(), Error> {
+    let paths = project_paths_for(manifest_path)?;
+    if quiet {
+        _ = dependencies::download(&paths, cli::QuietReporter::new(), None, Mode::Dev, options)?;
+    } else {
+        _ = dependencies::download(&paths, cli::Reporter::new(), None, Mode::Dev, options)?;
+    }
     Ok(())
 }
@@ -190,7 +190,7 @@ fn do_build_hex_tarball(paths: &ProjectPaths, config: &PackageConfig) -> Result<
             target: Some(target),
             codegen: Codegen::All,
         },
-        build::download_dependencies()?,
+        build::download_dependencies(Mode::Dev)?,
     )?;
 
     let generated_files = match target {
@@ -252,7 +252,7 @@ fn metadata_config<'a>(
         .dependencies
         .iter()
         .map(|(name, requirement)| match requirement {
-            Requirement::Hex { version } => Ok(ReleaseRequirement {
+            Requirement::Hex { version, .. } => Ok(ReleaseRequirement {
                 name,
                 requirement: version,
             }),
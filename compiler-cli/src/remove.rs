@@ -1,11 +1,12 @@
 use camino::{Utf8Path, Utf8PathBuf};
 
 use gleam_core::{
+    build::Mode,
     error::{FileIoAction, FileKind},
     Error, Result,
 };
 
-use crate::{cli, fs, UseManifest};
+use crate::{cli, dependencies::DownloadOptions, fs};
 
 pub fn command(packages: Vec<String>) -> Result<()> {
     // Read gleam.toml so we can remove deps from it
@@ -19,24 +20,77 @@ pub fn command(packages: Vec<String>) -> Result<()> {
         })?;
 
     // Remove the specified dependencies
-    for package_to_remove in packages.iter() {
-        #[allow(clippy::indexing_slicing)]
-        let _ = toml["dependencies"]
-            .as_table_mut()
-            .and_then(|deps| deps.remove(package_to_remove));
-        #[allow(clippy::indexing_slicing)]
-        let _ = toml["dev-dependencies"]
-            .as_table_mut()
-            .and_then(|deps| deps.remove(package_to_remove));
-    }
+    remove_requirements(&mut toml, &packages)?;
 
-    // Write the updated config
+    // Write the updated config, and then resolve the remaining requirements so
+    // that packages only needed by the removed ones are removed too
     fs::write(Utf8Path::new("gleam.toml"), &toml.to_string())?;
     let paths = crate::find_project_paths()?;
-    _ = crate::dependencies::download(&paths, cli::Reporter::new(), None, UseManifest::Yes)?;
+    _ = crate::dependencies::download(
+        &paths,
+        cli::Reporter::new(),
+        None,
+        Mode::Dev,
+        DownloadOptions::default(),
+    )?;
     for package_to_remove in packages {
         cli::print_removed(&package_to_remove);
     }
 
     Ok(())
 }
+
+/// Remove the packages from the dependencies and dev-dependencies of the
+/// gleam.toml document, erroring if any of them are in neither.
+fn remove_requirements(toml: &mut toml_edit::Document, packages: &[String]) -> Result<()> {
+    for package_to_remove in packages {
+        let mut removed = false;
+        for table in ["dependencies", "dev-dependencies"] {
+            removed |= toml
+                .get_mut(table)
+                .and_then(|deps| deps.as_table_like_mut())
+                .and_then(|deps| deps.remove(package_to_remove))
+                .is_some();
+        }
+        if !removed {
+            return Err(Error::NotADependency {
+                package: package_to_remove.as_str().into(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn remove_requirements_removes_direct_dependencies() {
+    let mut toml: toml_edit::Document = r#"name = "app"
+
+[dependencies]
+gleam_stdlib = "~> 0.34"
+gleam_json = "~> 1.0"
+
+[dev-dependencies]
+gleeunit = "~> 1.0"
+"#
+    .parse()
+    .unwrap();
+
+    remove_requirements(&mut toml, &["gleam_json".into(), "gleeunit".into()]).unwrap();
+    assert_eq!(
+        toml.to_string(),
+        r#"name = "app"
+
+[dependencies]
+gleam_stdlib = "~> 0.34"
+
+[dev-dependencies]
+"#
+    );
+
+    assert_eq!(
+        remove_requirements(&mut toml, &["gleam_json".into()]),
+        Err(Error::NotADependency {
+            package: "gleam_json".into()
+        })
+    );
+}
@@ -39,7 +39,7 @@ pub fn command(
     };
 
     // Download dependencies
-    let manifest = crate::build::download_dependencies()?;
+    let manifest = crate::build::download_dependencies(Mode::Dev)?;
 
     // Get the config for the module that is being run to check the target.
     let mod_config = match &module {
@@ -15,7 +15,7 @@ pub fn command() -> Result<(), Error> {
             mode: Mode::Dev,
             target: Some(Target::Erlang),
         },
-        crate::build::download_dependencies()?,
+        crate::build::download_dependencies(Mode::Dev)?,
     )?;
 
     // Don't exit on ctrl+c as it is used by child erlang shell
@@ -14,7 +14,10 @@ mod tests;
 pub use self::package_compiler::PackageCompiler;
 pub use self::package_loader::StaleTracker;
 pub use self::project_compiler::{Built, Options, ProjectCompiler};
-pub use self::telemetry::{NullTelemetry, Telemetry};
+pub use self::telemetry::{
+    ChannelTelemetry, NullTelemetry, ResolutionPhase, Telemetry, TelemetryEvent,
+    TelemetryEventHandler, TimestampedEvent,
+};
 
 use crate::ast::{
     CustomType, DefinitionLocation, TypedArg, TypedDefinition, TypedExpr, TypedFunction,
@@ -1,15 +1,52 @@
 use std::{
     fmt::Debug,
-    time::{Duration, Instant},
+    sync::mpsc::Sender,
+    time::{Duration, Instant, SystemTime},
 };
 
-use crate::Warning;
+use ecow::EcoString;
+use hexpm::{version::Version, RetirementStatus};
+
+use crate::{
+    manifest::{MissingPackageReason, PackageChange},
+    Error, Warning,
+};
 
 pub trait Telemetry: Debug {
     fn waiting_for_build_directory_lock(&self);
     fn resolving_package_versions(&self);
+    /// Called when versions are resolved again with the packages that differ
+    /// from the previous manifest.
+    fn resolved_package_changes(&self, changes: &[PackageChange]);
+    /// Called when a release that has been retired on Hex is resolved.
+    fn retired_package_resolved(
+        &self,
+        name: &str,
+        version: &Version,
+        retirement: &RetirementStatus,
+    );
+    /// Called when a package is resolved with the `>= 0.0.0` requirement,
+    /// which permits any version of it to be used.
+    fn unbounded_requirement_resolved(&self, name: &str, version: &Version);
+    /// Called when the files of a package that is no longer needed could not
+    /// be deleted.
+    fn extra_package_not_deleted(&self, name: &str, error: &Error);
+    /// Called when more than one of the resolved packages provides the same
+    /// OTP application.
+    fn duplicate_otp_app_resolved(&self, otp_app: &str, packages: &[EcoString]);
+    /// Called when a phase of resolving the versions of the dependencies has
+    /// finished, with how long it took.
+    fn resolution_phase_finished(&self, phase: ResolutionPhase, elapsed: Duration);
     fn downloading_package(&self, name: &str);
-    fn packages_downloaded(&self, start: Instant, count: usize);
+    /// Called for each package that is to be put into the build directory,
+    /// with why it is needed.
+    fn package_missing(&self, name: &str, reason: &MissingPackageReason);
+    /// Called as a package is downloaded with the number of bytes of its
+    /// tarball that have been received so far and the size of the tarball.
+    fn package_progress(&self, name: &str, bytes: u64, total: u64);
+    /// Called once packages have been downloaded with the total size of their
+    /// tarballs.
+    fn packages_downloaded(&self, start: Instant, count: usize, bytes: u64);
     fn compiling_package(&self, name: &str);
     fn checking_package(&self, name: &str);
 }
@@ -20,8 +57,253 @@ pub struct NullTelemetry;
 impl Telemetry for NullTelemetry {
     fn waiting_for_build_directory_lock(&self) {}
     fn resolving_package_versions(&self) {}
+    fn resolved_package_changes(&self, _changes: &[PackageChange]) {}
+    fn retired_package_resolved(
+        &self,
+        _name: &str,
+        _version: &Version,
+        _retirement: &RetirementStatus,
+    ) {
+    }
+    fn unbounded_requirement_resolved(&self, _name: &str, _version: &Version) {}
+    fn extra_package_not_deleted(&self, _name: &str, _error: &Error) {}
+    fn duplicate_otp_app_resolved(&self, _otp_app: &str, _packages: &[EcoString]) {}
+    fn resolution_phase_finished(&self, _phase: ResolutionPhase, _elapsed: Duration) {}
     fn downloading_package(&self, _name: &str) {}
+    fn package_missing(&self, _name: &str, _reason: &MissingPackageReason) {}
+    fn package_progress(&self, _name: &str, _bytes: u64, _total: u64) {}
     fn compiling_package(&self, _name: &str) {}
     fn checking_package(&self, _name: &str) {}
-    fn packages_downloaded(&self, _start: Instant, _count: usize) {}
+    fn packages_downloaded(&self, _start: Instant, _count: usize, _bytes: u64) {}
+}
+
+/// A part of resolving the versions of the dependencies that is timed on its
+/// own, showing whether the network or the solver is where the time goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionPhase {
+    /// Reading the local and git packages that are used instead of Hex ones.
+    ProvidedPackages,
+    /// Choosing the versions, including fetching package information.
+    Solver,
+    /// Looking up the releases of the chosen versions.
+    ReleaseLookup,
+}
+
+/// Something that happened while building, as a value that can be sent to and
+/// rendered by another program, such as an editor showing the progress of
+/// dependency downloads.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TelemetryEvent {
+    WaitingForBuildDirectoryLock,
+    ResolvingPackageVersions,
+    ResolvedPackageChanges {
+        changes: Vec<PackageChange>,
+    },
+    RetiredPackageResolved {
+        name: EcoString,
+        version: Version,
+        #[serde(serialize_with = "serialize_retirement")]
+        retirement: RetirementStatus,
+    },
+    UnboundedRequirementResolved {
+        name: EcoString,
+        version: Version,
+    },
+    ExtraPackageNotDeleted {
+        name: EcoString,
+        #[serde(serialize_with = "serialize_error")]
+        error: Error,
+    },
+    DuplicateOtpAppResolved {
+        otp_app: EcoString,
+        packages: Vec<EcoString>,
+    },
+    ResolutionPhaseFinished {
+        phase: ResolutionPhase,
+        elapsed: Duration,
+    },
+    DownloadingPackage {
+        name: EcoString,
+    },
+    PackageMissing {
+        name: EcoString,
+        reason: MissingPackageReason,
+    },
+    PackageProgress {
+        name: EcoString,
+        bytes: u64,
+        total: u64,
+    },
+    PackagesDownloaded {
+        elapsed: Duration,
+        count: usize,
+        bytes: u64,
+    },
+    CompilingPackage {
+        name: EcoString,
+    },
+    CheckingPackage {
+        name: EcoString,
+    },
+}
+
+fn serialize_retirement<S: serde::Serializer>(
+    retirement: &RetirementStatus,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeStruct;
+    let mut state = serializer.serialize_struct("RetirementStatus", 2)?;
+    state.serialize_field("reason", retirement.reason.to_str())?;
+    state.serialize_field("message", &retirement.message)?;
+    state.end()
+}
+
+fn serialize_error<S: serde::Serializer>(error: &Error, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&error.to_diagnostic().text)
+}
+
+/// Receives telemetry as events rather than as a call for each kind of event.
+/// Every handler is also a `Telemetry`.
+pub trait TelemetryEventHandler: Debug {
+    fn handle(&self, event: TelemetryEvent);
+}
+
+impl<Handler: TelemetryEventHandler> Telemetry for Handler {
+    fn waiting_for_build_directory_lock(&self) {
+        self.handle(TelemetryEvent::WaitingForBuildDirectoryLock)
+    }
+
+    fn resolving_package_versions(&self) {
+        self.handle(TelemetryEvent::ResolvingPackageVersions)
+    }
+
+    fn resolved_package_changes(&self, changes: &[PackageChange]) {
+        self.handle(TelemetryEvent::ResolvedPackageChanges {
+            changes: changes.to_vec(),
+        })
+    }
+
+    fn retired_package_resolved(
+        &self,
+        name: &str,
+        version: &Version,
+        retirement: &RetirementStatus,
+    ) {
+        self.handle(TelemetryEvent::RetiredPackageResolved {
+            name: name.into(),
+            version: version.clone(),
+            retirement: retirement.clone(),
+        })
+    }
+
+    fn unbounded_requirement_resolved(&self, name: &str, version: &Version) {
+        self.handle(TelemetryEvent::UnboundedRequirementResolved {
+            name: name.into(),
+            version: version.clone(),
+        })
+    }
+
+    fn extra_package_not_deleted(&self, name: &str, error: &Error) {
+        self.handle(TelemetryEvent::ExtraPackageNotDeleted {
+            name: name.into(),
+            error: error.clone(),
+        })
+    }
+
+    fn duplicate_otp_app_resolved(&self, otp_app: &str, packages: &[EcoString]) {
+        self.handle(TelemetryEvent::DuplicateOtpAppResolved {
+            otp_app: otp_app.into(),
+            packages: packages.to_vec(),
+        })
+    }
+
+    fn resolution_phase_finished(&self, phase: ResolutionPhase, elapsed: Duration) {
+        self.handle(TelemetryEvent::ResolutionPhaseFinished { phase, elapsed })
+    }
+
+    fn downloading_package(&self, name: &str) {
+        self.handle(TelemetryEvent::DownloadingPackage { name: name.into() })
+    }
+
+    fn package_missing(&self, name: &str, reason: &MissingPackageReason) {
+        self.handle(TelemetryEvent::PackageMissing {
+            name: name.into(),
+            reason: reason.clone(),
+        })
+    }
+
+    fn package_progress(&self, name: &str, bytes: u64, total: u64) {
+        self.handle(TelemetryEvent::PackageProgress {
+            name: name.into(),
+            bytes,
+            total,
+        })
+    }
+
+    fn packages_downloaded(&self, start: Instant, count: usize, bytes: u64) {
+        self.handle(TelemetryEvent::PackagesDownloaded {
+            elapsed: start.elapsed(),
+            count,
+            bytes,
+        })
+    }
+
+    fn compiling_package(&self, name: &str) {
+        self.handle(TelemetryEvent::CompilingPackage { name: name.into() })
+    }
+
+    fn checking_package(&self, name: &str) {
+        self.handle(TelemetryEvent::CheckingPackage { name: name.into() })
+    }
+}
+
+/// An event along with the time it happened, in milliseconds since the Unix
+/// epoch.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct TimestampedEvent {
+    pub timestamp: u128,
+    #[serde(flatten)]
+    pub event: TelemetryEvent,
+}
+
+/// Sends each event over a channel, for a consumer on another thread.
+#[derive(Debug, Clone)]
+pub struct ChannelTelemetry {
+    sender: Sender<TimestampedEvent>,
+}
+
+impl ChannelTelemetry {
+    pub fn new(sender: Sender<TimestampedEvent>) -> Self {
+        Self { sender }
+    }
+}
+
+impl TelemetryEventHandler for ChannelTelemetry {
+    fn handle(&self, event: TelemetryEvent) {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        // The consumer may have stopped listening, which does not stop the
+        // build.
+        let _ = self.sender.send(TimestampedEvent { timestamp, event });
+    }
+}
+
+#[test]
+fn timestamped_event_json() {
+    let event = TimestampedEvent {
+        timestamp: 1_700_000_000_000,
+        event: TelemetryEvent::PackageProgress {
+            name: "wibble".into(),
+            bytes: 10,
+            total: 20,
+        },
+    };
+    assert_eq!(
+        serde_json::to_string(&event).unwrap(),
+        r#"{"timestamp":1700000000000,"event":"package_progress","name":"wibble","bytes":10,"total":20}"#
+    );
 }
@@ -7,7 +7,7 @@ use crate::{Error, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 use ecow::EcoString;
 use globset::{Glob, GlobSetBuilder};
-use hexpm::version::Version;
+use hexpm::version::{Range, Version};
 use http::Uri;
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
@@ -97,6 +97,27 @@ pub struct PackageConfig {
     pub target: Target,
     #[serde(default)]
     pub internal_modules: Option<Vec<Glob>>,
+    #[serde(default)]
+    pub hex: HexConfig,
+    /// Other Hex repositories that dependencies can be fetched from by name.
+    #[serde(default)]
+    pub repositories: HashMap<EcoString, NamedRepository>,
+    /// Hex packages that are unpacked from tarball files on disc rather than
+    /// downloaded, for installs without network access.
+    #[serde(default)]
+    pub tarballs: HashMap<EcoString, Utf8PathBuf>,
+    /// Additional version requirements for packages anywhere in the
+    /// dependency tree, used to pin transitive dependencies.
+    #[serde(default)]
+    pub overrides: HashMap<EcoString, Range>,
+    /// Packages that are replaced with a local copy wherever they appear in
+    /// the dependency tree.
+    #[serde(default)]
+    pub patch: HashMap<EcoString, Patch>,
+    /// Other packages in the same repository whose dependencies are resolved
+    /// together with this package's into a single manifest.
+    #[serde(default)]
+    pub workspace: Workspace,
 }
 
 impl PackageConfig {
@@ -133,6 +154,26 @@ impl PackageConfig {
         Ok(config)
     }
 
+    /// Whether every package in the manifest satisfies the version overrides.
+    pub fn overrides_satisfied_by(&self, manifest: &Manifest) -> Result<bool> {
+        for (name, range) in &self.overrides {
+            let range = range
+                .to_pubgrub()
+                .map_err(|error| Error::InvalidVersionFormat {
+                    input: range.to_string(),
+                    error: error.to_string(),
+                })?;
+            let violated = manifest
+                .packages
+                .iter()
+                .any(|package| &package.name == name && !range.contains(&package.version));
+            if violated {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     /// Get the locked packages for the current config and a given (optional)
     /// manifest of previously locked packages.
     ///
@@ -357,6 +398,33 @@ fn locked_some_removed() {
     );
 }
 
+#[test]
+fn locked_removed_dependencies_of_removed_are_removed_too() {
+    let mut config = PackageConfig::default();
+    config.dependencies = [("prod1".into(), Requirement::hex("~> 1.0"))].into();
+    let manifest = Manifest {
+        requirements: [
+            ("prod1".into(), Requirement::hex("~> 1.0")),
+            ("prod2".into(), Requirement::hex("~> 1.0")), // Removed from config
+        ]
+        .into(),
+        packages: vec![
+            manifest_package("prod1", "1.1.0", &[]),
+            manifest_package("prod2", "1.2.0", &["prod2_dep"]),
+            manifest_package("prod2_dep", "1.3.0", &[]), // Only needed by prod2
+        ],
+    };
+    assert_eq!(
+        config.locked(Some(&manifest)).unwrap(),
+        [
+            // prod2 removed
+            // prod2_dep removed
+            locked_version("prod1", "1.1.0"),
+        ]
+        .into()
+    );
+}
+
 #[test]
 fn locked_some_changed() {
     let mut config = PackageConfig::default();
@@ -476,6 +544,21 @@ fn locked_unlock_new() {
     )
 }
 
+#[test]
+fn overrides_satisfied_by_manifest() {
+    let mut config = PackageConfig::default();
+    config.overrides = [("dep".into(), Range::new("< 1.2.0".into()))].into();
+    let manifest = |version| Manifest {
+        requirements: [("prod1".into(), Requirement::hex("~> 1.0"))].into(),
+        packages: vec![
+            manifest_package("prod1", "1.0.0", &["dep"]),
+            manifest_package("dep", version, &[]),
+        ],
+    };
+    assert!(config.overrides_satisfied_by(&manifest("1.1.0")).unwrap());
+    assert!(!config.overrides_satisfied_by(&manifest("1.2.0")).unwrap());
+}
+
 #[test]
 fn default_internal_modules() {
     // When no internal modules are specified then we default to
@@ -592,6 +675,7 @@ fn manifest_package(
         requirements: requirements.iter().map(|e| (*e).into()).collect(),
         source: crate::manifest::ManifestPackageSource::Hex {
             outer_checksum: Base16Checksum(vec![]),
+            repository: None,
         },
     }
 }
@@ -617,6 +701,12 @@ impl Default for PackageConfig {
             licences: Default::default(),
             links: Default::default(),
             internal_modules: Default::default(),
+            hex: Default::default(),
+            repositories: Default::default(),
+            tarballs: Default::default(),
+            overrides: Default::default(),
+            patch: Default::default(),
+            workspace: Default::default(),
             target: Target::Erlang,
         }
     }
@@ -626,6 +716,11 @@ impl Default for PackageConfig {
 pub struct ErlangConfig {
     #[serde(default)]
     pub application_start_module: Option<EcoString>,
+    /// The name of the OTP application, if it is different to the package
+    /// name. This is recorded in the manifest of projects that depend on this
+    /// package as a path or git dependency.
+    #[serde(default)]
+    pub application_name: Option<EcoString>,
     #[serde(default)]
     pub extra_applications: Vec<EcoString>,
 }
@@ -771,6 +866,72 @@ impl Default for Repository {
     }
 }
 
+/// The Hex repository that dependencies are resolved and downloaded from.
+/// Any unset value falls back to the public hex.pm repository.
+#[derive(Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+pub struct HexConfig {
+    #[serde(default, deserialize_with = "uri_serde_option::deserialize")]
+    pub repository: Option<Uri>,
+    #[serde(default, deserialize_with = "uri_serde_option::deserialize")]
+    pub api: Option<Uri>,
+    #[serde(default)]
+    pub public_key: Option<String>,
+    /// A directory holding a snapshot of the Hex repository, relative to the
+    /// project root. When set, versions are resolved from the snapshot only,
+    /// so they are the same however the repository changes.
+    #[serde(default)]
+    pub snapshot: Option<Utf8PathBuf>,
+    /// The user agent sent with requests to the repository, in place of the
+    /// default `gleam/<version>` one.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+}
+
+/// A Hex repository declared in the `[repositories]` section of gleam.toml,
+/// which dependencies are fetched from when they give its name. The API
+/// defaults to the repository URL, and the public key to the hex.pm one.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct NamedRepository {
+    #[serde(deserialize_with = "uri_serde::deserialize")]
+    pub url: Uri,
+    #[serde(default, deserialize_with = "uri_serde_option::deserialize")]
+    pub api: Option<Uri>,
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+/// The API keys and URLs of private Hex repositories, read from the user's
+/// credentials file. A repository is used for the packages that declare it as
+/// their organization.
+#[derive(Deserialize, Default, Debug, PartialEq, Eq, Clone)]
+pub struct HexCredentials {
+    #[serde(default)]
+    pub repositories: HashMap<EcoString, RepositoryCredentials>,
+}
+
+/// Any unset URL falls back to the organization's repository on the Hex
+/// server being used, and an unset API key to the global one.
+#[derive(Deserialize, Default, PartialEq, Eq, Clone)]
+pub struct RepositoryCredentials {
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default, deserialize_with = "uri_serde_option::deserialize")]
+    pub repository: Option<Uri>,
+    #[serde(default, deserialize_with = "uri_serde_option::deserialize")]
+    pub api: Option<Uri>,
+}
+
+// The API key is never logged.
+impl fmt::Debug for RepositoryCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RepositoryCredentials")
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("repository", &self.repository)
+            .field("api", &self.api)
+            .finish()
+    }
+}
+
 #[derive(Deserialize, Default, Debug, PartialEq, Eq, Clone)]
 pub struct Docs {
     #[serde(default)]
@@ -784,6 +945,20 @@ pub struct DocsPage {
     pub source: Utf8PathBuf,
 }
 
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Patch {
+    pub path: Utf8PathBuf,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct Workspace {
+    /// The directories of the member packages, relative to this package. A
+    /// glob pattern such as `packages/*` includes every matching directory
+    /// with a gleam.toml.
+    #[serde(default)]
+    pub members: Vec<Utf8PathBuf>,
+}
+
 #[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Link {
     pub title: String,
@@ -811,6 +986,17 @@ mod uri_serde {
     }
 }
 
+mod uri_serde_option {
+    use serde::Deserializer;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<http::Uri>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::uri_serde::deserialize(deserializer).map(Some)
+    }
+}
+
 // This prefixes https as a default in the event no scheme was provided
 mod uri_serde_default_https {
     use http::uri::InvalidUri;
@@ -887,3 +1073,65 @@ name = "1"
         "Package names may only container lowercase letters, numbers, and underscores for key `name` at line 1 column 1"
     )
 }
+
+#[test]
+fn hex_repository_config() {
+    let input = r#"
+name = "wibble"
+
+[hex]
+repository = "https://mirror.example.com/repo/"
+api = "https://mirror.example.com/api/"
+public_key = "-----BEGIN PUBLIC KEY-----"
+user_agent = "wibble-mirror/1.0"
+"#;
+    let config = toml::from_str::<PackageConfig>(input).unwrap();
+    assert_eq!(
+        config.hex,
+        HexConfig {
+            repository: Some(Uri::from_static("https://mirror.example.com/repo/")),
+            api: Some(Uri::from_static("https://mirror.example.com/api/")),
+            public_key: Some("-----BEGIN PUBLIC KEY-----".into()),
+            snapshot: None,
+            user_agent: Some("wibble-mirror/1.0".into()),
+        }
+    );
+}
+
+#[test]
+fn named_repositories_config() {
+    let input = r#"
+name = "wibble"
+
+[repositories.internal]
+url = "https://hex.internal.example.com/"
+public_key = "-----BEGIN PUBLIC KEY-----"
+
+[dependencies]
+wobble = { version = "~> 1.0", repository = "internal" }
+"#;
+    let config = toml::from_str::<PackageConfig>(input).unwrap();
+    assert_eq!(
+        config.repositories,
+        [(
+            "internal".into(),
+            NamedRepository {
+                url: Uri::from_static("https://hex.internal.example.com/"),
+                api: None,
+                public_key: Some("-----BEGIN PUBLIC KEY-----".into()),
+            }
+        )]
+        .into()
+    );
+}
+
+#[test]
+fn hex_repository_config_without_scheme() {
+    let input = r#"
+name = "wibble"
+
+[hex]
+repository = "mirror.example.com"
+"#;
+    assert!(toml::from_str::<PackageConfig>(input).is_err());
+}
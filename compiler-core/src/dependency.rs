@@ -1,12 +1,30 @@
-use std::{borrow::Borrow, cell::RefCell, collections::HashMap, error::Error as StdError};
+use std::{
+    borrow::Borrow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    error::Error as StdError,
+};
+
+use crate::{
+    config::{PackageConfig, Patch},
+    error::{FileIoAction, FileKind, ResolutionFailure},
+    hex::{self, HexRepository},
+    io::{FileSystemReader, HttpClient},
+    manifest::{Manifest, ManifestPackageSource},
+    paths::ProjectPaths,
+    requirement::Requirement,
+    warning::Warning,
+    Error, Result,
+};
 
-use crate::{Error, Result};
+use camino::{Utf8Path, Utf8PathBuf};
 
 use ecow::EcoString;
 use hexpm::{
     version::{Range, Version},
     Dependency, Release,
 };
+use itertools::Itertools;
 use pubgrub::{
     error::PubGrubError,
     solver::{choose_package_with_fewest_versions, Dependencies},
@@ -19,17 +37,95 @@ pub type ResolutionError = PubGrubError<String, Version>;
 
 type PubgrubRange = pubgrub::range::Range<Version>;
 
+/// Whether releases that have been retired on Hex may be selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetiredReleases {
+    /// Retired releases are only used if they are already locked.
+    #[default]
+    AllowLocked,
+    /// Retired releases are never used, so locked ones are unlocked.
+    Reject,
+}
+
+/// Rewrites the overrides map, package names to ranges, before anything is
+/// resolved or unlocked, such as to apply an organisation's policy of never
+/// using a version of a package, or of pinning a package to a range in every
+/// project. As with the overrides in gleam.toml
+/// the ranges apply to every requirement on a package, including those of
+/// other packages, and locked versions outside them are selected again.
+pub type OverridesTransform = dyn Fn(&mut HashMap<EcoString, Range>);
+
+/// How the requirements of the root package are resolved. By default nothing
+/// is locked, overridden, or patched.
+#[derive(Default)]
+pub struct ResolutionOptions {
+    /// The versions to keep, typically those from the manifest.
+    pub locked: HashMap<EcoString, Version>,
+    /// Ranges that replace every requirement on a package.
+    pub overrides: HashMap<EcoString, Range>,
+    /// Packages provided by a local copy wherever they are required.
+    pub patched: HashSet<EcoString>,
+    pub retired_releases: RetiredReleases,
+    /// A policy given the `overrides` above, and not the root requirements,
+    /// before resolution starts. The overrides it leaves are the ones used.
+    pub overrides_transform: Option<Box<OverridesTransform>>,
+}
+
+impl std::fmt::Debug for ResolutionOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResolutionOptions")
+            .field("locked", &self.locked)
+            .field("overrides", &self.overrides)
+            .field("patched", &self.patched)
+            .field("retired_releases", &self.retired_releases)
+            .finish_non_exhaustive()
+    }
+}
+
 pub fn resolve_versions<Requirements>(
     package_fetcher: Box<dyn PackageFetcher>,
     provided_packages: HashMap<EcoString, hexpm::Package>,
     root_name: EcoString,
     dependencies: Requirements,
-    locked: &HashMap<EcoString, Version>,
+    options: ResolutionOptions,
 ) -> Result<PackageVersions>
 where
     Requirements: Iterator<Item = (EcoString, Range)>,
 {
+    let ResolutionOptions {
+        locked,
+        mut overrides,
+        patched,
+        retired_releases,
+        overrides_transform,
+    } = options;
+    if let Some(transform) = overrides_transform {
+        transform(&mut overrides);
+    }
+    let (locked, overrides, patched) = (&locked, &overrides, &patched);
     tracing::info!("resolving_versions");
+    let prerelease_overrides: Vec<String> = overrides
+        .iter()
+        .filter(|(_, range)| requests_prerelease(range))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let overrides = parse_overrides(overrides).map_err(Error::dependency_resolution_failed)?;
+    let mut locked = match retired_releases {
+        RetiredReleases::AllowLocked => locked.clone(),
+        RetiredReleases::Reject => {
+            unlock_retired(package_fetcher.as_ref(), &provided_packages, locked)
+                .map_err(Error::dependency_resolution_failed)?
+        }
+    };
+    // Locked versions that don't satisfy an override are selected again, and
+    // patched packages use whatever version the local copy has.
+    locked.retain(|name, version| {
+        !patched.contains(name)
+            && overrides
+                .get(name.as_str())
+                .map_or(true, |range| range.contains(version))
+    });
+    let locked = &locked;
     let root_version = Version::new(0, 0, 0);
     let root = hexpm::Package {
         name: root_name.as_str().into(),
@@ -39,28 +135,471 @@ where
             outer_checksum: vec![],
             retirement_status: None,
             requirements: root_dependencies(dependencies, locked)
-                .map_err(Error::dependency_resolution_failed)?,
+                .map_err(Error::DependencyResolutionFailed)?,
             meta: (),
         }],
     };
 
-    let packages = pubgrub::solver::resolve(
-        &DependencyProvider::new(package_fetcher, provided_packages, root, locked),
-        root_name.as_str().into(),
-        root_version,
-    )
-    .map_err(Error::dependency_resolution_failed)?
-    .into_iter()
-    .filter(|(name, _)| name.as_str() != root_name.as_str())
-    .collect();
-
-    Ok(packages)
+    let provider = DependencyProvider::new(
+        package_fetcher,
+        provided_packages,
+        root,
+        locked,
+        overrides,
+        patched,
+    );
+    provider
+        .allow_prerelease
+        .borrow_mut()
+        .extend(prerelease_overrides);
+
+    // Optional dependencies are only included if some other package depends on
+    // them. If one is included but the version selected doesn't satisfy the
+    // optional requirement then we resolve again, respecting that requirement.
+    let packages = loop {
+        let packages =
+            pubgrub::solver::resolve(&provider, root_name.as_str().into(), root_version.clone())
+                .map_err(|error| provider.explain_failure(error, &root_name, &root_version))?;
+        if !provider.include_unsatisfied_optional_dependencies(&packages) {
+            break packages;
+        }
+    };
+    check_solution(&provider.packages.borrow(), patched, &packages)
+        .map_err(Error::DependencyResolutionFailed)?;
+
+    Ok(packages
+        .into_iter()
+        .filter(|(name, _)| name.as_str() != root_name.as_str())
+        .collect())
+}
+
+/// Resolve the versions of an in-memory set of Hex requirements and describe
+/// them as a manifest, without reading or writing gleam.toml or manifest.toml,
+/// so that tools can use the resolver as a library. Package information comes
+/// from the fetcher and each selected release is then looked up on Hex.
+pub async fn resolve_requirements<Http: HttpClient>(
+    package_fetcher: Box<dyn PackageFetcher>,
+    hex_repository: &HexRepository,
+    http: &Http,
+    requirements: HashMap<EcoString, Range>,
+) -> Result<Manifest> {
+    // Hex package names are never empty, so the root can't clash with one
+    let versions = resolve_versions(
+        package_fetcher,
+        HashMap::new(),
+        EcoString::new(),
+        requirements.clone().into_iter(),
+        ResolutionOptions::default(),
+    )?;
+    let mut packages = Vec::with_capacity(versions.len());
+    for (name, version) in versions.into_iter().sorted() {
+        let (package, _) = hex::lookup_package(name, version, hex_repository, http).await?;
+        packages.push(package);
+    }
+    let requirements = requirements
+        .into_iter()
+        .map(|(name, version)| {
+            let requirement = Requirement::Hex {
+                version,
+                organization: None,
+                repository: None,
+            };
+            (name, requirement)
+        })
+        .collect();
+    Ok(Manifest {
+        requirements,
+        packages,
+    })
+}
+
+/// Whether a project's manifest was resolved from its current requirements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestStatus {
+    UpToDate,
+    Outdated,
+    Missing,
+}
+
+impl ManifestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UpToDate => "up-to-date",
+            Self::Outdated => "outdated",
+            Self::Missing => "missing",
+        }
+    }
+}
+
+/// The status of a project's manifest, with the warnings about the manifest
+/// found while checking it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestStatusReport {
+    pub status: ManifestStatus,
+    pub warnings: Vec<Warning>,
+}
+
+/// Whether the dependencies of a project need to be resolved again, found
+/// without resolving or downloading anything, and without printing, so that
+/// editors and other tools can check cheaply.
+pub fn manifest_status<FS: FileSystemReader>(
+    fs: &FS,
+    paths: &ProjectPaths,
+    config: &PackageConfig,
+) -> Result<ManifestStatusReport> {
+    let path = paths.manifest();
+    if !fs.is_file(&path) {
+        return Ok(ManifestStatusReport {
+            status: ManifestStatus::Missing,
+            warnings: vec![],
+        });
+    }
+    let toml = fs.read(&path)?;
+    let manifest = Manifest::parse(&toml, &path)?;
+    let mut warnings = vec![];
+    if Manifest::is_edited(&toml) {
+        warnings.push(Warning::EditedManifest { path });
+    }
+    let status = if is_manifest_up_to_date(fs, &manifest, config, paths.root())? {
+        ManifestStatus::UpToDate
+    } else {
+        ManifestStatus::Outdated
+    };
+    Ok(ManifestStatusReport { status, warnings })
+}
+
+/// Whether the manifest was resolved from the current requirements in
+/// gleam.toml, so the dependencies don't need to be resolved again.
+pub fn is_manifest_up_to_date<FS: FileSystemReader>(
+    fs: &FS,
+    manifest: &Manifest,
+    config: &PackageConfig,
+    root_path: &Utf8Path,
+) -> Result<bool> {
+    Ok(is_same_requirements(
+        fs,
+        &manifest.requirements,
+        &project_requirements(fs, config, root_path)?,
+        root_path,
+    )? && config.overrides_satisfied_by(manifest)?
+        && patches_applied(fs, manifest, &config.patch, root_path)?)
+}
+
+fn is_same_requirements<FS: FileSystemReader>(
+    fs: &FS,
+    requirements1: &HashMap<EcoString, Requirement>,
+    requirements2: &HashMap<EcoString, Requirement>,
+    root_path: &Utf8Path,
+) -> Result<bool> {
+    if requirements1.len() != requirements2.len() {
+        return Ok(false);
+    }
+
+    for (key, requirement1) in requirements1 {
+        if !same_requirements(fs, requirement1, requirements2.get(key), root_path)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Whether every patched package in the manifest comes from its local copy.
+pub fn patches_applied<FS: FileSystemReader>(
+    fs: &FS,
+    manifest: &Manifest,
+    patches: &HashMap<EcoString, Patch>,
+    root_path: &Utf8Path,
+) -> Result<bool> {
+    for package in &manifest.packages {
+        let Some(patch) = patches.get(&package.name) else {
+            continue;
+        };
+        let applied = match &package.source {
+            ManifestPackageSource::Local { path } => same_requirements(
+                fs,
+                &Requirement::Path { path: path.clone() },
+                Some(&Requirement::Path {
+                    path: patch.path.clone(),
+                }),
+                root_path,
+            )?,
+            ManifestPackageSource::Hex { .. } | ManifestPackageSource::Git { .. } => false,
+        };
+        if !applied {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub fn same_requirements<FS: FileSystemReader>(
+    fs: &FS,
+    requirement1: &Requirement,
+    requirement2: Option<&Requirement>,
+    root_path: &Utf8Path,
+) -> Result<bool> {
+    let (left, right) = match (requirement1, requirement2) {
+        (Requirement::Path { path: path1 }, Some(Requirement::Path { path: path2 })) => {
+            (path1, path2)
+        }
+        (_, Some(requirement2)) => return Ok(requirement1 == requirement2),
+        (_, None) => return Ok(false),
+    };
+
+    let left = if left.is_absolute() {
+        left.to_owned()
+    } else {
+        fs.canonicalise(&root_path.join(left))?
+    };
+
+    let right = if right.is_absolute() {
+        right.to_owned()
+    } else {
+        fs.canonicalise(&root_path.join(right))?
+    };
+
+    Ok(left == right)
+}
+
+/// The requirements recorded in the manifest, which are the project's
+/// dependencies and the members of its workspace.
+pub fn project_requirements<FS: FileSystemReader>(
+    fs: &FS,
+    config: &PackageConfig,
+    root_path: &Utf8Path,
+) -> Result<HashMap<EcoString, Requirement>> {
+    let mut requirements = config.all_dependencies()?;
+    for (name, requirement) in workspace_dependencies(fs, config, root_path)? {
+        let _ = requirements.entry(name).or_insert(requirement);
+    }
+    Ok(requirements)
+}
+
+/// The members of the project's workspace as path dependencies, so that they
+/// and their dependencies are resolved together with the project's own.
+pub fn workspace_dependencies<FS: FileSystemReader>(
+    fs: &FS,
+    config: &PackageConfig,
+    root_path: &Utf8Path,
+) -> Result<HashMap<EcoString, Requirement>> {
+    let mut dependencies: HashMap<EcoString, Requirement> = HashMap::new();
+    for path in workspace_member_paths(fs, config, root_path)? {
+        let member = PackageConfig::read(root_path.join(&path).join("gleam.toml"), fs)?;
+        member.check_gleam_compatibility()?;
+        let requirement = Requirement::Path { path };
+        match dependencies.get(&member.name) {
+            // Two directories matched by the members are the same package
+            Some(existing) if existing != &requirement => {
+                return Err(Error::DependencyResolutionFailed(
+                    ResolutionFailure::ConflictingSources {
+                        package: member.name.to_string(),
+                        source_1: existing.to_toml(root_path),
+                        source_2: requirement.to_toml(root_path),
+                    },
+                ));
+            }
+            _ => {
+                let _ = dependencies.insert(member.name, requirement);
+            }
+        }
+    }
+    Ok(dependencies)
+}
+
+/// The directories of the workspace members, with each glob pattern such as
+/// `packages/*` replaced by the matching directories that have a gleam.toml,
+/// in order.
+fn workspace_member_paths<FS: FileSystemReader>(
+    fs: &FS,
+    config: &PackageConfig,
+    root_path: &Utf8Path,
+) -> Result<Vec<Utf8PathBuf>> {
+    let mut paths = vec![];
+    for member in &config.workspace.members {
+        if !member.as_str().contains(['*', '?', '[', '{']) {
+            paths.push(member.clone());
+            continue;
+        }
+        let glob = globset::GlobBuilder::new(member.as_str())
+            .literal_separator(true)
+            .build()
+            .map_err(|error| Error::InvalidWorkspaceMember {
+                member: member.clone(),
+                error: error.kind().to_string(),
+            })?
+            .compile_matcher();
+        // Only the directories below the part of the pattern without any
+        // wildcards need to be searched, and no deeper than the rest of the
+        // pattern unless it contains `**`.
+        let base: Utf8PathBuf = member
+            .components()
+            .take_while(|component| !component.as_str().contains(['*', '?', '[', '{']))
+            .collect();
+        let directory = root_path.join(&base);
+        if !fs.is_directory(&directory) {
+            continue;
+        }
+        let max_depth = (!member.as_str().contains("**"))
+            .then(|| member.components().count() - base.components().count());
+        let mut matches = vec![];
+        // Directories linked to from several places are searched once, so
+        // that links can't make the search go on forever.
+        let mut searched = HashSet::new();
+        let mut unsearched = vec![(directory, 0)];
+        while let Some((directory, depth)) = unsearched.pop() {
+            if max_depth.is_some_and(|max_depth| depth >= max_depth)
+                || !searched.insert(fs.canonicalise(&directory)?)
+            {
+                continue;
+            }
+            for child in subdirectories(fs, &directory)? {
+                let Ok(path) = child.strip_prefix(root_path) else {
+                    continue;
+                };
+                if glob.is_match(path) && fs.is_file(&child.join("gleam.toml")) {
+                    matches.push(path.to_path_buf());
+                }
+                unsearched.push((child, depth + 1));
+            }
+        }
+        matches.sort();
+        matches.dedup();
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+/// The directories directly within a directory. Some file systems list every
+/// file below the directory, so each entry is reduced to the directory
+/// directly within it that contains it.
+fn subdirectories<FS: FileSystemReader>(fs: &FS, directory: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let mut subdirectories = vec![];
+    for entry in fs.read_dir(directory)? {
+        let entry = entry.map_err(|error| Error::FileIo {
+            action: FileIoAction::Read,
+            kind: FileKind::Directory,
+            path: directory.to_path_buf(),
+            err: Some(error.to_string()),
+        })?;
+        let Some(child) = entry
+            .pathbuf
+            .strip_prefix(directory)
+            .ok()
+            .and_then(|path| path.components().next())
+        else {
+            continue;
+        };
+        let child = directory.join(child);
+        if fs.is_directory(&child) {
+            subdirectories.push(child);
+        }
+    }
+    subdirectories.sort();
+    subdirectories.dedup();
+    Ok(subdirectories)
+}
+
+/// Check that the version selected for each package satisfies every
+/// requirement on it, guarding against mistakes in the solver or in the
+/// provided packages. Patched packages satisfy any requirement.
+fn check_solution(
+    packages: &HashMap<EcoString, hexpm::Package>,
+    patched: &HashSet<EcoString>,
+    selected: &Map<String, Version>,
+) -> Result<(), ResolutionFailure> {
+    for (dependent, dependent_version) in selected.iter().sorted() {
+        let requirements = packages
+            .get(dependent.as_str())
+            .into_iter()
+            .flat_map(|p| p.releases.iter())
+            .filter(|r| &r.version == dependent_version)
+            .flat_map(|r| r.requirements.iter())
+            .filter(|(name, _)| !patched.contains(name.as_str()))
+            .sorted_by(|a, b| a.0.cmp(b.0));
+        for (name, dependency) in requirements {
+            let version = match selected.get(name) {
+                Some(version) => version,
+                // Optional dependencies are only selected if something else
+                // requires them.
+                None if dependency.optional => continue,
+                None => {
+                    return Err(ResolutionFailure::UnsatisfiedRequirement {
+                        package: name.clone(),
+                        version: "none".into(),
+                        dependent: format!("{dependent}@{dependent_version}"),
+                        requirement: dependency.requirement.to_string(),
+                    })
+                }
+            };
+            let range = dependency
+                .requirement
+                .to_pubgrub()
+                .map_err(|e| ResolutionFailure::Failure(e.to_string()))?;
+            if !range.contains(version) {
+                return Err(ResolutionFailure::UnsatisfiedRequirement {
+                    package: name.clone(),
+                    version: version.to_string(),
+                    dependent: format!("{dependent}@{dependent_version}"),
+                    requirement: dependency.requirement.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse the override requirements into ranges that can be intersected with
+/// the requirements of each package.
+fn parse_overrides(
+    overrides: &HashMap<EcoString, Range>,
+) -> Result<HashMap<String, PubgrubRange>, ResolutionError> {
+    overrides
+        .iter()
+        .map(|(name, range)| {
+            let parsed = range.to_pubgrub().map_err(|e| {
+                ResolutionError::Failure(format!("Failed to parse override for {name}: {e}"))
+            })?;
+            Ok((name.to_string(), parsed))
+        })
+        .collect()
+}
+
+/// Remove the locked versions of Hex packages that have been retired so that
+/// other versions are selected for them.
+fn unlock_retired(
+    package_fetcher: &dyn PackageFetcher,
+    provided_packages: &HashMap<EcoString, hexpm::Package>,
+    locked: &HashMap<EcoString, Version>,
+) -> Result<HashMap<EcoString, Version>, ResolutionError> {
+    let mut unlocked = HashMap::with_capacity(locked.len());
+    for (name, version) in locked {
+        if !provided_packages.contains_key(name) {
+            let package = package_fetcher.get_dependencies(name).map_err(|source| {
+                ResolutionError::ErrorRetrievingDependencies {
+                    package: name.to_string(),
+                    version: version.clone(),
+                    source,
+                }
+            })?;
+            let retired = package
+                .releases
+                .iter()
+                .any(|release| &release.version == version && release.is_retired());
+            if retired {
+                tracing::info!(name = name.as_str(), "unlocking_retired_package");
+                continue;
+            }
+        }
+        let _ = unlocked.insert(name.clone(), version.clone());
+    }
+    Ok(unlocked)
 }
 
 fn root_dependencies<Requirements>(
     base_requirements: Requirements,
     locked: &HashMap<EcoString, Version>,
-) -> Result<HashMap<String, Dependency>, ResolutionError>
+) -> Result<HashMap<String, Dependency>, ResolutionFailure>
 where
     Requirements: Iterator<Item = (EcoString, Range)>,
 {
@@ -101,16 +640,16 @@ where
             Some(locked_version) => {
                 let compatible = range
                     .to_pubgrub()
-                    .map_err(|e| ResolutionError::Failure(format!("Failed to parse range {}", e)))?
+                    .map_err(|e| {
+                        ResolutionFailure::Failure(format!("Failed to parse range {}", e))
+                    })?
                     .contains(locked_version);
                 if !compatible {
-                    return Err(ResolutionError::Failure(format!(
-                        "{package} is specified with the requirement `{requirement}`, \
-but it is locked to {version}, which is incompatible.",
-                        package = name,
-                        requirement = range,
-                        version = locked_version,
-                    )));
+                    return Err(ResolutionFailure::UnsatisfiableConstraint {
+                        package: name.to_string(),
+                        requirement: range.to_string(),
+                        version: locked_version.to_string(),
+                    });
                 }
             }
         };
@@ -119,6 +658,18 @@ but it is locked to {version}, which is incompatible.",
     Ok(requirements)
 }
 
+/// Whether a requirement names a pre-release version, such as
+/// `>= 1.0.0-rc1`, opting in to pre-releases of the package. As in semantic
+/// versioning, pre-releases are otherwise not used to satisfy a requirement.
+fn requests_prerelease(range: &Range) -> bool {
+    range
+        .as_str()
+        .split_whitespace()
+        .map(|part| part.trim_start_matches(['<', '>', '=', '~', '!']))
+        .filter_map(|part| Version::parse(part).ok())
+        .any(|version| version.is_pre())
+}
+
 pub trait PackageFetcher {
     fn get_dependencies(&self, package: &str) -> Result<hexpm::Package, Box<dyn StdError>>;
 }
@@ -127,6 +678,19 @@ struct DependencyProvider<'a> {
     packages: RefCell<HashMap<EcoString, hexpm::Package>>,
     remote: Box<dyn PackageFetcher>,
     locked: &'a HashMap<EcoString, Version>,
+    /// Requirements that are added to every requirement on these packages.
+    overrides: HashMap<String, PubgrubRange>,
+    /// Provided packages that satisfy every requirement on them.
+    patched: &'a HashSet<EcoString>,
+    /// Packages whose optional requirements are treated as regular ones
+    /// because they have been included by another package.
+    included_optional: RefCell<HashSet<String>>,
+    /// Whether retired releases that are not locked may be selected, which is
+    /// only the case when explaining why resolution failed.
+    allow_retired: Cell<bool>,
+    /// Packages with a requirement that names a pre-release version. Other
+    /// packages only use pre-releases that have been locked.
+    allow_prerelease: RefCell<HashSet<String>>,
 }
 
 impl<'a> DependencyProvider<'a> {
@@ -135,13 +699,102 @@ impl<'a> DependencyProvider<'a> {
         mut packages: HashMap<EcoString, hexpm::Package>,
         root: hexpm::Package,
         locked: &'a HashMap<EcoString, Version>,
+        overrides: HashMap<String, PubgrubRange>,
+        patched: &'a HashSet<EcoString>,
     ) -> Self {
         let _ = packages.insert(root.name.as_str().into(), root);
         Self {
             packages: RefCell::new(packages),
             locked,
+            overrides,
+            patched,
             remote,
+            included_optional: RefCell::new(HashSet::new()),
+            allow_retired: Cell::new(false),
+            allow_prerelease: RefCell::new(HashSet::new()),
+        }
+    }
+
+    /// Retired releases are only used if they have been locked, and
+    /// pre-releases only if they have been locked or a requirement on the
+    /// package opts in to them.
+    fn is_selectable(&self, name: &str, release: &Release<()>) -> bool {
+        let locked = self.locked.get(name) == Some(&release.version);
+        let allowed_retired = !release.is_retired() || self.allow_retired.get() || locked;
+        let allowed_prerelease =
+            !release.version.is_pre() || locked || self.allow_prerelease.borrow().contains(name);
+        allowed_retired && allowed_prerelease
+    }
+
+    /// Convert a resolution error into an error for the user. If there was no
+    /// solution only because retired releases were skipped then those
+    /// releases are reported instead of the solver's explanation.
+    fn explain_failure(
+        &self,
+        error: ResolutionError,
+        root_name: &str,
+        root_version: &Version,
+    ) -> Error {
+        if !matches!(error, ResolutionError::NoSolution(_)) || self.allow_retired.get() {
+            return Error::dependency_resolution_failed(error);
+        }
+        self.allow_retired.set(true);
+        let solution = pubgrub::solver::resolve(self, root_name.into(), root_version.clone());
+        self.allow_retired.set(false);
+        let Ok(solution) = solution else {
+            return Error::dependency_resolution_failed(error);
+        };
+
+        let packages = self.packages.borrow();
+        let releases = solution
+            .iter()
+            .filter(|(name, version)| {
+                packages
+                    .get(name.as_str())
+                    .into_iter()
+                    .flat_map(|p| p.releases.iter())
+                    .any(|r| &r.version == *version && r.is_retired())
+            })
+            .filter(|(name, version)| self.locked.get(name.as_str()) != Some(*version))
+            .map(|(name, version)| format!("{name}@{version}"))
+            .sorted()
+            .collect_vec();
+        if releases.is_empty() {
+            return Error::dependency_resolution_failed(error);
+        }
+        Error::DependencyResolutionFailed(ResolutionFailure::OnlyRetiredReleases { releases })
+    }
+
+    /// Record any optional dependencies that were selected with a version
+    /// that doesn't satisfy the optional requirement so that the requirement
+    /// is used next time. Returns whether any were found.
+    fn include_unsatisfied_optional_dependencies(&self, selected: &Map<String, Version>) -> bool {
+        let packages = self.packages.borrow();
+        let mut included = self.included_optional.borrow_mut();
+        let mut changed = false;
+        for (name, version) in selected {
+            let requirements = packages
+                .get(name.as_str())
+                .into_iter()
+                .flat_map(|p| p.releases.iter())
+                .filter(|r| &r.version == version)
+                .flat_map(|r| r.requirements.iter())
+                .filter(|(_, d)| d.optional);
+            for (dependency, d) in requirements {
+                let Some(selected_version) = selected.get(dependency) else {
+                    continue;
+                };
+                let satisfied = d
+                    .requirement
+                    .to_pubgrub()
+                    .map(|range| range.contains(selected_version))
+                    .unwrap_or(false);
+                if !satisfied {
+                    changed |= included.insert(dependency.clone());
+                }
+            }
         }
+        changed
     }
 
     /// Download information about the package from the registry into the local
@@ -189,13 +842,15 @@ impl<'a> pubgrub::solver::DependencyProvider<PackageName, Version> for Dependenc
             })
             .collect::<Result<_, _>>()?;
         let list_available_versions = |name: &String| {
+            let name = name.clone();
             self.packages
                 .borrow()
                 .get(name.as_str())
                 .cloned()
                 .into_iter()
                 .flat_map(|p| p.releases.into_iter())
-                .map(|p| p.version)
+                .filter(move |r| self.is_selectable(&name, r))
+                .map(|r| r.version)
         };
         Ok(choose_package_with_fewest_versions(
             list_available_versions,
@@ -220,14 +875,25 @@ impl<'a> pubgrub::solver::DependencyProvider<PackageName, Version> for Dependenc
             None => return Ok(Dependencies::Unknown),
         };
 
-        // Only use retired versions if they have been locked
-        if release.is_retired() && self.locked.get(name.as_str()) != Some(version) {
+        if !self.is_selectable(name, release) {
             return Ok(Dependencies::Unknown);
         }
 
+        let included_optional = self.included_optional.borrow();
         let mut deps: Map<String, PubgrubRange> = Default::default();
         for (name, d) in &release.requirements {
-            let range = d.requirement.to_pubgrub()?;
+            if d.optional && !included_optional.contains(name) {
+                continue;
+            }
+            if requests_prerelease(&d.requirement) {
+                let _ = self.allow_prerelease.borrow_mut().insert(name.clone());
+            }
+            let mut range = d.requirement.to_pubgrub()?;
+            if self.patched.contains(name.as_str()) {
+                range = PubgrubRange::any();
+            } else if let Some(override_range) = self.overrides.get(name) {
+                range = range.intersection(override_range);
+            }
             let _ = deps.insert(name.clone(), range);
         }
         Ok(Dependencies::Known(deps))
@@ -237,6 +903,10 @@ impl<'a> pubgrub::solver::DependencyProvider<PackageName, Version> for Dependenc
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        io::{memory::InMemoryFileSystem, FileSystemWriter},
+        manifest::{Base16Checksum, ManifestPackage},
+    };
 
     struct Remote {
         deps: HashMap<String, hexpm::Package>,
@@ -373,6 +1043,43 @@ mod tests {
                 ],
             },
         );
+        let _ = deps.insert(
+            "package_with_optional".into(),
+            hexpm::Package {
+                name: "package_with_optional".into(),
+                repository: "hexpm".into(),
+                releases: vec![Release {
+                    version: Version::try_from("0.1.0").unwrap(),
+                    requirements: [(
+                        "gleam_otp".into(),
+                        Dependency {
+                            app: None,
+                            optional: true,
+                            repository: None,
+                            requirement: Range::new("~> 0.1.0".into()),
+                        },
+                    )]
+                    .into(),
+                    retirement_status: None,
+                    outer_checksum: vec![1, 2, 3],
+                    meta: (),
+                }],
+            },
+        );
+        let _ = deps.insert(
+            "prerelease_only".into(),
+            hexpm::Package {
+                name: "prerelease_only".into(),
+                repository: "hexpm".into(),
+                releases: vec![Release {
+                    version: Version::try_from("1.0.0-rc1").unwrap(),
+                    requirements: [].into(),
+                    retirement_status: None,
+                    outer_checksum: vec![1, 2, 3],
+                    meta: (),
+                }],
+            },
+        );
         Box::new(Remote { deps })
     }
 
@@ -384,7 +1091,10 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_stdlib".into(), Range::new("~> 0.1".into()))].into_iter(),
-            &vec![locked_stdlib].into_iter().collect(),
+            ResolutionOptions {
+                locked: vec![locked_stdlib].into_iter().collect(),
+                ..Default::default()
+            },
         )
         .unwrap();
         assert_eq!(
@@ -402,7 +1112,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap();
         assert_eq!(result, vec![].into_iter().collect())
@@ -415,7 +1125,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_stdlib".into(), Range::new("~> 0.1".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap();
         assert_eq!(
@@ -426,6 +1136,144 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resolve_requirements_returns_manifest() {
+        use crate::manifest::{Base16Checksum, ManifestPackage, ManifestPackageSource};
+
+        struct ReleaseHttpClient;
+
+        #[async_trait::async_trait]
+        impl HttpClient for ReleaseHttpClient {
+            async fn send(
+                &self,
+                request: http::Request<Vec<u8>>,
+            ) -> Result<http::Response<Vec<u8>>> {
+                assert_eq!(
+                    request.uri().path(),
+                    "/api/packages/gleam_stdlib/releases/0.3.0"
+                );
+                let body = r#"{"version": "0.3.0", "requirements": {}, "retirement_status": null,
+                    "checksum": "0102", "meta": {"app": "gleam_stdlib", "build_tools": ["gleam"]}}"#;
+                Ok(http::Response::builder()
+                    .status(http::StatusCode::OK)
+                    .body(body.as_bytes().to_vec())
+                    .expect("response"))
+            }
+        }
+
+        let requirements: HashMap<EcoString, Range> =
+            [("gleam_stdlib".into(), Range::new("~> 0.1".into()))].into();
+        let manifest = futures::executor::block_on(resolve_requirements(
+            make_remote(),
+            &HexRepository::default(),
+            &ReleaseHttpClient,
+            requirements,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            manifest,
+            Manifest {
+                requirements: [("gleam_stdlib".into(), Requirement::hex("~> 0.1"))].into(),
+                packages: vec![ManifestPackage {
+                    name: "gleam_stdlib".into(),
+                    version: Version::new(0, 3, 0),
+                    build_tools: vec!["gleam".into()],
+                    otp_app: Some("gleam_stdlib".into()),
+                    requirements: vec![],
+                    source: ManifestPackageSource::Hex {
+                        outer_checksum: Base16Checksum(vec![1, 2]),
+                        repository: None,
+                    },
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn resolution_with_overrides_transform() {
+        // A policy pinning gleam_stdlib below the latest version
+        let pin_stdlib = |overrides: &mut HashMap<EcoString, Range>| {
+            let _ = overrides.insert("gleam_stdlib".into(), Range::new("~> 0.2.0".into()));
+        };
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("gleam_stdlib".into(), Range::new("~> 0.1".into()))].into_iter(),
+            ResolutionOptions {
+                overrides_transform: Some(Box::new(pin_stdlib)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![("gleam_stdlib".into(), Version::try_from("0.2.2").unwrap())]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn overrides_changed_by_transform_are_honoured() {
+        // The policy replaces the override given by the project
+        let replace_override = |overrides: &mut HashMap<EcoString, Range>| {
+            let _ = overrides.insert("gleam_stdlib".into(), Range::new(">= 0.2.0".into()));
+        };
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("gleam_otp".into(), Range::new("~> 0.1.0".into()))].into_iter(),
+            ResolutionOptions {
+                overrides: [("gleam_stdlib".into(), Range::new("< 0.2.0".into()))].into(),
+                overrides_transform: Some(Box::new(replace_override)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                ("gleam_otp".into(), Version::try_from("0.1.0").unwrap()),
+                ("gleam_stdlib".into(), Version::try_from("0.3.0").unwrap())
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn overrides_transform_applies_to_transitive_and_locked_packages() {
+        // A policy never using a release of gleam_stdlib, which is only
+        // required by gleam_otp and has been locked to that release
+        let never_latest = |overrides: &mut HashMap<EcoString, Range>| {
+            let _ = overrides.insert("gleam_stdlib".into(), Range::new("!= 0.3.0".into()));
+        };
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("gleam_otp".into(), Range::new("~> 0.1".into()))].into_iter(),
+            ResolutionOptions {
+                locked: [("gleam_stdlib".into(), Version::new(0, 3, 0))].into(),
+                overrides_transform: Some(Box::new(never_latest)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                ("gleam_otp".into(), Version::try_from("0.2.0").unwrap()),
+                ("gleam_stdlib".into(), Version::try_from("0.2.2").unwrap())
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
     #[test]
     fn resolution_with_nested_deps() {
         let result = resolve_versions(
@@ -433,7 +1281,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_otp".into(), Range::new("~> 0.1".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap();
         assert_eq!(
@@ -454,7 +1302,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_otp".into(), Range::new("~> 0.1.0".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap();
         assert_eq!(
@@ -468,6 +1316,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resolution_overrides_transitive_dependency() {
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("gleam_otp".into(), Range::new("~> 0.1.0".into()))].into_iter(),
+            ResolutionOptions {
+                // The locked version doesn't satisfy the override so is replaced
+                locked: vec![("gleam_stdlib".into(), Version::new(0, 3, 0))]
+                    .into_iter()
+                    .collect(),
+                overrides: vec![("gleam_stdlib".into(), Range::new("< 0.2.0".into()))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                ("gleam_otp".into(), Version::try_from("0.1.0").unwrap()),
+                ("gleam_stdlib".into(), Version::try_from("0.1.0").unwrap())
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn resolution_unsatisfiable_override() {
+        let _ = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("gleam_otp".into(), Range::new("~> 0.1.0".into()))].into_iter(),
+            ResolutionOptions {
+                overrides: vec![("gleam_stdlib".into(), Range::new("> 1.0.0".into()))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+    }
+
     #[test]
     fn resolution_retired_versions_not_used_by_default() {
         let result = resolve_versions(
@@ -475,7 +1370,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("package_with_retired".into(), Range::new("> 0.0.0".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap();
         assert_eq!(
@@ -490,6 +1385,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resolution_only_retired_versions_satisfy_requirement() {
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("package_with_retired".into(), Range::new("~> 0.2".into()))].into_iter(),
+            ResolutionOptions::default(),
+        );
+        assert_eq!(
+            result,
+            Err(Error::DependencyResolutionFailed(
+                ResolutionFailure::OnlyRetiredReleases {
+                    releases: vec!["package_with_retired@0.2.0".into()]
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn inconsistent_solution_is_rejected() {
+        let provided = hexpm::Package {
+            name: "wibble".into(),
+            repository: "local".into(),
+            releases: vec![Release {
+                version: Version::new(1, 0, 0),
+                requirements: [(
+                    "gleam_stdlib".into(),
+                    Dependency {
+                        app: None,
+                        optional: false,
+                        repository: None,
+                        requirement: Range::new("~> 0.2".into()),
+                    },
+                )]
+                .into(),
+                retirement_status: None,
+                outer_checksum: vec![],
+                meta: (),
+            }],
+        };
+        let packages = [("wibble".into(), provided)].into();
+        let selected: Map<String, Version> = [
+            ("wibble".into(), Version::new(1, 0, 0)),
+            ("gleam_stdlib".into(), Version::new(0, 1, 0)),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            check_solution(&packages, &HashSet::new(), &selected),
+            Err(ResolutionFailure::UnsatisfiedRequirement {
+                package: "gleam_stdlib".into(),
+                version: "0.1.0".into(),
+                dependent: "wibble@1.0.0".into(),
+                requirement: "~> 0.2".into(),
+            })
+        );
+        // Patched packages are not held to the requirements on them
+        assert_eq!(
+            check_solution(&packages, &["gleam_stdlib".into()].into(), &selected),
+            Ok(())
+        );
+    }
+
     #[test]
     fn resolution_retired_versions_can_be_used_if_locked() {
         let result = resolve_versions(
@@ -497,9 +1457,12 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("package_with_retired".into(), Range::new("> 0.0.0".into()))].into_iter(),
-            &vec![("package_with_retired".into(), Version::new(0, 2, 0))]
-                .into_iter()
-                .collect(),
+            ResolutionOptions {
+                locked: vec![("package_with_retired".into(), Version::new(0, 2, 0))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
         )
         .unwrap();
         assert_eq!(
@@ -514,6 +1477,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resolution_retired_versions_rejected_even_if_locked() {
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("package_with_retired".into(), Range::new("> 0.0.0".into()))].into_iter(),
+            ResolutionOptions {
+                locked: vec![("package_with_retired".into(), Version::new(0, 2, 0))]
+                    .into_iter()
+                    .collect(),
+                retired_releases: RetiredReleases::Reject,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![(
+                "package_with_retired".into(),
+                // Uses the older version that hasn't been retired
+                Version::new(0, 1, 0)
+            ),]
+            .into_iter()
+            .collect()
+        );
+    }
+
     #[test]
     fn resolution_prerelease_can_be_selected() {
         let result = resolve_versions(
@@ -521,7 +1512,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_otp".into(), Range::new("~> 0.3.0-rc1".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap();
         assert_eq!(
@@ -535,6 +1526,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resolution_prerelease_only_used_when_requested() {
+        let resolve = |requirement: &str| {
+            resolve_versions(
+                make_remote(),
+                HashMap::new(),
+                "app".into(),
+                vec![("prerelease_only".into(), Range::new(requirement.into()))].into_iter(),
+                ResolutionOptions::default(),
+            )
+        };
+        assert!(resolve(">= 0.1.0").is_err());
+        assert_eq!(
+            resolve(">= 1.0.0-rc1").unwrap(),
+            vec![(
+                "prerelease_only".into(),
+                Version::try_from("1.0.0-rc1").unwrap()
+            )]
+            .into_iter()
+            .collect(),
+        );
+    }
+
+    #[test]
+    fn resolution_optional_deps_excluded_by_default() {
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![("package_with_optional".into(), Range::new("~> 0.1".into()))].into_iter(),
+            ResolutionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![(
+                "package_with_optional".into(),
+                Version::try_from("0.1.0").unwrap()
+            )]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn resolution_optional_deps_included_when_required_elsewhere() {
+        let result = resolve_versions(
+            make_remote(),
+            HashMap::new(),
+            "app".into(),
+            vec![
+                ("package_with_optional".into(), Range::new("~> 0.1".into())),
+                ("gleam_otp".into(), Range::new("~> 0.1".into())),
+            ]
+            .into_iter(),
+            ResolutionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (
+                    "package_with_optional".into(),
+                    Version::try_from("0.1.0").unwrap()
+                ),
+                // The optional requirement of ~> 0.1.0 is respected
+                ("gleam_otp".into(), Version::try_from("0.1.0").unwrap()),
+                ("gleam_stdlib".into(), Version::try_from("0.3.0").unwrap()),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
     #[test]
     fn resolution_not_found_dep() {
         let _ = resolve_versions(
@@ -542,7 +1607,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("unknown".into(), Range::new("~> 0.1".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap_err();
     }
@@ -554,7 +1619,7 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_stdlib".into(), Range::new("~> 99.0".into()))].into_iter(),
-            &vec![].into_iter().collect(),
+            ResolutionOptions::default(),
         )
         .unwrap_err();
     }
@@ -566,18 +1631,160 @@ mod tests {
             HashMap::new(),
             "app".into(),
             vec![("gleam_stdlib".into(), Range::new("~> 0.1.0".into()))].into_iter(),
-            &vec![("gleam_stdlib".into(), Version::new(0, 2, 0))]
-                .into_iter()
-                .collect(),
+            ResolutionOptions {
+                locked: vec![("gleam_stdlib".into(), Version::new(0, 2, 0))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
         )
         .unwrap_err();
 
-        match err {
-        Error::DependencyResolutionFailed(msg) => assert_eq!(
-            msg,
-            "An unrecoverable error happened while solving dependencies: gleam_stdlib is specified with the requirement `~> 0.1.0`, but it is locked to 0.2.0, which is incompatible."
-        ),
-        _ => panic!("wrong error: {}", err),
-        }
+        assert_eq!(
+            err,
+            Error::DependencyResolutionFailed(ResolutionFailure::UnsatisfiableConstraint {
+                package: "gleam_stdlib".into(),
+                requirement: "~> 0.1.0".into(),
+                version: "0.2.0".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn resolution_failure_messages() {
+        let message = |failure: ResolutionFailure| failure.to_string();
+        assert_eq!(
+            message(ResolutionFailure::ConflictingSources {
+                package: "wibble".into(),
+                source_1: r#"{ version = "~> 1.0" }"#.into(),
+                source_2: r#"{ path = "../wibble" }"#.into(),
+            }),
+            r#"The package `wibble` is provided as both `{ version = "~> 1.0" }` and `{ path = "../wibble" }`."#
+        );
+        assert_eq!(
+            message(ResolutionFailure::UnsatisfiableConstraint {
+                package: "gleam_stdlib".into(),
+                requirement: "~> 0.1.0".into(),
+                version: "0.2.0".into(),
+            }),
+            "gleam_stdlib is specified with the requirement `~> 0.1.0`, \
+but it is locked to 0.2.0, which is incompatible."
+        );
+        assert_eq!(
+            message(ResolutionFailure::PackageNameMismatch {
+                path: "../wobble".into(),
+                expected: "wibble".into(),
+                found: "wobble".into(),
+            }),
+            "Expected package `wibble` at path `../wobble` but found `wobble` instead."
+        );
+        assert_eq!(
+            message(ResolutionFailure::GitUnsupported),
+            "Git dependencies are not currently supported."
+        );
+        assert_eq!(
+            message(ResolutionFailure::NoSolution(
+                "Because there is no version of wibble in >1.0.0 we are done.".into()
+            )),
+            "Because there is no version of wibble in >1.0.0 we are done."
+        );
+        assert_eq!(
+            Error::dependency_resolution_failed(ResolutionError::Failure("Boom".into()))
+                .to_string(),
+            "Dependency tree resolution failed: \
+An unrecoverable error happened while solving dependencies: Boom"
+        );
+    }
+
+    #[test]
+    fn manifest_status_of_each_outcome() {
+        let fs = InMemoryFileSystem::new();
+        let paths = ProjectPaths::new("/app".into());
+        let mut config = PackageConfig::default();
+        config.dependencies = [("gleam_stdlib".into(), Requirement::hex("~> 0.34"))].into();
+        let status = |config: &PackageConfig| manifest_status(&fs, &paths, config).unwrap();
+
+        assert_eq!(status(&config).status, ManifestStatus::Missing);
+
+        let manifest = Manifest {
+            requirements: config.all_dependencies().unwrap(),
+            packages: vec![ManifestPackage {
+                name: "gleam_stdlib".into(),
+                version: Version::new(0, 34, 0),
+                build_tools: ["gleam".into()].into(),
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1, 2, 3]),
+                    repository: None,
+                },
+            }],
+        };
+        let toml = manifest.to_toml(paths.root());
+        fs.write(&paths.manifest(), &toml).unwrap();
+        assert_eq!(
+            status(&config),
+            ManifestStatusReport {
+                status: ManifestStatus::UpToDate,
+                warnings: vec![],
+            }
+        );
+
+        // Edits to the manifest are reported rather than printed
+        let edited = toml.replace(r#"version = "0.34.0""#, r#"version = "0.34.1""#);
+        fs.write(&paths.manifest(), &edited).unwrap();
+        assert_eq!(
+            status(&config).warnings,
+            vec![Warning::EditedManifest {
+                path: paths.manifest()
+            }]
+        );
+        fs.write(&paths.manifest(), &toml).unwrap();
+
+        let _ = config
+            .dependencies
+            .insert("gleam_json".into(), Requirement::hex("~> 1.0"));
+        assert_eq!(status(&config).status, ManifestStatus::Outdated);
+    }
+
+    #[test]
+    fn workspace_member_globs_match_directories_at_their_depth() {
+        let fs = InMemoryFileSystem::new();
+        let package = |name: &str| format!("name = \"{name}\"\nversion = \"1.0.0\"\n");
+        fs.write(
+            &Utf8PathBuf::from("/app/packages/one/gleam.toml"),
+            &package("one"),
+        )
+        .unwrap();
+        fs.write(
+            &Utf8PathBuf::from("/app/packages/one/nested/gleam.toml"),
+            &package("nested"),
+        )
+        .unwrap();
+        fs.write(&Utf8PathBuf::from("/app/packages/notes/README.md"), "")
+            .unwrap();
+        let mut config = PackageConfig::default();
+        config.workspace.members = vec!["packages/*".into()];
+
+        assert_eq!(
+            workspace_dependencies(&fs, &config, Utf8Path::new("/app")).unwrap(),
+            [(
+                "one".into(),
+                Requirement::Path {
+                    path: "packages/one".into()
+                }
+            )]
+            .into()
+        );
+
+        config.workspace.members = vec!["packages/**".into()];
+        assert_eq!(
+            workspace_dependencies(&fs, &config, Utf8Path::new("/app"))
+                .unwrap()
+                .into_keys()
+                .sorted()
+                .collect_vec(),
+            vec![EcoString::from("nested"), EcoString::from("one")]
+        );
     }
 }
@@ -17,6 +17,7 @@ use itertools::Itertools;
 use std::env;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::time::Duration;
 use termcolor::Buffer;
 use thiserror::Error;
 
@@ -41,6 +42,61 @@ pub struct UnknownImportDetails {
     pub modules: Vec<EcoString>,
 }
 
+/// The reason the versions of the dependency packages could not be resolved.
+#[derive(Debug, Eq, PartialEq, Error, Clone)]
+pub enum ResolutionFailure {
+    #[error("The package `{package}` is provided as both `{source_1}` and `{source_2}`.")]
+    ConflictingSources {
+        package: String,
+        source_1: String,
+        source_2: String,
+    },
+
+    #[error(
+        "{package} is specified with the requirement `{requirement}`, \
+but it is locked to {version}, which is incompatible."
+    )]
+    UnsatisfiableConstraint {
+        package: String,
+        requirement: String,
+        version: String,
+    },
+
+    #[error("Expected package `{expected}` at path `{path}` but found `{found}` instead.")]
+    PackageNameMismatch {
+        path: Utf8PathBuf,
+        expected: String,
+        found: String,
+    },
+
+    #[error("Git dependencies are not currently supported.")]
+    GitUnsupported,
+
+    /// The solver selected a version that doesn't satisfy a requirement on it.
+    #[error(
+        "The version of {package} selected was {version} but {dependent} requires `{requirement}`."
+    )]
+    UnsatisfiedRequirement {
+        package: String,
+        version: String,
+        dependent: String,
+        requirement: String,
+    },
+
+    #[error(
+        "The requirements can only be satisfied by releases that have been retired on Hex: {}",
+        releases.join(", ")
+    )]
+    OnlyRetiredReleases { releases: Vec<String> },
+
+    /// No versions satisfy the requirements, explained by the solver.
+    #[error("{0}")]
+    NoSolution(String),
+
+    #[error("{0}")]
+    Failure(String),
+}
+
 #[derive(Debug, Eq, PartialEq, Error, Clone)]
 pub enum Error {
     #[error("failed to parse Gleam source code")]
@@ -109,6 +165,14 @@ pub enum Error {
     #[error("{error}")]
     ExpandTar { error: String },
 
+    #[error("failed to unpack package {package}")]
+    UnpackPackage {
+        package: EcoString,
+        path: Utf8PathBuf,
+        reason: UnpackFailureReason,
+        error: String,
+    },
+
     #[error("{err}")]
     AddTar { path: Utf8PathBuf, err: String },
 
@@ -190,34 +254,65 @@ file_names.iter().map(|x| x.as_str()).join(", "))]
         error: String,
     },
 
+    #[error("package {package} {version} has checksum {actual} but {expected} was expected")]
+    PackageChecksumMismatch {
+        package: String,
+        version: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Hex package {package} {version} has no checksum")]
+    MissingPackageChecksum { package: String, version: String },
+
     #[error("{0}")]
     Http(String),
 
-    #[error("Git dependencies are currently unsupported")]
-    GitDependencyUnsupported,
+    #[error("HTTP request to {url} timed out after {timeout:?}")]
+    HttpTimeout { url: String, timeout: Duration },
+
+    #[error("HTTP request to {url} was not redirected: {problem}")]
+    HttpRedirect {
+        url: String,
+        problem: RedirectProblem,
+    },
+
+    #[error("Failed to check out commit {commit} of git dependency {package}: {error}")]
+    GitDependencyCheckoutFailed {
+        package: EcoString,
+        repo: EcoString,
+        commit: EcoString,
+        error: String,
+    },
+
+    #[error("Authentication failed for git dependency {package} from {repo}: {error}")]
+    GitDependencyAuthenticationFailed {
+        package: EcoString,
+        repo: EcoString,
+        error: String,
+    },
+
+    #[error("Repository {repo} for git dependency {package} was not found")]
+    GitDependencyRepositoryNotFound {
+        package: EcoString,
+        repo: EcoString,
+        error: String,
+    },
 
     #[error("Failed to create canonical path for package {0}")]
     DependencyCanonicalizationFailed(String),
 
     #[error("Dependency tree resolution failed: {0}")]
-    DependencyResolutionFailed(String),
+    DependencyResolutionFailed(ResolutionFailure),
 
     #[error("The package {0} is listed in dependencies and dev-dependencies")]
     DuplicateDependency(EcoString),
 
-    #[error("Expected package {expected} at path {path} but found {found} instead")]
-    WrongDependencyProvided {
-        path: Utf8PathBuf,
-        expected: String,
-        found: String,
-    },
+    #[error("Local dependency {package} at {path} has no gleam.toml")]
+    MissingLocalDependencyConfig { package: String, path: Utf8PathBuf },
 
-    #[error("The package {package} is provided multiple times, as {source_1} and {source_2}")]
-    ProvidedDependencyConflict {
-        package: String,
-        source_1: String,
-        source_2: String,
-    },
+    #[error("Local dependency {package} at {path} is not a directory")]
+    LocalDependencyNotADirectory { package: String, path: Utf8PathBuf },
 
     #[error("The package was missing required fields for publishing")]
     MissingHexPublishFields {
@@ -246,6 +341,69 @@ file_names.iter().map(|x| x.as_str()).join(", "))]
 
     #[error("The --javascript-prelude flag must be given when compiling to JavaScript")]
     JavaScriptPreludeRequired,
+
+    #[error("Dependency versions cannot be resolved without network access")]
+    OfflineDependencyResolution,
+
+    #[error("manifest.toml is missing or out of date")]
+    FrozenManifestOutdated,
+
+    #[error("{package} is not a dependency of this project")]
+    NotADependency { package: EcoString },
+
+    #[error("Packages have licences that are not allowed: {}", packages.join(", "))]
+    DisallowedLicences { packages: Vec<EcoString> },
+
+    #[error("Packages are not in the local package cache: {}", packages.join(", "))]
+    PackagesNotCached { packages: Vec<String> },
+
+    #[error("The workspace member {member} is not a valid glob pattern")]
+    InvalidWorkspaceMember { member: Utf8PathBuf, error: String },
+
+    #[error("Packages are corrupted: {}", packages.join(", "))]
+    PackagesCorrupted { packages: Vec<String> },
+
+    #[error("Packages are not in the manifest: {}", packages.join(", "))]
+    PackagesNotInManifest { packages: Vec<String> },
+
+    #[error("Invalid package names: {}", names.join(", "))]
+    InvalidPackageNames { names: Vec<EcoString> },
+
+    #[error("The dependency requirements in gleam.toml are invalid")]
+    InvalidDependencyRequirements { problems: Vec<RequirementProblem> },
+
+    #[error("manifest.toml is not consistent with gleam.toml")]
+    InconsistentManifest { problems: Vec<RequirementProblem> },
+
+    #[error("Packages would be downgraded: {}", packages.iter().map(|(name, _, _)| name).join(", "))]
+    PackagesDowngraded {
+        packages: Vec<(EcoString, hexpm::version::Version, hexpm::version::Version)>,
+    },
+
+    #[error("manifest version {version} is newer than the supported version {supported}")]
+    ManifestVersionTooNew {
+        path: Utf8PathBuf,
+        version: u32,
+        supported: u32,
+    },
+
+    #[error("failed to start the async runtime: {error}")]
+    AsyncRuntimeStart { error: String },
+
+    #[error("{variable} is not a valid Hex repository URL: {url}")]
+    InvalidHexRepositoryUrl { variable: String, url: String },
+
+    #[error("{package} uses the repository {repository} which is not declared")]
+    UnknownHexRepository {
+        package: EcoString,
+        repository: EcoString,
+    },
+
+    #[error("The Hex response for {package} from {repository} could not be verified")]
+    HexResponseUnverified { package: String, repository: String },
+
+    #[error("The Hex snapshot at {snapshot} does not contain {path}")]
+    NotInHexSnapshot { snapshot: Utf8PathBuf, path: String },
 }
 
 impl Error {
@@ -282,44 +440,54 @@ impl Error {
     }
 
     pub fn dependency_resolution_failed(error: ResolutionError) -> Error {
+        // Errors from fetching package information are reported as they are
+        let source = match &error {
+            ResolutionError::ErrorRetrievingDependencies { source, .. } => Some(source),
+            ResolutionError::ErrorChoosingPackageVersion(err) => Some(err),
+            _ => None,
+        };
+        if let Some(error) = source.and_then(|source| source.downcast_ref::<Error>()) {
+            return error.clone();
+        }
+
         Self::DependencyResolutionFailed(match error {
             ResolutionError::NoSolution(mut derivation_tree) => {
                 derivation_tree.collapse_no_versions();
                 let report = DefaultStringReporter::report(&derivation_tree);
-                wrap(&report)
+                ResolutionFailure::NoSolution(wrap(&report))
             }
 
             ResolutionError::ErrorRetrievingDependencies {
                 package,
                 version,
                 source,
-            } => format!(
+            } => ResolutionFailure::Failure(format!(
                 "An error occured while trying to retrieve dependencies of {package}@{version}: {source}",
-            ),
+            )),
 
             ResolutionError::DependencyOnTheEmptySet {
                 package,
                 version,
                 dependent,
-            } => format!(
+            } => ResolutionFailure::Failure(format!(
                 "{package}@{version} has an impossible dependency on {dependent}",
-            ),
+            )),
 
             ResolutionError::SelfDependency { package, version } => {
-                format!("{package}@{version} somehow depends on itself.")
+                ResolutionFailure::Failure(format!("{package}@{version} somehow depends on itself."))
             }
 
             ResolutionError::ErrorChoosingPackageVersion(err) => {
-                format!("Unable to determine package versions: {err}")
+                ResolutionFailure::Failure(format!("Unable to determine package versions: {err}"))
             }
 
             ResolutionError::ErrorInShouldCancel(err) => {
-                format!("Dependency resolution was cancelled. {err}")
+                ResolutionFailure::Failure(format!("Dependency resolution was cancelled. {err}"))
             }
 
-            ResolutionError::Failure(err) => format!(
+            ResolutionError::Failure(err) => ResolutionFailure::Failure(format!(
                 "An unrecoverable error happened while solving dependencies: {err}"
-            ),
+            )),
         })
     }
 
@@ -349,6 +517,44 @@ impl From<capnp::NotInSchema> for Error {
     }
 }
 
+/// Why the contents of a package could not be written to the build directory.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UnpackFailureReason {
+    DiskFull,
+    PermissionDenied,
+    Other,
+}
+
+impl UnpackFailureReason {
+    pub fn from_io_error(error: &std::io::Error) -> Self {
+        // `ENOSPC` on Unix, `ERROR_HANDLE_DISK_FULL` and `ERROR_DISK_FULL` on
+        // Windows.
+        let disk_full: &[i32] = if cfg!(windows) { &[39, 112] } else { &[28] };
+        if error.kind() == std::io::ErrorKind::PermissionDenied {
+            Self::PermissionDenied
+        } else if error
+            .raw_os_error()
+            .is_some_and(|code| disk_full.contains(&code))
+        {
+            Self::DiskFull
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// The most redirects followed for a single HTTP request.
+pub const MAX_HTTP_REDIRECTS: usize = 5;
+
+/// Why a HTTP redirect was not followed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Error)]
+pub enum RedirectProblem {
+    #[error("too many redirects")]
+    TooMany,
+    #[error("redirect from HTTPS to HTTP")]
+    InsecureDowngrade,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum InvalidProjectNameReason {
     Format,
@@ -382,7 +588,7 @@ pub enum FileIoAction {
     Read,
     Parse,
     Delete,
-    // Rename,
+    Rename,
     Create,
     WriteTo,
     Canonicalise,
@@ -400,7 +606,7 @@ impl FileIoAction {
             FileIoAction::Read => "read",
             FileIoAction::Parse => "parse",
             FileIoAction::Delete => "delete",
-            // FileIoAction::Rename => "rename",
+            FileIoAction::Rename => "rename",
             FileIoAction::Create => "create",
             FileIoAction::WriteTo => "write to",
             FileIoAction::FindParent => "find the parent of",
@@ -713,6 +919,42 @@ This was error from the tar library:
                 }
             }
 
+            Error::UnpackPackage {
+                package,
+                path,
+                reason,
+                error,
+            } => {
+                let text = format!(
+                    "An error occurred while unpacking the package `{package}` into
+this directory:
+
+    {path}
+
+The error message from the file IO library was:
+
+    {error}"
+                );
+                let hint = match reason {
+                    UnpackFailureReason::DiskFull => {
+                        Some("The disk is full. Free up some space and try again.".into())
+                    }
+                    UnpackFailureReason::PermissionDenied => Some(
+                        "The build directory could not be written to. Check its permissions
+and try again."
+                            .into(),
+                    ),
+                    UnpackFailureReason::Other => None,
+                };
+                Diagnostic {
+                    title: "Failed to unpack package".into(),
+                    text,
+                    hint,
+                    level: Level::Error,
+                    location: None,
+                }
+            }
+
             Error::ExpandTar { error } => {
                 let text = format!(
                     "There was a problem when attempting to expand a to a tar archive.
@@ -2472,6 +2714,49 @@ The error from the package manager client was:
                 }
             }
 
+            Error::PackageChecksumMismatch {
+                package,
+                version,
+                expected,
+                actual,
+            } => {
+                let text = format!(
+                    "The downloaded tarball for `{package}` {version} does not match the
+checksum recorded in manifest.toml.
+
+Expected: {expected}
+Actual:   {actual}
+
+The package repository may be serving a different package than the one
+that was locked."
+                );
+                Diagnostic {
+                    title: "Package checksum mismatch".into(),
+                    text,
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::MissingPackageChecksum { package, version } => {
+                let text = format!(
+                    "The Hex package `{package}` {version} has an empty checksum, so its
+contents cannot be verified and it will not be used."
+                );
+                Diagnostic {
+                    title: "Missing package checksum".into(),
+                    text,
+                    hint: Some(
+                        "Remove the package from manifest.toml and run `gleam deps download`
+to resolve it again."
+                            .into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
             Error::Http(error) => {
                 let text = format!(
                     "A HTTP request failed.
@@ -2488,6 +2773,43 @@ The error from the HTTP client was:
                 }
             }
 
+            Error::HttpTimeout { url, timeout } => {
+                let text = format!(
+                    "A HTTP request to {url} did not complete within {timeout:?}.
+The server may be unavailable or the network connection may be slow."
+                );
+                Diagnostic {
+                    title: "HTTP request timed out".into(),
+                    text,
+                    hint: Some(
+                        "The timeouts can be increased by setting the GLEAM_HTTP_CONNECT_TIMEOUT
+and GLEAM_HTTP_READ_TIMEOUT environment variables to a number of seconds."
+                            .into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::HttpRedirect { url, problem } => {
+                let text = match problem {
+                    RedirectProblem::TooMany => format!(
+                        "A HTTP request to {url} was redirected more than {MAX_HTTP_REDIRECTS} times."
+                    ),
+                    RedirectProblem::InsecureDowngrade => format!(
+                        "A HTTPS request to {url} was redirected to an insecure HTTP address.
+The redirect was not followed as the response could have been tampered with."
+                    ),
+                };
+                Diagnostic {
+                    title: "HTTP redirect not followed".into(),
+                    text,
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
             Error::InvalidVersionFormat { input, error } => {
                 let text = format!(
                     "I was unable to parse the version \"{input}\".
@@ -2516,63 +2838,176 @@ The error from the parser was:
                 }
             }
 
-            Error::DependencyResolutionFailed(error) => {
-                let text = format!(
-                    "An error occurred while determining what dependency packages and
+            Error::DependencyResolutionFailed(failure) => match failure {
+                ResolutionFailure::ConflictingSources { .. } => Diagnostic {
+                    title: "Conflicting provided dependencies".into(),
+                    text: failure.to_string(),
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                },
+
+                ResolutionFailure::PackageNameMismatch { .. } => Diagnostic {
+                    title: "Wrong dependency provided".into(),
+                    text: failure.to_string(),
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                },
+
+                ResolutionFailure::GitUnsupported => Diagnostic {
+                    title: "Git dependencies are not currently supported".into(),
+                    text: "Please remove all git dependencies from the gleam.toml file".into(),
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                },
+
+                ResolutionFailure::OnlyRetiredReleases { releases } => Diagnostic {
+                    title: "Only retired releases are compatible".into(),
+                    text: format!(
+                        "The version requirements can only be satisfied by releases that
+have been retired on Hex:
+
+{}",
+                        releases
+                            .iter()
+                            .map(|release| format!("  - {release}"))
+                            .join("\n")
+                    ),
+                    hint: Some(
+                        "Change the requirements so that a release that has not been retired
+can be used."
+                            .into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                },
+
+                ResolutionFailure::UnsatisfiableConstraint { .. }
+                | ResolutionFailure::UnsatisfiedRequirement { .. }
+                | ResolutionFailure::NoSolution(_)
+                | ResolutionFailure::Failure(_) => {
+                    let text = format!(
+                        "An error occurred while determining what dependency packages and
 versions should be downloaded.
 The error from the version resolver library was:
 
 {}",
-                    wrap(error)
+                        wrap(&failure.to_string())
+                    );
+                    Diagnostic {
+                        title: "Dependency resolution failed".into(),
+                        text,
+                        hint: None,
+                        location: None,
+                        level: Level::Error,
+                    }
+                }
+            },
+
+            Error::GitDependencyCheckoutFailed {
+                package,
+                repo,
+                commit,
+                error,
+            } => {
+                let text = format!(
+                    "A problem was encountered when checking out commit {commit} of
+{repo} for the package `{package}`.
+The error from git was:
+
+    {error}"
                 );
                 Diagnostic {
-                    title: "Dependency resolution failed".into(),
+                    title: "Failed to check out git dependency".into(),
                     text,
-                    hint: None,
+                    hint: Some(
+                        "If the commit no longer exists the repository may have been \
+force-pushed. Update the dependency to lock a commit that exists."
+                            .into(),
+                    ),
                     location: None,
                     level: Level::Error,
                 }
             }
 
-            Error::GitDependencyUnsupported => Diagnostic {
-                title: "Git dependencies are not currently supported".into(),
-                text: "Please remove all git dependencies from the gleam.toml file".into(),
-                hint: None,
-                location: None,
-                level: Level::Error,
-            },
-
-            Error::WrongDependencyProvided {
-                path,
-                expected,
-                found,
+            Error::GitDependencyAuthenticationFailed {
+                package,
+                repo,
+                error,
             } => {
                 let text = format!(
-                    "Expected package `{expected}` at path `{path}` but found `{found}` instead.",
-                );
+                    "Git could not authenticate with {repo} to fetch the package `{package}`.
+The error from git was:
 
+    {error}"
+                );
                 Diagnostic {
-                    title: "Wrong dependency provided".into(),
+                    title: "Git dependency authentication failed".into(),
                     text,
-                    hint: None,
+                    hint: Some(
+                        "For SSH repositories check that your SSH agent has a key for \
+this host, or set GLEAM_GIT_SSH_KEY to the path of a key file. For HTTPS \
+repositories configure a git credential helper."
+                            .into(),
+                    ),
                     location: None,
                     level: Level::Error,
                 }
             }
 
-            Error::ProvidedDependencyConflict {
+            Error::GitDependencyRepositoryNotFound {
                 package,
-                source_1,
-                source_2,
+                repo,
+                error,
             } => {
                 let text = format!(
-                    "The package `{package}` is provided as both `{source_1}` and `{source_2}`.",
+                    "The repository {repo} for the package `{package}` could not be found.
+The error from git was:
+
+    {error}"
+                );
+                Diagnostic {
+                    title: "Git dependency repository not found".into(),
+                    text,
+                    hint: Some(
+                        "Check the repository URL is correct. Private repositories may \
+also report that they are not found if you do not have access."
+                            .into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::MissingLocalDependencyConfig { package, path } => {
+                let text = format!(
+                    "The local dependency `{package}` at path `{path}` has no gleam.toml file.",
                 );
 
                 Diagnostic {
-                    title: "Conflicting provided dependencies".into(),
+                    title: "Local dependency is not a Gleam project".into(),
                     text,
-                    hint: None,
+                    hint: Some(format!(
+                        "Check the path given for `{package}` in your gleam.toml."
+                    )),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::LocalDependencyNotADirectory { package, path } => {
+                let text = format!(
+                    "The local dependency `{package}` at path `{path}` is not a directory.",
+                );
+
+                Diagnostic {
+                    title: "Local dependency is not a directory".into(),
+                    text,
+                    hint: Some(format!(
+                        "Check the path given for `{package}` in your gleam.toml."
+                    )),
                     location: None,
                     level: Level::Error,
                 }
@@ -2724,6 +3159,315 @@ but you are using v{gleam_version}.",
                 location: None,
                 hint: None,
             },
+
+            Error::OfflineDependencyResolution => Diagnostic {
+                title: "Dependency resolution requires network access".into(),
+                text: wrap(
+                    "Network access is disabled but manifest.toml is missing or does \
+not match the requirements in gleam.toml, so dependency versions would have to be \
+resolved using Hex.",
+                ),
+                hint: Some(
+                    "Run the command again with network access to update manifest.toml.".into(),
+                ),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::FrozenManifestOutdated => Diagnostic {
+                title: "Manifest out of date".into(),
+                text: wrap(
+                    "The manifest is frozen but manifest.toml is missing or does not \
+match the requirements in gleam.toml, so it would have to be changed.",
+                ),
+                hint: Some(
+                    "Run the command again without --frozen to update manifest.toml.".into(),
+                ),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::NotADependency { package } => Diagnostic {
+                title: "Not a dependency".into(),
+                text: wrap(&format!(
+                    "The package `{package}` is not a dependency of this project, so it \
+cannot be removed."
+                )),
+                hint: Some(
+                    "Only packages listed in the dependencies or dev-dependencies of \
+gleam.toml can be removed."
+                        .into(),
+                ),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::DisallowedLicences { packages } => {
+                let text = format!(
+                    "These packages have no licence, or none of their licences are allowed:
+
+{}",
+                    packages.iter().map(|name| format!("  - {name}")).join("\n")
+                );
+                Diagnostic {
+                    title: "Licences not allowed".into(),
+                    text,
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::InvalidWorkspaceMember { member, error } => Diagnostic {
+                title: "Invalid workspace member".into(),
+                text: format!(
+                    "The workspace member `{member}` in gleam.toml is not a valid glob
+pattern: {error}"
+                ),
+                hint: None,
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::PackagesCorrupted { packages } => {
+                let text = format!(
+                    "These packages in the build directory do not match the checksums
+in manifest.toml:
+
+{}",
+                    packages
+                        .iter()
+                        .map(|package| format!("  - {package}"))
+                        .join("\n")
+                );
+                Diagnostic {
+                    title: "Packages corrupted".into(),
+                    text,
+                    hint: Some(
+                        "Run `gleam deps download --verify` to download them again.".into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::PackagesNotCached { packages } => {
+                let text = format!(
+                    "Network access is disabled and these packages are not in the local
+package cache:
+
+{}",
+                    packages
+                        .iter()
+                        .map(|package| format!("  - {package}"))
+                        .join("\n")
+                );
+                Diagnostic {
+                    title: "Packages not cached".into(),
+                    text,
+                    hint: Some(
+                        "Run the command again with network access to download them.".into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::PackagesDowngraded { packages } => {
+                let text = format!(
+                    "The latest versions that can be used are lower than the ones \
+currently locked in manifest.toml for these packages, which may be because the \
+newer versions have been removed from the package repository:
+
+{}",
+                    packages
+                        .iter()
+                        .map(|(name, locked, resolved)| format!(
+                            "  - {name} {locked} -> {resolved}"
+                        ))
+                        .join("\n")
+                );
+                Diagnostic {
+                    title: "Packages would be downgraded".into(),
+                    text,
+                    hint: Some(
+                        "Run the command again with --allow-downgrade to use these versions."
+                            .into(),
+                    ),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::PackagesNotInManifest { packages } => {
+                let text = format!(
+                    "These packages are not dependencies of this project:
+
+{}",
+                    packages
+                        .iter()
+                        .map(|package| format!("  - {package}"))
+                        .join("\n")
+                );
+                Diagnostic {
+                    title: "Packages not in manifest".into(),
+                    text,
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::InvalidPackageNames { names } => {
+                let text = format!(
+                    "These package names are not valid:
+
+{}
+
+Package names must start with a lowercase letter and may only contain
+lowercase letters, numbers and underscores.",
+                    names.iter().map(|name| format!("  - {name}")).join("\n")
+                );
+                Diagnostic {
+                    title: "Invalid package names".into(),
+                    text,
+                    hint: Some("Check the names of the dependencies in gleam.toml.".into()),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::InvalidDependencyRequirements { problems } => {
+                let text = format!(
+                    "The following dependencies in gleam.toml are not valid:
+
+{}",
+                    problems
+                        .iter()
+                        .map(|RequirementProblem { package, problem }| {
+                            format!("  - {package}: {problem}")
+                        })
+                        .join("\n")
+                );
+                Diagnostic {
+                    title: "Invalid dependency requirements".into(),
+                    text,
+                    hint: None,
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::InconsistentManifest { problems } => {
+                let text = format!(
+                    "The following packages in manifest.toml are not consistent with \
+gleam.toml or with each other:
+
+{}",
+                    problems
+                        .iter()
+                        .map(|RequirementProblem { package, problem }| {
+                            format!("  - {package}: {problem}")
+                        })
+                        .join("\n")
+                );
+                Diagnostic {
+                    title: "Inconsistent manifest".into(),
+                    text,
+                    hint: Some("Run `gleam deps download` to update manifest.toml.".into()),
+                    location: None,
+                    level: Level::Error,
+                }
+            }
+
+            Error::ManifestVersionTooNew {
+                path,
+                version,
+                supported,
+            } => Diagnostic {
+                title: "Manifest from a newer version of Gleam".into(),
+                text: format!(
+                    "The manifest at {path} uses format version {version}, but this
+version of Gleam only understands versions up to {supported}."
+                ),
+                hint: Some("Upgrade Gleam to use this project.".into()),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::AsyncRuntimeStart { error } => Diagnostic {
+                title: "Failed to start async runtime".into(),
+                text: format!(
+                    "The async runtime used to make network requests could not be started.
+The error from the operating system was:
+
+    {error}"
+                ),
+                hint: None,
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::InvalidHexRepositoryUrl { variable, url } => Diagnostic {
+                title: "Invalid Hex repository URL".into(),
+                text: format!(
+                    "The {variable} environment variable is set to `{url}`,
+which is not a valid URL."
+                ),
+                hint: Some("Use a full URL such as https://repo.hex.pm/".into()),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::UnknownHexRepository {
+                package,
+                repository,
+            } => Diagnostic {
+                title: "Unknown repository".into(),
+                text: wrap_format!(
+                    "The dependency `{package}` is to be fetched from the repository \
+`{repository}`, but there is no repository with that name in gleam.toml."
+                ),
+                hint: Some(format!(
+                    "Add the repository to gleam.toml:\n\n[repositories.{repository}]\nurl = \"https://...\""
+                )),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::HexResponseUnverified {
+                package,
+                repository,
+            } => Diagnostic {
+                title: "Unverified Hex response".into(),
+                text: format!(
+                    "The response from {repository} for the package `{package}` was not
+a signed Hex payload, so it could not be verified. This can happen
+when a proxy, login page, or maintenance page replaces the response."
+                ),
+                hint: Some(
+                    "Check your internet connection and that the Hex repository URL is
+correct."
+                        .into(),
+                ),
+                location: None,
+                level: Level::Error,
+            },
+
+            Error::NotInHexSnapshot { snapshot, path } => Diagnostic {
+                title: "Missing from Hex snapshot".into(),
+                text: format!(
+                    "Versions are resolved against the Hex snapshot at {snapshot},
+but it does not contain the response for `{path}`."
+                ),
+                hint: Some(
+                    "Add the response to the snapshot, or remove `snapshot` from the
+`[hex]` section of gleam.toml to resolve against Hex."
+                        .into(),
+                ),
+                location: None,
+                level: Level::Error,
+            },
         }
     }
 }
@@ -2808,6 +3552,12 @@ functions from the `gleam/string` module.",
     )
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequirementProblem {
+    pub package: EcoString,
+    pub problem: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Unformatted {
     pub source: Utf8PathBuf,
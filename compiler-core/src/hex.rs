@@ -1,13 +1,20 @@
-use camino::Utf8Path;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
 use debug_ignore::DebugIgnore;
+use ecow::EcoString;
 use flate2::read::GzDecoder;
-use futures::future;
+use futures::{stream, StreamExt};
 use hexpm::version::Version;
+use itertools::Itertools;
+use sha2::Digest;
 use tar::Archive;
 
 use crate::{
-    io::{FileSystemReader, FileSystemWriter, HttpClient, TarUnpacker},
-    manifest::{ManifestPackage, ManifestPackageSource},
+    build::{NullTelemetry, Telemetry},
+    config::{HexConfig, HexCredentials, NamedRepository, RepositoryCredentials},
+    io::{FileSystemReader, FileSystemWriter, HttpClient, ResponseProgress, TarUnpacker},
+    manifest::{Base16Checksum, ManifestPackage, ManifestPackageSource},
     paths::{self, ProjectPaths},
     Error, Result,
 };
@@ -23,6 +30,273 @@ J1i2xWFndWa6nfFnRxZmCStCOZWYYPlaxr+FZceFbpMwzTNs4g3d4tLNUcbKAIH4
 -----END PUBLIC KEY-----
 ";
 
+/// A Hex repository that packages are resolved and downloaded from, along with
+/// the public key used to verify the package information it serves.
+///
+/// Packages that belong to a private Hex organization are requested from that
+/// organization's repository using the API key, if one has been given. The
+/// key is never logged, so this type does not include it in its debug output.
+#[derive(Clone)]
+pub struct HexRepository {
+    pub config: hexpm::Config,
+    pub public_key: Vec<u8>,
+    api_key: Option<String>,
+    organizations: HashMap<EcoString, EcoString>,
+    repositories: HashMap<EcoString, RepositoryCredentials>,
+    named_repositories: HashMap<EcoString, NamedHexRepository>,
+    package_repositories: HashMap<EcoString, EcoString>,
+}
+
+/// A repository declared in gleam.toml, which is used instead of the Hex one
+/// for the packages that name it.
+#[derive(Debug, Clone)]
+struct NamedHexRepository {
+    config: hexpm::Config,
+    public_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for HexRepository {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HexRepository")
+            .field("config", &self.config)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("organizations", &self.organizations)
+            .field("repositories", &self.repositories)
+            .field("named_repositories", &self.named_repositories)
+            .field("package_repositories", &self.package_repositories)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HexRepository {
+    pub fn from_config(config: &HexConfig) -> Self {
+        let mut repository = Self::default();
+        if let Some(uri) = &config.repository {
+            repository.config.repository_base = with_trailing_slash(uri);
+        }
+        if let Some(uri) = &config.api {
+            repository.config.api_base = with_trailing_slash(uri);
+        }
+        if let Some(key) = &config.public_key {
+            repository.public_key = key.as_bytes().to_vec();
+        }
+        repository
+    }
+
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.api_key = Some(api_key);
+        self
+    }
+
+    /// Use the API keys and URLs of the repositories in the credentials for
+    /// the packages of the organizations with the same names.
+    pub fn with_credentials(mut self, credentials: HexCredentials) -> Self {
+        self.repositories.extend(credentials.repositories);
+        self
+    }
+
+    /// Record that a package belongs to a private Hex organization.
+    pub fn with_organization(mut self, package: EcoString, organization: EcoString) -> Self {
+        let _ = self.organizations.insert(package, organization);
+        self
+    }
+
+    /// Make a repository declared in gleam.toml available to packages by name.
+    pub fn with_named_repository(mut self, name: EcoString, repository: &NamedRepository) -> Self {
+        let url = with_trailing_slash(&repository.url);
+        let config = hexpm::Config {
+            api_base: repository
+                .api
+                .as_ref()
+                .map_or_else(|| url.clone(), with_trailing_slash),
+            repository_base: url,
+        };
+        let public_key = repository
+            .public_key
+            .as_ref()
+            .map_or_else(|| HEXPM_PUBLIC_KEY.to_vec(), |key| key.as_bytes().to_vec());
+        let _ = self
+            .named_repositories
+            .insert(name, NamedHexRepository { config, public_key });
+        self
+    }
+
+    /// Record that a package is fetched from a repository declared in
+    /// gleam.toml rather than the Hex one.
+    pub fn with_package_repository(mut self, package: EcoString, repository: EcoString) -> Self {
+        let _ = self.package_repositories.insert(package, repository);
+        self
+    }
+
+    /// The name of the repository declared in gleam.toml that a package is
+    /// fetched from, if it isn't the Hex one.
+    pub fn package_repository(&self, package: &str) -> Option<&EcoString> {
+        self.package_repositories
+            .get(package)
+            .filter(|name| self.named_repositories.contains_key(*name))
+    }
+
+    /// The name Hex gives the repository a package is fetched from: the name
+    /// of a repository declared in gleam.toml, or `hexpm:<organization>` for
+    /// the packages of a private organization. Packages fetched from the Hex
+    /// repository itself have none.
+    pub fn source_repository(&self, package: &str) -> Option<EcoString> {
+        match self.package_repository(package) {
+            Some(name) => Some(name.clone()),
+            None => self
+                .organizations
+                .get(package)
+                .map(|organization| ecow::eco_format!("hexpm:{organization}")),
+        }
+    }
+
+    /// Record that a package is fetched from the repository with the name Hex
+    /// gives it, as returned by `source_repository`. Returns false if the name
+    /// is not one of a declared repository or an organization.
+    pub fn route_package(&mut self, package: EcoString, repository: &str) -> bool {
+        if let Some(organization) = repository.strip_prefix("hexpm:") {
+            let _ = self.organizations.insert(package, organization.into());
+        } else if self.named_repositories.contains_key(repository) {
+            let _ = self.package_repositories.insert(package, repository.into());
+        } else {
+            return repository == "hexpm";
+        }
+        true
+    }
+
+    /// Route the dependencies of a release of a package to the repositories
+    /// its requirements name. A requirement without a repository, or with one
+    /// that isn't known here, is fetched from the same repository as the
+    /// package. Packages that already have a repository keep it, so direct
+    /// dependencies come from where gleam.toml says.
+    pub fn route_dependencies(
+        &mut self,
+        package: &str,
+        requirements: &HashMap<String, hexpm::Dependency>,
+    ) {
+        let inherited = self.source_repository(package);
+        for (name, requirement) in requirements {
+            if self.source_repository(name).is_some() {
+                continue;
+            }
+            let routed = requirement
+                .repository
+                .as_deref()
+                .is_some_and(|repository| self.route_package(name.as_str().into(), repository));
+            if let Some(inherited) = inherited.as_deref().filter(|_| !routed) {
+                let _ = self.route_package(name.as_str().into(), inherited);
+            }
+        }
+    }
+
+    fn named_repository(&self, package: &str) -> Option<&NamedHexRepository> {
+        self.package_repositories
+            .get(package)
+            .and_then(|name| self.named_repositories.get(name))
+    }
+
+    /// The public key used to verify the package information served for a
+    /// package by its repository.
+    pub fn public_key_for(&self, package: &str) -> &[u8] {
+        match self.named_repository(package) {
+            Some(repository) => &repository.public_key,
+            None => &self.public_key,
+        }
+    }
+
+    /// Whether an API key has been given, without exposing the key itself.
+    pub fn has_api_key(&self) -> bool {
+        self.api_key.is_some()
+    }
+
+    /// The private organizations that packages are requested from, sorted by
+    /// name.
+    pub fn organizations(&self) -> Vec<&EcoString> {
+        self.organizations.values().sorted().dedup().collect()
+    }
+
+    /// The Hex configuration and API key to use when requesting a package.
+    /// The API key is only sent for packages that belong to an organization,
+    /// preferring the one given for that repository in the credentials, and
+    /// never to the repositories declared in gleam.toml.
+    pub fn for_package(&self, package: &str) -> (hexpm::Config, Option<&str>) {
+        if let Some(repository) = self.named_repository(package) {
+            return (repository.config.clone(), None);
+        }
+        match self.organizations.get(package) {
+            Some(organization) => self.for_organization(organization),
+            None => (self.config.clone(), None),
+        }
+    }
+
+    /// The Hex configuration and API key to use for the packages of a private
+    /// organization.
+    pub fn for_organization(&self, organization: &str) -> (hexpm::Config, Option<&str>) {
+        let credentials = self.repositories.get(organization);
+        let uri = |configured: Option<&http::Uri>, base: &http::Uri| match configured {
+            Some(uri) => with_trailing_slash(uri),
+            None => organization_uri(base, organization),
+        };
+        let config = hexpm::Config {
+            api_base: uri(
+                credentials.and_then(|c| c.api.as_ref()),
+                &self.config.api_base,
+            ),
+            repository_base: uri(
+                credentials.and_then(|c| c.repository.as_ref()),
+                &self.config.repository_base,
+            ),
+        };
+        let api_key = credentials
+            .and_then(|c| c.api_key.as_deref())
+            .or(self.api_key.as_deref());
+        (config, api_key)
+    }
+
+    /// The repositories declared in gleam.toml, sorted by name.
+    pub fn named_repositories(&self) -> Vec<&EcoString> {
+        self.named_repositories.keys().sorted().collect()
+    }
+
+    /// The Hex configuration and API key to use for the packages of a
+    /// repository declared in gleam.toml. The API key is never sent to them.
+    pub fn for_named_repository(&self, name: &str) -> Option<(hexpm::Config, Option<&str>)> {
+        let repository = self.named_repositories.get(name)?;
+        Some((repository.config.clone(), None))
+    }
+}
+
+impl Default for HexRepository {
+    fn default() -> Self {
+        Self {
+            config: hexpm::Config::new(),
+            public_key: HEXPM_PUBLIC_KEY.to_vec(),
+            api_key: None,
+            organizations: HashMap::new(),
+            repositories: HashMap::new(),
+            named_repositories: HashMap::new(),
+            package_repositories: HashMap::new(),
+        }
+    }
+}
+
+// Organization names are validated when gleam.toml is read so they are always
+// valid in a URI path.
+fn organization_uri(base: &http::Uri, organization: &str) -> http::Uri {
+    format!("{base}repos/{organization}/")
+        .parse()
+        .expect("Organization repository URI")
+}
+
+// The hexpm request functions append paths directly to the base URI, so it
+// needs to end with a slash.
+fn with_trailing_slash(uri: &http::Uri) -> http::Uri {
+    if uri.path().ends_with('/') {
+        return uri.clone();
+    }
+    format!("{uri}/").parse().unwrap_or_else(|_| uri.clone())
+}
+
 fn key_name(hostname: &str) -> String {
     format!("gleam-{hostname}")
 }
@@ -122,14 +396,20 @@ pub async fn remove_api_key<Http: HttpClient>(
     hexpm::remove_api_key_response(response).map_err(Error::hex)
 }
 
+/// The number of packages that are downloaded at the same time by default.
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 8;
+
 #[derive(Debug)]
 pub struct Downloader {
     fs_reader: DebugIgnore<Box<dyn FileSystemReader>>,
     fs_writer: DebugIgnore<Box<dyn FileSystemWriter>>,
     http: DebugIgnore<Box<dyn HttpClient>>,
     untar: DebugIgnore<Box<dyn TarUnpacker>>,
-    hex_config: hexpm::Config,
+    hex_repository: HexRepository,
     paths: ProjectPaths,
+    concurrency_limit: usize,
+    telemetry: Arc<dyn Telemetry>,
+    local_tarballs: HashMap<EcoString, Utf8PathBuf>,
 }
 
 impl Downloader {
@@ -145,21 +425,58 @@ impl Downloader {
             fs_writer: DebugIgnore(fs_writer),
             http: DebugIgnore(http),
             untar: DebugIgnore(untar),
-            hex_config: hexpm::Config::new(),
+            hex_repository: HexRepository::default(),
             paths,
+            concurrency_limit: DEFAULT_DOWNLOAD_CONCURRENCY,
+            telemetry: Arc::new(NullTelemetry),
+            local_tarballs: HashMap::new(),
         }
     }
 
+    /// Report the progress of each package as it is downloaded to the given
+    /// telemetry.
+    pub fn with_telemetry(mut self, telemetry: Arc<dyn Telemetry>) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    /// Download packages from the given Hex repository instead of hex.pm.
+    pub fn with_hex_repository(mut self, repository: HexRepository) -> Self {
+        self.hex_repository = repository;
+        self
+    }
+
+    /// Set the maximum number of packages to download at the same time. A
+    /// limit of zero is treated as one.
+    pub fn with_concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit = limit.max(1);
+        self
+    }
+
+    /// Copy these packages into the package cache from tarball files on disc
+    /// rather than downloading them, for installs without network access.
+    /// Their checksums are verified just as for downloaded packages.
+    pub fn with_local_tarballs(mut self, tarballs: HashMap<EcoString, Utf8PathBuf>) -> Self {
+        self.local_tarballs = tarballs;
+        self
+    }
+
+    /// Whether the package is copied from a tarball on disc rather than
+    /// downloaded.
+    pub fn has_local_tarball(&self, package: &str) -> bool {
+        self.local_tarballs.contains_key(package)
+    }
+
     pub async fn ensure_package_downloaded(
         &self,
         package: &ManifestPackage,
     ) -> Result<bool, Error> {
-        let outer_checksum = if let ManifestPackageSource::Hex { outer_checksum } = &package.source
-        {
-            outer_checksum
-        } else {
-            panic!("Attempt to download non-hex package from hex")
-        };
+        let outer_checksum =
+            if let ManifestPackageSource::Hex { outer_checksum, .. } = &package.source {
+                outer_checksum
+            } else {
+                panic!("Attempt to download non-hex package from hex")
+            };
 
         let tarball_path = paths::global_package_cache_package_tarball(
             &package.name,
@@ -173,19 +490,63 @@ impl Downloader {
             );
             return Ok(false);
         }
+        // Only packages provided from outside of Hex are built without a
+        // checksum, so an empty one would let any tarball through.
+        if outer_checksum.0.is_empty() {
+            return Err(Error::MissingPackageChecksum {
+                package: package.name.to_string(),
+                version: package.version.to_string(),
+            });
+        }
+
+        if let Some(path) = self.local_tarballs.get(&package.name) {
+            tracing::info!(
+                package = package.name.as_str(),
+                path = path.as_str(),
+                "copying_local_tarball_to_cache"
+            );
+            let tarball = self.fs_reader.read_bytes(path)?;
+            verify_checksum(package, &tarball, outer_checksum)?;
+            self.fs_writer.write_bytes(&tarball_path, &tarball)?;
+            return Ok(true);
+        }
+
         tracing::info!(
             package = &package.name.as_str(),
             version = %package.version,
             "downloading_package_to_cache"
         );
 
-        let request = hexpm::get_package_tarball_request(
+        let (config, api_key) = self.hex_repository.for_package(&package.name);
+        let mut request = hexpm::get_package_tarball_request(
             &package.name,
             &package.version.to_string(),
-            None,
-            &self.hex_config,
+            api_key,
+            &config,
         );
-        let response = self.http.send(request).await?;
+
+        // The HTTP client may report progress from another thread, so it is
+        // passed back here to be reported while the download continues.
+        let (progress, received) = futures::channel::mpsc::unbounded();
+        let _ = request
+            .extensions_mut()
+            .insert(ResponseProgress::new(move |bytes, total| {
+                let _ = progress.unbounded_send((bytes, total));
+            }));
+        let report_progress = received.for_each(|(bytes, total)| {
+            let total = total.unwrap_or(bytes);
+            self.telemetry.package_progress(&package.name, bytes, total);
+            futures::future::ready(())
+        });
+        let (response, ()) = futures::join!(self.http.send(request), report_progress);
+        let response = response?;
+
+        // Verify the tarball is the one that was locked before doing anything
+        // with it, so a repository serving a different artifact is rejected
+        // with a clear explanation rather than a generic download failure.
+        if response.status() == http::StatusCode::OK {
+            verify_checksum(package, response.body(), outer_checksum)?;
+        }
 
         let tarball =
             hexpm::get_package_tarball_response(response, &outer_checksum.0).map_err(|error| {
@@ -199,31 +560,49 @@ impl Downloader {
         Ok(true)
     }
 
+    /// Returns the size of the package tarball if it was unpacked.
     pub async fn ensure_package_in_build_directory(
         &self,
         package: &ManifestPackage,
-    ) -> Result<bool> {
+    ) -> Result<Option<u64>> {
         let _ = self.ensure_package_downloaded(package).await?;
         self.extract_package_from_cache(&package.name, &package.version)
     }
 
+    /// Returns the size of the package tarball if it was unpacked, or `None`
+    /// if the package was already in the build directory.
+    //
     // It would be really nice if this was async but the library is sync
-    pub fn extract_package_from_cache(&self, name: &str, version: &Version) -> Result<bool> {
+    pub fn extract_package_from_cache(&self, name: &str, version: &Version) -> Result<Option<u64>> {
         let contents_path = Utf8Path::new("contents.tar.gz");
         let destination = self.paths.build_packages_package(name);
 
         // If the directory already exists then there's nothing for us to do
         if self.fs_reader.is_directory(&destination) {
             tracing::info!(package = name, "Package already in build directory");
-            return Ok(false);
+            return Ok(None);
         }
 
         tracing::info!(package = name, "writing_package_to_target");
         let tarball = paths::global_package_cache_package_tarball(name, &version.to_string());
-        let reader = self.fs_reader.reader(&tarball)?;
-        let mut archive = Archive::new(reader);
+        let mut archive = Archive::new(self.fs_reader.reader(&tarball)?);
+
+        // The package is unpacked into a staging directory and only moved into
+        // place once complete, so that an unpack that fails or is interrupted
+        // never leaves a partial package that would later be mistaken for a
+        // complete one. The staging directory is removed however this returns,
+        // but not if the process is killed, such as by Ctrl-C. Anything left
+        // that way is removed by the next run before packages are downloaded,
+        // and before this package is unpacked again.
+        let staging = self.paths.build_packages_staging_directory().join(name);
+        self.fs_writer.delete_directory(&staging)?;
+        let _staging = RemoveOnDrop {
+            fs: self.fs_writer.as_ref(),
+            path: &staging,
+        };
 
         // Find the source code from within the outer tarball
+        let mut unpacked = false;
         for entry in self.untar.entries(&mut archive)? {
             let file = entry.map_err(Error::expand_tar)?;
 
@@ -231,24 +610,23 @@ impl Downloader {
             if path.as_ref() == contents_path {
                 // Expand this inner source code and write to the file system
                 let archive = Archive::new(GzDecoder::new(file));
-                let result = self.untar.unpack(&destination, archive);
-
-                // If we failed to expand the tarball remove any source code
-                // that was partially written so that we don't mistakenly think
-                // the operation succeeded next time we run.
-                return match result {
-                    Ok(()) => Ok(true),
-                    Err(err) => {
-                        self.fs_writer.delete_directory(&destination)?;
-                        Err(err)
-                    }
-                };
+                self.untar.unpack(name, &staging, archive)?;
+                self.fs_writer.rename(&staging, &destination)?;
+                unpacked = true;
+                break;
             }
         }
+        if !unpacked {
+            return Err(Error::ExpandTar {
+                error: "Unable to locate Hex package contents.tar.gz".into(),
+            });
+        }
 
-        Err(Error::ExpandTar {
-            error: "Unable to locate Hex package contents.tar.gz".into(),
-        })
+        // The end of the outer tarball may not have been read, so the rest is
+        // read to find its size.
+        let mut reader = archive.into_inner();
+        let _ = std::io::copy(&mut reader, &mut std::io::sink()).map_err(Error::expand_tar)?;
+        Ok(Some(reader.bytes_read()))
     }
 
     pub async fn download_hex_packages<'a, Packages: Iterator<Item = &'a ManifestPackage>>(
@@ -256,21 +634,62 @@ impl Downloader {
         packages: Packages,
         project_name: &str,
     ) -> Result<()> {
+        let start = Instant::now();
         let futures = packages
             .filter(|package| project_name != package.name)
             .map(|package| self.ensure_package_in_build_directory(package));
 
-        // Run the futures to download the packages concurrently
-        let results = future::join_all(futures).await;
+        // Run the futures to download the packages concurrently, limiting how
+        // many are in flight at once so we don't overwhelm the Hex API
+        let results: Vec<_> = stream::iter(futures)
+            .buffer_unordered(self.concurrency_limit)
+            .collect()
+            .await;
 
-        // Count the number of packages downloaded while checking for errors
+        // Total the size of the packages downloaded while checking for errors
+        let count = results.len();
+        let mut bytes = 0;
         for result in results {
-            let _ = result?;
+            bytes += result?.unwrap_or(0);
         }
+        self.telemetry.packages_downloaded(start, count, bytes);
         Ok(())
     }
 }
 
+/// Deletes a directory when dropped, including when a download is cancelled or
+/// panics part way through. Destructors don't run when the process is killed,
+/// so the staging directories are also cleared at the start of each download.
+struct RemoveOnDrop<'a> {
+    fs: &'a dyn FileSystemWriter,
+    path: &'a Utf8Path,
+}
+
+impl Drop for RemoveOnDrop<'_> {
+    fn drop(&mut self) {
+        if let Err(error) = self.fs.delete_directory(self.path) {
+            tracing::warn!(path = %self.path, error = ?error, "failed_to_remove_staging_directory");
+        }
+    }
+}
+
+fn verify_checksum(
+    package: &ManifestPackage,
+    tarball: &[u8],
+    expected: &Base16Checksum,
+) -> Result<()> {
+    let actual = Base16Checksum(sha2::Sha256::digest(tarball).to_vec());
+    if &actual == expected {
+        return Ok(());
+    }
+    Err(Error::PackageChecksumMismatch {
+        package: package.name.to_string(),
+        version: package.version.to_string(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    })
+}
+
 pub async fn publish_documentation<Http: HttpClient>(
     name: &str,
     version: &Version,
@@ -290,6 +709,7 @@ pub async fn get_package_release<Http: HttpClient>(
     name: &str,
     version: &Version,
     config: &hexpm::Config,
+    api_key: Option<&str>,
     http: &Http,
 ) -> Result<hexpm::Release<hexpm::ReleaseMeta>> {
     let version = version.to_string();
@@ -298,7 +718,853 @@ pub async fn get_package_release<Http: HttpClient>(
         version = version.as_str(),
         "looking_up_package_release"
     );
-    let request = hexpm::get_package_release_request(name, &version, None, config);
+    let request = hexpm::get_package_release_request(name, &version, api_key, config);
     let response = http.send(request).await?;
     hexpm::get_package_release_response(response).map_err(Error::hex)
 }
+
+/// Look up the release of a package resolved from Hex and describe it as it
+/// is recorded in the manifest, along with its retirement status.
+pub async fn lookup_package<Http: HttpClient>(
+    name: String,
+    version: Version,
+    hex_repository: &HexRepository,
+    http: &Http,
+) -> Result<(ManifestPackage, Option<hexpm::RetirementStatus>)> {
+    let (config, api_key) = hex_repository.for_package(&name);
+    let release = get_package_release(&name, &version, &config, api_key, http).await?;
+    if release.outer_checksum.is_empty() {
+        return Err(Error::MissingPackageChecksum {
+            package: name,
+            version: version.to_string(),
+        });
+    }
+    let build_tools = release
+        .meta
+        .build_tools
+        .iter()
+        .map(|s| EcoString::from(s.as_str()))
+        .collect_vec();
+    let requirements = release
+        .requirements
+        .keys()
+        .map(|s| EcoString::from(s.as_str()))
+        .collect_vec();
+    let repository = hex_repository.source_repository(&name);
+    let package = ManifestPackage {
+        name: name.into(),
+        version,
+        otp_app: Some(release.meta.app.into()),
+        build_tools,
+        requirements,
+        source: ManifestPackageSource::Hex {
+            outer_checksum: Base16Checksum(release.outer_checksum),
+            repository,
+        },
+    };
+    Ok((package, release.retirement_status))
+}
+
+/// Get the licences of a release of a package from the Hex API.
+pub async fn get_package_licences<Http: HttpClient>(
+    name: &str,
+    version: &Version,
+    config: &hexpm::Config,
+    api_key: Option<&str>,
+    http: &Http,
+) -> Result<Vec<String>> {
+    #[derive(serde::Deserialize)]
+    struct Release {
+        meta: Meta,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Meta {
+        #[serde(default)]
+        licenses: Vec<String>,
+    }
+
+    let version = version.to_string();
+    tracing::info!(
+        name = name,
+        version = version.as_str(),
+        "looking_up_package_licences"
+    );
+    let request = hexpm::get_package_release_request(name, &version, api_key, config);
+    let response = http.send(request).await?;
+    let (parts, body) = response.into_parts();
+    if !parts.status.is_success() {
+        return Err(Error::Hex(format!(
+            "Unexpected response looking up {name} {version}: {}",
+            parts.status
+        )));
+    }
+    let release: Release = serde_json::from_slice(&body).map_err(Error::hex)?;
+    Ok(release.meta.licenses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build::{TelemetryEvent, TelemetryEventHandler},
+        error::UnpackFailureReason,
+        io::{memory::InMemoryFileSystem, WrappedReader},
+    };
+    use async_trait::async_trait;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(Debug)]
+    struct ResponseHttpClient {
+        body: Vec<u8>,
+    }
+
+    #[async_trait]
+    impl HttpClient for ResponseHttpClient {
+        async fn send(&self, _request: http::Request<Vec<u8>>) -> Result<http::Response<Vec<u8>>> {
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(self.body.clone())
+                .expect("response"))
+        }
+    }
+
+    #[derive(Debug)]
+    struct PanickingUntar;
+
+    impl TarUnpacker for PanickingUntar {
+        fn io_result_entries<'a>(
+            &self,
+            _archive: &'a mut Archive<WrappedReader>,
+        ) -> std::io::Result<tar::Entries<'a, WrappedReader>> {
+            panic!("tarball should not be unpacked")
+        }
+
+        fn io_result_unpack(
+            &self,
+            _path: &Utf8Path,
+            _archive: Archive<GzDecoder<tar::Entry<'_, WrappedReader>>>,
+        ) -> std::io::Result<()> {
+            panic!("tarball should not be unpacked")
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingHttpClient {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl HttpClient for Arc<CountingHttpClient> {
+        async fn send(&self, _request: http::Request<Vec<u8>>) -> Result<http::Response<Vec<u8>>> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            let _ = self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+            // Yield to the executor so other downloads can start
+            let mut yielded = false;
+            futures::future::poll_fn(|context| {
+                if yielded {
+                    std::task::Poll::Ready(())
+                } else {
+                    yielded = true;
+                    context.waker().wake_by_ref();
+                    std::task::Poll::Pending
+                }
+            })
+            .await;
+
+            let _ = self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(http::Response::builder()
+                .status(http::StatusCode::NOT_FOUND)
+                .body(vec![])
+                .expect("response"))
+        }
+    }
+
+    #[test]
+    fn download_concurrency_is_limited() {
+        let fs = InMemoryFileSystem::new();
+        let http = Arc::new(CountingHttpClient::default());
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs),
+            Box::new(http.clone()),
+            Box::new(PanickingUntar),
+            ProjectPaths::new("/app".into()),
+        )
+        .with_concurrency_limit(2);
+        let packages: Vec<_> = (0..5)
+            .map(|i| ManifestPackage {
+                name: format!("gleam_concurrency_test_{i}").into(),
+                version: Version::new(1, 0, 0),
+                build_tools: vec!["gleam".into()],
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1]),
+                    repository: None,
+                },
+            })
+            .collect();
+
+        let result =
+            futures::executor::block_on(downloader.download_hex_packages(packages.iter(), "root"));
+
+        assert!(result.is_err());
+        assert_eq!(http.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn download_with_incorrect_checksum() {
+        let fs = InMemoryFileSystem::new();
+        let paths = ProjectPaths::new("/app".into());
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ResponseHttpClient {
+                body: b"not the locked tarball".to_vec(),
+            }),
+            Box::new(PanickingUntar),
+            paths.clone(),
+        );
+        let package = ManifestPackage {
+            name: "gleam_checksum_test".into(),
+            version: Version::new(1, 0, 0),
+            build_tools: vec!["gleam".into()],
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(
+                    sha2::Sha256::digest(b"the locked tarball").to_vec(),
+                ),
+                repository: None,
+            },
+        };
+
+        let result =
+            futures::executor::block_on(downloader.ensure_package_in_build_directory(&package));
+
+        assert!(matches!(
+            result,
+            Err(Error::PackageChecksumMismatch { ref package, .. }) if package == "gleam_checksum_test"
+        ));
+        assert!(fs.paths().is_empty());
+        assert!(!fs.is_directory(&paths.build_packages_package("gleam_checksum_test")));
+    }
+
+    #[test]
+    fn download_with_empty_checksum() {
+        let fs = InMemoryFileSystem::new();
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ResponseHttpClient {
+                body: b"any tarball".to_vec(),
+            }),
+            Box::new(PanickingUntar),
+            ProjectPaths::new("/app".into()),
+        );
+        let package = ManifestPackage {
+            name: "gleam_empty_checksum_test".into(),
+            version: Version::new(1, 0, 0),
+            build_tools: vec!["gleam".into()],
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(vec![]),
+                repository: None,
+            },
+        };
+
+        let result =
+            futures::executor::block_on(downloader.ensure_package_in_build_directory(&package));
+
+        assert_eq!(
+            result,
+            Err(Error::MissingPackageChecksum {
+                package: "gleam_empty_checksum_test".into(),
+                version: "1.0.0".into(),
+            })
+        );
+        assert!(fs.paths().is_empty());
+    }
+
+    #[derive(Debug)]
+    struct ReadingUntar;
+
+    impl TarUnpacker for ReadingUntar {
+        fn io_result_entries<'a>(
+            &self,
+            archive: &'a mut Archive<WrappedReader>,
+        ) -> std::io::Result<tar::Entries<'a, WrappedReader>> {
+            archive.entries()
+        }
+
+        fn io_result_unpack(
+            &self,
+            _path: &Utf8Path,
+            mut archive: Archive<GzDecoder<tar::Entry<'_, WrappedReader>>>,
+        ) -> std::io::Result<()> {
+            for entry in archive.entries()? {
+                let _ = std::io::copy(&mut entry?, &mut std::io::sink())?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct ProgressTelemetry {
+        progress: std::sync::Mutex<Vec<(String, u64, u64)>>,
+        downloaded: std::sync::Mutex<Option<(usize, u64)>>,
+    }
+
+    impl TelemetryEventHandler for ProgressTelemetry {
+        fn handle(&self, event: TelemetryEvent) {
+            match event {
+                TelemetryEvent::PackageProgress { name, bytes, total } => self
+                    .progress
+                    .lock()
+                    .expect("progress lock")
+                    .push((name.into(), bytes, total)),
+                TelemetryEvent::PackagesDownloaded { count, bytes, .. } => {
+                    *self.downloaded.lock().expect("downloaded lock") = Some((count, bytes))
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn package_tarball(source: &[u8]) -> Vec<u8> {
+        let mut contents = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(source.len() as u64);
+        contents
+            .append_data(&mut header, "src/wibble.gleam", source)
+            .expect("append source");
+        let contents = contents
+            .into_inner()
+            .expect("contents")
+            .finish()
+            .expect("gzip");
+
+        let mut tarball = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        tarball
+            .append_data(&mut header, "contents.tar.gz", contents.as_slice())
+            .expect("append contents");
+        tarball.into_inner().expect("tarball")
+    }
+
+    /// Responds with the body, reporting its progress in parts as a client
+    /// receiving it over the network would.
+    struct ProgressHttpClient {
+        body: Vec<u8>,
+    }
+
+    #[async_trait]
+    impl HttpClient for ProgressHttpClient {
+        async fn send(&self, request: http::Request<Vec<u8>>) -> Result<http::Response<Vec<u8>>> {
+            if let Some(progress) = request.extensions().get::<ResponseProgress>() {
+                let total = self.body.len() as u64;
+                let mut received = 0;
+                for part in self.body.chunks(512) {
+                    received += part.len() as u64;
+                    progress.report(received, Some(total));
+                }
+            }
+            Ok(http::Response::builder()
+                .status(http::StatusCode::OK)
+                .body(self.body.clone())
+                .expect("response"))
+        }
+    }
+
+    fn hex_package(name: &str, tarball: &[u8]) -> ManifestPackage {
+        ManifestPackage {
+            name: name.into(),
+            version: Version::new(1, 0, 0),
+            build_tools: vec!["gleam".into()],
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(sha2::Sha256::digest(tarball).to_vec()),
+                repository: None,
+            },
+        }
+    }
+
+    #[test]
+    fn downloading_package_reports_progress() {
+        let fs = InMemoryFileSystem::new();
+        let telemetry = Arc::new(ProgressTelemetry::default());
+        let tarball = package_tarball(&[b'a'; 20_000]);
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ProgressHttpClient {
+                body: tarball.clone(),
+            }),
+            Box::new(ReadingUntar),
+            ProjectPaths::new("/app".into()),
+        )
+        .with_telemetry(telemetry.clone());
+
+        assert_eq!(
+            futures::executor::block_on(
+                downloader.ensure_package_in_build_directory(&hex_package("wibble", &tarball))
+            ),
+            Ok(Some(tarball.len() as u64))
+        );
+
+        let total = tarball.len() as u64;
+        let progress = telemetry.progress.lock().expect("progress lock");
+        assert!(progress.len() > 1);
+        assert!(progress
+            .iter()
+            .all(|(name, _, package_total)| name == "wibble" && *package_total == total));
+        assert!(progress.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert_eq!(progress.last(), Some(&("wibble".into(), total, total)));
+    }
+
+    #[derive(Debug)]
+    struct ReadOnlyUntar;
+
+    impl TarUnpacker for ReadOnlyUntar {
+        fn io_result_entries<'a>(
+            &self,
+            archive: &'a mut Archive<WrappedReader>,
+        ) -> std::io::Result<tar::Entries<'a, WrappedReader>> {
+            archive.entries()
+        }
+
+        fn io_result_unpack(
+            &self,
+            _path: &Utf8Path,
+            _archive: Archive<GzDecoder<tar::Entry<'_, WrappedReader>>>,
+        ) -> std::io::Result<()> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Read-only file system",
+            ))
+        }
+    }
+
+    #[test]
+    fn extracting_package_failure_names_the_package() {
+        let fs = InMemoryFileSystem::new();
+        let paths = ProjectPaths::new("/app".into());
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ResponseHttpClient { body: vec![] }),
+            Box::new(ReadOnlyUntar),
+            paths.clone(),
+        );
+        fs.write_bytes(
+            &paths::global_package_cache_package_tarball("wibble", "1.0.0"),
+            &package_tarball(b"pub fn main() { Nil }"),
+        )
+        .expect("write tarball");
+
+        assert_eq!(
+            downloader.extract_package_from_cache("wibble", &Version::new(1, 0, 0)),
+            Err(Error::UnpackPackage {
+                package: "wibble".into(),
+                path: paths.build_packages_staging_directory().join("wibble"),
+                reason: UnpackFailureReason::PermissionDenied,
+                error: "Read-only file system".into(),
+            })
+        );
+    }
+
+    #[derive(Debug)]
+    struct PanickingHttpClient;
+
+    #[async_trait]
+    impl HttpClient for PanickingHttpClient {
+        async fn send(&self, _request: http::Request<Vec<u8>>) -> Result<http::Response<Vec<u8>>> {
+            panic!("no requests should be made")
+        }
+    }
+
+    #[derive(Debug)]
+    struct WritingUntar {
+        fs: InMemoryFileSystem,
+    }
+
+    impl TarUnpacker for WritingUntar {
+        fn io_result_entries<'a>(
+            &self,
+            archive: &'a mut Archive<WrappedReader>,
+        ) -> std::io::Result<tar::Entries<'a, WrappedReader>> {
+            archive.entries()
+        }
+
+        fn io_result_unpack(
+            &self,
+            path: &Utf8Path,
+            mut archive: Archive<GzDecoder<tar::Entry<'_, WrappedReader>>>,
+        ) -> std::io::Result<()> {
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().to_string();
+                let mut contents = vec![];
+                let _ = std::io::Read::read_to_end(&mut entry, &mut contents)?;
+                self.fs
+                    .write_bytes(&path.join(name), &contents)
+                    .expect("write unpacked file");
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn local_tarball_is_unpacked_without_downloading() {
+        let fs = InMemoryFileSystem::new();
+        let paths = ProjectPaths::new("/app".into());
+        let tarball = package_tarball(b"pub fn main() { Nil }");
+        let local_tarball = Utf8PathBuf::from("/offline/wibble-1.0.0.tar");
+        fs.write_bytes(&local_tarball, &tarball)
+            .expect("write tarball");
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(PanickingHttpClient),
+            Box::new(WritingUntar { fs: fs.clone() }),
+            paths.clone(),
+        )
+        .with_local_tarballs([("gleam_local_tarball_test".into(), local_tarball)].into());
+        let package = |checksum: Vec<u8>| ManifestPackage {
+            name: "gleam_local_tarball_test".into(),
+            version: Version::new(1, 0, 0),
+            build_tools: vec!["gleam".into()],
+            otp_app: None,
+            requirements: vec![],
+            source: ManifestPackageSource::Hex {
+                outer_checksum: Base16Checksum(checksum),
+                repository: None,
+            },
+        };
+
+        // A tarball that isn't the locked one is rejected
+        let result = futures::executor::block_on(downloader.ensure_package_in_build_directory(
+            &package(sha2::Sha256::digest(b"the locked tarball").to_vec()),
+        ));
+        assert!(matches!(result, Err(Error::PackageChecksumMismatch { .. })));
+        assert!(!fs.is_directory(&paths.build_packages_package("gleam_local_tarball_test")));
+
+        let result =
+            futures::executor::block_on(downloader.ensure_package_in_build_directory(&package(
+                sha2::Sha256::digest(&tarball).to_vec(),
+            )));
+        assert_eq!(result, Ok(Some(tarball.len() as u64)));
+        assert_eq!(
+            fs.read(
+                &paths
+                    .build_packages_package("gleam_local_tarball_test")
+                    .join("src/wibble.gleam")
+            ),
+            Ok("pub fn main() { Nil }".into())
+        );
+    }
+
+    #[derive(Debug)]
+    struct InterruptedUntar {
+        fs: InMemoryFileSystem,
+    }
+
+    impl TarUnpacker for InterruptedUntar {
+        fn io_result_entries<'a>(
+            &self,
+            archive: &'a mut Archive<WrappedReader>,
+        ) -> std::io::Result<tar::Entries<'a, WrappedReader>> {
+            archive.entries()
+        }
+
+        fn io_result_unpack(
+            &self,
+            path: &Utf8Path,
+            _archive: Archive<GzDecoder<tar::Entry<'_, WrappedReader>>>,
+        ) -> std::io::Result<()> {
+            self.fs
+                .write(&path.join("src/wibble.gleam"), "pub fn")
+                .expect("write partial module");
+            panic!("Interrupted while unpacking")
+        }
+    }
+
+    #[test]
+    fn interrupted_extraction_leaves_no_partial_package() {
+        let fs = InMemoryFileSystem::new();
+        let paths = ProjectPaths::new("/app".into());
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ResponseHttpClient { body: vec![] }),
+            Box::new(InterruptedUntar { fs: fs.clone() }),
+            paths.clone(),
+        );
+        fs.write_bytes(
+            &paths::global_package_cache_package_tarball("wibble", "1.0.0"),
+            &package_tarball(b"pub fn main() { Nil }"),
+        )
+        .expect("write tarball");
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            downloader.extract_package_from_cache("wibble", &Version::new(1, 0, 0))
+        }));
+
+        assert!(result.is_err());
+        assert!(!fs.is_directory(&paths.build_packages_package("wibble")));
+        assert!(!fs.is_directory(&paths.build_packages_staging_directory()));
+    }
+
+    #[test]
+    fn downloading_packages_reports_total_size() {
+        let fs = InMemoryFileSystem::new();
+        let telemetry = Arc::new(ProgressTelemetry::default());
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ResponseHttpClient { body: vec![] }),
+            Box::new(ReadingUntar),
+            ProjectPaths::new("/app".into()),
+        )
+        .with_telemetry(telemetry.clone());
+        let tarballs = [
+            ("wibble", package_tarball(&[b'a'; 20_000])),
+            ("wobble", package_tarball(b"pub fn main() { Nil }")),
+        ];
+        for (name, tarball) in &tarballs {
+            fs.write_bytes(
+                &paths::global_package_cache_package_tarball(name, "1.0.0"),
+                tarball,
+            )
+            .expect("write tarball");
+        }
+        let packages: Vec<_> = tarballs
+            .iter()
+            .map(|(name, _)| ManifestPackage {
+                name: (*name).into(),
+                version: Version::new(1, 0, 0),
+                build_tools: vec!["gleam".into()],
+                otp_app: None,
+                requirements: vec![],
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![]),
+                    repository: None,
+                },
+            })
+            .collect();
+
+        futures::executor::block_on(downloader.download_hex_packages(packages.iter(), "root"))
+            .expect("download packages");
+
+        let total = tarballs
+            .iter()
+            .map(|(_, tarball)| tarball.len() as u64)
+            .sum();
+        assert_eq!(
+            *telemetry.downloaded.lock().expect("downloaded lock"),
+            Some((2, total))
+        );
+    }
+
+    #[test]
+    fn downloading_packages_emits_events() {
+        use crate::build::{ChannelTelemetry, TelemetryEvent};
+
+        let fs = InMemoryFileSystem::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let tarball = package_tarball(&[b'a'; 20_000]);
+        let downloader = Downloader::new(
+            Box::new(fs.clone()),
+            Box::new(fs.clone()),
+            Box::new(ProgressHttpClient {
+                body: tarball.clone(),
+            }),
+            Box::new(ReadingUntar),
+            ProjectPaths::new("/app".into()),
+        )
+        .with_telemetry(Arc::new(ChannelTelemetry::new(sender)));
+        let package = hex_package("wibble", &tarball);
+
+        futures::executor::block_on(
+            downloader.download_hex_packages([&package].into_iter(), "root"),
+        )
+        .expect("download packages");
+        drop(downloader);
+
+        let total = tarball.len() as u64;
+        let events: Vec<_> = receiver.iter().collect();
+        assert!(events
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        let (last, progress) = events.split_last().expect("events");
+        assert!(matches!(
+            last.event,
+            TelemetryEvent::PackagesDownloaded { count: 1, bytes, .. } if bytes == total
+        ));
+        assert!(progress.len() > 1);
+        assert!(progress.iter().all(|event| matches!(
+            &event.event,
+            TelemetryEvent::PackageProgress { name, total: package_total, .. }
+                if name == "wibble" && *package_total == total
+        )));
+        assert_eq!(
+            progress.last().map(|event| &event.event),
+            Some(&TelemetryEvent::PackageProgress {
+                name: "wibble".into(),
+                bytes: total,
+                total,
+            })
+        );
+    }
+
+    #[test]
+    fn repository_from_config() {
+        let config = HexConfig {
+            repository: Some(http::Uri::from_static("https://mirror.example.com/repo")),
+            api: None,
+            public_key: Some("key".into()),
+            snapshot: None,
+            user_agent: None,
+        };
+        let repository = HexRepository::from_config(&config);
+        assert_eq!(
+            repository.config.repository_base,
+            http::Uri::from_static("https://mirror.example.com/repo/")
+        );
+        assert_eq!(
+            repository.config.api_base,
+            http::Uri::from_static("https://hex.pm/api/")
+        );
+        assert_eq!(repository.public_key, b"key".to_vec());
+    }
+
+    #[test]
+    fn named_repository_packages_use_named_repository() {
+        let repository = HexRepository::default()
+            .with_api_key("secret".into())
+            .with_named_repository(
+                "internal".into(),
+                &NamedRepository {
+                    url: http::Uri::from_static("https://hex.internal.example.com"),
+                    api: None,
+                    public_key: Some("internal-key".into()),
+                },
+            )
+            .with_package_repository("private".into(), "internal".into());
+
+        let (config, api_key) = repository.for_package("private");
+        assert_eq!(
+            config.repository_base,
+            http::Uri::from_static("https://hex.internal.example.com/")
+        );
+        assert_eq!(
+            config.api_base,
+            http::Uri::from_static("https://hex.internal.example.com/")
+        );
+        assert_eq!(api_key, None);
+        assert_eq!(repository.public_key_for("private"), b"internal-key");
+        assert_eq!(repository.public_key_for("public"), HEXPM_PUBLIC_KEY);
+        assert_eq!(
+            repository.for_package("public").0.repository_base,
+            repository.config.repository_base
+        );
+    }
+
+    #[test]
+    fn dependencies_are_routed_to_the_repository_of_their_dependent() {
+        let requirement = |repository: Option<&str>| hexpm::Dependency {
+            requirement: hexpm::version::Range::new("~> 1.0".into()),
+            optional: false,
+            app: None,
+            repository: repository.map(String::from),
+        };
+        let mut repository = HexRepository::default()
+            .with_named_repository(
+                "internal".into(),
+                &NamedRepository {
+                    url: http::Uri::from_static("https://hex.internal.example.com"),
+                    api: None,
+                    public_key: None,
+                },
+            )
+            .with_package_repository("private".into(), "internal".into())
+            .with_organization("secret".into(), "acme".into());
+
+        repository.route_dependencies(
+            "private",
+            &[
+                ("private_helper".into(), requirement(None)),
+                ("public".into(), requirement(Some("hexpm"))),
+                ("shared".into(), requirement(Some("hexpm:acme"))),
+                ("secret".into(), requirement(Some("internal"))),
+            ]
+            .into(),
+        );
+        repository.route_dependencies(
+            "shared",
+            &[("shared_helper".into(), requirement(Some("unknown")))].into(),
+        );
+
+        assert_eq!(
+            repository.source_repository("private_helper"),
+            Some("internal".into())
+        );
+        assert_eq!(repository.source_repository("public"), None);
+        assert_eq!(
+            repository.source_repository("shared"),
+            Some("hexpm:acme".into())
+        );
+        assert_eq!(
+            repository.source_repository("shared_helper"),
+            Some("hexpm:acme".into())
+        );
+        // Direct dependencies keep the repository gleam.toml gives them
+        assert_eq!(
+            repository.source_repository("secret"),
+            Some("hexpm:acme".into())
+        );
+        assert_eq!(
+            repository.for_package("private_helper").0.repository_base,
+            http::Uri::from_static("https://hex.internal.example.com/")
+        );
+    }
+
+    #[test]
+    fn organization_packages_use_organization_repository() {
+        let repository = HexRepository::default()
+            .with_api_key("secret".into())
+            .with_organization("private".into(), "acme".into());
+
+        let (config, api_key) = repository.for_package("private");
+        assert_eq!(
+            config.repository_base,
+            http::Uri::from_static("https://repo.hex.pm/repos/acme/")
+        );
+        assert_eq!(
+            config.api_base,
+            http::Uri::from_static("https://hex.pm/api/repos/acme/")
+        );
+        assert_eq!(api_key, Some("secret"));
+
+        let (config, api_key) = repository.for_package("public");
+        assert_eq!(
+            config.repository_base,
+            http::Uri::from_static("https://repo.hex.pm/")
+        );
+        assert_eq!(api_key, None);
+
+        assert!(!format!("{repository:?}").contains("secret"));
+    }
+}
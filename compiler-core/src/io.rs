@@ -1,10 +1,10 @@
 pub mod memory;
 
-use crate::error::{Error, FileIoAction, FileKind, Result};
+use crate::error::{Error, FileIoAction, FileKind, Result, UnpackFailureReason};
 use async_trait::async_trait;
 use debug_ignore::DebugIgnore;
 use flate2::read::GzDecoder;
-use std::{fmt::Debug, io, time::SystemTime, vec::IntoIter};
+use std::{fmt::Debug, io, sync::Arc, time::SystemTime, vec::IntoIter};
 use tar::{Archive, Entry};
 
 use camino::{Utf8Path, Utf8PathBuf};
@@ -227,6 +227,8 @@ pub trait FileSystemWriter {
     fn hardlink(&self, from: &Utf8Path, to: &Utf8Path) -> Result<(), Error>;
     fn symlink_dir(&self, from: &Utf8Path, to: &Utf8Path) -> Result<(), Error>;
     fn delete_file(&self, path: &Utf8Path) -> Result<(), Error>;
+    /// Move a file or directory to a path that doesn't exist yet.
+    fn rename(&self, from: &Utf8Path, to: &Utf8Path) -> Result<(), Error>;
 }
 
 #[derive(Debug)]
@@ -234,6 +236,7 @@ pub trait FileSystemWriter {
 pub struct WrappedReader {
     path: Utf8PathBuf,
     inner: DebugIgnore<Box<dyn std::io::Read>>,
+    bytes_read: u64,
 }
 
 impl WrappedReader {
@@ -241,11 +244,19 @@ impl WrappedReader {
         Self {
             path: path.to_path_buf(),
             inner: DebugIgnore(inner),
+            bytes_read: 0,
         }
     }
 
+    /// The number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
     fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
-        self.inner.read(buffer)
+        let count = self.inner.read(buffer)?;
+        self.bytes_read += count as u64;
+        Ok(count)
     }
 }
 
@@ -266,6 +277,29 @@ impl Reader for WrappedReader {
     }
 }
 
+/// Attached to the extensions of a request to be told how many bytes of the
+/// response body have been received so far, and how many there are in total
+/// if the response says. Clients that receive the body in parts report each
+/// part as it arrives.
+#[derive(Clone)]
+pub struct ResponseProgress(Arc<dyn Fn(u64, Option<u64>) + Send + Sync>);
+
+impl ResponseProgress {
+    pub fn new(on_progress: impl Fn(u64, Option<u64>) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(on_progress))
+    }
+
+    pub fn report(&self, received: u64, total: Option<u64>) {
+        (self.0)(received, total)
+    }
+}
+
+impl Debug for ResponseProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseProgress").finish_non_exhaustive()
+    }
+}
+
 #[async_trait]
 pub trait HttpClient {
     async fn send(&self, request: http::Request<Vec<u8>>)
@@ -299,16 +333,17 @@ pub trait TarUnpacker {
 
     fn unpack(
         &self,
+        package: &str,
         path: &Utf8Path,
         archive: Archive<GzDecoder<Entry<'_, WrappedReader>>>,
     ) -> Result<()> {
         tracing::debug!(path = ?path, "unpacking tar archive");
         self.io_result_unpack(path, archive)
-            .map_err(|e| Error::FileIo {
-                action: FileIoAction::WriteTo,
-                kind: FileKind::Directory,
+            .map_err(|e| Error::UnpackPackage {
+                package: package.into(),
                 path: path.to_path_buf(),
-                err: Some(e.to_string()),
+                reason: UnpackFailureReason::from_io_error(&e),
+                error: e.to_string(),
             })
     }
 }
@@ -84,7 +84,22 @@ impl InMemoryFileSystem {
 impl FileSystemWriter for InMemoryFileSystem {
     fn delete_directory(&self, path: &Utf8Path) -> Result<(), Error> {
         let mut files = self.files.deref().borrow_mut();
-        let _ = files.remove(path);
+        files.retain(|file_path, _| !file_path.starts_with(path));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Utf8Path, to: &Utf8Path) -> Result<(), Error> {
+        let mut files = self.files.deref().borrow_mut();
+        let moved = files
+            .keys()
+            .filter(|file_path| file_path.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for file_path in moved {
+            let file = files.remove(&file_path).expect("moved file");
+            let relative = file_path.strip_prefix(from).expect("moved file prefix");
+            let _ = files.insert(to.join(relative), file);
+        }
         Ok(())
     }
 
@@ -200,9 +215,12 @@ impl FileSystemReader for InMemoryFileSystem {
             .any(|file_path| file_path.starts_with(path))
     }
 
-    fn reader(&self, _path: &Utf8Path) -> Result<WrappedReader, Error> {
-        // TODO
-        unreachable!("Memory reader unimplemented")
+    fn reader(&self, path: &Utf8Path) -> Result<WrappedReader, Error> {
+        let bytes = self.read_bytes(path)?;
+        Ok(WrappedReader::new(
+            path,
+            Box::new(std::io::Cursor::new(bytes)),
+        ))
     }
 
     fn read_dir(&self, path: &Utf8Path) -> Result<ReadDir> {
@@ -93,6 +93,10 @@ where
     fn delete_file(&self, path: &Utf8Path) -> Result<()> {
         self.io.delete_file(path)
     }
+
+    fn rename(&self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        self.io.rename(from, to)
+    }
 }
 
 impl<IO> FileSystemReader for FileSystemProxy<IO>
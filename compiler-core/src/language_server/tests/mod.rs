@@ -99,6 +99,7 @@ impl LanguageServerTestIO {
             name: name.into(),
             source: ManifestPackageSource::Hex {
                 outer_checksum: Base16Checksum(vec![]),
+                repository: None,
             },
             build_tools: vec!["gleam".into()],
             ..Default::default()
@@ -186,6 +187,10 @@ impl FileSystemWriter for LanguageServerTestIO {
         self.io.delete_file(path)
     }
 
+    fn rename(&self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        self.io.rename(from, to)
+    }
+
     fn write(&self, path: &Utf8Path, content: &str) -> Result<(), crate::Error> {
         self.io.write(path, content)
     }
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
+use crate::error::{FileIoAction, FileKind};
 use crate::io::make_relative;
 use crate::requirement::Requirement;
-use crate::Result;
+use crate::{Error, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 use ecow::EcoString;
 use hexpm::version::Version;
@@ -16,7 +17,72 @@ pub struct Manifest {
     pub packages: Vec<ManifestPackage>,
 }
 
+/// The version of the manifest format written by this version of Gleam.
+/// Manifests written before the format was versioned have no `version` field
+/// and are treated as version 0.
+pub const MANIFEST_VERSION: u32 = 2;
+
+const CHECKSUM_PREFIX: &str = "\nchecksum = \"";
+
+/// The checksum of the packages and requirements of a manifest. Line endings
+/// are normalised so that a checkout that converts them doesn't count as an
+/// edit.
+fn body_checksum(body: &str) -> String {
+    use sha2::Digest;
+    let body = body.replace("\r\n", "\n");
+    base16::encode_lower(&sha2::Sha256::digest(body.as_bytes()))
+}
+
 impl Manifest {
+    /// Parse a manifest, upgrading it in memory if it was written in an older
+    /// format. Manifests in a newer format than this version of Gleam
+    /// understands are rejected.
+    pub fn parse(toml: &str, path: &Utf8Path) -> Result<Self> {
+        let parse_error = |error: toml::de::Error| Error::FileIo {
+            action: FileIoAction::Parse,
+            kind: FileKind::File,
+            path: path.to_path_buf(),
+            err: Some(error.to_string()),
+        };
+
+        #[derive(serde::Deserialize)]
+        struct Versioned {
+            #[serde(default)]
+            version: u32,
+        }
+
+        let Versioned { version } = toml::from_str(toml).map_err(parse_error)?;
+        if version > MANIFEST_VERSION {
+            return Err(Error::ManifestVersionTooNew {
+                path: path.to_path_buf(),
+                version,
+                supported: MANIFEST_VERSION,
+            });
+        }
+
+        // Version 1 only added the `version` field, and version 2 the optional
+        // `repository` of Hex packages, so older manifests need no other
+        // changes. Migrations for future format changes go here, applied in
+        // order from the manifest's version.
+
+        toml::from_str(toml).map_err(parse_error)
+    }
+
+    /// Whether the manifest has been edited since Gleam wrote it, found by
+    /// comparing the checksum written in it with one of the rest of its
+    /// contents. Manifests written without a checksum are never reported as
+    /// edited.
+    pub fn is_edited(toml: &str) -> bool {
+        let Some((checksum_line, body)) = toml
+            .split_once(CHECKSUM_PREFIX)
+            .and_then(|(_, rest)| rest.split_once('\n'))
+        else {
+            return false;
+        };
+        let recorded = checksum_line.trim().trim_end_matches('"');
+        recorded != body_checksum(body.trim_start_matches(['\r', '\n']))
+    }
+
     // Rather than using the toml library to do serialization we implement it
     // manually so that we can control the formatting.
     // We want to keep entries on a single line each so that they are more
@@ -28,13 +94,6 @@ impl Manifest {
             packages,
         } = self;
 
-        buffer.push_str(
-            "# This file was generated by Gleam
-# You typically do not need to edit this file
-
-",
-        );
-
         // Packages
         buffer.push_str("packages = [\n");
         for ManifestPackage {
@@ -79,17 +138,34 @@ impl Manifest {
             }
 
             match source {
-                ManifestPackageSource::Hex { outer_checksum } => {
+                ManifestPackageSource::Hex {
+                    outer_checksum,
+                    repository,
+                } => {
                     buffer.push_str(r#", source = "hex", outer_checksum = ""#);
                     buffer.push_str(&outer_checksum.to_string());
                     buffer.push('"');
+                    if let Some(repository) = repository {
+                        buffer.push_str(r#", repository = ""#);
+                        buffer.push_str(repository);
+                        buffer.push('"');
+                    }
                 }
-                ManifestPackageSource::Git { repo, commit } => {
+                ManifestPackageSource::Git {
+                    repo,
+                    commit,
+                    subdir,
+                } => {
                     buffer.push_str(r#", source = "git", repo = ""#);
                     buffer.push_str(repo);
                     buffer.push_str(r#"", commit = ""#);
                     buffer.push_str(commit);
                     buffer.push('"');
+                    if let Some(subdir) = subdir {
+                        buffer.push_str(r#", subdir = ""#);
+                        buffer.push_str(subdir.as_str());
+                        buffer.push('"');
+                    }
                 }
                 ManifestPackageSource::Local { path } => {
                     buffer.push_str(r#", source = "local", path = ""#);
@@ -111,10 +187,116 @@ impl Manifest {
             buffer.push('\n');
         }
 
-        buffer
+        // The header, format version, and a checksum of the rest of the
+        // manifest so that edits to it can be noticed.
+        format!(
+            "# This file was generated by Gleam
+# You typically do not need to edit this file
+
+version = {MANIFEST_VERSION}{CHECKSUM_PREFIX}{}\"
+
+{buffer}",
+            body_checksum(&buffer)
+        )
+    }
+
+    /// The packages that have been added, removed, or have changed version in
+    /// the `new` manifest compared to this one, sorted by name.
+    pub fn changes_to(&self, new: &Manifest) -> Vec<PackageChange> {
+        let old_versions: HashMap<_, _> = self
+            .packages
+            .iter()
+            .map(|p| (&p.name, &p.version))
+            .collect();
+        let new_versions: HashMap<_, _> =
+            new.packages.iter().map(|p| (&p.name, &p.version)).collect();
+
+        let added_or_changed =
+            new_versions
+                .iter()
+                .filter_map(|(name, new)| match old_versions.get(name) {
+                    None => Some(PackageChange::Added {
+                        name: (*name).clone(),
+                        version: (*new).clone(),
+                    }),
+                    Some(old) if old != new => Some(PackageChange::Changed {
+                        name: (*name).clone(),
+                        old: (*old).clone(),
+                        new: (*new).clone(),
+                    }),
+                    Some(_) => None,
+                });
+        let removed = old_versions
+            .iter()
+            .filter(|(name, _)| !new_versions.contains_key(*name))
+            .map(|(name, version)| PackageChange::Removed {
+                name: (*name).clone(),
+                version: (*version).clone(),
+            });
+
+        added_or_changed
+            .chain(removed)
+            .sorted_by(|a, b| a.name().cmp(b.name()))
+            .collect()
+    }
+
+    /// The packages that have a lower version in the `new` manifest than in
+    /// this one, along with their old and new versions, sorted by name.
+    pub fn downgrades_to(&self, new: &Manifest) -> Vec<(EcoString, Version, Version)> {
+        self.changes_to(new)
+            .into_iter()
+            .filter_map(|change| match change {
+                PackageChange::Changed { name, old, new } if new < old => Some((name, old, new)),
+                PackageChange::Added { .. }
+                | PackageChange::Removed { .. }
+                | PackageChange::Changed { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// A difference in the packages of two manifests.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum PackageChange {
+    Added {
+        name: EcoString,
+        version: Version,
+    },
+    Removed {
+        name: EcoString,
+        version: Version,
+    },
+    Changed {
+        name: EcoString,
+        old: Version,
+        new: Version,
+    },
+}
+
+impl PackageChange {
+    pub fn name(&self) -> &EcoString {
+        match self {
+            PackageChange::Added { name, .. }
+            | PackageChange::Removed { name, .. }
+            | PackageChange::Changed { name, .. } => name,
+        }
     }
 }
 
+/// Why a package of the manifest is to be put into the build directory.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MissingPackageReason {
+    /// The package is not in the build directory.
+    NotPresent,
+    /// A different version of the package is in the build directory.
+    VersionChanged { old: Version, new: Version },
+    /// The version is the same but its source is not on disc, such as when a
+    /// git package is locked to a different commit.
+    SourceMissing,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Base16Checksum(pub Vec<u8>);
 
@@ -176,6 +358,11 @@ impl ManifestPackage {
         matches!(self.source, ManifestPackageSource::Hex { .. })
     }
 
+    #[inline]
+    pub fn is_git(&self) -> bool {
+        matches!(self.source, ManifestPackageSource::Git { .. })
+    }
+
     #[inline]
     pub fn is_local(&self) -> bool {
         matches!(self.source, ManifestPackageSource::Local { .. })
@@ -186,9 +373,22 @@ impl ManifestPackage {
 #[serde(tag = "source")]
 pub enum ManifestPackageSource {
     #[serde(rename = "hex")]
-    Hex { outer_checksum: Base16Checksum },
+    Hex {
+        outer_checksum: Base16Checksum,
+        /// The name of the repository declared in gleam.toml that the package
+        /// was fetched from, or `hexpm:<organization>` for the packages of a
+        /// private organization. Packages from the Hex repository have none.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repository: Option<EcoString>,
+    },
     #[serde(rename = "git")]
-    Git { repo: EcoString, commit: EcoString },
+    Git {
+        repo: EcoString,
+        commit: EcoString,
+        /// The directory of the repository that contains the package.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subdir: Option<Utf8PathBuf>,
+    },
     #[serde(rename = "local")]
     Local { path: Utf8PathBuf }, // should be the canonical path
 }
@@ -258,6 +458,7 @@ mod tests {
                     requirements: vec![],
                     source: ManifestPackageSource::Hex {
                         outer_checksum: Base16Checksum(vec![1, 22]),
+                        repository: None,
                     },
                 },
                 ManifestPackage {
@@ -268,6 +469,7 @@ mod tests {
                     requirements: vec!["zzz".into(), "gleam_stdlib".into()],
                     source: ManifestPackageSource::Hex {
                         outer_checksum: Base16Checksum(vec![3, 22]),
+                        repository: None,
                     },
                 },
                 ManifestPackage {
@@ -278,6 +480,7 @@ mod tests {
                     requirements: vec![],
                     source: ManifestPackageSource::Hex {
                         outer_checksum: Base16Checksum(vec![3, 22]),
+                        repository: None,
                     },
                 },
                 ManifestPackage {
@@ -289,6 +492,7 @@ mod tests {
                     source: ManifestPackageSource::Git {
                         repo: "https://github.com/gleam-lang/gleam.git".into(),
                         commit: "bd9fe02f72250e6a136967917bcb1bdccaffa3c8".into(),
+                        subdir: None,
                     },
                 },
                 ManifestPackage {
@@ -309,6 +513,7 @@ mod tests {
                     requirements: vec!["gleam_stdlib".into()],
                     source: ManifestPackageSource::Hex {
                         outer_checksum: Base16Checksum(vec![3, 46]),
+                        repository: None,
                     },
                 },
             ],
@@ -320,6 +525,9 @@ mod tests {
             r#"# This file was generated by Gleam
 # You typically do not need to edit this file
 
+version = 2
+checksum = "1fd28eddb95ee7a611417cdbfe207afe723b515d668bdfb340d75c6c928ab0e6"
+
 packages = [
   { name = "aaa", version = "0.4.0", build_tools = ["rebar3", "make"], requirements = ["zzz", "gleam_stdlib"], otp_app = "aaa_app", source = "hex", outer_checksum = "0316" },
   { name = "awsome_local1", version = "1.2.3", build_tools = ["gleam"], requirements = [], source = "local", path = "../path/to/package" },
@@ -340,6 +548,232 @@ zzz = { version = "> 0.0.0" }
         );
     }
 
+    #[test]
+    fn parse_version_0_manifest() {
+        let toml = r#"# This file was generated by Gleam
+# You typically do not need to edit this file
+
+packages = [
+  { name = "gleam_stdlib", version = "0.17.1", build_tools = ["gleam"], requirements = [], source = "hex", outer_checksum = "0116" },
+]
+
+[requirements]
+gleam_stdlib = { version = "~> 0.17" }
+"#;
+        let manifest = Manifest::parse(toml, Utf8Path::new("manifest.toml")).unwrap();
+        assert_eq!(
+            manifest,
+            Manifest {
+                requirements: [("gleam_stdlib".into(), Requirement::hex("~> 0.17"))].into(),
+                packages: vec![ManifestPackage {
+                    name: "gleam_stdlib".into(),
+                    version: Version::new(0, 17, 1),
+                    build_tools: ["gleam".into()].into(),
+                    otp_app: None,
+                    requirements: vec![],
+                    source: ManifestPackageSource::Hex {
+                        outer_checksum: Base16Checksum(vec![1, 22]),
+                        repository: None,
+                    },
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_written_manifest() {
+        let manifest = Manifest {
+            requirements: [("gleam_stdlib".into(), Requirement::hex("~> 0.17"))].into(),
+            packages: vec![ManifestPackage {
+                name: "gleam_stdlib".into(),
+                ..Default::default()
+            }],
+        };
+        let toml = manifest.to_toml(HOME.into());
+        assert_eq!(
+            Manifest::parse(&toml, Utf8Path::new("manifest.toml")),
+            Ok(manifest)
+        );
+    }
+
+    #[test]
+    fn parse_written_manifest_with_git_subdir() {
+        let manifest = Manifest {
+            requirements: HashMap::new(),
+            packages: vec![ManifestPackage {
+                name: "foo".into(),
+                source: ManifestPackageSource::Git {
+                    repo: "https://example.com/monorepo.git".into(),
+                    commit: "bd9fe02f72250e6a136967917bcb1bdccaffa3c8".into(),
+                    subdir: Some("packages/foo".into()),
+                },
+                ..Default::default()
+            }],
+        };
+        let toml = manifest.to_toml(HOME.into());
+        assert!(toml.contains(r#", subdir = "packages/foo""#));
+        assert_eq!(
+            Manifest::parse(&toml, Utf8Path::new("manifest.toml")),
+            Ok(manifest)
+        );
+    }
+
+    #[test]
+    fn parse_written_manifest_with_hex_repository() {
+        let manifest = Manifest {
+            requirements: HashMap::new(),
+            packages: vec![ManifestPackage {
+                name: "foo".into(),
+                source: ManifestPackageSource::Hex {
+                    outer_checksum: Base16Checksum(vec![1, 22]),
+                    repository: Some("internal".into()),
+                },
+                ..Default::default()
+            }],
+        };
+        let toml = manifest.to_toml(HOME.into());
+        assert!(toml.contains(r#"outer_checksum = "0116", repository = "internal""#));
+        assert_eq!(
+            Manifest::parse(&toml, Utf8Path::new("manifest.toml")),
+            Ok(manifest)
+        );
+    }
+
+    #[test]
+    fn edited_manifest_is_detected() {
+        let manifest = Manifest {
+            requirements: [("gleam_stdlib".into(), Requirement::hex("~> 0.17"))].into(),
+            packages: vec![ManifestPackage {
+                name: "gleam_stdlib".into(),
+                version: Version::new(0, 17, 1),
+                ..Default::default()
+            }],
+        };
+        let toml = manifest.to_toml(HOME.into());
+        assert!(!Manifest::is_edited(&toml));
+        assert!(!Manifest::is_edited(&toml.replace('\n', "\r\n")));
+
+        let edited = toml.replace(r#"version = "0.17.1""#, r#"version = "0.18.0""#);
+        assert_ne!(edited, toml);
+        assert!(Manifest::is_edited(&edited));
+    }
+
+    #[test]
+    fn manifest_without_checksum_is_not_edited() {
+        let toml = r#"version = 1
+packages = []
+
+[requirements]
+"#;
+        assert!(!Manifest::is_edited(toml));
+    }
+
+    #[test]
+    fn parse_manifest_from_newer_version() {
+        let toml = r#"version = 3
+packages = []
+
+[requirements]
+"#;
+        assert_eq!(
+            Manifest::parse(toml, Utf8Path::new("manifest.toml")),
+            Err(Error::ManifestVersionTooNew {
+                path: "manifest.toml".into(),
+                version: 3,
+                supported: MANIFEST_VERSION,
+            })
+        );
+    }
+
+    #[test]
+    fn changes_between_manifests() {
+        let package = |name: &str, version: Version| ManifestPackage {
+            name: name.into(),
+            version,
+            ..Default::default()
+        };
+        let old = Manifest {
+            requirements: HashMap::new(),
+            packages: vec![
+                package("changed", Version::new(1, 0, 0)),
+                package("downgraded", Version::new(2, 0, 0)),
+                package("removed", Version::new(1, 0, 0)),
+                package("same", Version::new(1, 0, 0)),
+            ],
+        };
+        let new = Manifest {
+            requirements: HashMap::new(),
+            packages: vec![
+                package("added", Version::new(0, 1, 0)),
+                package("changed", Version::new(1, 1, 0)),
+                package("downgraded", Version::new(1, 9, 0)),
+                package("same", Version::new(1, 0, 0)),
+            ],
+        };
+
+        assert_eq!(
+            old.changes_to(&new),
+            vec![
+                PackageChange::Added {
+                    name: "added".into(),
+                    version: Version::new(0, 1, 0),
+                },
+                PackageChange::Changed {
+                    name: "changed".into(),
+                    old: Version::new(1, 0, 0),
+                    new: Version::new(1, 1, 0),
+                },
+                PackageChange::Changed {
+                    name: "downgraded".into(),
+                    old: Version::new(2, 0, 0),
+                    new: Version::new(1, 9, 0),
+                },
+                PackageChange::Removed {
+                    name: "removed".into(),
+                    version: Version::new(1, 0, 0),
+                },
+            ]
+        );
+        assert_eq!(new.changes_to(&new), vec![]);
+    }
+
+    #[test]
+    fn downgrades_between_manifests() {
+        let package = |name: &str, version: Version| ManifestPackage {
+            name: name.into(),
+            version,
+            ..Default::default()
+        };
+        let old = Manifest {
+            requirements: HashMap::new(),
+            packages: vec![
+                package("downgraded", Version::new(2, 0, 0)),
+                package("removed", Version::new(1, 0, 0)),
+                package("same", Version::new(1, 0, 0)),
+                package("upgraded", Version::new(1, 0, 0)),
+            ],
+        };
+        let new = Manifest {
+            requirements: HashMap::new(),
+            packages: vec![
+                package("added", Version::new(0, 1, 0)),
+                package("downgraded", Version::parse("2.0.0-rc1").unwrap()),
+                package("same", Version::new(1, 0, 0)),
+                package("upgraded", Version::new(1, 1, 0)),
+            ],
+        };
+
+        assert_eq!(
+            old.downgrades_to(&new),
+            vec![(
+                "downgraded".into(),
+                Version::new(2, 0, 0),
+                Version::parse("2.0.0-rc1").unwrap()
+            )]
+        );
+        assert_eq!(new.downgrades_to(&new), vec![]);
+    }
+
     impl Default for ManifestPackage {
         fn default() -> Self {
             Self {
@@ -350,6 +784,7 @@ zzz = { version = "> 0.0.0" }
                 version: Version::new(1, 0, 0),
                 source: ManifestPackageSource::Hex {
                     outer_checksum: Base16Checksum(vec![]),
+                    repository: None,
                 },
             }
         }
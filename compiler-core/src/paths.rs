@@ -1,6 +1,7 @@
 use crate::build::{Mode, Target};
 
 use camino::{Utf8Path, Utf8PathBuf};
+use std::{ffi::OsString, path::PathBuf};
 
 pub const ARTEFACT_DIRECTORY_NAME: &str = "_gleam_artefacts";
 
@@ -48,6 +49,14 @@ impl ProjectPaths {
         self.root.join("test")
     }
 
+    pub fn vendor_directory(&self) -> Utf8PathBuf {
+        self.root.join("vendor")
+    }
+
+    pub fn vendor_package(&self, package_name: &str) -> Utf8PathBuf {
+        self.vendor_directory().join(package_name)
+    }
+
     pub fn build_directory(&self) -> Utf8PathBuf {
         self.root.join("build")
     }
@@ -64,6 +73,17 @@ impl ProjectPaths {
         self.build_packages_directory().join(package_name)
     }
 
+    /// Packages are unpacked here and then moved into the packages directory
+    /// once complete, so an interrupted unpack never leaves a partial package.
+    pub fn build_packages_staging_directory(&self) -> Utf8PathBuf {
+        self.build_packages_directory().join(".staging")
+    }
+
+    /// Hex package information kept between version resolutions.
+    pub fn build_hex_metadata_directory(&self) -> Utf8PathBuf {
+        self.build_directory().join("hex_metadata")
+    }
+
     // build_deps_package_config
     pub fn build_packages_package_config(&self, package_name: &str) -> Utf8PathBuf {
         self.build_packages_package(package_name).join("gleam.toml")
@@ -126,18 +146,55 @@ pub fn global_package_cache_package_tarball(package_name: &str, version: &str) -
     global_packages_cache().join(format!("{package_name}-{version}.tar"))
 }
 
-fn global_packages_cache() -> Utf8PathBuf {
+pub fn global_packages_cache() -> Utf8PathBuf {
     default_global_gleam_cache()
         .join("hex")
         .join("hexpm")
         .join("packages")
 }
 
+/// The directory of bare git repositories that git dependencies are fetched
+/// into, shared by all projects.
+pub fn global_git_cache() -> Utf8PathBuf {
+    default_global_gleam_cache().join("git")
+}
+
+/// The directory of the global caches, which can be set with the
+/// `GLEAM_CACHE` environment variable so that it can be kept between CI runs.
 pub fn default_global_gleam_cache() -> Utf8PathBuf {
-    Utf8PathBuf::from_path_buf(
-        dirs_next::cache_dir()
+    global_gleam_cache_from(std::env::var_os("GLEAM_CACHE"))
+}
+
+fn global_gleam_cache_from(variable: Option<OsString>) -> Utf8PathBuf {
+    let path = match variable {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => dirs_next::cache_dir()
             .expect("Failed to determine user cache directory")
             .join("gleam"),
+    };
+    Utf8PathBuf::from_path_buf(path).expect("Non Utf8 Path")
+}
+
+/// A file containing the API key used to fetch packages from private Hex
+/// organizations.
+pub fn global_hex_api_key() -> Utf8PathBuf {
+    default_global_gleam_config().join("hex_api_key")
+}
+
+/// A TOML file of the API keys and URLs of private Hex repositories, if the
+/// user has a home directory.
+pub fn global_hex_credentials() -> Option<Utf8PathBuf> {
+    let home = dirs_next::home_dir()?;
+    Some(
+        Utf8PathBuf::from_path_buf(home.join(".gleam").join("credentials")).expect("Non Utf8 Path"),
+    )
+}
+
+pub fn default_global_gleam_config() -> Utf8PathBuf {
+    Utf8PathBuf::from_path_buf(
+        dirs_next::config_dir()
+            .expect("Failed to determine user config directory")
+            .join("gleam"),
     )
     .expect("Non Utf8 Path")
 }
@@ -152,10 +209,10 @@ pub fn unnest(within: &Utf8Path) -> Utf8PathBuf {
 
 #[test]
 fn paths() {
-    assert!(default_global_gleam_cache().ends_with("gleam"));
-
     assert!(global_packages_cache().ends_with("hex/hexpm/packages"));
 
+    assert_eq!(global_git_cache(), default_global_gleam_cache().join("git"));
+
     assert!(
         global_package_cache_package_tarball("gleam_stdlib", "0.17.1")
             .ends_with("hex/hexpm/packages/gleam_stdlib-0.17.1.tar")
@@ -164,3 +221,13 @@ fn paths() {
     assert!(global_package_cache_package_tarball("elli", "1.0.0")
         .ends_with("hex/hexpm/packages/elli-1.0.0.tar"));
 }
+
+#[test]
+fn global_gleam_cache_from_variable() {
+    assert_eq!(
+        global_gleam_cache_from(Some("/mnt/cache".into())),
+        Utf8PathBuf::from("/mnt/cache")
+    );
+    assert!(global_gleam_cache_from(Some("".into())).ends_with("gleam"));
+    assert!(global_gleam_cache_from(None).ends_with("gleam"));
+}
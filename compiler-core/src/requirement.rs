@@ -13,15 +13,33 @@ use serde::Deserialize;
 #[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(untagged, remote = "Self")]
 pub enum Requirement {
-    Hex { version: Range },
-    Path { path: Utf8PathBuf },
-    Git { git: EcoString },
+    Hex {
+        version: Range,
+        #[serde(default, deserialize_with = "organization_name")]
+        organization: Option<EcoString>,
+        /// The name of the repository in the `[repositories]` section of
+        /// gleam.toml to fetch the package from, rather than the Hex one.
+        #[serde(default)]
+        repository: Option<EcoString>,
+    },
+    Path {
+        path: Utf8PathBuf,
+    },
+    Git {
+        git: EcoString,
+        /// The directory within the repository that contains the package,
+        /// for repositories that don't have it at their root.
+        #[serde(default, deserialize_with = "subdirectory")]
+        subdir: Option<Utf8PathBuf>,
+    },
 }
 
 impl Requirement {
     pub fn hex(range: &str) -> Requirement {
         Requirement::Hex {
             version: Range::new(range.to_string()),
+            organization: None,
+            repository: None,
         }
     }
 
@@ -30,13 +48,27 @@ impl Requirement {
     }
 
     pub fn git(url: &str) -> Requirement {
-        Requirement::Git { git: url.into() }
+        Requirement::Git {
+            git: url.into(),
+            subdir: None,
+        }
     }
 
     pub fn to_toml(&self, root_path: &Utf8Path) -> String {
         match self {
-            Requirement::Hex { version: range } => {
-                format!(r#"{{ version = "{}" }}"#, range)
+            Requirement::Hex {
+                version: range,
+                organization,
+                repository,
+            } => {
+                let mut fields = vec![format!(r#"version = "{}""#, range)];
+                if let Some(organization) = organization {
+                    fields.push(format!(r#"organization = "{}""#, organization));
+                }
+                if let Some(repository) = repository {
+                    fields.push(format!(r#"repository = "{}""#, repository));
+                }
+                format!("{{ {} }}", fields.join(", "))
             }
             Requirement::Path { path } => {
                 format!(
@@ -44,7 +76,14 @@ impl Requirement {
                     make_relative(root_path, path).as_str().replace('\\', "/")
                 )
             }
-            Requirement::Git { git: url } => format!(r#"{{ git = "{}" }}"#, url),
+            Requirement::Git {
+                git: url,
+                subdir: None,
+            } => format!(r#"{{ git = "{}" }}"#, url),
+            Requirement::Git {
+                git: url,
+                subdir: Some(subdir),
+            } => format!(r#"{{ git = "{}", subdir = "{}" }}"#, url, subdir),
         }
     }
 }
@@ -56,11 +95,28 @@ impl Serialize for Requirement {
     where
         S: Serializer,
     {
-        let mut map = serializer.serialize_map(Some(1))?;
+        let mut map = serializer.serialize_map(None)?;
         match self {
-            Requirement::Hex { version: range } => map.serialize_entry("version", range)?,
+            Requirement::Hex {
+                version: range,
+                organization,
+                repository,
+            } => {
+                map.serialize_entry("version", range)?;
+                if let Some(organization) = organization {
+                    map.serialize_entry("organization", organization)?;
+                }
+                if let Some(repository) = repository {
+                    map.serialize_entry("repository", repository)?;
+                }
+            }
             Requirement::Path { path } => map.serialize_entry("path", path)?,
-            Requirement::Git { git: url } => map.serialize_entry("git", url)?,
+            Requirement::Git { git: url, subdir } => {
+                map.serialize_entry("git", url)?;
+                if let Some(subdir) = subdir {
+                    map.serialize_entry("subdir", subdir)?;
+                }
+            }
         }
         map.end()
     }
@@ -68,6 +124,45 @@ impl Serialize for Requirement {
 
 // Deserialization
 
+// Organization names are used in Hex repository URLs, so they are restricted to
+// the same characters Hex allows.
+fn organization_name<'de, D>(deserializer: D) -> Result<Option<EcoString>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name: &str = Deserialize::deserialize(deserializer)?;
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        Ok(Some(name.into()))
+    } else {
+        Err(de::Error::custom(
+            "Organization names may only contain lowercase letters, numbers, and underscores",
+        ))
+    }
+}
+
+// The package is read from this directory of the checked out repository, so it
+// must not lead outside of it.
+fn subdirectory<'de, D>(deserializer: D) -> Result<Option<Utf8PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path: Utf8PathBuf = Deserialize::deserialize(deserializer)?;
+    let is_contained = path
+        .components()
+        .all(|component| matches!(component, camino::Utf8Component::Normal(_)));
+    if is_contained && path.components().next().is_some() {
+        Ok(Some(path))
+    } else {
+        Err(de::Error::custom(
+            "Git subdirectories must be relative paths within the repository",
+        ))
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Void;
 
@@ -125,6 +220,7 @@ mod tests {
             hex = { version = "~> 1.0.0" }
             local = { path = "/path/to/package" }
             github = { git = "https://github.com/gleam-lang/otp.git" }
+            private = { version = "~> 1.0", organization = "acme" }
         "#;
         let deps: HashMap<String, Requirement> = toml::from_str(toml).unwrap();
         assert_eq!(deps["short"], Requirement::hex("~> 0.5"));
@@ -134,5 +230,68 @@ mod tests {
             deps["github"],
             Requirement::git("https://github.com/gleam-lang/otp.git")
         );
+        assert_eq!(
+            deps["private"],
+            Requirement::Hex {
+                version: Range::new("~> 1.0".into()),
+                organization: Some("acme".into()),
+                repository: None,
+            }
+        );
+    }
+
+    #[test]
+    fn read_requirement_with_repository() {
+        let toml = r#"
+            internal = { version = "~> 1.0", repository = "internal" }
+        "#;
+        let deps: HashMap<String, Requirement> = toml::from_str(toml).unwrap();
+        assert_eq!(
+            deps["internal"],
+            Requirement::Hex {
+                version: Range::new("~> 1.0".into()),
+                organization: None,
+                repository: Some("internal".into()),
+            }
+        );
+        assert_eq!(
+            deps["internal"].to_toml(Utf8Path::new("/")),
+            r#"{ version = "~> 1.0", repository = "internal" }"#
+        );
+    }
+
+    #[test]
+    fn read_requirement_with_invalid_organization() {
+        let toml = r#"
+            private = { version = "~> 1.0", organization = "../acme" }
+        "#;
+        assert!(toml::from_str::<HashMap<String, Requirement>>(toml).is_err());
+    }
+
+    #[test]
+    fn read_git_requirement_with_subdir() {
+        let toml = r#"
+            foo = { git = "https://example.com/monorepo.git", subdir = "packages/foo" }
+        "#;
+        let deps: HashMap<String, Requirement> = toml::from_str(toml).unwrap();
+        assert_eq!(
+            deps["foo"],
+            Requirement::Git {
+                git: "https://example.com/monorepo.git".into(),
+                subdir: Some("packages/foo".into()),
+            }
+        );
+        assert_eq!(
+            deps["foo"].to_toml(Utf8Path::new("/")),
+            r#"{ git = "https://example.com/monorepo.git", subdir = "packages/foo" }"#
+        );
+    }
+
+    #[test]
+    fn read_git_requirement_with_subdir_outside_repository() {
+        let toml = r#"
+            foo = { git = "https://example.com/monorepo.git", subdir = "../foo" }
+        "#;
+        assert!(toml::from_str::<HashMap<String, Requirement>>(toml).is_err());
     }
 }
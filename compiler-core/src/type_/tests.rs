@@ -164,6 +164,21 @@ fn get_warnings(src: &str, deps: Vec<DependencyModule<'_>>) -> Vec<Warning> {
             crate::Warning::Type { warning, .. } => warning,
             crate::Warning::Parse { .. } => panic!("Unexpected parse warning"),
             crate::Warning::InvalidSource { .. } => panic!("Invalid module file name"),
+            crate::Warning::MissingBuildTool { .. } => panic!("Unexpected build tool warning"),
+            crate::Warning::RetiredPackage { .. } => panic!("Unexpected retired package warning"),
+            crate::Warning::ReadableCredentials { .. } => {
+                panic!("Unexpected readable credentials warning")
+            }
+            crate::Warning::UnboundedRequirement { .. } => {
+                panic!("Unexpected unbounded requirement warning")
+            }
+            crate::Warning::ExtraPackageNotDeleted { .. } => {
+                panic!("Unexpected extra package warning")
+            }
+            crate::Warning::EditedManifest { .. } => panic!("Unexpected edited manifest warning"),
+            crate::Warning::DuplicateOtpApp { .. } => {
+                panic!("Unexpected duplicate OTP app warning")
+            }
         })
         .collect_vec()
 }
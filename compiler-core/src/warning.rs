@@ -7,6 +7,8 @@ use crate::{
 use camino::Utf8PathBuf;
 use debug_ignore::DebugIgnore;
 use ecow::EcoString;
+use hexpm::version::Version;
+use itertools::Itertools;
 use std::sync::atomic::AtomicUsize;
 use std::{
     io::Write,
@@ -152,6 +154,33 @@ pub enum Warning {
     InvalidSource {
         path: Utf8PathBuf,
     },
+    MissingBuildTool {
+        package: EcoString,
+        build_tools: Vec<EcoString>,
+    },
+    RetiredPackage {
+        package: EcoString,
+        version: Version,
+        retirement: hexpm::RetirementStatus,
+    },
+    ReadableCredentials {
+        path: Utf8PathBuf,
+    },
+    UnboundedRequirement {
+        package: EcoString,
+        version: Version,
+    },
+    ExtraPackageNotDeleted {
+        package: EcoString,
+        error: Box<crate::Error>,
+    },
+    EditedManifest {
+        path: Utf8PathBuf,
+    },
+    DuplicateOtpApp {
+        otp_app: EcoString,
+        packages: Vec<EcoString>,
+    },
 }
 
 impl Warning {
@@ -194,6 +223,114 @@ a future version of Gleam."
                     "Rename `{path}` to be valid, or remove this file from the project source."
                 )),
             },
+
+            Warning::MissingBuildTool {
+                package,
+                build_tools,
+            } => Diagnostic {
+                title: "Build tool not installed".into(),
+                text: format!(
+                    "The package `{package}` is built with {}, which could not
+be found. Is it installed?",
+                    build_tools
+                        .iter()
+                        .map(|tool| format!("`{tool}`"))
+                        .join(" or ")
+                ),
+                level: diagnostic::Level::Warning,
+                location: None,
+                hint: None,
+            },
+
+            Warning::RetiredPackage {
+                package,
+                version,
+                retirement,
+            } => {
+                let mut text = format!(
+                    "Version {version} of `{package}` has been retired on Hex, with the
+reason \"{}\".",
+                    retirement.reason.to_str()
+                );
+                if !retirement.message.is_empty() {
+                    text.push_str(&format!("\nThe message given was: {}", retirement.message));
+                }
+                Diagnostic {
+                    title: "Retired package used".into(),
+                    text,
+                    level: diagnostic::Level::Warning,
+                    location: None,
+                    hint: Some(
+                        "Run `gleam update` to use a version that has not been retired.".into(),
+                    ),
+                }
+            }
+
+            Warning::ReadableCredentials { path } => Diagnostic {
+                title: "Credentials file can be read by other users".into(),
+                text: format!(
+                    "The Hex credentials file at {path} can be read by every user
+of this computer, which would let them use your API keys."
+                ),
+                level: diagnostic::Level::Warning,
+                location: None,
+                hint: Some(format!("Run `chmod 600 {path}` to make it private.")),
+            },
+
+            Warning::UnboundedRequirement { package, version } => Diagnostic {
+                title: "Unbounded version requirement".into(),
+                text: format!(
+                    "The requirement for `{package}` is `>= 0.0.0`, so any version of it
+can be used, including ones with breaking changes. Version {version}
+was selected."
+                ),
+                level: diagnostic::Level::Warning,
+                location: None,
+                hint: Some(format!(
+                    "Change the requirement in gleam.toml to `~> {}.{}` to only allow
+compatible versions.",
+                    version.major, version.minor
+                )),
+            },
+            Warning::ExtraPackageNotDeleted { package, error } => Diagnostic {
+                title: "Unneeded package not deleted".into(),
+                text: format!(
+                    "The package `{package}` is no longer needed but its files could not
+be deleted. The build will continue using the other packages.
+{}",
+                    error.to_diagnostic().text
+                ),
+                level: diagnostic::Level::Warning,
+                location: None,
+                hint: Some("Delete the files yourself or run `gleam clean`.".into()),
+            },
+            Warning::EditedManifest { path } => Diagnostic {
+                title: "Manifest edited".into(),
+                text: format!(
+                    "The contents of {path} don't match the checksum Gleam recorded
+when it wrote them, so the file may have been edited by hand and may
+not be consistent with the project's requirements."
+                ),
+                level: diagnostic::Level::Warning,
+                location: None,
+                hint: Some("Run `gleam deps update` to write the manifest again.".into()),
+            },
+            Warning::DuplicateOtpApp { otp_app, packages } => Diagnostic {
+                title: "Duplicate OTP application".into(),
+                text: format!(
+                    "These packages all provide the OTP application `{otp_app}`, which
+will clash when the project runs on the BEAM:
+
+{}",
+                    packages
+                        .iter()
+                        .map(|package| format!("  - {package}"))
+                        .join("\n")
+                ),
+                level: diagnostic::Level::Warning,
+                location: None,
+                hint: Some("Remove all but one of these packages from the project.".into()),
+            },
             Self::Type { path, warning, src } => match warning {
                 type_::Warning::UnusedFunctionBody { location } => Diagnostic {
                     title: "Unused function body".into(),
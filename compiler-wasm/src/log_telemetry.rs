@@ -1,4 +1,10 @@
-use gleam_core::build::Telemetry;
+use ecow::EcoString;
+use gleam_core::{
+    build::{ResolutionPhase, Telemetry},
+    manifest::{MissingPackageReason, PackageChange},
+    Error,
+};
+use hexpm::{version::Version, RetirementStatus};
 #[derive(Debug)]
 pub struct LogTelemetry;
 
@@ -15,12 +21,60 @@ impl Telemetry for LogTelemetry {
         tracing::info!("Downloading package: {}", name);
     }
 
+    fn package_missing(&self, name: &str, reason: &MissingPackageReason) {
+        tracing::info!("Package missing: {} ({:?})", name, reason);
+    }
+
+    fn package_progress(&self, name: &str, bytes: u64, total: u64) {
+        tracing::debug!("Package progress: {} {}/{}", name, bytes, total);
+    }
+
     fn resolving_package_versions(&self) {
         tracing::info!("Resolving package versions");
     }
 
-    fn packages_downloaded(&self, _start: std::time::Instant, count: usize) {
-        tracing::info!("Downloaded {} packages", count);
+    fn resolved_package_changes(&self, changes: &[PackageChange]) {
+        for change in changes {
+            tracing::info!("Resolved package change: {:?}", change);
+        }
+    }
+
+    fn retired_package_resolved(
+        &self,
+        name: &str,
+        version: &Version,
+        retirement: &RetirementStatus,
+    ) {
+        tracing::warn!(
+            "Retired package resolved: {} {} ({})",
+            name,
+            version,
+            retirement.reason.to_str()
+        );
+    }
+
+    fn unbounded_requirement_resolved(&self, name: &str, version: &Version) {
+        tracing::warn!("Unbounded requirement resolved: {} {}", name, version);
+    }
+
+    fn extra_package_not_deleted(&self, name: &str, error: &Error) {
+        tracing::warn!("Extra package not deleted: {} ({})", name, error);
+    }
+
+    fn duplicate_otp_app_resolved(&self, otp_app: &str, packages: &[EcoString]) {
+        tracing::warn!(
+            "Duplicate OTP application resolved: {} ({})",
+            otp_app,
+            packages.join(", ")
+        );
+    }
+
+    fn resolution_phase_finished(&self, phase: ResolutionPhase, elapsed: std::time::Duration) {
+        tracing::info!("Resolution phase {:?} finished in {:?}", phase, elapsed);
+    }
+
+    fn packages_downloaded(&self, _start: std::time::Instant, count: usize, bytes: u64) {
+        tracing::info!("Downloaded {} packages ({} bytes)", count, bytes);
     }
 
     fn waiting_for_build_directory_lock(&self) {
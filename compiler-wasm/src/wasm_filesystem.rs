@@ -61,6 +61,11 @@ impl FileSystemWriter for WasmFileSystem {
         self.imfs.delete_file(path)
     }
 
+    fn rename(&self, from: &Utf8Path, to: &Utf8Path) -> Result<(), Error> {
+        tracing::trace!("rename {:?} to {:?}", from, to);
+        self.imfs.rename(from, to)
+    }
+
     fn write(&self, path: &Utf8Path, content: &str) -> Result<(), Error> {
         tracing::trace!("write {:?}", path);
         self.imfs.write(path, content)